@@ -0,0 +1,8 @@
+// Completes with the promise of an async function that awaits other promises
+async function greet() {
+  const word = await Promise.resolve("do");
+  console.log("awaited", word);
+  return word + "ne";
+}
+
+(async () => await greet())();
@@ -0,0 +1,22 @@
+// Round-trips binary bodies through the server started by test_binary_fetch
+const server = "http://localhost:8045";
+
+const download = fetch({ url: server + "/bytes" });
+const upload = fetch({
+  url: server + "/upload",
+  method: "POST",
+  headers: { "Content-Type": "application/octet-stream" },
+  body: download.body,
+  body_encoding: "base64",
+});
+const text = fetch({ url: server + "/text", body_encoding: "base64" });
+const json = JSON.parse(
+  hoyaFetch(JSON.stringify({ url: server + "/bytes", method: "GET", headers: {} }))
+);
+
+JSON.stringify({
+  download: [download.body_encoding, download.body],
+  upload: [upload.body_encoding, upload.body],
+  text: [text.body_encoding, text.body],
+  hoyaFetch: [json.body_encoding, json.body],
+});
@@ -0,0 +1,4 @@
+// Completes with an object referring to itself, which has no JSON form
+const node = { name: "loop" };
+node.next = node;
+node;
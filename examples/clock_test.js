@@ -0,0 +1,5 @@
+// Reads every clock once, in a fixed order. Returns
+// [get_unixtime_ms(), performance.now(), performance.now(), get_unixtime(),
+// performance.timeOrigin]; with deterministic: true these are the same on
+// every run.
+[get_unixtime_ms(), performance.now(), performance.now(), get_unixtime(), performance.timeOrigin];
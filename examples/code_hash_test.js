@@ -0,0 +1,2 @@
+// Returns the digest the script sees of its own code
+HOYA_CODE_SHA256;
@@ -0,0 +1,16 @@
+// Test file for console severities
+// log, info and debug go to stdout; warn, error and trace go to stderr
+
+console.log("log line");
+console.info("info line");
+console.debug("debug", { n: 1 });
+console.warn("warn line");
+console.error("error line");
+
+function inner() {
+  console.trace("tracing");
+}
+inner();
+
+// Result value returned from the script
+("console levels");
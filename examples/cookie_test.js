@@ -0,0 +1,11 @@
+// Visits the private page before and after logging in on the server started by test_fetch_cookies
+const server = "http://localhost:8094";
+
+function get(path, options = {}) {
+  return JSON.parse(hoyaFetch(JSON.stringify({ url: server + path, method: "GET", headers: {}, ...options })));
+}
+
+const before = get("/private").status;
+const login = get("/login").status;
+const after = get("/private", { cookies: true });
+JSON.stringify({ before, login, after: after.status, cookies: after.cookies ?? null });
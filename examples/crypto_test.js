@@ -0,0 +1,23 @@
+// Draws from crypto and Math.random; with the same seed every value repeats
+const bytes = crypto.getRandomValues(new Uint8Array(16));
+const words = crypto.getRandomValues(new Uint32Array(2));
+
+const errors = [];
+for (const call of [
+  () => crypto.getRandomValues(new Float64Array(1)),
+  () => crypto.getRandomValues(new Uint8Array(65537)),
+]) {
+  try {
+    call();
+  } catch (error) {
+    errors.push(error.name);
+  }
+}
+
+({
+  bytes: Array.from(bytes),
+  words: Array.from(words),
+  uuid: crypto.randomUUID(),
+  random: Math.random(),
+  errors,
+});
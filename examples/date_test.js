@@ -0,0 +1,5 @@
+// Reads Date's current time and the local-time helpers once each, in a fixed
+// order. Returns [new Date().toISOString(), Date.now(), hoyaLocalTime(),
+// new Date(0).toISOString(), new Date() instanceof Date]; with
+// deterministic: true these are the same on every run.
+[new Date().toISOString(), Date.now(), hoyaLocalTime(), new Date(0).toISOString(), new Date() instanceof Date];
@@ -0,0 +1,8 @@
+// Reports what the script sees of the request's env
+JSON.stringify({
+  api: env.API_URL,
+  names: Object.keys(env),
+  frozen: Object.isFrozen(env),
+  missing: env.MISSING === undefined,
+  hostPath: typeof env.PATH,
+});
@@ -0,0 +1,10 @@
+// Throws a TypeError from line 7; the error details carry its name and line
+function checkout(order) {
+  let total = 0;
+  for (const item of order.items) {
+    total += item.price;
+  }
+  throw new TypeError("order " + order.id + " has no currency: " + total);
+}
+
+checkout({ id: 7, items: [{ price: 3 }, { price: 4 }] });
@@ -0,0 +1,19 @@
+// Runs into each fetch call limit against the server started by test_fetch_limits
+const server = "http://localhost:8046";
+
+function code(options) {
+  try {
+    return fetch(options).status;
+  } catch (error) {
+    return error.code + ": " + error.message;
+  }
+}
+
+JSON.stringify({
+  timeout: code({ url: server + "/slow", timeout_ms: 200 }),
+  redirects: code({ url: server + "/loop", max_redirects: 2 }),
+  clamped: code({ url: server + "/loop", max_redirects: 1000 }),
+  declared: code({ url: server + "/large", max_response_bytes: 100 }),
+  streamed: code({ url: server + "/stream", max_response_bytes: 100 }),
+  within: code({ url: server + "/large", max_response_bytes: 1000 }),
+});
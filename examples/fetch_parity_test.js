@@ -0,0 +1,4 @@
+// Sends the request's input, the options of one fetch call, through hoyaFetch
+// and returns the response JSON as is. wasm-fetch-parity-test/parity.wat does
+// the same through the wasm fetch import.
+hoyaFetch(JSON.stringify(input));
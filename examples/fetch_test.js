@@ -0,0 +1,24 @@
+// Exercises the fetch global against the echo server started by test_js_fetch
+const echo = "http://localhost:8021";
+
+const get = fetch({ url: echo + "/get", headers: { "X-Hoya-Test": "get" } });
+const post = fetch({
+  url: echo + "/post",
+  method: "POST",
+  headers: { "Content-Type": "application/json" },
+  body: JSON.stringify({ n: 1 }),
+});
+
+let refused;
+try {
+  fetch({ url: "http://localhost:1/" });
+  refused = "no error";
+} catch (error) {
+  refused = error.code + (error instanceof Error ? "" : " (not an Error)");
+}
+
+JSON.stringify({
+  get: { status: get.status, type: get.headers["content-type"], echo: JSON.parse(get.body) },
+  post: { status: post.status, echo: JSON.parse(post.body) },
+  refused,
+});
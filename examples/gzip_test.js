@@ -0,0 +1,50 @@
+// Property test for hoyaGzip/hoyaGunzip: random payloads must round-trip,
+// corrupt input and decompression bombs must fail with their own error codes.
+// Run with `deterministic: true` to make the payloads reproducible.
+const failures = [];
+
+function randomPayload() {
+  const length = Math.floor(Math.random() * 8192);
+  const payload = new Uint8Array(length);
+  // Mix incompressible noise with runs so both deflate paths are exercised
+  const alphabet = Math.random() < 0.5 ? 256 : 4;
+  for (let i = 0; i < length; i++) {
+    payload[i] = Math.floor(Math.random() * alphabet);
+  }
+  return payload;
+}
+
+function equal(a, b) {
+  if (a.length !== b.length) return false;
+  for (let i = 0; i < a.length; i++) {
+    if (a[i] !== b[i]) return false;
+  }
+  return true;
+}
+
+function errorCode(fn) {
+  try {
+    fn();
+    return "no error";
+  } catch (e) {
+    return e.code;
+  }
+}
+
+for (let run = 0; run < 200; run++) {
+  const payload = randomPayload();
+  const restored = hoyaGunzip(hoyaGzip(payload));
+  if (!equal(payload, restored)) {
+    failures.push("round-trip of " + payload.length + " bytes");
+  }
+}
+
+const corrupt = errorCode(() => hoyaGunzip(new Uint8Array([1, 2, 3, 4, 5, 6, 7, 8])));
+if (corrupt !== "GZIP_CORRUPT") failures.push("corrupt input: " + corrupt);
+
+// 17 MiB of zeros compresses to a few KiB but exceeds the 16 MiB cap
+const bomb = hoyaGzip(new Uint8Array(17 * 1024 * 1024));
+const tooLarge = errorCode(() => hoyaGunzip(bomb));
+if (tooLarge !== "GZIP_TOO_LARGE") failures.push("bomb: " + tooLarge);
+
+failures.length === 0 ? "ok" : "FAILED: " + failures.join("; ");
@@ -0,0 +1,5 @@
+// Answers with a greeting for the request's input and its numbers doubled
+({
+  greeting: "hello " + input.name,
+  doubled: input.numbers.map((n) => n * 2),
+});
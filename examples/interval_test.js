@@ -0,0 +1,3 @@
+// An interval that is never cleared runs until the execution times out
+setInterval(() => console.log("tick"), 10);
+"never returned";
@@ -0,0 +1,10 @@
+// Completes with an object holding values JSON has no direct form for
+({
+  name: "report",
+  count: 3,
+  tags: ["a", "b"],
+  total: 12345678901234567890n,
+  created: new Date(0),
+  format: function formatReport() {},
+  bytes: new Uint8Array([1, 2, 3]),
+});
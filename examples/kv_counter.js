@@ -0,0 +1,6 @@
+// Counts its own executions in the host key-value store. Run with a
+// "kv_namespace" to count across executions; without one every execution
+// starts from an empty namespace. Returns the count after this execution.
+const count = Number(hoya.kv.get("count") || "0") + 1;
+hoya.kv.set("count", String(count));
+String(count);
@@ -0,0 +1,16 @@
+// Writes keys until the namespace's entry quota refuses one, then removes
+// them again. Returns the number of keys written, the code of the refusal
+// and whether the first key was still there to delete.
+let written = 0;
+let refusal = "none";
+try {
+  for (; written < 100; written++) {
+    hoya.kv.set("key" + written, "value");
+  }
+} catch (e) {
+  refusal = e.code;
+}
+for (let i = 0; i < written; i++) {
+  hoya.kv.delete("key" + i);
+}
+written + "," + refusal + "," + hoya.kv.delete("key0");
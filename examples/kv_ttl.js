@@ -0,0 +1,7 @@
+// Keeps a "session" entry for 300 ms. Returns "fresh" while the entry
+// lives, and "set" when it had to write it again.
+const session = hoya.kv.get("session");
+if (session === null) {
+  hoya.kv.set("session", "fresh", { ttl_ms: 300 });
+}
+session ?? "set";
@@ -0,0 +1,13 @@
+// Makes three guest fetches, then logs ten lines; the result lists each
+// fetch's status or error code
+const outcomes = [];
+for (let i = 0; i < 3; i++) {
+  const response = JSON.parse(
+    hoyaFetch(JSON.stringify({ url: "http://localhost:3000/metrics", method: "GET", headers: {} }))
+  );
+  outcomes.push(response.error ? response.error.code : String(response.status));
+}
+for (let i = 0; i < 10; i++) {
+  console.log("line " + i);
+}
+outcomes.join(",");
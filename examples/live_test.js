@@ -0,0 +1,7 @@
+// Writes to every channel with a second of work in between, for test_live_stream
+console.log("started");
+const end = Date.now() + 1000;
+while (Date.now() < end) {}
+console.error("still working");
+app_log("info", "done");
+"finished";
@@ -0,0 +1,5 @@
+// Pathological logging loop: far more console calls than js.max_console_calls allows
+for (let i = 0; i < 2000000; i++) {
+  console.log("");
+}
+"done";
@@ -0,0 +1,6 @@
+// Keeps every chunk reachable until the heap limit stops it
+console.log("allocating");
+const chunks = [];
+for (let i = 0; ; i++) {
+  chunks.push(new Array(1 << 16).fill(i));
+}
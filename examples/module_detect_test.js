@@ -0,0 +1,4 @@
+// Served as .js, but its export makes it run as an ES module
+const parts = ["detected", "as", "module"];
+
+export default parts.join(" ");
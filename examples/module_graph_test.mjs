@@ -0,0 +1,6 @@
+// ES module run by test_js_module_imports: imports two modules that import
+// each other, resolved against this module's URL
+import { ping } from "./lib/cycle_a.mjs";
+import { pong } from "./lib/cycle_b.mjs";
+
+export default { ping: ping(4), pong: pong(3) };
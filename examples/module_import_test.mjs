@@ -0,0 +1,5 @@
+// ES module with a static import of a bare package name, which fails with
+// MODULE_RESOLUTION_DISABLED
+import { helper } from "helper";
+
+export default helper();
@@ -0,0 +1,5 @@
+// ES module run by test_js_module_imports: imports a module whose own
+// import is not found
+import { value } from "./lib/uses_missing.mjs";
+
+export default value;
@@ -0,0 +1,17 @@
+// ES module run by test_js_modules: imports a data: URL and exports main
+const helper = await import(
+  "data:text/javascript,export const twice = (n) => n * 2; export default 'helper';"
+);
+const encoded = await import("data:text/javascript;base64,ZXhwb3J0IGRlZmF1bHQgImJhc2U2NCI7");
+
+export const answer = helper.twice(21);
+
+export async function main() {
+  let refused = null;
+  try {
+    await import("left-pad");
+  } catch (error) {
+    refused = String(error.message).includes("MODULE_RESOLUTION_DISABLED");
+  }
+  return { answer, helper: helper.default, encoded: encoded.default, refused };
+}
@@ -0,0 +1,3 @@
+// Compiles, then throws a SyntaxError at run time, which is an ordinary exception
+console.log("parsing");
+JSON.parse("{ not json");
@@ -0,0 +1,12 @@
+// Leaves a global and a prototype property behind, after checking that no
+// earlier execution on the same pooled runtime left them for it
+const leftovers = [typeof globalThis.tenant, typeof {}.tenantTag];
+globalThis.tenant = input.tenant;
+Object.prototype.tenantTag = input.tenant;
+
+// Waits a little, so concurrent executions overlap
+new Promise((done) => setTimeout(done, 20)).then(() => ({
+  tenant: globalThis.tenant,
+  tag: {}.tenantTag,
+  leftovers,
+}));
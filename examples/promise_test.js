@@ -0,0 +1,2 @@
+// Completes with a promise; the engine runs the job queue and returns 42
+Promise.resolve(21).then((half) => half * 2);
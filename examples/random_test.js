@@ -0,0 +1,7 @@
+// Draws a few values from Math.random; in deterministic mode the sequence
+// depends only on the request's seed.
+const values = [];
+for (let i = 0; i < 5; i++) {
+  values.push(Math.random());
+}
+values.join(",");
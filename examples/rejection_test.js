@@ -0,0 +1,7 @@
+// Completes with a promise rejected from inside an async function
+async function load() {
+  await null;
+  throw new Error("nothing to load");
+}
+
+load();
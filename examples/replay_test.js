@@ -0,0 +1,6 @@
+// Deterministic script for replay testing: same code, same output on every run
+const squares = [1, 2, 3, 4].map((n) => n * n);
+console.log("squares:", squares.join(","));
+console.error("no clock, no randomness");
+
+squares.reduce((sum, n) => sum + n, 0);
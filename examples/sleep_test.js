@@ -0,0 +1,8 @@
+// Sleeps 300 ms with sleep(), printing before and after. Resolves to the
+// value sleep() resolved to; run with a timeout under 300 ms, the execution
+// ends before the sleep does.
+console.log("before");
+sleep(300).then((status) => {
+  console.log("status " + status);
+  return status;
+});
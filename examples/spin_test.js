@@ -0,0 +1,4 @@
+// Prints a line, then loops forever; only the execution timeout ends it
+console.log("before the loop");
+console.error("still before the loop");
+while (true) {}
@@ -0,0 +1,20 @@
+// Exercises the outbound policy against the redirect server started by test_fetch_policy
+const server = "http://localhost:8032";
+
+function code(url) {
+  try {
+    fetch({ url });
+    return "no error";
+  } catch (error) {
+    return error.code;
+  }
+}
+
+const followed = fetch({ url: server + "/hop" });
+
+JSON.stringify({
+  loopback: code("http://127.0.0.1:8032/secret"),
+  metadata: code("http://169.254.169.254/latest/meta-data/"),
+  redirected: code(server + "/redirect"),
+  followed: { status: followed.status, body: followed.body },
+});
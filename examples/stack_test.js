@@ -0,0 +1,13 @@
+// Throws from a nested function call; the error's stack names the script's URL
+function parseConfig(text) {
+  return validate(JSON.parse(text));
+}
+
+function validate(config) {
+  if (!config.name) {
+    throw new Error("config has no name");
+  }
+  return config;
+}
+
+parseConfig("{}");
@@ -0,0 +1,5 @@
+// Counts its own executions in the persistent store; run with
+// "permissions": ["storage"]. Returns the count after this execution.
+const count = Number(hoyaStore.get("count") || "0") + 1;
+hoyaStore.set("count", String(count));
+String(count);
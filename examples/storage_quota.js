@@ -0,0 +1,16 @@
+// Writes keys until the namespace's key quota refuses one, then removes
+// them again; run with "permissions": ["storage"]. Returns the number of
+// keys written and the code of the refusal.
+let written = 0;
+let refusal = "none";
+try {
+  for (; written < 100; written++) {
+    hoyaStore.set("key" + written, "value");
+  }
+} catch (e) {
+  refusal = e.code;
+}
+for (let i = 0; i < written; i++) {
+  hoyaStore.delete("key" + i);
+}
+written + "," + refusal + "," + hoyaStore.delete("key0");
@@ -0,0 +1,8 @@
+// Large output for streamed responses: about 750 KB of stdout, with quotes,
+// backslashes and multi-byte characters that must survive chunked escaping
+const line = 'Grüße "quoted" \\ 🎉 '.repeat(150);
+for (let i = 0; i < 200; i++) {
+  console.log(`${i} ${line}`);
+}
+console.error("stderr stays separate");
+"streamed";
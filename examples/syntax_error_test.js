@@ -0,0 +1,3 @@
+// Does not compile: the script is rejected before any of it runs
+console.log("never printed");
+const total = (1 + ;
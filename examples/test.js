@@ -13,11 +13,12 @@ app_log("INFO", `Current Unix timestamp: ${timestamp}`);
 // Test console.log (should be captured by app_log internally)
 console.log("This is a console.log message");
 
-// Try to use fetch API (will throw an error since it's not fully implemented)
+// Test fetch API (fetches this file from the server serving it)
 try {
-  fetch({ url: "https://example.com" });
+  const response = fetch({ url: "http://localhost:8000/test.js" });
+  app_log("INFO", `Fetched ${response.body.length} characters with status ${response.status}`);
 } catch (error) {
-  app_log("ERROR", `Fetch failed as expected: ${error.message}`);
+  app_log("ERROR", `Fetch failed with ${error.code}: ${error.message}`);
 }
 
 // Return a value (will be sent back in the response)
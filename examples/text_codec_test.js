@@ -0,0 +1,36 @@
+// TextEncoder/TextDecoder and atob/btoa round-trip UTF-8 text and bytes
+const text = "héllo, wörld — 你好 👋";
+const bytes = new TextEncoder().encode(text);
+const decoder = new TextDecoder();
+
+let fatal = null;
+try {
+  new TextDecoder("utf-8", { fatal: true }).decode(new Uint8Array([0xff, 0x41]));
+} catch (error) {
+  fatal = error.name;
+}
+
+const encoded = btoa(String.fromCharCode(...bytes));
+const restored = decoder.decode(Uint8Array.from(atob(encoded), (c) => c.charCodeAt(0)));
+
+const errors = [];
+for (const call of [() => atob("not base64!"), () => atob("aGVsbG8==="), () => btoa("€")]) {
+  try {
+    call();
+  } catch (error) {
+    errors.push(error.name);
+  }
+}
+
+({
+  length: bytes.length,
+  decoded: decoder.decode(bytes) === text,
+  partial: new TextDecoder("utf8").decode(new DataView(bytes.buffer, 0, 5)),
+  bom: decoder.decode(new Uint8Array([0xef, 0xbb, 0xbf, 0x41])),
+  fatal,
+  lossy: decoder.decode(new Uint8Array([0xff, 0x41])),
+  hello: btoa("hello"),
+  forgiving: atob(" aGVs\nbG8 "),
+  restored: restored === text,
+  errors,
+});
@@ -0,0 +1,2 @@
+// Throws an object that is not an Error; the error details carry it as JSON
+throw { code: "E_QUOTA", retryAfter: 30 };
@@ -0,0 +1,43 @@
+// Table-driven test for hoyaFormatRfc3339: known timestamps must format to
+// the same UTC strings as the wasm format_rfc3339 import and parse back to the
+// same seconds, and times outside chrono's range must throw a RangeError.
+const failures = [];
+
+const table = [
+  [0, "1970-01-01T00:00:00Z"],
+  [-1, "1969-12-31T23:59:59Z"],
+  [946684799, "1999-12-31T23:59:59Z"],
+  [951782400, "2000-02-29T00:00:00Z"],
+  [1709251199, "2024-02-29T23:59:59Z"],
+  [2147483648, "2038-01-19T03:14:08Z"],
+  [4102444800, "2100-01-01T00:00:00Z"],
+  [253402300799, "9999-12-31T23:59:59Z"],
+];
+
+for (const [seconds, expected] of table) {
+  const formatted = hoyaFormatRfc3339(seconds);
+  if (formatted !== expected) {
+    failures.push(seconds + " formatted as " + formatted);
+  }
+  if (Date.parse(formatted) / 1000 !== seconds) {
+    failures.push(formatted + " parsed as " + Date.parse(formatted) / 1000);
+  }
+}
+
+// Fractional seconds are truncated towards the earlier second
+const fractional = hoyaFormatRfc3339(-0.5);
+if (fractional !== "1969-12-31T23:59:59Z") failures.push("-0.5 formatted as " + fractional);
+
+// Past 9999 chrono writes a signed year, which Date.parse does not accept
+const farFuture = hoyaFormatRfc3339(253402300800);
+if (farFuture !== "+10000-01-01T00:00:00Z") failures.push("year 10000 formatted as " + farFuture);
+
+for (const seconds of [NaN, Infinity, 1e20]) {
+  try {
+    failures.push(seconds + " formatted as " + hoyaFormatRfc3339(seconds));
+  } catch (e) {
+    if (!(e instanceof RangeError)) failures.push(seconds + " threw " + e);
+  }
+}
+
+failures.length === 0 ? "ok" : "FAILED: " + failures.join("; ");
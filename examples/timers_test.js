@@ -0,0 +1,18 @@
+// Timers and microtasks fire after the script has run, in the order they fall due
+const order = [];
+queueMicrotask(() => order.push("microtask"));
+setTimeout((word) => {
+  order.push(word);
+  console.log("timeout", word);
+}, 20, "later");
+setTimeout(() => order.push("sooner"), 0);
+const cancelled = setTimeout(() => order.push("cancelled"), 10);
+clearTimeout(cancelled);
+
+let ticks = 0;
+const interval = setInterval(() => {
+  ticks += 1;
+  if (ticks === 3) clearInterval(interval);
+}, 5);
+
+new Promise((resolve) => setTimeout(resolve, 50)).then(() => ({ order, ticks }));
@@ -0,0 +1,47 @@
+// Checks the time zone helpers across both 2024 DST transitions of
+// Europe/Berlin; run with "timezone": "Europe/Berlin". Returns "ok" or the
+// failed checks, one per line.
+const failures = [];
+function check(name, actual, expected) {
+  if (actual !== expected) {
+    failures.push(`${name}: expected ${expected}, got ${actual}`);
+  }
+}
+
+check("HOYA_TIMEZONE", HOYA_TIMEZONE, "Europe/Berlin");
+
+// Clocks jump from 02:00 CET to 03:00 CEST at 01:00 UTC on 2024-03-31
+const beforeSpring = new Date(Date.UTC(2024, 2, 31, 0, 59, 59));
+const afterSpring = new Date(Date.UTC(2024, 2, 31, 1, 0, 0));
+check("offset before spring", hoyaUtcOffsetSeconds(beforeSpring.getTime()), 3600);
+check("offset after spring", hoyaUtcOffsetSeconds(afterSpring.getTime()), 7200);
+check("hours before spring", beforeSpring.getHours(), 1);
+check("hours after spring", afterSpring.getHours(), 3);
+check("getTimezoneOffset after spring", afterSpring.getTimezoneOffset(), -120);
+check("toString before spring", beforeSpring.toString(),
+  "Sun Mar 31 2024 01:59:59 GMT+0100 (Europe/Berlin)");
+check("toString after spring", afterSpring.toString(),
+  "Sun Mar 31 2024 03:00:00 GMT+0200 (Europe/Berlin)");
+
+// Clocks fall back from 03:00 CEST to 02:00 CET at 01:00 UTC on 2024-10-27
+const beforeAutumn = new Date(Date.UTC(2024, 9, 27, 0, 59, 59));
+const afterAutumn = new Date(Date.UTC(2024, 9, 27, 1, 0, 0));
+check("offset before autumn", hoyaUtcOffsetSeconds(beforeAutumn.getTime()), 7200);
+check("offset after autumn", hoyaUtcOffsetSeconds(afterAutumn.getTime()), 3600);
+check("toTimeString before autumn", beforeAutumn.toTimeString(), "02:59:59 GMT+0200 (Europe/Berlin)");
+check("toTimeString after autumn", afterAutumn.toTimeString(), "02:00:00 GMT+0100 (Europe/Berlin)");
+check("toDateString", afterAutumn.toDateString(), "Sun Oct 27 2024");
+
+// The day rolls over in local time before it does in UTC
+const lateUtc = new Date(Date.UTC(2024, 11, 31, 23, 30, 0));
+check("local date at new year", `${lateUtc.getFullYear()}-${lateUtc.getMonth() + 1}-${lateUtc.getDate()}`, "2025-1-1");
+check("local weekday at new year", lateUtc.getDay(), 3);
+
+// The current local time carries the current offset
+const local = hoyaLocalTime();
+const offset = hoyaUtcOffsetSeconds();
+const suffix = offset === 7200 ? "+02:00" : "+01:00";
+check("hoyaLocalTime offset", local.slice(-6), suffix);
+check("hoyaLocalTime instant", Math.abs(Date.parse(local) - Date.now()) < 5000, true);
+
+failures.length === 0 ? "ok" : failures.join("\n");
@@ -0,0 +1,5 @@
+// Makes one guest fetch so the execution's trace contains a guest_fetch span
+const response = JSON.parse(
+  hoyaFetch(JSON.stringify({ url: "http://localhost:3000/health", method: "GET", headers: {} }))
+);
+"health status " + response.status;
@@ -0,0 +1,6 @@
+// Completes with a string, but leaves a rejected promise without a handler
+const handled = Promise.reject(new Error("handled later"));
+handled.catch(() => {});
+
+Promise.reject(new RangeError("nobody listens"));
+"done";
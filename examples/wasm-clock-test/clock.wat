@@ -0,0 +1,16 @@
+;; Reads get_unixtime_ms, monotonic_now_ns twice and get_unixtime, in that
+;; order, and returns the four values as little-endian u64s through
+;; set_output.
+(module
+  (import "env" "get_unixtime" (func $get_unixtime (result i64)))
+  (import "env" "get_unixtime_ms" (func $get_unixtime_ms (result i64)))
+  (import "env" "monotonic_now_ns" (func $monotonic_now_ns (result i64)))
+  (import "env" "set_output" (func $set_output (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (func (export "_start")
+    (i64.store (i32.const 0) (call $get_unixtime_ms))
+    (i64.store (i32.const 8) (call $monotonic_now_ns))
+    (i64.store (i32.const 16) (call $monotonic_now_ns))
+    (i64.store (i32.const 24) (call $get_unixtime))
+    (call $set_output (i32.const 0) (i32.const 32))))
@@ -0,0 +1,16 @@
+;; Returns the digest get_code_hash writes into memory through set_output,
+;; after checking that a buffer one byte short gets the negated length back
+;; (the result is then "short buffer" instead).
+(module
+  (import "env" "get_code_hash" (func $get_code_hash (param i32 i32) (result i32)))
+  (import "env" "set_output" (func $set_output (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 128) "short buffer")
+
+  (func (export "_start")
+    (if (i32.ne (call $get_code_hash (i32.const 0) (i32.const 63)) (i32.const -64))
+      (then
+        (call $set_output (i32.const 128) (i32.const 12))
+        (return)))
+    (call $set_output (i32.const 0) (call $get_code_hash (i32.const 0) (i32.const 64)))))
@@ -0,0 +1,34 @@
+;; Reads the request's env through the env_* imports and logs what it finds:
+;; the value of API_URL, whether there are two variables, the name of the
+;; second one, and the results of a missing name and a too-small buffer.
+(module
+  (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+  (import "env" "env_get" (func $env_get (param i32 i32 i32 i32) (result i32)))
+  (import "env" "env_count" (func $env_count (result i32)))
+  (import "env" "env_key_at" (func $env_key_at (param i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "INFO")
+  (data (i32.const 16) "API_URL")
+  (data (i32.const 32) "MISSING")
+  (data (i32.const 48) "missing")
+  (data (i32.const 64) "too small")
+  (data (i32.const 80) "two variables")
+
+  (func $log (param $ptr i32) (param $len i32)
+    (if (i32.ge_s (local.get $len) (i32.const 0))
+      (then (call $app_log (i32.const 0) (i32.const 4) (local.get $ptr) (local.get $len)))))
+
+  (func (export "_start")
+    (call $log (i32.const 256)
+      (call $env_get (i32.const 16) (i32.const 7) (i32.const 256) (i32.const 256)))
+    (if (i32.eq (call $env_count) (i32.const 2))
+      (then (call $log (i32.const 80) (i32.const 13))))
+    (call $log (i32.const 512)
+      (call $env_key_at (i32.const 1) (i32.const 512) (i32.const 256)))
+    (if (i32.eq (call $env_get (i32.const 32) (i32.const 7) (i32.const 256) (i32.const 256))
+                (i32.const -1))
+      (then (call $log (i32.const 48) (i32.const 7))))
+    (if (i32.eq (call $env_get (i32.const 16) (i32.const 7) (i32.const 256) (i32.const 4))
+                (i32.const -23))
+      (then (call $log (i32.const 64) (i32.const 9))))))
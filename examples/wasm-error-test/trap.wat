@@ -0,0 +1,11 @@
+;; Prints a line, then divides by zero. Assembled into trap.wasm with
+;; `wat2wasm trap.wat`.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "dividing")
+
+  (func (export "_start")
+    (call $capture_stdout (i32.const 0) (i32.const 8))
+    (drop (i32.div_u (i32.const 1) (i32.const 0)))))
@@ -0,0 +1,8 @@
+;; Imports a function the host does not provide, so it compiles but cannot be
+;; instantiated. Assembled into unlinked.wasm with `wat2wasm unlinked.wat`.
+(module
+  (import "env" "launch_rockets" (func $launch_rockets))
+  (memory (export "memory") 1)
+
+  (func (export "_start")
+    (call $launch_rockets)))
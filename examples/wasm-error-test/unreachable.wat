@@ -0,0 +1,13 @@
+;; Reaches an `unreachable` instruction two calls deep. Served as text, so the
+;; server assembles it and the function names end up in the trap's backtrace.
+(module
+  (memory (export "memory") 1)
+
+  (func $fail
+    unreachable)
+
+  (func $validate
+    (call $fail))
+
+  (func (export "_start")
+    (call $validate)))
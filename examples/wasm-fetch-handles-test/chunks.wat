@@ -0,0 +1,54 @@
+;; Reads a response larger than its read buffer in 1000-byte pieces through
+;; fetch_begin, fetch_len and fetch_read, returns it with set_output, then
+;; closes its handle twice and uses it after closing. Prints one line per
+;; check: "length=ok" when the pieces add up to fetch_len, "close1=ok",
+;; "close2=invalid", "read_closed=invalid" and "len_closed=invalid" when the
+;; closed handle is refused with -2147483648. Assembled into chunks.wasm with
+;; `wat2wasm chunks.wat`.
+(module
+  (import "env" "fetch_begin" (func $fetch_begin (param i32 i32) (result i32)))
+  (import "env" "fetch_len" (func $fetch_len (param i32) (result i32)))
+  (import "env" "fetch_read" (func $fetch_read (param i32 i32 i32) (result i32)))
+  (import "env" "fetch_close" (func $fetch_close (param i32) (result i32)))
+  (import "env" "set_output" (func $set_output (param i32 i32)))
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 4)
+
+  (data (i32.const 0) "{\"url\": \"http://localhost:8031/big.txt\", \"method\": \"GET\", \"headers\": {}}")
+  (data (i32.const 256) "length=ok")
+  (data (i32.const 272) "length=mismatch")
+  (data (i32.const 288) "close1=ok")
+  (data (i32.const 304) "close2=invalid")
+  (data (i32.const 320) "read_closed=invalid")
+  (data (i32.const 344) "len_closed=invalid")
+
+  (func (export "_start")
+    (local $handle i32)
+    (local $read i32)
+    (local $piece i32)
+    (local.set $handle (call $fetch_begin (i32.const 0) (i32.const 72)))
+
+    ;; The response goes to the pages past the first one
+    (block $done
+      (loop $next
+        (local.set $piece
+          (call $fetch_read (local.get $handle)
+                            (i32.add (i32.const 65536) (local.get $read))
+                            (i32.const 1000)))
+        (br_if $done (i32.le_s (local.get $piece) (i32.const 0)))
+        (local.set $read (i32.add (local.get $read) (local.get $piece)))
+        (br $next)))
+    (call $set_output (i32.const 65536) (local.get $read))
+
+    (if (i32.eq (local.get $read) (call $fetch_len (local.get $handle)))
+      (then (call $capture_stdout (i32.const 256) (i32.const 9)))
+      (else (call $capture_stdout (i32.const 272) (i32.const 15))))
+    (if (i32.eqz (call $fetch_close (local.get $handle)))
+      (then (call $capture_stdout (i32.const 288) (i32.const 9))))
+    (if (i32.eq (call $fetch_close (local.get $handle)) (i32.const -2147483648))
+      (then (call $capture_stdout (i32.const 304) (i32.const 14))))
+    (if (i32.eq (call $fetch_read (local.get $handle) (i32.const 65536) (i32.const 16))
+                (i32.const -2147483648))
+      (then (call $capture_stdout (i32.const 320) (i32.const 19))))
+    (if (i32.eq (call $fetch_len (local.get $handle)) (i32.const -2147483648))
+      (then (call $capture_stdout (i32.const 344) (i32.const 18))))))
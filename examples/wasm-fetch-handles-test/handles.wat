@@ -0,0 +1,30 @@
+;; Opens 16 responses with fetch_begin without closing them, then tries a
+;; 17th, which is refused with -2147483647 before any request is sent
+;; ("open17=refused"). After closing the first handle another response can
+;; be opened, under the next unused handle, 17 ("reopen=17"). Assembled into
+;; handles.wasm with `wat2wasm handles.wat`.
+(module
+  (import "env" "fetch_begin" (func $fetch_begin (param i32 i32) (result i32)))
+  (import "env" "fetch_close" (func $fetch_close (param i32) (result i32)))
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "{\"url\": \"http://localhost:8031/small.txt\", \"method\": \"GET\", \"headers\": {}}")
+  (data (i32.const 256) "open_failed")
+  (data (i32.const 272) "open17=refused")
+  (data (i32.const 288) "reopen=17")
+
+  (func (export "_start")
+    (local $opened i32)
+    (block $done
+      (loop $next
+        (if (i32.le_s (call $fetch_begin (i32.const 0) (i32.const 74)) (i32.const 0))
+          (then (call $capture_stdout (i32.const 256) (i32.const 11))))
+        (local.set $opened (i32.add (local.get $opened) (i32.const 1)))
+        (br_if $next (i32.lt_u (local.get $opened) (i32.const 16)))))
+
+    (if (i32.eq (call $fetch_begin (i32.const 0) (i32.const 74)) (i32.const -2147483647))
+      (then (call $capture_stdout (i32.const 272) (i32.const 14))))
+    (drop (call $fetch_close (i32.const 1)))
+    (if (i32.eq (call $fetch_begin (i32.const 0) (i32.const 74)) (i32.const 17))
+      (then (call $capture_stdout (i32.const 288) (i32.const 9))))))
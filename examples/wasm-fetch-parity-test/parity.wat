@@ -0,0 +1,20 @@
+;; Sends the request's input, the options of one fetch call as JSON, through
+;; the fetch import and returns the response JSON as is through set_output.
+;; examples/fetch_parity_test.js does the same through hoyaFetch.
+(module
+  (import "env" "input_read" (func $input_read (param i32 i32) (result i32)))
+  (import "env" "fetch" (func $fetch (param i32 i32 i32 i32) (result i32)))
+  (import "env" "set_output" (func $set_output (param i32 i32)))
+  (memory (export "memory") 2)
+
+  ;; The options are read to 0..4096, the response written from 4096 on
+  (func (export "_start")
+    (local $options_len i32)
+    (local $response_len i32)
+    (local.set $options_len (call $input_read (i32.const 0) (i32.const 4096)))
+    (if (i32.lt_s (local.get $options_len) (i32.const 0)) (then unreachable))
+    (local.set $response_len
+      (call $fetch (i32.const 0) (local.get $options_len) (i32.const 4096) (i32.const 126976)))
+    ;; A negative length is that of a response that did not fit
+    (if (i32.lt_s (local.get $response_len) (i32.const 0)) (then unreachable))
+    (call $set_output (i32.const 4096) (local.get $response_len))))
@@ -0,0 +1,19 @@
+;; Writes 100000 lines to stdout, then one line to stderr: a small
+;; max_stdout_bytes truncates stdout while stderr is still captured, and the
+;; loop keeps running at full speed once stdout is closed. Assembled into
+;; flood.wasm with `wat2wasm flood.wat`.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (import "env" "capture_stderr" (func $capture_stderr (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "flooding stdout with one more line")
+  (data (i32.const 64) "done")
+
+  (func (export "_start")
+    (local $i i32)
+    (loop $lines
+      (call $capture_stdout (i32.const 0) (i32.const 34))
+      (local.set $i (i32.add (local.get $i) (i32.const 1)))
+      (br_if $lines (i32.lt_u (local.get $i) (i32.const 100000))))
+    (call $capture_stderr (i32.const 64) (i32.const 4))))
@@ -0,0 +1,17 @@
+;; Counts to 1,000,000 and prints "done". Each iteration costs 7 units of fuel
+;; (loop and end are free), so a run consumes a little over 7,000,000.
+;; Assembled into loop.wasm with `wat2wasm loop.wat`.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "done")
+
+  (func (export "_start")
+    (local $i i32)
+    (loop $count
+      (local.tee $i (i32.add (local.get $i) (i32.const 1)))
+      (i32.const 1000000)
+      (i32.lt_u)
+      (br_if $count))
+    (call $capture_stdout (i32.const 0) (i32.const 4))))
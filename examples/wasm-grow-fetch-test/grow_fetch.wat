@@ -0,0 +1,18 @@
+;; Grows its 1-page memory by a page, then calls fetch with the response
+;; buffer at the start of the new page and prints the response it got. The
+;; response must land in the grown region. Assembled into grow_fetch.wasm
+;; with `wat2wasm grow_fetch.wat`.
+(module
+  (import "env" "fetch" (func $fetch (param i32 i32 i32 i32) (result i32)))
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "{\"url\": \"http://localhost:8030/hello.txt\", \"method\": \"GET\", \"headers\": {}}")
+
+  (func (export "_start")
+    (local $len i32)
+    (drop (memory.grow (i32.const 1)))
+    (local.set $len
+      (call $fetch (i32.const 0) (i32.const 74) (i32.const 65536) (i32.const 65536)))
+    (if (i32.gt_s (local.get $len) (i32.const 0))
+      (then (call $capture_stdout (i32.const 65536) (local.get $len))))))
@@ -0,0 +1,118 @@
+;; Property test for the gzip_compress/gzip_decompress imports, assembled into
+;; gzip_test.wasm with `wat2wasm gzip_test.wat`.
+;;
+;; Round-trips 200 pseudo-random payloads (fixed LCG seed), checks the
+;; negative-required-length convention, corrupt input (i32::MIN) and the
+;; decompressed-size cap (i32::MIN + 1), and prints one line per check.
+(module
+  (import "env" "gzip_compress" (func $gzip_compress (param i32 i32 i32 i32) (result i32)))
+  (import "env" "gzip_decompress" (func $gzip_decompress (param i32 i32 i32 i32) (result i32)))
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 4)
+
+  ;; Messages
+  (data (i32.const 0) "gzip round-trip ok")      ;; 18 bytes
+  (data (i32.const 32) "gzip round-trip FAILED") ;; 22 bytes
+  (data (i32.const 64) "gzip corrupt ok")        ;; 15 bytes
+  (data (i32.const 96) "gzip corrupt FAILED")    ;; 19 bytes
+  (data (i32.const 128) "gzip bomb ok")          ;; 12 bytes
+  (data (i32.const 160) "gzip bomb FAILED")      ;; 16 bytes
+
+  ;; Buffers: source at 64 KiB, compressed at 128 KiB, restored at 192 KiB
+  (global $src i32 (i32.const 65536))
+  (global $comp i32 (i32.const 131072))
+  (global $out i32 (i32.const 196608))
+  (global $buf_len i32 (i32.const 65536))
+  (global $rng (mut i32) (i32.const 12345))
+
+  (func $next_byte (result i32)
+    (global.set $rng
+      (i32.add (i32.mul (global.get $rng) (i32.const 1103515245)) (i32.const 12345)))
+    (i32.and (i32.shr_u (global.get $rng) (i32.const 16)) (i32.const 255)))
+
+  ;; Compress, decompress and compare one payload of `len` bytes; returns 1 on success
+  (func $round_trip (param $len i32) (result i32)
+    (local $i i32)
+    (local $comp_len i32)
+    (local $out_len i32)
+    (local $mask i32)
+    ;; Half of the payloads use a 4-symbol alphabet so they actually compress
+    (local.set $mask (select (i32.const 255) (i32.const 3)
+      (i32.and (call $next_byte) (i32.const 1))))
+    (local.set $i (i32.const 0))
+    (block $filled
+      (loop $fill
+        (br_if $filled (i32.ge_u (local.get $i) (local.get $len)))
+        (i32.store8 (i32.add (global.get $src) (local.get $i))
+          (i32.and (call $next_byte) (local.get $mask)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $fill)))
+
+    (local.set $comp_len
+      (call $gzip_compress (global.get $src) (local.get $len) (global.get $comp) (global.get $buf_len)))
+    (if (i32.le_s (local.get $comp_len) (i32.const 0)) (then (return (i32.const 0))))
+
+    ;; A zero-length destination reports the required length as a negative number
+    (if (i32.gt_u (local.get $len) (i32.const 0))
+      (then
+        (if (i32.ne
+              (call $gzip_decompress (global.get $comp) (local.get $comp_len) (global.get $out) (i32.const 0))
+              (i32.sub (i32.const 0) (local.get $len)))
+          (then (return (i32.const 0))))))
+
+    (local.set $out_len
+      (call $gzip_decompress (global.get $comp) (local.get $comp_len) (global.get $out) (global.get $buf_len)))
+    (if (i32.ne (local.get $out_len) (local.get $len)) (then (return (i32.const 0))))
+
+    (local.set $i (i32.const 0))
+    (block $compared
+      (loop $compare
+        (br_if $compared (i32.ge_u (local.get $i) (local.get $len)))
+        (if (i32.ne
+              (i32.load8_u (i32.add (global.get $src) (local.get $i)))
+              (i32.load8_u (i32.add (global.get $out) (local.get $i))))
+          (then (return (i32.const 0))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $compare)))
+    (i32.const 1))
+
+  (func (export "_start")
+    (local $run i32)
+    (local $ok i32)
+    (local $zeros i32)
+    (local $comp_len i32)
+
+    ;; Round-trips of payloads between 0 and 8191 bytes
+    (local.set $ok (i32.const 1))
+    (local.set $run (i32.const 0))
+    (block $done
+      (loop $runs
+        (br_if $done (i32.ge_u (local.get $run) (i32.const 200)))
+        (if (i32.eqz (call $round_trip
+              (i32.and (i32.or (i32.shl (call $next_byte) (i32.const 8)) (call $next_byte))
+                (i32.const 8191))))
+          (then (local.set $ok (i32.const 0))))
+        (local.set $run (i32.add (local.get $run) (i32.const 1)))
+        (br $runs)))
+    (if (local.get $ok)
+      (then (call $capture_stdout (i32.const 0) (i32.const 18)))
+      (else (call $capture_stdout (i32.const 32) (i32.const 22))))
+
+    ;; The first 64 source bytes are noise, not a gzip stream
+    (if (i32.eq
+          (call $gzip_decompress (global.get $src) (i32.const 64) (global.get $out) (global.get $buf_len))
+          (i32.const -2147483648))
+      (then (call $capture_stdout (i32.const 64) (i32.const 15)))
+      (else (call $capture_stdout (i32.const 96) (i32.const 19))))
+
+    ;; 17 MiB of fresh (zeroed) pages compress to a few KiB but exceed the 16 MiB cap
+    (local.set $zeros (i32.mul (memory.grow (i32.const 272)) (i32.const 65536)))
+    (local.set $comp_len
+      (call $gzip_compress (local.get $zeros) (i32.const 17825792) (global.get $comp) (global.get $buf_len)))
+    (if (i32.and
+          (i32.gt_s (local.get $comp_len) (i32.const 0))
+          (i32.eq
+            (call $gzip_decompress (global.get $comp) (local.get $comp_len) (global.get $out) (global.get $buf_len))
+            (i32.const -2147483647)))
+      (then (call $capture_stdout (i32.const 128) (i32.const 12)))
+      (else (call $capture_stdout (i32.const 160) (i32.const 16))))))
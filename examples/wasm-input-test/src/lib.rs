@@ -0,0 +1,34 @@
+//! A WebAssembly module echoing the request's input back, transformed
+//!
+//! The module reads the `input` of the request as JSON through `input_len`
+//! and `input_read`, and returns `{"echo": <input>, "bytes": <length>}`
+//! through `set_output`: a function taking a request and returning a
+//! response, with no output printed along the way.
+
+// Forward declarations of the functions imported from the host
+extern "C" {
+    fn input_len() -> u32;
+    fn input_read(dst_ptr: *mut u8, dst_max: u32) -> i32;
+    fn set_output(ptr: *const u8, len: u32);
+}
+
+// Entrypoint for WebAssembly module
+#[no_mangle]
+pub extern "C" fn _start() {
+    let len = unsafe { input_len() };
+    let mut input = vec![0u8; len as usize];
+    let read = unsafe { input_read(input.as_mut_ptr(), len) };
+
+    // Requests without input echo null
+    let input = match read {
+        1.. => String::from_utf8_lossy(&input[..read as usize]).into_owned(),
+        _ => "null".to_string(),
+    };
+    let result = format!("{{\"echo\":{},\"bytes\":{}}}", input, len);
+
+    // The host reads the result after _start returns, so it is never freed
+    let result: &'static str = result.leak();
+    unsafe {
+        set_output(result.as_ptr(), result.len() as u32);
+    }
+}
@@ -0,0 +1,21 @@
+;; Returns the "count" entry of its key-value namespace through set_output,
+;; or "missing" when there is none, after writing "wasm" under "writer".
+(module
+  (import "env" "kv_get" (func $kv_get (param i32 i32 i32 i32) (result i32)))
+  (import "env" "kv_set" (func $kv_set (param i32 i32 i32 i32) (result i32)))
+  (import "env" "set_output" (func $set_output (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "count")
+  (data (i32.const 16) "missing")
+  (data (i32.const 32) "writer")
+  (data (i32.const 48) "wasm")
+
+  (func (export "_start")
+    (local $len i32)
+    (drop (call $kv_set (i32.const 32) (i32.const 6) (i32.const 48) (i32.const 4)))
+    (local.set $len
+      (call $kv_get (i32.const 0) (i32.const 5) (i32.const 256) (i32.const 256)))
+    (if (i32.lt_s (local.get $len) (i32.const 0))
+      (then (call $set_output (i32.const 16) (i32.const 7)))
+      (else (call $set_output (i32.const 256) (local.get $len))))))
@@ -0,0 +1,21 @@
+;; Calls app_log with one level of each spelling the host normalizes: a
+;; lowercase alias, mixed case, upper case and an unknown level, which is
+;; logged as INFO. Assembled into levels.wasm with `wat2wasm levels.wat`.
+(module
+  (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "warning")
+  (data (i32.const 16) "Debug")
+  (data (i32.const 32) "ERROR")
+  (data (i32.const 48) "custom")
+  (data (i32.const 64) "disk almost full")
+  (data (i32.const 96) "cache miss")
+  (data (i32.const 128) "write failed")
+  (data (i32.const 160) "plain note")
+
+  (func (export "_start")
+    (call $app_log (i32.const 0) (i32.const 7) (i32.const 64) (i32.const 16))
+    (call $app_log (i32.const 16) (i32.const 5) (i32.const 96) (i32.const 10))
+    (call $app_log (i32.const 32) (i32.const 5) (i32.const 128) (i32.const 12))
+    (call $app_log (i32.const 48) (i32.const 6) (i32.const 160) (i32.const 10))))
@@ -0,0 +1,14 @@
+;; Carries a hoya.manifest section whose timeout_ms is not a number, so
+;; executing or inspecting it fails with INVALID_MANIFEST before it runs;
+;; assembled into malformed_manifest.wasm with `wat2wasm --enable-annotations
+;; malformed_manifest.wat`.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "unreachable\n")
+
+  (func (export "_start")
+    (call $capture_stdout (i32.const 0) (i32.const 12)))
+
+  (@custom "hoya.manifest" "{\"timeout_ms\": \"soon\"}"))
@@ -0,0 +1,24 @@
+;; Declares a hoya.manifest custom section allowing 2 pages (131072 bytes) of
+;; memory, then grows its 1-page memory twice and prints whether each grow
+;; succeeded. Under the manifest's limit it prints "grow1=ok" and the second
+;; grow traps with MEMORY_LIMIT_EXCEEDED;
+;; assembled into manifest_test.wasm with `wat2wasm --enable-annotations
+;; manifest_test.wat`.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "grow1=ok\n")
+  (data (i32.const 16) "grow1=denied\n")
+  (data (i32.const 32) "grow2=ok\n")
+  (data (i32.const 48) "grow2=denied\n")
+
+  (func (export "_start")
+    (if (i32.eq (memory.grow (i32.const 1)) (i32.const -1))
+      (then (call $capture_stdout (i32.const 16) (i32.const 13)))
+      (else (call $capture_stdout (i32.const 0) (i32.const 9))))
+    (if (i32.eq (memory.grow (i32.const 1)) (i32.const -1))
+      (then (call $capture_stdout (i32.const 48) (i32.const 13)))
+      (else (call $capture_stdout (i32.const 32) (i32.const 9)))))
+
+  (@custom "hoya.manifest" "{\"timeout_ms\": 5000, \"max_memory_bytes\": 131072, \"permissions\": [\"fetch\"], \"fetch_allowlist\": [\"Example.com\"]}"))
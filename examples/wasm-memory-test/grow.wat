@@ -0,0 +1,16 @@
+;; Grows its memory 1 MiB at a time until growing fails, then prints a line.
+;; Hoya traps the growth that crosses `limits.memory_bytes`, so the line is
+;; only printed if the limit is not enforced. Assembled into grow.wasm with
+;; `wat2wasm grow.wat`.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "grow refused")
+
+  (func (export "_start")
+    (block $refused
+      (loop $grow
+        (br_if $refused (i32.eq (memory.grow (i32.const 16)) (i32.const -1)))
+        (br $grow)))
+    (call $capture_stdout (i32.const 0) (i32.const 12))))
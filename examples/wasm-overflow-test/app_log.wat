@@ -0,0 +1,12 @@
+;; Calls app_log with a message range whose end, 0xfffffffe (-2) + 16, does not
+;; fit in 32 bits; the call must trap rather than log the bytes a wrapped
+;; range would select. Assembled into app_log.wasm with `wat2wasm app_log.wat`.
+(module
+  (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "INFO")
+  (data (i32.const 16) "wrapped around")
+
+  (func (export "_start")
+    (call $app_log (i32.const 0) (i32.const 4) (i32.const -2) (i32.const 16))))
@@ -0,0 +1,11 @@
+;; Calls capture_stdout with a range whose end, 0xfffffffe (-2) + 16, does not fit
+;; in 32 bits; the call must trap rather than capture the bytes a wrapped
+;; range would select. Assembled into capture.wasm with `wat2wasm capture.wat`.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "wrapped around")
+
+  (func (export "_start")
+    (call $capture_stdout (i32.const -2) (i32.const 16))))
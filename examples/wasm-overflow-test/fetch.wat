@@ -0,0 +1,9 @@
+;; Calls fetch with an options range whose end, 0xfffffffe (-2) + 16, does not fit
+;; in 32 bits; the call must trap before any request is sent. Assembled into
+;; fetch.wasm with `wat2wasm fetch.wat`.
+(module
+  (import "env" "fetch" (func $fetch (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+
+  (func (export "_start")
+    (drop (call $fetch (i32.const -2) (i32.const 16) (i32.const 1024) (i32.const 1024)))))
@@ -0,0 +1,16 @@
+;; Draws 16 bytes with random_bytes and returns them through set_output, and
+;; logs "too large" when a call over the per-call limit is refused.
+(module
+  (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+  (import "env" "random_bytes" (func $random_bytes (param i32 i32) (result i32)))
+  (import "env" "set_output" (func $set_output (param i32 i32)))
+  (memory (export "memory") 2)
+
+  (data (i32.const 0) "INFO")
+  (data (i32.const 16) "too large")
+
+  (func (export "_start")
+    (if (i32.eq (call $random_bytes (i32.const 1024) (i32.const 65537)) (i32.const 0x80000000))
+      (then (call $app_log (i32.const 0) (i32.const 4) (i32.const 16) (i32.const 9))))
+    (drop (call $random_bytes (i32.const 256) (i32.const 16)))
+    (call $set_output (i32.const 256) (i32.const 16))))
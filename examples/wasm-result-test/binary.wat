@@ -0,0 +1,11 @@
+;; Returns three bytes that are not valid UTF-8 through set_output, so the
+;; response carries them base64-encoded ("//4A"). Assembled into binary.wasm
+;; with `wat2wasm binary.wat`.
+(module
+  (import "env" "set_output" (func $set_output (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "\ff\fe\00")
+
+  (func (export "_start")
+    (call $set_output (i32.const 0) (i32.const 3))))
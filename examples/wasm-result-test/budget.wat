@@ -0,0 +1,12 @@
+;; Prints 30000 bytes and then returns the same 30000 bytes through
+;; set_output, so the result and stdout together need about 60 KB of the
+;; output budget.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (import "env" "set_output" (func $set_output (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (func (export "_start")
+    (memory.fill (i32.const 0) (i32.const 0x61) (i32.const 30000))
+    (call $capture_stdout (i32.const 0) (i32.const 30000))
+    (call $set_output (i32.const 0) (i32.const 30000))))
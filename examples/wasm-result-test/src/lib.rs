@@ -0,0 +1,28 @@
+//! A WebAssembly module returning its result through `set_output`
+//!
+//! Instead of printing its result, the module builds a 100 KB string and
+//! hands its location to the host, which reads it once `_start` returns and
+//! returns it as the response's `output`.
+
+// Forward declaration of the function imported from the host
+extern "C" {
+    fn set_output(ptr: *const u8, len: u32);
+}
+
+/// Size of the returned string in bytes
+const RESULT_LEN: usize = 100 * 1024;
+
+// Entrypoint for WebAssembly module
+#[no_mangle]
+pub extern "C" fn _start() {
+    // Digits cycling through 0-9, so a truncated or shifted result shows
+    let result: String = (0..RESULT_LEN)
+        .map(|i| char::from(b'0' + (i % 10) as u8))
+        .collect();
+
+    // The host reads the result after _start returns, so it is never freed
+    let result: &'static str = result.leak();
+    unsafe {
+        set_output(result.as_ptr(), result.len() as u32);
+    }
+}
@@ -0,0 +1,19 @@
+;; Prints "before", sleeps 300 ms with sleep_ms, then prints "status N" with
+;; the value sleep_ms returned. Run with a timeout under 300 ms, the sleep
+;; is cut short by the deadline and the execution ends before the status.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (import "env" "sleep_ms" (func $sleep_ms (param i32) (result i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "before")
+  (data (i32.const 16) "status ?")
+
+  (func $print (param $ptr i32) (param $len i32)
+    (call $capture_stdout (local.get $ptr) (local.get $len)))
+
+  (func (export "_start")
+    (call $print (i32.const 0) (i32.const 6))
+    (i32.store8 (i32.const 23)
+      (i32.add (i32.const 48) (call $sleep_ms (i32.const 300))))
+    (call $print (i32.const 16) (i32.const 8))))
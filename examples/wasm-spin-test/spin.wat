@@ -0,0 +1,15 @@
+;; Prints a line to stdout and stderr, then loops forever; only the execution
+;; timeout ends it. Assembled into spin.wasm with `wat2wasm spin.wat`.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (import "env" "capture_stderr" (func $capture_stderr (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "before the loop")
+  (data (i32.const 16) "still before the loop")
+
+  (func (export "_start")
+    (call $capture_stdout (i32.const 0) (i32.const 15))
+    (call $capture_stderr (i32.const 16) (i32.const 21))
+    (loop $spin
+      (br $spin))))
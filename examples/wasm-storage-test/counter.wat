@@ -0,0 +1,36 @@
+;; Counts its own executions under the key "count" (a little-endian i32) and
+;; prints "count=N" for N below 10, or "denied" when the execution was not
+;; granted storage; assembled into counter.wasm with `wat2wasm counter.wat`.
+(module
+  (import "env" "pkv_get" (func $pkv_get (param i32 i32 i32 i32) (result i32)))
+  (import "env" "pkv_set" (func $pkv_set (param i32 i32 i32 i32) (result i32)))
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "count")
+  (data (i32.const 16) "count=0")
+  (data (i32.const 32) "denied")
+
+  ;; PKV_ERR_DENIED
+  (global $denied i32 (i32.const -2147483647))
+  (global $value i32 (i32.const 64))
+
+  (func (export "_start")
+    (local $result i32)
+    (local $count i32)
+
+    (local.set $result (call $pkv_get (i32.const 0) (i32.const 5) (global.get $value) (i32.const 4)))
+    (if (i32.eq (local.get $result) (global.get $denied))
+      (then
+        (call $capture_stdout (i32.const 32) (i32.const 6))
+        (return)))
+    (if (i32.eq (local.get $result) (i32.const 4))
+      (then (local.set $count (i32.load (global.get $value)))))
+
+    (local.set $count (i32.add (local.get $count) (i32.const 1)))
+    (i32.store (global.get $value) (local.get $count))
+    (drop (call $pkv_set (i32.const 0) (i32.const 5) (global.get $value) (i32.const 4)))
+
+    ;; Replace the trailing "0" with the count's digit
+    (i32.store8 (i32.const 22) (i32.add (i32.const 48) (local.get $count)))
+    (call $capture_stdout (i32.const 16) (i32.const 7))))
@@ -0,0 +1,101 @@
+;; Table-driven test for the format_rfc3339/parse_rfc3339 imports, assembled
+;; into time_test.wasm with `wat2wasm time_test.wat`.
+;;
+;; Every table row is formatted and its expected string parsed back, so the
+;; two functions must round-trip; the edge cases cover leap seconds, UTC
+;; offsets, invalid input (i64::MIN), out-of-range times (i32::MIN) and the
+;; negative-required-length convention. Prints one line per check.
+(module
+  (import "env" "format_rfc3339" (func $format_rfc3339 (param i64 i32 i32) (result i32)))
+  (import "env" "parse_rfc3339" (func $parse_rfc3339 (param i32 i32) (result i64)))
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  ;; Messages
+  (data (i32.const 0) "rfc3339 round-trip ok")      ;; 21 bytes
+  (data (i32.const 32) "rfc3339 round-trip FAILED") ;; 25 bytes
+  (data (i32.const 64) "rfc3339 edge cases ok")     ;; 21 bytes
+  (data (i32.const 96) "rfc3339 edge cases FAILED") ;; 25 bytes
+
+  ;; Edge-case inputs
+  (data (i32.const 128) "2016-12-31T23:59:60Z")      ;; 20 bytes, leap second
+  (data (i32.const 160) "2024-01-01T05:30:00+05:30") ;; 25 bytes
+  (data (i32.const 192) "2024-13-01T00:00:00Z")      ;; 20 bytes, no month 13
+
+  ;; Table rows of 48 bytes from 1024: i64 unix seconds, i32 length, the string at +16
+  (data (i32.const 1024) "\00\00\00\00\00\00\00\00" "\14\00\00\00\00\00\00\00" "1970-01-01T00:00:00Z")
+  (data (i32.const 1072) "\7f\43\6d\38\00\00\00\00" "\14\00\00\00\00\00\00\00" "1999-12-31T23:59:59Z")
+  (data (i32.const 1120) "\00\0c\bb\38\00\00\00\00" "\14\00\00\00\00\00\00\00" "2000-02-29T00:00:00Z")
+  (data (i32.const 1168) "\7f\1a\e1\65\00\00\00\00" "\14\00\00\00\00\00\00\00" "2024-02-29T23:59:59Z")
+  (data (i32.const 1216) "\00\00\00\80\00\00\00\00" "\14\00\00\00\00\00\00\00" "2038-01-19T03:14:08Z")
+  (data (i32.const 1264) "\00\57\86\f4\00\00\00\00" "\14\00\00\00\00\00\00\00" "2100-01-01T00:00:00Z")
+  (data (i32.const 1312) "\7f\41\f4\ff\3a\00\00\00" "\14\00\00\00\00\00\00\00" "9999-12-31T23:59:59Z")
+  (data (i32.const 1360) "\80\41\f4\ff\3a\00\00\00" "\16\00\00\00\00\00\00\00" "+10000-01-01T00:00:00Z")
+
+  (global $table i32 (i32.const 1024))
+  (global $rows i32 (i32.const 8))
+  (global $out i32 (i32.const 4096))
+
+  ;; Format and parse one table row; returns 1 on success
+  (func $check_row (param $row i32) (result i32)
+    (local $secs i64)
+    (local $len i32)
+    (local $i i32)
+    (local.set $secs (i64.load (local.get $row)))
+    (local.set $len (i32.load offset=8 (local.get $row)))
+
+    (if (i32.ne
+          (call $format_rfc3339 (local.get $secs) (global.get $out) (i32.const 64))
+          (local.get $len))
+      (then (return (i32.const 0))))
+    (local.set $i (i32.const 0))
+    (block $compared
+      (loop $compare
+        (br_if $compared (i32.ge_u (local.get $i) (local.get $len)))
+        (if (i32.ne
+              (i32.load8_u offset=16 (i32.add (local.get $row) (local.get $i)))
+              (i32.load8_u (i32.add (global.get $out) (local.get $i))))
+          (then (return (i32.const 0))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $compare)))
+
+    (i64.eq
+      (call $parse_rfc3339 (i32.add (local.get $row) (i32.const 16)) (local.get $len))
+      (local.get $secs)))
+
+  (func (export "_start")
+    (local $row i32)
+    (local $ok i32)
+
+    (local.set $ok (i32.const 1))
+    (local.set $row (i32.const 0))
+    (block $done
+      (loop $each
+        (br_if $done (i32.ge_u (local.get $row) (global.get $rows)))
+        (if (i32.eqz (call $check_row
+              (i32.add (global.get $table) (i32.mul (local.get $row) (i32.const 48)))))
+          (then (local.set $ok (i32.const 0))))
+        (local.set $row (i32.add (local.get $row) (i32.const 1)))
+        (br $each)))
+    (if (local.get $ok)
+      (then (call $capture_stdout (i32.const 0) (i32.const 21)))
+      (else (call $capture_stdout (i32.const 32) (i32.const 25))))
+
+    (local.set $ok (i32.const 1))
+    ;; A leap second parses to the preceding second
+    (if (i64.ne (call $parse_rfc3339 (i32.const 128) (i32.const 20)) (i64.const 1483228799))
+      (then (local.set $ok (i32.const 0))))
+    ;; Offsets are folded into UTC
+    (if (i64.ne (call $parse_rfc3339 (i32.const 160) (i32.const 25)) (i64.const 1704067200))
+      (then (local.set $ok (i32.const 0))))
+    (if (i64.ne (call $parse_rfc3339 (i32.const 192) (i32.const 20)) (i64.const 0x8000000000000000))
+      (then (local.set $ok (i32.const 0))))
+    ;; u64::MAX seconds is far beyond chrono's range
+    (if (i32.ne (call $format_rfc3339 (i64.const -1) (global.get $out) (i32.const 64)) (i32.const -2147483648))
+      (then (local.set $ok (i32.const 0))))
+    ;; A short buffer reports the required length as a negative number
+    (if (i32.ne (call $format_rfc3339 (i64.const 0) (global.get $out) (i32.const 4)) (i32.const -20))
+      (then (local.set $ok (i32.const 0))))
+    (if (local.get $ok)
+      (then (call $capture_stdout (i32.const 64) (i32.const 21)))
+      (else (call $capture_stdout (i32.const 96) (i32.const 25))))))
@@ -0,0 +1,40 @@
+;; Prints the current local time from get_localtime and the UTC offset from
+;; get_utc_offset_seconds in decimal, one line each; assembled into
+;; timezone_test.wasm with `wat2wasm timezone_test.wat`. The caller checks
+;; that the offset matches the suffix of the local time.
+(module
+  (import "env" "get_localtime" (func $get_localtime (param i32 i32) (result i32)))
+  (import "env" "get_utc_offset_seconds" (func $get_utc_offset_seconds (result i32)))
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  (global $time i32 (i32.const 0))
+  (global $digits_end i32 (i32.const 128))
+
+  (func (export "_start")
+    (local $len i32)
+    (local $offset i32)
+    (local $abs i32)
+    (local $pos i32)
+
+    ;; Local time, e.g. 2024-03-31T03:00:00+02:00
+    (local.set $len (call $get_localtime (global.get $time) (i32.const 64)))
+    (call $capture_stdout (global.get $time) (local.get $len))
+
+    ;; Offset in seconds, written backwards from $digits_end
+    (local.set $offset (call $get_utc_offset_seconds))
+    (local.set $abs (local.get $offset))
+    (if (i32.lt_s (local.get $offset) (i32.const 0))
+      (then (local.set $abs (i32.sub (i32.const 0) (local.get $offset)))))
+    (local.set $pos (global.get $digits_end))
+    (loop $digit
+      (local.set $pos (i32.sub (local.get $pos) (i32.const 1)))
+      (i32.store8 (local.get $pos)
+        (i32.add (i32.const 48) (i32.rem_u (local.get $abs) (i32.const 10))))
+      (local.set $abs (i32.div_u (local.get $abs) (i32.const 10)))
+      (br_if $digit (i32.ne (local.get $abs) (i32.const 0))))
+    (if (i32.lt_s (local.get $offset) (i32.const 0))
+      (then
+        (local.set $pos (i32.sub (local.get $pos) (i32.const 1)))
+        (i32.store8 (local.get $pos) (i32.const 45))))
+    (call $capture_stdout (local.get $pos) (i32.sub (global.get $digits_end) (local.get $pos)))))
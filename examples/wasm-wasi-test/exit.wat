@@ -0,0 +1,16 @@
+;; Writes a line to stderr through WASI, then exits with code 3. Assembled into
+;; exit.wasm with `wat2wasm exit.wat`.
+(module
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory (export "memory") 1)
+
+  ;; iovec { buf = 16, buf_len = 8 }; fd_write stores the bytes written at 8
+  (data (i32.const 0) "\10\00\00\00\08\00\00\00")
+  (data (i32.const 16) "exiting\n")
+
+  (func (export "_start")
+    (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))
+    (call $proc_exit (i32.const 3))
+    (unreachable)))
@@ -0,0 +1,16 @@
+;; Writes a line through WASI fd_write and another through the env import, then
+;; returns. Assembled into hello.wasm with `wat2wasm hello.wat`.
+(module
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+
+  ;; iovec { buf = 16, buf_len = 16 }; fd_write stores the bytes written at 8
+  (data (i32.const 0) "\10\00\00\00\10\00\00\00")
+  (data (i32.const 16) "hello from wasi\n")
+  (data (i32.const 48) "hello from env")
+
+  (func (export "_start")
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    (call $capture_stdout (i32.const 48) (i32.const 14))))
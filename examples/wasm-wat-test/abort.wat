@@ -0,0 +1,17 @@
+;; Lays out its strings the way AssemblyScript does (UTF-16LE, with the
+;; byte length in the word before the pointer) and fails like a failed
+;; `assert` in assembly/index.ts: it prints a line, then calls abort.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (import "env" "abort" (func $abort (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "before abort")
+  ;; "boom": 8 bytes at 64
+  (data (i32.const 60) "\08\00\00\00b\00o\00o\00m\00")
+  ;; "assembly/index.ts": 34 bytes at 96
+  (data (i32.const 92) "\22\00\00\00a\00s\00s\00e\00m\00b\00l\00y\00/\00i\00n\00d\00e\00x\00.\00t\00s\00")
+
+  (func (export "_start")
+    (call $capture_stdout (i32.const 0) (i32.const 12))
+    (call $abort (i32.const 64) (i32.const 96) (i32.const 12) (i32.const 5))))
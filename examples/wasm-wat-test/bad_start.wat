@@ -0,0 +1,5 @@
+;; Exports _start with a signature no command module has.
+(module
+  (memory (export "memory") 1)
+  (func (export "_start") (param i32) (result i32)
+    (local.get 0)))
@@ -0,0 +1,11 @@
+;; Prints a line, then logs a message with a stray 0xff byte.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "before log")
+  (data (i32.const 16) "info")
+  (data (i32.const 32) "bad \ff byte")
+  (func (export "_start")
+    (call $capture_stdout (i32.const 0) (i32.const 10))
+    (call $app_log (i32.const 16) (i32.const 4) (i32.const 32) (i32.const 10))))
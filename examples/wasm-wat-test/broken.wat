@@ -0,0 +1,6 @@
+;; Does not assemble: the body of _start, on line 6, uses an operator that
+;; does not exist.
+(module
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (i32.frobnicate)))
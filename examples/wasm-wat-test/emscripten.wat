@@ -0,0 +1,26 @@
+;; Imports what a small standalone emcc build of a C program does: it
+;; prints through WASI, grows its heap the way malloc does, then fails a
+;; C assert(heap > 0) on line 7 of main.c in main.
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "env" "emscripten_resize_heap" (func $resize_heap (param i32) (result i32)))
+  (import "env" "emscripten_notify_memory_growth" (func $notify_memory_growth (param i32)))
+  (import "env" "__assert_fail" (func $assert_fail (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 16) "hello from emcc\n")
+  (data (i32.const 64) "heap > 0\00")
+  (data (i32.const 80) "main.c\00")
+  (data (i32.const 96) "main\00")
+
+  (func (export "_start")
+    ;; One iovec at 0 pointing at the 16 bytes of the line
+    (i32.store (i32.const 0) (i32.const 16))
+    (i32.store (i32.const 4) (i32.const 16))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    (if (i32.eqz (call $resize_heap (i32.const 131072)))
+      (then unreachable))
+    (call $notify_memory_growth (i32.const 0))
+    (if (i32.ne (memory.size) (i32.const 2))
+      (then unreachable))
+    (call $assert_fail (i32.const 64) (i32.const 80) (i32.const 7) (i32.const 96))))
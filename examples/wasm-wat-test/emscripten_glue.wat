@@ -0,0 +1,8 @@
+;; Needs Emscripten's JavaScript glue beyond the shims: embind and
+;; invoke_* trampolines the host does not provide.
+(module
+  (import "env" "emscripten_resize_heap" (func (param i32) (result i32)))
+  (import "env" "_embind_register_class" (func (param i32)))
+  (import "env" "invoke_vi" (func (param i32 i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")))
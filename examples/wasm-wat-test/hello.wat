@@ -0,0 +1,11 @@
+;; Runs straight from the text format, with no wat2wasm step: the server
+;; assembles it before the module is compiled, and it logs one app_log line.
+(module
+  (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "INFO")
+  (data (i32.const 16) "hello from wat")
+
+  (func (export "_start")
+    (call $app_log (i32.const 0) (i32.const 4) (i32.const 16) (i32.const 14))))
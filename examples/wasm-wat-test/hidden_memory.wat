@@ -0,0 +1,8 @@
+;; Keeps memory 0 to itself and exports two others, neither named `memory`:
+;; no exported memory is the module's, so it fails with INVALID_MODULE.
+(module
+  (memory $private 1)
+  (memory (export "a") 1)
+  (memory (export "b") 1)
+
+  (func (export "_start")))
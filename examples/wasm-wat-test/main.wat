@@ -0,0 +1,5 @@
+;; A plain main() -> i32 without _start, returning 3.
+(module
+  (memory (export "memory") 1)
+  (func (export "main") (result i32)
+    (i32.const 3)))
@@ -0,0 +1,11 @@
+;; Exports its memory as `mem`, as some toolchains do; host functions find it
+;; by type, so the app_log line still reads from it.
+(module
+  (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+  (memory (export "mem") 1)
+
+  (data (i32.const 0) "INFO")
+  (data (i32.const 16) "hello from mem")
+
+  (func (export "_start")
+    (call $app_log (i32.const 0) (i32.const 4) (i32.const 16) (i32.const 14))))
@@ -0,0 +1,12 @@
+;; Uses two memories, neither named `memory`; host functions read memory 0.
+(module
+  (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+  (memory $heap (export "heap") 1)
+  (memory $scratch (export "scratch") 1)
+
+  (data (memory $heap) (i32.const 0) "INFO")
+  (data (memory $heap) (i32.const 16) "hello from memory 0")
+  (data (memory $scratch) (i32.const 16) "wrong memory")
+
+  (func (export "_start")
+    (call $app_log (i32.const 0) (i32.const 4) (i32.const 16) (i32.const 19))))
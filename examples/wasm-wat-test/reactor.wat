@@ -0,0 +1,13 @@
+;; A reactor: _initialize sets a flag that main checks, printing a line
+;; only when it was called first.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (memory (export "memory") 1)
+  (global $initialized (mut i32) (i32.const 0))
+  (data (i32.const 0) "initialized")
+  (func (export "_initialize")
+    (global.set $initialized (i32.const 1)))
+  (func (export "main") (result i32)
+    (if (global.get $initialized)
+      (then (call $capture_stdout (i32.const 0) (i32.const 11))))
+    (i32.const 0)))
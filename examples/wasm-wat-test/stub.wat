@@ -0,0 +1,11 @@
+;; Imports two functions the host does not define; only the second is
+;; called, after a line is printed.
+(module
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (import "env" "draw" (func $draw (param i32 i32)))
+  (import "env" "beep" (func $beep (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "before beep")
+  (func (export "_start")
+    (call $capture_stdout (i32.const 0) (i32.const 11))
+    (drop (call $beep))))
@@ -0,0 +1,125 @@
+//! # Execution admission
+//!
+//! At most `execution.max_concurrent` executions started by `/execute`,
+//! `/execute/stream` and replays run at once, so a burst of requests cannot
+//! start more stores and runtimes than the server has memory for. Requests
+//! past that wait for a running execution to finish, up to
+//! `execution.queue_timeout_ms`, and are then refused with `SERVER_BUSY`
+//! (429) and a `Retry-After` header. Jobs are limited by `jobs.max_concurrent`
+//! instead.
+//!
+//! The running and waiting counts are reported by `GET /status` and the
+//! `hoya_executions_queued` gauge.
+
+use crate::config::ExecutionConfig;
+use crate::error::AppError;
+use crate::metrics::Metrics;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Slots for running executions, shared by the handlers
+pub struct Admission {
+    /// Executions allowed to run at once
+    max_concurrent: usize,
+    /// How long a request waits for a slot
+    queue_timeout: Duration,
+    /// One permit per execution allowed to run at once
+    slots: Arc<Semaphore>,
+    /// Requests waiting for a slot
+    queued: AtomicUsize,
+}
+
+/// Occupancy of the execution slots, as reported by `GET /status`
+#[derive(Serialize, Debug)]
+pub struct AdmissionStatus {
+    /// Executions allowed to run at once
+    pub max_concurrent: usize,
+    /// Executions holding a slot
+    pub running: usize,
+    /// Requests waiting for a slot
+    pub queued: usize,
+    /// Milliseconds a request waits for a slot before it is refused
+    pub queue_timeout_ms: u64,
+}
+
+/// A request counted as waiting until it is dropped, also when its client goes away
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Admission {
+    /// Create the slots of the `[execution]` section, all free
+    ///
+    /// ```
+    /// use hoya::admission::Admission;
+    /// use hoya::config::ExecutionConfig;
+    ///
+    /// let admission = Admission::new(&ExecutionConfig {
+    ///     max_concurrent: 2,
+    ///     ..ExecutionConfig::default()
+    /// });
+    /// assert_eq!(admission.status().running, 0);
+    /// assert_eq!(admission.retry_after_secs(), 1);
+    /// ```
+    pub fn new(config: &ExecutionConfig) -> Self {
+        Admission {
+            max_concurrent: config.max_concurrent,
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+            slots: Arc::new(Semaphore::new(config.max_concurrent)),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait for a slot; the execution holds it until the permit is dropped
+    ///
+    /// # Arguments
+    ///
+    /// * `metrics` - Metrics whose queued gauge counts the request while it waits
+    ///
+    /// # Returns
+    ///
+    /// * `Result<OwnedSemaphorePermit, AppError>` - The slot, or `ServerBusy`
+    ///   when none became free within `execution.queue_timeout_ms`
+    pub async fn admit(&self, metrics: &Metrics) -> Result<OwnedSemaphorePermit, AppError> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        let started = Instant::now();
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&self.queued);
+        let _gauge = metrics.queue_execution();
+        match tokio::time::timeout(self.queue_timeout, self.slots.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            // The semaphore is never closed; a closed one admits nothing either
+            Ok(Err(_)) | Err(_) => {
+                metrics.reject_busy();
+                Err(AppError::ServerBusy {
+                    max_concurrent: self.max_concurrent,
+                    waited_ms: started.elapsed().as_millis() as u64,
+                })
+            }
+        }
+    }
+
+    /// Seconds a refused client is asked to wait before retrying, at least 1
+    pub fn retry_after_secs(&self) -> u64 {
+        self.queue_timeout.as_secs_f64().ceil().max(1.0) as u64
+    }
+
+    /// Current occupancy of the slots
+    pub fn status(&self) -> AdmissionStatus {
+        AdmissionStatus {
+            max_concurrent: self.max_concurrent,
+            running: self.max_concurrent - self.slots.available_permits(),
+            queued: self.queued.load(Ordering::Relaxed),
+            queue_timeout_ms: self.queue_timeout.as_millis() as u64,
+        }
+    }
+}
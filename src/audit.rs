@@ -0,0 +1,206 @@
+//! # Audit log
+//!
+//! An append-only JSON Lines record of every execution, written when the
+//! `audit.path` config is set. Records describe what ran and how it ended but
+//! never contain code, request headers or captured output.
+//!
+//! Records are handed to a dedicated writer thread over a channel so request
+//! handling never waits on disk I/O. The writer buffers lines, flushes
+//! whenever it runs out of queued records, rotates the file by size and
+//! flushes a final time on shutdown.
+//!
+//! Each line parses back into the `ExecutionRecord` it was written from:
+//!
+//! ```
+//! use hoya::audit::AuditLog;
+//! use hoya::config::AuditConfig;
+//! use hoya::record::ExecutionRecord;
+//!
+//! let path = std::env::temp_dir().join(format!("hoya-audit-doctest-{}.jsonl", std::process::id()));
+//! let record = ExecutionRecord {
+//!     timestamp: "2026-10-15T12:00:00+00:00".to_string(),
+//!     request_id: "req-1".to_string(),
+//!     caller: Some("ci".to_string()),
+//!     source_url: "https://example.com/app.wasm".to_string(),
+//!     code_sha256: Some("ab".repeat(32)),
+//!     code_type: "webassembly".to_string(),
+//!     status: "error".to_string(),
+//!     http_status: 504,
+//!     error_code: Some("EXECUTION_TIMEOUT".to_string()),
+//!     request_duration_ms: 5012,
+//!     execution_time_ms: 5000,
+//!     fetch_hosts: vec!["api.example.com".to_string()],
+//!     output_bytes: 0,
+//!     stdout_bytes: 12,
+//!     stderr_bytes: 0,
+//! };
+//!
+//! let log = AuditLog::start(&AuditConfig::default(), &path).unwrap();
+//! log.record(record.clone());
+//! log.shutdown();
+//!
+//! let written = std::fs::read_to_string(&path).unwrap();
+//! std::fs::remove_file(&path).unwrap();
+//! let lines: Vec<&str> = written.lines().collect();
+//! assert_eq!(lines.len(), 1);
+//! assert_eq!(serde_json::from_str::<ExecutionRecord>(lines[0]).unwrap(), record);
+//! ```
+
+use crate::config::AuditConfig;
+use crate::record::ExecutionRecord;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+/// Handle to the audit writer thread
+pub struct AuditLog {
+    /// Queue of records for the writer; taken on shutdown to stop the writer
+    sender: Mutex<Option<Sender<ExecutionRecord>>>,
+    /// The writer thread, joined on shutdown
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AuditLog {
+    /// Open the audit file and start the writer thread
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Rotation settings
+    /// * `path` - File the records are appended to
+    ///
+    /// # Returns
+    ///
+    /// * `std::io::Result<AuditLog>` - The handle, or the error opening the file
+    pub fn start(config: &AuditConfig, path: &Path) -> std::io::Result<Self> {
+        let mut writer =
+            RotatingWriter::open(path.to_path_buf(), config.max_bytes, config.max_files)?;
+        let (sender, receiver) = mpsc::channel();
+        let handle = std::thread::Builder::new()
+            .name("hoya-audit-writer".to_string())
+            .spawn(move || writer.run(receiver))?;
+
+        Ok(AuditLog {
+            sender: Mutex::new(Some(sender)),
+            writer: Mutex::new(Some(handle)),
+        })
+    }
+
+    /// Queue a record for writing
+    pub fn record(&self, record: ExecutionRecord) {
+        if let Ok(sender) = self.sender.lock() {
+            if let Some(sender) = sender.as_ref() {
+                // Fails only after shutdown, when there is nowhere left to write
+                let _ = sender.send(record);
+            }
+        }
+    }
+
+    /// Write all queued records, flush the file and stop the writer thread
+    pub fn shutdown(&self) {
+        if let Ok(mut sender) = self.sender.lock() {
+            sender.take();
+        }
+        let handle = self.writer.lock().ok().and_then(|mut writer| writer.take());
+        if let Some(handle) = handle {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Buffered audit file that is rotated once it reaches a size limit
+struct RotatingWriter {
+    /// Path of the active file; rotated files get `.1`, `.2`, ... suffixes
+    path: PathBuf,
+    /// Size at which the active file is rotated
+    max_bytes: u64,
+    /// Number of rotated files kept
+    max_files: usize,
+    /// Buffered handle to the active file
+    file: BufWriter<File>,
+    /// Bytes in the active file, including buffered ones
+    size: u64,
+}
+
+impl RotatingWriter {
+    /// Open (or create) the active file for appending
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingWriter {
+            path,
+            max_bytes,
+            max_files,
+            file: BufWriter::new(file),
+            size,
+        })
+    }
+
+    /// Write records until the sending side is dropped, then flush
+    fn run(&mut self, receiver: Receiver<ExecutionRecord>) {
+        while let Ok(record) = receiver.recv() {
+            self.write(&record);
+            // Drain whatever else is queued before paying for a flush
+            while let Ok(record) = receiver.try_recv() {
+                self.write(&record);
+            }
+            if let Err(e) = self.file.flush() {
+                tracing::error!("Failed to flush audit log {}: {}", self.path.display(), e);
+            }
+        }
+        if let Err(e) = self.file.flush() {
+            tracing::error!("Failed to flush audit log {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Append one record, rotating first if it would push the file past the limit
+    fn write(&mut self, record: &ExecutionRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!("Failed to serialize audit record: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            if let Err(e) = self.rotate() {
+                tracing::error!("Failed to rotate audit log {}: {}", self.path.display(), e);
+            }
+        }
+        match self.file.write_all(&line) {
+            Ok(()) => self.size += line.len() as u64,
+            Err(e) => tracing::error!("Failed to write audit log {}: {}", self.path.display(), e),
+        }
+    }
+
+    /// Shift rotated files up by one, move the active file to `.1` and start a new one
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated_path(1))?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Path of the `index`-th rotated file
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+}
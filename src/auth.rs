@@ -0,0 +1,218 @@
+//! # API keys
+//!
+//! With `auth.keys` configured, every request outside `auth.public_paths`
+//! must carry one of the keys, as `Authorization: Bearer <key>` or
+//! `X-Api-Key: <key>`; anything else is refused with `UNAUTHORIZED` (401).
+//! Without keys every request is let through, as before.
+//!
+//! Keys may carry quotas, counted in memory for as long as the server runs:
+//! `requests_per_minute` counts every request made with the key in windows
+//! of one minute, and `max_concurrent` the executions it runs at once
+//! through `/execute`, `/execute/stream` and replays. A request over either
+//! fails with `QUOTA_EXCEEDED` (429).
+//!
+//! The key's name, never its secret, is recorded in the execution's span
+//! and in `metadata.api_key`.
+
+use crate::config::AuthConfig;
+use crate::error::AppError;
+use axum::http::{header, HeaderMap};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Header carrying an API key, as an alternative to `Authorization: Bearer`
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Length of a `requests_per_minute` window
+const QUOTA_WINDOW: Duration = Duration::from_secs(60);
+
+/// Requests counted in the current window of a key
+struct Window {
+    /// When the window started
+    started: Instant,
+    /// Requests made in it
+    requests: u64,
+}
+
+/// A configured key, with its quota counters
+pub struct ApiKey {
+    /// Name identifying the key in spans and metadata
+    pub name: String,
+    /// SHA-256 digest of the secret
+    digest: [u8; 32],
+    /// Requests accepted per minute, if limited
+    requests_per_minute: Option<u64>,
+    /// Executions running at once, if limited
+    max_concurrent: Option<usize>,
+    /// Requests counted towards `requests_per_minute`
+    window: Mutex<Window>,
+    /// Executions running with the key
+    running: AtomicUsize,
+}
+
+/// An execution counted against its key's `max_concurrent` until it is dropped
+pub struct KeySlot(Arc<ApiKey>);
+
+impl Drop for KeySlot {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The configured keys and the paths served without one
+pub struct Auth {
+    /// Keys accepted
+    keys: Vec<Arc<ApiKey>>,
+    /// Paths served without a key
+    public_paths: Vec<String>,
+}
+
+/// SHA-256 digest of a secret
+fn digest(secret: &[u8]) -> [u8; 32] {
+    Sha256::digest(secret).into()
+}
+
+/// Compare two digests without stopping at the first difference
+fn same_digest(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Auth {
+    /// Load the keys of the `[auth]` section
+    ///
+    /// ```
+    /// use axum::http::{HeaderMap, HeaderValue};
+    /// use hoya::auth::Auth;
+    /// use hoya::config::{ApiKeyConfig, AuthConfig};
+    ///
+    /// let auth = Auth::new(&AuthConfig {
+    ///     keys: vec![ApiKeyConfig {
+    ///         name: "ci".to_string(),
+    ///         key: "s3cret".to_string(),
+    ///         requests_per_minute: None,
+    ///         max_concurrent: None,
+    ///     }],
+    ///     ..AuthConfig::default()
+    /// });
+    /// let mut headers = HeaderMap::new();
+    /// assert!(auth.authenticate(&headers).is_err());
+    /// headers.insert("authorization", HeaderValue::from_static("Bearer s3cret"));
+    /// assert_eq!(auth.authenticate(&headers).unwrap().name, "ci");
+    /// ```
+    pub fn new(config: &AuthConfig) -> Self {
+        let now = Instant::now();
+        Auth {
+            keys: config
+                .keys
+                .iter()
+                .map(|key| {
+                    Arc::new(ApiKey {
+                        name: key.name.clone(),
+                        digest: digest(key.key.as_bytes()),
+                        requests_per_minute: key.requests_per_minute,
+                        max_concurrent: key.max_concurrent,
+                        window: Mutex::new(Window {
+                            started: now,
+                            requests: 0,
+                        }),
+                        running: AtomicUsize::new(0),
+                    })
+                })
+                .collect(),
+            public_paths: config.public_paths.clone(),
+        }
+    }
+
+    /// Whether a request for `path` needs a key
+    pub fn required(&self, path: &str) -> bool {
+        !self.keys.is_empty() && !self.public_paths.iter().any(|public| public == path)
+    }
+
+    /// Find the key a request carries and count the request against its quota
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Arc<ApiKey>, AppError>` - The key, `Unauthorized` when the
+    ///   request carries none or an unknown one, or `QuotaExceeded` when the
+    ///   key is over its `requests_per_minute`
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Arc<ApiKey>, AppError> {
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let presented = bearer
+            .or_else(|| {
+                headers
+                    .get(API_KEY_HEADER)
+                    .and_then(|value| value.to_str().ok())
+            })
+            .map(str::trim)
+            .filter(|secret| !secret.is_empty())
+            .ok_or_else(|| {
+                AppError::Unauthorized(
+                    "An API key is required, as Authorization: Bearer <key> or X-Api-Key"
+                        .to_string(),
+                )
+            })?;
+        let presented = digest(presented.as_bytes());
+        let key = self
+            .keys
+            .iter()
+            .find(|key| same_digest(&key.digest, &presented))
+            .ok_or_else(|| AppError::Unauthorized("Unknown API key".to_string()))?;
+        key.count_request()?;
+        Ok(key.clone())
+    }
+}
+
+impl ApiKey {
+    /// Count a request against `requests_per_minute`, refusing it once the window is full
+    fn count_request(&self) -> Result<(), AppError> {
+        let Some(limit) = self.requests_per_minute else {
+            return Ok(());
+        };
+        let mut window = self
+            .window
+            .lock()
+            .map_err(|_| AppError::Internal("The API key quota lock is poisoned".to_string()))?;
+        let now = Instant::now();
+        if now.duration_since(window.started) >= QUOTA_WINDOW {
+            window.started = now;
+            window.requests = 0;
+        }
+        if window.requests >= limit {
+            let reset = QUOTA_WINDOW.saturating_sub(now.duration_since(window.started));
+            return Err(AppError::QuotaExceeded {
+                key: self.name.clone(),
+                quota: "requests_per_minute",
+                limit,
+                retry_after_secs: Some(reset.as_secs_f64().ceil().max(1.0) as u64),
+            });
+        }
+        window.requests += 1;
+        Ok(())
+    }
+
+    /// Count an execution against `max_concurrent` until the returned slot is dropped
+    ///
+    /// # Returns
+    ///
+    /// * `Result<KeySlot, AppError>` - The slot, or `QuotaExceeded` when the
+    ///   key already runs `max_concurrent` executions
+    pub fn start_execution(self: &Arc<Self>) -> Result<KeySlot, AppError> {
+        let running = self.running.fetch_add(1, Ordering::Relaxed);
+        // The slot is taken before the check, so it is given back on refusal too
+        let slot = KeySlot(self.clone());
+        match self.max_concurrent {
+            Some(limit) if running >= limit => Err(AppError::QuotaExceeded {
+                key: self.name.clone(),
+                quota: "max_concurrent",
+                limit: limit as u64,
+                retry_after_secs: None,
+            }),
+            _ => Ok(slot),
+        }
+    }
+}
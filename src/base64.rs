@@ -0,0 +1,59 @@
+//! Standard, padded base64 (RFC 4648 section 4).
+//!
+//! Binary data crosses JSON documents as base64: module results returned
+//! through `set_output`, and guest fetch bodies with `body_encoding: "base64"`.
+//!
+//! ```
+//! use hoya::base64;
+//!
+//! assert_eq!(base64::encode(&[0xff, 0xfe, 0x00]), "//4A");
+//! assert_eq!(base64::decode("//4A"), Some(vec![0xff, 0xfe, 0x00]));
+//! ```
+
+/// The standard alphabet
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes as standard, padded base64
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | ((byte as u32) << (16 - 8 * i))
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((group >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decode standard base64; the trailing padding may be left out
+///
+/// # Returns
+///
+/// * `Option<Vec<u8>>` - The bytes, or `None` for characters outside the
+///   alphabet and lengths no encoding produces
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text
+        .strip_suffix("==")
+        .or_else(|| text.strip_suffix('='))
+        .unwrap_or(text);
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3 + 2);
+    let mut group = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        group = (group << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((group >> bits) as u8);
+        }
+    }
+    // A lone character in the last group holds less than a byte
+    (bits < 6).then_some(bytes)
+}
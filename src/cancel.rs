@@ -0,0 +1,189 @@
+//! # Execution cancellation
+//!
+//! Every execution is registered under its `execution_id` while it runs,
+//! with a token that `POST /executions/{id}/cancel` sets. The engines check
+//! the token where they already check their deadline: the QuickJS interrupt
+//! handler, and the epoch callback of WebAssembly stores, which runs once per
+//! epoch tick. Guest fetch calls made after the token is set are refused, but
+//! a call already in progress finishes first.
+//!
+//! Ids of recently finished executions are remembered, so cancelling one of
+//! them can be told apart from cancelling an id that never existed.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Response header carrying the id of an execution
+pub const EXECUTION_ID_HEADER: &str = "x-hoya-execution-id";
+
+/// Number of finished execution ids remembered
+const FINISHED_IDS_KEPT: usize = 4096;
+
+/// Flag shared between an execution and whoever may cancel it
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Ask the execution to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the execution was asked to stop
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A running execution
+struct Entry {
+    /// Token the engine checks
+    token: CancelToken,
+    /// URL of the code, without credentials, query or fragment
+    source_url: String,
+    /// When the execution started
+    started: Instant,
+}
+
+/// A running execution, as listed by `GET /executions/running`
+#[derive(Serialize, Debug, Clone)]
+pub struct RunningSummary {
+    /// Identifier of the execution
+    pub execution_id: String,
+    /// URL of the code, without credentials, query or fragment
+    pub source_url: String,
+    /// Time since the execution started, in milliseconds
+    pub running_ms: u64,
+}
+
+/// What cancelling an execution id did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelOutcome {
+    /// The execution is running and was asked to stop
+    Cancelled,
+    /// The execution has already finished
+    Finished,
+    /// No execution with that id ran recently
+    Unknown,
+}
+
+/// Ids of recently finished executions, oldest first
+#[derive(Default)]
+struct Finished {
+    /// Order in which the ids finished
+    order: VecDeque<String>,
+    /// The same ids, for lookups
+    ids: HashSet<String>,
+}
+
+/// Executions currently running, with the ids of those that recently finished
+#[derive(Default)]
+pub struct Executions {
+    /// Running executions by id
+    running: Mutex<HashMap<String, Entry>>,
+    /// Recently finished ids
+    finished: Mutex<Finished>,
+}
+
+/// Registration of a running execution; dropping it marks the execution finished
+pub struct Registration<'a> {
+    /// The registry the execution is listed in
+    executions: &'a Executions,
+    /// Identifier of the execution
+    execution_id: String,
+    /// Token the engine checks
+    pub token: CancelToken,
+}
+
+impl Executions {
+    /// List an execution as running until the returned registration is dropped
+    ///
+    /// # Arguments
+    ///
+    /// * `execution_id` - Identifier of the execution
+    /// * `source_url` - URL of the code, without credentials, query or fragment
+    ///
+    /// # Returns
+    ///
+    /// * `Registration` - Holds the token to hand to the engine
+    pub fn register(&self, execution_id: &str, source_url: String) -> Registration<'_> {
+        let token = CancelToken::default();
+        if let Ok(mut running) = self.running.lock() {
+            running.insert(
+                execution_id.to_string(),
+                Entry {
+                    token: token.clone(),
+                    source_url,
+                    started: Instant::now(),
+                },
+            );
+        }
+        Registration {
+            executions: self,
+            execution_id: execution_id.to_string(),
+            token,
+        }
+    }
+
+    /// Cancel a running execution
+    pub fn cancel(&self, execution_id: &str) -> CancelOutcome {
+        let token = self
+            .running
+            .lock()
+            .ok()
+            .and_then(|running| running.get(execution_id).map(|entry| entry.token.clone()));
+        if let Some(token) = token {
+            token.cancel();
+            return CancelOutcome::Cancelled;
+        }
+        match self.finished.lock() {
+            Ok(finished) if finished.ids.contains(execution_id) => CancelOutcome::Finished,
+            _ => CancelOutcome::Unknown,
+        }
+    }
+
+    /// Running executions, longest running first
+    pub fn running(&self) -> Vec<RunningSummary> {
+        let Ok(running) = self.running.lock() else {
+            return Vec::new();
+        };
+        let mut summaries: Vec<RunningSummary> = running
+            .iter()
+            .map(|(execution_id, entry)| RunningSummary {
+                execution_id: execution_id.clone(),
+                source_url: entry.source_url.clone(),
+                running_ms: entry.started.elapsed().as_millis() as u64,
+            })
+            .collect();
+        summaries.sort_by(|a, b| b.running_ms.cmp(&a.running_ms));
+        summaries
+    }
+
+    /// Move an execution from the running ones to the finished ones
+    ///
+    /// The id is listed as finished before it leaves the running ones, so a
+    /// concurrent `cancel` never reports it unknown.
+    fn finish(&self, execution_id: &str) {
+        if let Ok(mut finished) = self.finished.lock() {
+            while finished.order.len() >= FINISHED_IDS_KEPT {
+                if let Some(oldest) = finished.order.pop_front() {
+                    finished.ids.remove(&oldest);
+                }
+            }
+            finished.order.push_back(execution_id.to_string());
+            finished.ids.insert(execution_id.to_string());
+        }
+        if let Ok(mut running) = self.running.lock() {
+            running.remove(execution_id);
+        }
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.executions.finish(&self.execution_id);
+    }
+}
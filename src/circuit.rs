@@ -0,0 +1,354 @@
+//! # Circuit breaker
+//!
+//! Callers that keep requesting a URL that always fails (a 404, a module
+//! that always traps) would otherwise cost a download and a compile on every
+//! attempt. Failures are counted per source URL, without credentials, query
+//! or fragment, over a sliding window. Once `failure_threshold` failures fall
+//! into the window the source's circuit opens and requests for it fail fast
+//! with `CIRCUIT_OPEN` for `open_secs`. The first request after that is let
+//! through as a probe: its success closes the circuit, its failure opens it
+//! again. A probe abandoned before it reports, e.g. because its client
+//! disconnected, hands the next request the chance to probe.
+//!
+//! Only failures that point at the source count: download, decompression,
+//! detection, manifest and engine errors. Invalid requests, timeouts and exhausted
+//! output budgets depend on what the caller asked for and are ignored. A
+//! success forgets the source's earlier failures.
+//!
+//! State is kept in memory for at most `max_circuits` sources; when full,
+//! the least recently updated one is forgotten.
+
+use crate::config::CircuitBreakerConfig;
+use crate::error::AppError;
+use crate::record;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A failure counted against a source
+#[derive(Serialize, Debug, Clone)]
+pub struct FailureSummary {
+    /// RFC 3339 time of the failure
+    pub timestamp: String,
+    /// Error code of the failed execution
+    pub code: String,
+    /// Error message of the failed execution
+    pub message: String,
+}
+
+/// State of one source's circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Requests run; failures are being counted
+    Closed,
+    /// Requests fail fast until the given time
+    Open(Instant),
+    /// A probe request is running; others fail fast until it finishes
+    HalfOpen,
+}
+
+/// Failure tracking for one source
+#[derive(Debug)]
+struct Circuit {
+    /// Current state
+    state: State,
+    /// Failures within the window, oldest first, with the time they were counted
+    failures: VecDeque<(Instant, FailureSummary)>,
+    /// Last time the circuit changed, used to pick the one to forget when full
+    updated: Instant,
+}
+
+/// A circuit as shown by `/stats`
+#[derive(Serialize, Debug)]
+pub struct CircuitStatus {
+    /// The source URL, without credentials, query or fragment
+    pub url: String,
+    /// "closed", "open" or "half_open"
+    pub state: &'static str,
+    /// Seconds until a probe is let through, while open
+    pub retry_after_secs: Option<u64>,
+    /// Failures within the window, oldest first
+    pub recent_failures: Vec<FailureSummary>,
+}
+
+/// Per-source circuits, shared by all executions
+pub struct CircuitBreaker {
+    /// Failures within the window that open a circuit
+    failure_threshold: usize,
+    /// Length of the sliding failure window
+    window: Duration,
+    /// How long an open circuit fails requests before probing
+    open_for: Duration,
+    /// Maximum number of sources tracked
+    max_circuits: usize,
+    /// Circuits by source URL
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+/// Permission to run a request for a source; report its outcome with `finish`
+///
+/// A permit dropped without `finish` reports nothing, except that a probe's
+/// circuit goes back to open with no time left, so the next request probes:
+///
+/// ```
+/// use hoya::circuit::CircuitBreaker;
+/// use hoya::config::CircuitBreakerConfig;
+/// use hoya::error::AppError;
+///
+/// let breaker = CircuitBreaker::new(&CircuitBreakerConfig {
+///     failure_threshold: 1,
+///     open_secs: 0,
+///     ..CircuitBreakerConfig::default()
+/// });
+/// let url = "https://example.com/app.wasm";
+/// let failure = AppError::Download("404 Not Found".to_string());
+/// breaker.admit(url).unwrap().finish(Some(&failure));
+///
+/// // The probe is abandoned mid-flight, as when its client disconnects
+/// let probe = breaker.admit(url).unwrap();
+/// assert_eq!(breaker.snapshot()[0].state, "half_open");
+/// assert!(breaker.admit(url).is_err());
+/// drop(probe);
+///
+/// assert_eq!(breaker.snapshot()[0].state, "open");
+/// breaker.admit(url).unwrap().finish(None);
+/// assert!(breaker.snapshot().is_empty());
+/// ```
+#[must_use]
+pub struct Permit<'a> {
+    /// The breaker that admitted the request
+    breaker: &'a CircuitBreaker,
+    /// Key of the source's circuit; taken by `finish`
+    source: Option<String>,
+}
+
+impl CircuitBreaker {
+    /// Create a breaker with no open circuits
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        CircuitBreaker {
+            failure_threshold: config.failure_threshold as usize,
+            window: Duration::from_secs(config.window_secs),
+            open_for: Duration::from_secs(config.open_secs),
+            max_circuits: config.max_circuits,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admit a request for a source, unless its circuit is open
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - URL the code is requested from
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Permit, AppError>` - The permit, or `CircuitOpen` with the
+    ///   source's recent failures
+    pub fn admit(&self, url: &str) -> Result<Permit<'_>, AppError> {
+        let source = record::redact_url(url);
+        let now = Instant::now();
+        if let Ok(mut circuits) = self.circuits.lock() {
+            if let Some(circuit) = circuits.get_mut(&source) {
+                let retry_after = match circuit.state {
+                    State::Closed => None,
+                    State::Open(until) if now >= until => {
+                        circuit.state = State::HalfOpen;
+                        circuit.updated = now;
+                        None
+                    }
+                    State::Open(until) => Some(until - now),
+                    // The probe finishes within its execution timeout; ask again shortly
+                    State::HalfOpen => Some(Duration::from_secs(1)),
+                };
+                if let Some(retry_after) = retry_after {
+                    return Err(AppError::CircuitOpen {
+                        url: source,
+                        retry_after_secs: retry_after.as_secs_f64().ceil() as u64,
+                        recent_failures: circuit
+                            .failures
+                            .iter()
+                            .map(|(_, failure)| failure.clone())
+                            .collect(),
+                    });
+                }
+            }
+        }
+        Ok(Permit {
+            breaker: self,
+            source: Some(source),
+        })
+    }
+
+    /// Hand a source's probe to the next request when its permit was dropped unfinished
+    fn abandon(&self, source: &str) {
+        let Ok(mut circuits) = self.circuits.lock() else {
+            return;
+        };
+        if let Some(circuit) = circuits.get_mut(source) {
+            if circuit.state == State::HalfOpen {
+                let now = Instant::now();
+                circuit.state = State::Open(now);
+                circuit.updated = now;
+            }
+        }
+    }
+
+    /// Report how an admitted request for a source ended
+    fn record(&self, source: String, error: Option<&AppError>) {
+        let Ok(mut circuits) = self.circuits.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        let error = match error {
+            None => {
+                circuits.remove(&source);
+                return;
+            }
+            Some(error) if counts_against_source(error) => error,
+            Some(_) => {
+                // An inconclusive probe hands the next request the chance to probe
+                if let Some(circuit) = circuits.get_mut(&source) {
+                    if circuit.state == State::HalfOpen {
+                        circuit.state = State::Open(now);
+                    }
+                }
+                return;
+            }
+        };
+
+        if !circuits.contains_key(&source) && circuits.len() >= self.max_circuits {
+            let oldest = circuits
+                .iter()
+                .min_by_key(|(_, circuit)| circuit.updated)
+                .map(|(source, _)| source.clone());
+            if let Some(oldest) = oldest {
+                circuits.remove(&oldest);
+            }
+        }
+        let circuit = circuits.entry(source).or_insert_with(|| Circuit {
+            state: State::Closed,
+            failures: VecDeque::new(),
+            updated: now,
+        });
+        circuit.failures.push_back((
+            now,
+            FailureSummary {
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                code: error.code().to_string(),
+                message: error.to_string(),
+            },
+        ));
+        while circuit.failures.len() > self.failure_threshold
+            || circuit
+                .failures
+                .front()
+                .is_some_and(|(at, _)| now.duration_since(*at) > self.window)
+        {
+            circuit.failures.pop_front();
+        }
+        circuit.updated = now;
+        if circuit.state == State::HalfOpen || circuit.failures.len() >= self.failure_threshold {
+            circuit.state = State::Open(now + self.open_for);
+        }
+    }
+
+    /// List the tracked circuits, open ones first
+    pub fn snapshot(&self) -> Vec<CircuitStatus> {
+        let Ok(circuits) = self.circuits.lock() else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        let mut statuses: Vec<CircuitStatus> = circuits
+            .iter()
+            .map(|(source, circuit)| {
+                let (state, retry_after_secs) = match circuit.state {
+                    State::Closed => ("closed", None),
+                    State::Open(until) => (
+                        "open",
+                        Some(until.saturating_duration_since(now).as_secs_f64().ceil() as u64),
+                    ),
+                    State::HalfOpen => ("half_open", None),
+                };
+                CircuitStatus {
+                    url: source.clone(),
+                    state,
+                    retry_after_secs,
+                    recent_failures: circuit
+                        .failures
+                        .iter()
+                        .map(|(_, failure)| failure.clone())
+                        .collect(),
+                }
+            })
+            .collect();
+        statuses.sort_by(|a, b| (a.state == "closed", &a.url).cmp(&(b.state == "closed", &b.url)));
+        statuses
+    }
+
+    /// Forget a source's circuit, closing it
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - URL of the source, with or without query and credentials
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the source had a circuit
+    pub fn reset(&self, url: &str) -> bool {
+        let source = record::redact_url(url);
+        self.circuits
+            .lock()
+            .map(|mut circuits| circuits.remove(&source).is_some())
+            .unwrap_or(false)
+    }
+}
+
+impl Permit<'_> {
+    /// Report how the request ended
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The error the request failed with, or `None` on success
+    pub fn finish(mut self, error: Option<&AppError>) {
+        if let Some(source) = self.source.take() {
+            self.breaker.record(source, error);
+        }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if let Some(source) = self.source.take() {
+            self.breaker.abandon(&source);
+        }
+    }
+}
+
+/// Whether an error says something about the source rather than the request
+fn counts_against_source(error: &AppError) -> bool {
+    matches!(
+        error.cause(),
+        AppError::QuickJs(_)
+            | AppError::JsException { .. }
+            | AppError::JsSyntaxError { .. }
+            | AppError::ModuleResolutionDisabled { .. }
+            | AppError::Wasmtime(_)
+            | AppError::WasmTrap { .. }
+            | AppError::InvalidModule(_)
+            | AppError::MissingImports { .. }
+            | AppError::StubbedImportCalled(_)
+            | AppError::InvalidEntrypoint { .. }
+            | AppError::NoUsableMemory { .. }
+            | AppError::WatSyntaxError { .. }
+            | AppError::WasiExit { .. }
+            | AppError::GuestAbort(_)
+            | AppError::Reqwest(_)
+            | AppError::Download(_)
+            | AppError::CodeTooLarge { .. }
+            | AppError::Decompression(_)
+            | AppError::UnsupportedCodeType { .. }
+            | AppError::CodeTypeUndetected { .. }
+            | AppError::InvalidManifest(_)
+    )
+}
@@ -0,0 +1,108 @@
+//! # Guest clocks
+//!
+//! Guests read the time through `GuestClock`: `get_unixtime`,
+//! `get_unixtime_ms`, `monotonic_now_ns` and the local-time functions in
+//! WebAssembly, `get_unixtime`, `get_unixtime_ms`, `performance.now`, the
+//! local-time helpers and, in deterministic mode, `Date` in JavaScript. The
+//! wall clock is the host's; the monotonic clock counts from the start of the
+//! execution, so it measures durations inside the guest without jumping with
+//! the host's time.
+//!
+//! When a request sets `deterministic: true`, both clocks are linearized
+//! instead: the wall clock starts at `DETERMINISTIC_EPOCH_MS`, the monotonic
+//! clock at zero, and every reading of either advances both by exactly one
+//! millisecond. Two runs with the same code therefore read the same times,
+//! and later readings are still later than earlier ones.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Wall-clock time deterministic executions start at: 2024-01-01T00:00:00Z, in milliseconds
+pub const DETERMINISTIC_EPOCH_MS: u64 = 1_704_067_200_000;
+
+/// How far a deterministic clock advances per reading, in milliseconds
+const DETERMINISTIC_STEP_MS: u64 = 1;
+
+/// Clocks of one execution
+///
+/// ```
+/// use hoya::clock::{GuestClock, DETERMINISTIC_EPOCH_MS};
+///
+/// let clock = GuestClock::new(true);
+/// assert_eq!(clock.unix_ms(), Some(DETERMINISTIC_EPOCH_MS + 1));
+/// assert_eq!(clock.monotonic_ns(), 2_000_000);
+///
+/// let host = GuestClock::new(false);
+/// let earlier = host.monotonic_ns();
+/// assert!(host.monotonic_ns() >= earlier);
+/// ```
+pub struct GuestClock {
+    /// Start of the execution, the origin of the monotonic clock
+    started: Instant,
+    /// Wall-clock time at the start of the execution, in milliseconds since the Unix epoch
+    origin_unix_ms: u64,
+    /// Readings so far, for linearized clocks in deterministic mode
+    readings: Option<AtomicU64>,
+}
+
+impl GuestClock {
+    /// Clocks starting now
+    ///
+    /// # Arguments
+    ///
+    /// * `deterministic` - Linearize both clocks instead of reading the host's
+    pub fn new(deterministic: bool) -> Self {
+        let origin_unix_ms = match deterministic {
+            true => DETERMINISTIC_EPOCH_MS,
+            false => host_unix_ms().unwrap_or(0),
+        };
+        GuestClock {
+            started: Instant::now(),
+            origin_unix_ms,
+            readings: deterministic.then(|| AtomicU64::new(0)),
+        }
+    }
+
+    /// Whether both clocks are linearized rather than the host's
+    pub fn is_deterministic(&self) -> bool {
+        self.readings.is_some()
+    }
+
+    /// Wall-clock time the monotonic clock counts from, in milliseconds since the Unix epoch
+    pub fn origin_unix_ms(&self) -> u64 {
+        self.origin_unix_ms
+    }
+
+    /// Milliseconds advanced by the linearized clocks once this reading is
+    /// counted, or `None` for the host's clocks
+    fn tick(&self) -> Option<u64> {
+        let readings = self.readings.as_ref()?;
+        Some((readings.fetch_add(1, Ordering::Relaxed) + 1) * DETERMINISTIC_STEP_MS)
+    }
+
+    /// Milliseconds since the Unix epoch
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u64>` - The time, or `None` if the host's clock is set before 1970
+    pub fn unix_ms(&self) -> Option<u64> {
+        if let Some(elapsed_ms) = self.tick() {
+            return Some(DETERMINISTIC_EPOCH_MS + elapsed_ms);
+        }
+        host_unix_ms()
+    }
+
+    /// Nanoseconds since the start of the execution
+    pub fn monotonic_ns(&self) -> u64 {
+        match self.tick() {
+            Some(elapsed_ms) => elapsed_ms * 1_000_000,
+            None => u64::try_from(self.started.elapsed().as_nanos()).unwrap_or(u64::MAX),
+        }
+    }
+}
+
+/// The host's time in milliseconds since the Unix epoch, unless its clock is set before 1970
+fn host_unix_ms() -> Option<u64> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some(u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX))
+}
@@ -0,0 +1,82 @@
+//! gzip helpers for downloaded code artifacts and guest payloads.
+//!
+//! CI pipelines commonly publish gzipped WebAssembly (`.wasm.gz`). These
+//! helpers inflate such artifacts after download while enforcing an upper
+//! bound on the decompressed size so a small "zip bomb" cannot exhaust memory.
+//! The same helpers back the gzip host functions offered to guests.
+
+use crate::error::AppError;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// Maximum number of bytes a compressed artifact may expand to
+pub const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// Maximum number of bytes a guest's gzip payload may expand to
+pub const MAX_GUEST_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Why a gzip stream could not be inflated
+#[derive(Debug)]
+pub enum InflateError {
+    /// The input is not a valid gzip stream
+    Corrupt(std::io::Error),
+    /// The output would exceed the size limit
+    TooLarge,
+}
+
+/// Decompress a gzip stream, distinguishing corrupt input from oversized output
+///
+/// # Arguments
+///
+/// * `compressed` - The gzip-compressed bytes
+/// * `limit` - Maximum allowed size of the decompressed output in bytes
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, InflateError>` - Decompressed bytes or the reason they are unavailable
+pub fn inflate(compressed: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    let mut decompressed = Vec::new();
+    // Read one byte past the limit so an exact-limit stream is still accepted
+    GzDecoder::new(compressed)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(InflateError::Corrupt)?;
+
+    if decompressed.len() > limit {
+        return Err(InflateError::TooLarge);
+    }
+    Ok(decompressed)
+}
+
+/// Compress bytes into a gzip stream with the default compression level
+pub fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Decompress a gzip stream, failing if the output would exceed `limit` bytes
+///
+/// # Arguments
+///
+/// * `compressed` - The gzip-compressed bytes
+/// * `limit` - Maximum allowed size of the decompressed output in bytes
+///
+/// # Returns
+///
+/// * `Result<bytes::Bytes, AppError>` - Decompressed bytes or a decompression error
+pub fn gunzip(compressed: &[u8], limit: usize) -> Result<bytes::Bytes, AppError> {
+    match inflate(compressed, limit) {
+        Ok(decompressed) => Ok(bytes::Bytes::from(decompressed)),
+        Err(InflateError::Corrupt(e)) => Err(AppError::Decompression(format!(
+            "Invalid gzip stream: {}",
+            e
+        ))),
+        Err(InflateError::TooLarge) => Err(AppError::Decompression(format!(
+            "Decompressed size exceeds the limit of {} bytes",
+            limit
+        ))),
+    }
+}
@@ -0,0 +1,1243 @@
+//! # Server configuration
+//!
+//! Settings are read from a TOML file whose path is given by `--config` or
+//! the `HOYA_CONFIG` environment variable. Every field has a default, so the
+//! file (and any section in it) is optional.
+//!
+//! Single settings can be overridden without editing the file: environment
+//! variables named `HOYA__<SECTION>__<FIELD>` (e.g.
+//! `HOYA__EXECUTION__MAX_TIMEOUT_MS=60000`) apply over the file, and
+//! `--set <section>.<field>=<value>`, `--bind` and `--port` apply over those.
+//! Values are read as TOML when they parse as TOML and as strings otherwise.
+//! `RUST_LOG` sets `server.log_level` over the file, below both.
+//!
+//! ```toml
+//! [server]
+//! bind = "0.0.0.0"
+//! port = 8080
+//! log_level = "info,hoya::guest=warn"
+//! log_format = "json"
+//!
+//! [auth]
+//! public_paths = ["/health"]
+//!
+//! [[auth.keys]]
+//! name = "ci"
+//! key = "..."
+//! requests_per_minute = 600
+//! max_concurrent = 4
+//!
+//! [fetch]
+//! http1_only = true
+//! pool_idle_timeout = 30
+//! allow_domains = ["internal-api.example.com"]
+//! deny_domains = ["metadata.google.internal"]
+//! max_timeout_ms = 10000
+//! max_response_bytes = 1048576
+//!
+//! [download]
+//! max_bytes = 536870912
+//! timeout_secs = 600
+//! max_resumes = 5
+//! cache_max_bytes = 67108864
+//! forward_headers = ["Authorization", "X-Api-Key"]
+//! allowed_schemes = ["https"]
+//! allow_hosts = ["*.example.com"]
+//! deny_hosts = ["scratch.example.com"]
+//!
+//! [download.headers]
+//! Authorization = "Bearer ..."
+//!
+//! [metrics]
+//! fetch_hosts = ["api.example.com"]
+//!
+//! [execution]
+//! default_timeout_ms = 10000
+//! max_timeout_ms = 60000
+//! max_output_bytes = 65536
+//! max_stdout_bytes = 32768
+//! max_stderr_bytes = 16384
+//! max_log_bytes = 16384
+//! max_fetch_calls = 100
+//! max_fetch_bytes = 1048576
+//! max_log_entries = 10000
+//! max_env_bytes = 16384
+//! max_concurrent = 32
+//! queue_timeout_ms = 2000
+//!
+//! [capture]
+//! echo_to_host = false
+//!
+//! [js]
+//! lock_math_random = true
+//! max_console_calls = 100000
+//! default_memory_bytes = 67108864
+//! max_memory_bytes = 1073741824
+//! max_stack_bytes = 1048576
+//! runtime_pool_size = 8
+//! runtime_checkout_timeout_ms = 5000
+//!
+//! [wasm]
+//! epoch_tick_ms = 10
+//! module_cache_capacity = 64
+//! module_cache_max_bytes = 268435456
+//! disk_cache_path = "/var/cache/hoya/modules"
+//! disk_cache_max_bytes = 1073741824
+//! default_memory_bytes = 67108864
+//! max_memory_bytes = 268435456
+//! max_tables = 8
+//! max_instances = 1
+//! default_fuel = 1000000000
+//! max_fuel = 10000000000
+//!
+//! [warmup]
+//! urls = ["https://cdn.example.com/hot.wasm"]
+//! mode = "block"
+//! refresh_interval_secs = 300
+//!
+//! [modules]
+//! max_modules = 64
+//! max_bytes = 67108864
+//!
+//! [audit]
+//! path = "/var/log/hoya/audit.jsonl"
+//!
+//! [history]
+//! capacity = 1000
+//!
+//! [jobs]
+//! max_concurrent = 4
+//! max_queued = 100
+//! capacity = 1000
+//! ttl_secs = 3600
+//!
+//! [storage]
+//! path = "/var/lib/hoya/storage.db"
+//! max_keys = 1000
+//! max_bytes = 1048576
+//!
+//! [kv]
+//! max_namespaces = 1000
+//! max_keys = 1000
+//! max_bytes = 1048576
+//!
+//! [circuit_breaker]
+//! failure_threshold = 5
+//! window_secs = 60
+//! open_secs = 30
+//!
+//! [tracing]
+//! endpoint = "http://tempo:4318/v1/traces"
+//! sampling_ratio = 0.1
+//!
+//! [tracing.headers]
+//! X-Scope-OrgID = "hoya"
+//!
+//! [[reporters]]
+//! type = "webhook"
+//! url = "https://incidents.example.com/hoya"
+//! errors_only = true
+//!
+//! [[reporters]]
+//! type = "log"
+//! ```
+
+use crate::download;
+use crate::outbound::OutboundPolicy;
+use crate::wasm_engine::{MAX_EPOCH_TICK_MS, MIN_EPOCH_TICK_MS};
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_subscriber::filter::Targets;
+
+/// Environment variable holding the path of the configuration file
+pub const CONFIG_PATH_ENV: &str = "HOYA_CONFIG";
+
+/// Smallest `js.max_stack_bytes`; less does not fit the interpreter's own frames
+pub const MIN_JS_STACK_BYTES: usize = 64 << 10;
+
+/// Largest `js.max_stack_bytes`; scripts run on blocking-pool or runtime pool threads with 2 MiB stacks
+pub const MAX_JS_STACK_BYTES: usize = 1536 << 10;
+
+/// Largest `js.runtime_pool_size`; every pooled runtime has a thread of its own
+pub const MAX_JS_RUNTIME_POOL_SIZE: usize = 1024;
+
+/// Environment variable overriding `server.log_level`, as in other Rust services
+pub const LOG_FILTER_ENV: &str = "RUST_LOG";
+
+/// Prefix of environment variables overriding a single setting
+pub const OVERRIDE_ENV_PREFIX: &str = "HOYA__";
+
+/// Where the configuration comes from, as given on the command line
+#[derive(Parser, Debug, Default)]
+#[command(
+    name = "hoya",
+    about = "Runs JavaScript and WebAssembly code downloaded from URLs",
+    after_help = "\
+Environment variables named HOYA__<SECTION>__<FIELD> override single settings
+too, e.g. HOYA__DOWNLOAD__MAX_BYTES=1048576; command line options win over them.
+RUST_LOG sets server.log_level, below both."
+)]
+pub struct Args {
+    /// Configuration file (default: $HOYA_CONFIG)
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<String>,
+    /// Address to listen on, same as --set server.bind=<ADDRESS>
+    #[arg(long, value_name = "ADDRESS")]
+    pub bind: Option<IpAddr>,
+    /// Port to listen on, same as --set server.port=<PORT>
+    #[arg(long, value_name = "PORT")]
+    pub port: Option<u16>,
+    /// Override one setting, e.g. --set wasm.default_fuel=5000000
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_override)]
+    pub set: Vec<(String, String)>,
+}
+
+impl Args {
+    /// `(key, value)` settings applied over the file and the environment:
+    /// `--bind` and `--port`, then every `--set` in the order given
+    pub fn overrides(&self) -> Vec<(String, String)> {
+        let mut overrides = Vec::new();
+        if let Some(bind) = self.bind {
+            overrides.push(("server.bind".to_string(), bind.to_string()));
+        }
+        if let Some(port) = self.port {
+            overrides.push(("server.port".to_string(), port.to_string()));
+        }
+        overrides.extend(self.set.iter().cloned());
+        overrides
+    }
+}
+
+/// Split a `--set` value into its key and value
+fn parse_override(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected <KEY>=<VALUE>, got {:?}", value))
+}
+
+/// Top-level server configuration
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where the server listens
+    pub server: ServerConfig,
+    /// API keys requests must present
+    pub auth: AuthConfig,
+    /// Settings for the HTTP client backing guest fetch calls
+    pub fetch: FetchConfig,
+    /// Settings for downloading the code to execute
+    pub download: DownloadConfig,
+    /// Settings for the Prometheus metrics endpoint
+    pub metrics: MetricsConfig,
+    /// Limits applied to each execution
+    pub execution: ExecutionConfig,
+    /// Handling of captured guest output
+    pub capture: CaptureConfig,
+    /// Settings for the JavaScript engine
+    pub js: JsConfig,
+    /// Settings for the WebAssembly engine
+    pub wasm: WasmConfig,
+    /// Code warmed into the caches at startup
+    pub warmup: WarmupConfig,
+    /// Limits of the registry of named modules
+    pub modules: ModulesConfig,
+    /// Settings for the execution audit log
+    pub audit: AuditConfig,
+    /// Settings for the in-memory execution history
+    pub history: HistoryConfig,
+    /// Settings for asynchronous jobs
+    pub jobs: JobsConfig,
+    /// Settings for the persistent key-value store
+    pub storage: StorageConfig,
+    /// Settings for the in-memory key-value store
+    pub kv: KvConfig,
+    /// Fast-failing of sources that keep failing
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Settings for OpenTelemetry trace export
+    pub tracing: TracingConfig,
+    /// Reporters notified of every finished execution
+    pub reporters: Vec<ReporterConfig>,
+}
+
+/// Where the server listens
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address to listen on; `0.0.0.0` or `::` listens on every interface
+    pub bind: IpAddr,
+    /// Port to listen on
+    pub port: u16,
+    /// Log events printed, as `RUST_LOG`-style directives such as `info` or
+    /// `hoya=debug,hoya::guest=warn`; `RUST_LOG` overrides the file
+    pub log_level: String,
+    /// How log events are printed
+    pub log_format: LogFormat,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 3000,
+            log_level: "info".to_string(),
+            log_format: LogFormat::default(),
+        }
+    }
+}
+
+/// How log events are printed to stdout
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One human-readable line per event, prefixed with its spans
+    #[default]
+    Pretty,
+    /// One JSON object per event, with its fields and spans
+    Json,
+}
+
+impl ServerConfig {
+    /// Socket address the server listens on
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.port)
+    }
+
+    /// Base URL at which the server reaches itself, over loopback when bound to every interface
+    pub fn local_url(&self) -> String {
+        let host = match self.bind {
+            IpAddr::V4(v4) if v4.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(v6) if v6.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            bind => bind,
+        };
+        format!("http://{}", SocketAddr::new(host, self.port))
+    }
+}
+
+/// API keys requests must present
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Keys accepted in `Authorization: Bearer` or `X-Api-Key`; empty lets every request through
+    pub keys: Vec<ApiKeyConfig>,
+    /// Paths served without a key, e.g. for load balancer health checks
+    pub public_paths: Vec<String>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            keys: Vec::new(),
+            public_paths: vec!["/health".to_string()],
+        }
+    }
+}
+
+/// An API key and the quotas of the requests made with it
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// Name identifying the key in logs, traces and metadata
+    pub name: String,
+    /// The secret itself
+    pub key: String,
+    /// Requests accepted per minute; unset does not limit them
+    pub requests_per_minute: Option<u64>,
+    /// Executions running at once; unset leaves only `execution.max_concurrent`
+    pub max_concurrent: Option<usize>,
+}
+
+/// Largest `max_attempts` of a webhook reporter
+pub const MAX_WEBHOOK_ATTEMPTS: u32 = 10;
+
+/// A built-in reporter, selected by its `type`
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ReporterConfig {
+    /// POST every event as JSON to a URL
+    Webhook(WebhookReporterConfig),
+    /// Print every event to the server's stdout/stderr
+    Log,
+}
+
+/// Settings for a webhook reporter
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookReporterConfig {
+    /// URL the events are posted to
+    pub url: String,
+    /// Headers sent with every request (e.g. credentials)
+    pub headers: HashMap<String, String>,
+    /// Attempts per event before it is dropped; network errors, 429 and 5xx are retried
+    pub max_attempts: u32,
+    /// Timeout of each attempt in milliseconds
+    pub timeout_ms: u64,
+    /// Only post failed executions
+    pub errors_only: bool,
+}
+
+impl Default for WebhookReporterConfig {
+    fn default() -> Self {
+        WebhookReporterConfig {
+            url: String::new(),
+            headers: HashMap::new(),
+            max_attempts: 3,
+            timeout_ms: 5_000,
+            errors_only: false,
+        }
+    }
+}
+
+/// Encoding of spans sent to the OTLP endpoint
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OtlpProtocol {
+    /// Protocol buffers over HTTP, what collectors expect by default
+    #[default]
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+    /// JSON over HTTP
+    #[serde(rename = "http/json")]
+    HttpJson,
+}
+
+/// Settings for OpenTelemetry trace export
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingConfig {
+    /// OTLP/HTTP traces URL, including the `/v1/traces` path; unset disables tracing
+    pub endpoint: Option<String>,
+    /// Encoding of the exported spans
+    pub protocol: OtlpProtocol,
+    /// Headers sent with every export request (e.g. tenant or credentials)
+    pub headers: HashMap<String, String>,
+    /// Fraction of traces started here that are exported; callers' sampling decisions are kept
+    pub sampling_ratio: f64,
+    /// `service.name` resource attribute of the exported spans
+    pub service_name: String,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            endpoint: None,
+            protocol: OtlpProtocol::HttpProtobuf,
+            headers: HashMap::new(),
+            sampling_ratio: 1.0,
+            service_name: "hoya".to_string(),
+        }
+    }
+}
+
+/// Settings for the in-memory execution history
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Number of recent executions kept for lookup and replay; `0` disables the history
+    pub capacity: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig { capacity: 1000 }
+    }
+}
+
+/// Settings for asynchronous jobs
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobsConfig {
+    /// Jobs running at once; the others wait in the queue
+    pub max_concurrent: usize,
+    /// Jobs queued or running before submissions fail with `JOB_QUEUE_FULL`
+    pub max_queued: usize,
+    /// Completed jobs kept for `GET /jobs/{id}`; the oldest are evicted first
+    pub capacity: usize,
+    /// Seconds a completed job is kept
+    pub ttl_secs: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            max_concurrent: 4,
+            max_queued: 100,
+            capacity: 1000,
+            ttl_secs: 3600,
+        }
+    }
+}
+
+/// Settings for the persistent key-value store
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// SQLite database holding every namespace; unset disables storage
+    pub path: Option<PathBuf>,
+    /// Keys allowed per namespace
+    pub max_keys: u64,
+    /// Bytes of keys and values allowed per namespace
+    pub max_bytes: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            path: None,
+            max_keys: 1000,
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Settings for the in-memory key-value store
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct KvConfig {
+    /// Shared namespaces held at once
+    pub max_namespaces: usize,
+    /// Entries allowed per namespace
+    pub max_keys: u64,
+    /// Bytes of keys and values allowed per namespace
+    pub max_bytes: u64,
+}
+
+impl Default for KvConfig {
+    fn default() -> Self {
+        KvConfig {
+            max_namespaces: 1000,
+            max_keys: 1000,
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Fast-failing of sources that keep failing
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// Failures of one source within the window that open its circuit; `0` disables the breaker
+    pub failure_threshold: u32,
+    /// Length of the sliding failure window in seconds
+    pub window_secs: u64,
+    /// Seconds an open circuit rejects requests before letting a probe through
+    pub open_secs: u64,
+    /// Number of sources tracked; the least recently updated one is forgotten first
+    pub max_circuits: usize,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            window_secs: 60,
+            open_secs: 30,
+            max_circuits: 10_000,
+        }
+    }
+}
+
+/// Settings for the execution audit log
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// JSON Lines file receiving one record per execution; unset disables auditing
+    pub path: Option<PathBuf>,
+    /// Size in bytes at which the file is rotated
+    pub max_bytes: u64,
+    /// Number of rotated files kept (`path.1` is the most recent)
+    pub max_files: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            path: None,
+            max_bytes: 64 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+/// Limits applied to each execution
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExecutionConfig {
+    /// Timeout in milliseconds used when a request does not set `timeout_ms`
+    pub default_timeout_ms: u64,
+    /// Largest `timeout_ms` a request may ask for
+    pub max_timeout_ms: u64,
+    /// Bytes of output captured per execution across all channels; requests may lower it
+    pub max_output_bytes: usize,
+    /// Bytes of stdout captured per execution; requests may lower it
+    pub max_stdout_bytes: usize,
+    /// Bytes of stderr captured per execution; requests may lower it
+    pub max_stderr_bytes: usize,
+    /// Bytes of `app_log` messages captured per execution; requests may lower it
+    pub max_log_bytes: usize,
+    /// Guest fetch calls per execution; requests may lower it
+    pub max_fetch_calls: u64,
+    /// Response body bytes guest fetch calls may receive per execution; requests may lower it
+    pub max_fetch_bytes: u64,
+    /// Messages captured per execution across stdout, stderr and `app_log`; requests may lower it
+    pub max_log_entries: u64,
+    /// Terminate executions that exhaust their output budget unless the request says otherwise
+    pub strict_output: bool,
+    /// Longest name of a request `env` variable, in bytes
+    pub max_env_key_bytes: usize,
+    /// Longest value of a request `env` variable, in bytes
+    pub max_env_value_bytes: usize,
+    /// Bytes of names and values a request's `env` may hold in total; 0 refuses any variable
+    pub max_env_bytes: usize,
+    /// Bytes a request's `input` may take, serialized as JSON
+    pub max_input_bytes: usize,
+    /// Executions of `/execute`, `/execute/stream` and replays running at once
+    pub max_concurrent: usize,
+    /// Milliseconds a request waits for a running execution to finish before
+    /// failing with `SERVER_BUSY`; 0 refuses it at once
+    pub queue_timeout_ms: u64,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        ExecutionConfig {
+            default_timeout_ms: 30_000,
+            max_timeout_ms: 300_000,
+            max_output_bytes: 1024 * 1024,
+            max_stdout_bytes: 1024 * 1024,
+            max_stderr_bytes: 1024 * 1024,
+            max_log_bytes: 1024 * 1024,
+            max_fetch_calls: 1_000,
+            max_fetch_bytes: 10 * 1024 * 1024,
+            max_log_entries: 100_000,
+            strict_output: false,
+            max_env_key_bytes: 256,
+            max_env_value_bytes: 8 * 1024,
+            max_env_bytes: 64 * 1024,
+            max_input_bytes: 1024 * 1024,
+            max_concurrent: 64,
+            queue_timeout_ms: 1000,
+        }
+    }
+}
+
+/// Handling of captured guest output
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Also print every captured line to the server's stdout/stderr
+    ///
+    /// Useful while debugging; in production it floods the service logs and
+    /// doubles the cost of chatty guests.
+    pub echo_to_host: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig { echo_to_host: true }
+    }
+}
+
+/// Settings for the JavaScript engine
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct JsConfig {
+    /// In deterministic mode, prevent scripts from replacing the seeded `Math.random`
+    pub lock_math_random: bool,
+    /// Console/capture calls per execution before further calls are dropped
+    pub max_console_calls: u64,
+    /// Heap a script may allocate, in bytes, when the request does not set `limits.memory_bytes`
+    pub default_memory_bytes: u64,
+    /// Most heap a request may let a script allocate, in bytes
+    pub max_memory_bytes: u64,
+    /// Native stack the interpreter may use, in bytes; deeper recursion throws a `RangeError`
+    pub max_stack_bytes: usize,
+    /// QuickJS runtimes kept ready, each on a thread of its own; 0 builds a runtime per execution
+    pub runtime_pool_size: usize,
+    /// Milliseconds an execution waits for a pooled runtime before failing with `RUNTIME_POOL_EXHAUSTED`
+    pub runtime_checkout_timeout_ms: u64,
+}
+
+impl Default for JsConfig {
+    fn default() -> Self {
+        JsConfig {
+            lock_math_random: true,
+            max_console_calls: 100_000,
+            default_memory_bytes: 64 << 20,
+            max_memory_bytes: 1 << 30,
+            max_stack_bytes: 1 << 20,
+            runtime_pool_size: 8,
+            runtime_checkout_timeout_ms: 5000,
+        }
+    }
+}
+
+/// Settings for the WebAssembly engine
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WasmConfig {
+    /// Milliseconds between epoch ticks; this is the granularity of wasm timeouts
+    pub epoch_tick_ms: u64,
+    /// Number of compiled modules kept in memory; 0 disables the module cache
+    pub module_cache_capacity: usize,
+    /// Compiled code kept in the module cache, in bytes; 0 disables the module cache
+    pub module_cache_max_bytes: usize,
+    /// Directory compiled modules are kept in across restarts; unset keeps them in memory only
+    pub disk_cache_path: Option<PathBuf>,
+    /// Bytes of compiled modules kept in `disk_cache_path`
+    pub disk_cache_max_bytes: u64,
+    /// Largest size a module's memories may grow to, in bytes, when neither the manifest nor the request sets one
+    pub default_memory_bytes: u64,
+    /// Most a manifest or request may let a module's memories grow to, in bytes
+    pub max_memory_bytes: u64,
+    /// Tables a module may define or import
+    pub max_tables: usize,
+    /// Module instances a store may hold
+    pub max_instances: usize,
+    /// Fuel (roughly one unit per instruction) given to executions that do not set `limits.max_fuel`
+    pub default_fuel: u64,
+    /// Most fuel a request may ask for
+    pub max_fuel: u64,
+}
+
+impl Default for WasmConfig {
+    fn default() -> Self {
+        WasmConfig {
+            epoch_tick_ms: 10,
+            module_cache_capacity: 64,
+            module_cache_max_bytes: 256 << 20,
+            disk_cache_path: None,
+            disk_cache_max_bytes: 1 << 30,
+            default_memory_bytes: 64 << 20,
+            max_memory_bytes: 1 << 32,
+            max_tables: 8,
+            max_instances: 1,
+            default_fuel: 10_000_000_000,
+            max_fuel: 100_000_000_000,
+        }
+    }
+}
+
+/// When the server reports ready relative to the startup warmup
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WarmupMode {
+    /// Serve requests right away; `/health` reports ready once warmup finished
+    #[default]
+    Background,
+    /// Finish warmup before accepting connections
+    Block,
+}
+
+/// Code warmed into the caches at startup
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WarmupConfig {
+    /// URLs downloaded, verified and compiled at startup
+    pub urls: Vec<String>,
+    /// Whether startup waits for the warmup
+    pub mode: WarmupMode,
+    /// Seconds between repeated warmups after startup; 0 warms only once
+    pub refresh_interval_secs: u64,
+    /// Attempts per URL before its warmup is given up
+    pub max_attempts: u32,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        WarmupConfig {
+            urls: Vec::new(),
+            mode: WarmupMode::Background,
+            refresh_interval_secs: 0,
+            max_attempts: 5,
+        }
+    }
+}
+
+/// Limits of the registry of named modules
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModulesConfig {
+    /// Modules registered at once; `0` disables registration
+    pub max_modules: usize,
+    /// Bytes of code the registered modules may hold together
+    pub max_bytes: u64,
+}
+
+impl Default for ModulesConfig {
+    fn default() -> Self {
+        ModulesConfig {
+            max_modules: 64,
+            max_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Settings for the Prometheus metrics endpoint
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Guest fetch destination hosts labeled individually; all others are labeled "other"
+    pub fetch_hosts: Vec<String>,
+}
+
+/// Settings for downloading the code to execute
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadConfig {
+    /// Headers sent with every code download; request `download_headers` override them
+    pub headers: HashMap<String, String>,
+    /// Header names a request's `download_headers` may set
+    pub forward_headers: Vec<String>,
+    /// Largest artifact downloaded, in bytes
+    pub max_bytes: u64,
+    /// Seconds a download may take, across all of its attempts
+    pub timeout_secs: u64,
+    /// Times an interrupted download is resumed before it fails
+    pub max_resumes: u32,
+    /// Downloaded code kept for reuse and revalidation, in bytes; 0 disables the cache
+    pub cache_max_bytes: u64,
+    /// URL schemes code may be downloaded over, `https` and `http`
+    pub allowed_schemes: Vec<String>,
+    /// Host patterns code may be downloaded from, `*` matching any run of
+    /// characters; empty allows every host
+    pub allow_hosts: Vec<String>,
+    /// Host patterns code may never be downloaded from, even when allowed
+    pub deny_hosts: Vec<String>,
+    /// Download code from loopback, link-local, private and unique local addresses
+    pub allow_private_addresses: bool,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        DownloadConfig {
+            headers: HashMap::new(),
+            forward_headers: vec!["Authorization".to_string(), "X-Api-Key".to_string()],
+            max_bytes: 1024 * 1024 * 1024,
+            timeout_secs: 300,
+            max_resumes: 5,
+            cache_max_bytes: 64 * 1024 * 1024,
+            allowed_schemes: vec!["https".to_string()],
+            allow_hosts: Vec::new(),
+            deny_hosts: Vec::new(),
+            allow_private_addresses: false,
+        }
+    }
+}
+
+/// Settings for the HTTP client backing guest fetch calls
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    /// Speak HTTP/2 without negotiation (for upstreams known to support it)
+    pub http2_prior_knowledge: bool,
+    /// Restrict the client to HTTP/1.1 (for upstreams with broken HTTP/2)
+    pub http1_only: bool,
+    /// Seconds an idle pooled connection is kept open; `0` disables pooling
+    pub pool_idle_timeout: u64,
+    /// Seconds between TCP keepalive probes; unset disables keepalive
+    pub tcp_keepalive: Option<u64>,
+    /// Let guests fetch loopback, link-local, private and unique local addresses
+    pub allow_private_addresses: bool,
+    /// Domains, with their subdomains, guests may fetch whatever they resolve to
+    pub allow_domains: Vec<String>,
+    /// Domains, with their subdomains, guests may never fetch
+    pub deny_domains: Vec<String>,
+    /// Longest timeout of a single fetch call in milliseconds, and the default
+    pub max_timeout_ms: u64,
+    /// Most redirects a single fetch call follows, and the default
+    pub max_redirects: usize,
+    /// Largest response body of a single fetch call in bytes, and the default
+    pub max_response_bytes: u64,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            http2_prior_knowledge: false,
+            http1_only: false,
+            pool_idle_timeout: 90,
+            tcp_keepalive: None,
+            allow_private_addresses: false,
+            allow_domains: Vec::new(),
+            deny_domains: Vec::new(),
+            max_timeout_ms: 30_000,
+            max_redirects: 10,
+            max_response_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration and apply the environment and command line overrides
+    ///
+    /// The file is `--config`, else the one named by `HOYA_CONFIG`, else none
+    /// and every setting keeps its default.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The parsed command line
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Config, String>` - The validated configuration or a description of the problem,
+    ///   naming the file line or the override at fault
+    pub fn load(args: &Args) -> Result<Config, String> {
+        let path = args
+            .config
+            .clone()
+            .or_else(|| std::env::var(CONFIG_PATH_ENV).ok());
+        let mut table = match &path {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+                // Deserialize the file on its own first, so errors point at its lines
+                toml::from_str::<Config>(&contents)
+                    .map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+                toml::from_str::<toml::Table>(&contents)
+                    .map_err(|e| format!("Invalid config file {}: {}", path, e))?
+            }
+            None => toml::Table::new(),
+        };
+
+        // RUST_LOG is always a filter string, never read as TOML
+        if let Ok(filter) = std::env::var(LOG_FILTER_ENV) {
+            set_key(&mut table, "server.log_level", toml::Value::String(filter))
+                .map_err(|e| format!("Invalid {}: {}", LOG_FILTER_ENV, e))?;
+        }
+
+        let mut environment: Vec<(String, String, String)> = std::env::vars_os()
+            .filter_map(|(name, value)| {
+                let (name, value) = (name.into_string().ok()?, value.into_string().ok()?);
+                let key = name
+                    .strip_prefix(OVERRIDE_ENV_PREFIX)?
+                    .to_ascii_lowercase()
+                    .replace("__", ".");
+                Some((format!("environment variable {}", name), key, value))
+            })
+            .collect();
+        environment.sort();
+        let command_line = args
+            .overrides()
+            .into_iter()
+            .map(|(key, value)| ("command line".to_string(), key, value));
+        for (origin, key, value) in environment.into_iter().chain(command_line) {
+            // Apply one override at a time, so an error names the one at fault
+            set_key(&mut table, &key, parse_value(&value))
+                .and_then(|_| {
+                    toml::Value::Table(table.clone())
+                        .try_into::<Config>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .map_err(|e| format!("Invalid setting {} from the {}: {}", key, origin, e))?;
+        }
+
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("Invalid configuration: {}", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reject combinations of settings that contradict each other
+    fn validate(&self) -> Result<(), String> {
+        if self.server.port == 0 {
+            return Err("server.port must be greater than 0".to_string());
+        }
+        if let Err(e) = self.server.log_level.parse::<Targets>() {
+            return Err(format!(
+                "server.log_level (or {}) is not a valid log filter, got {:?}: {}",
+                LOG_FILTER_ENV, self.server.log_level, e
+            ));
+        }
+        if self.fetch.http1_only && self.fetch.http2_prior_knowledge {
+            return Err(
+                "fetch.http1_only and fetch.http2_prior_knowledge cannot both be enabled"
+                    .to_string(),
+            );
+        }
+        let fetch = &self.fetch;
+        if fetch.allow_domains.iter().any(String::is_empty)
+            || fetch.deny_domains.iter().any(String::is_empty)
+        {
+            return Err(
+                "fetch.allow_domains and fetch.deny_domains cannot list an empty domain"
+                    .to_string(),
+            );
+        }
+        if fetch.max_timeout_ms == 0 {
+            return Err("fetch.max_timeout_ms must be greater than 0".to_string());
+        }
+        if !(MIN_EPOCH_TICK_MS..=MAX_EPOCH_TICK_MS).contains(&self.wasm.epoch_tick_ms) {
+            return Err(format!(
+                "wasm.epoch_tick_ms must be between {} and {}, got {}",
+                MIN_EPOCH_TICK_MS, MAX_EPOCH_TICK_MS, self.wasm.epoch_tick_ms
+            ));
+        }
+        if self.execution.max_timeout_ms == 0 {
+            return Err("execution.max_timeout_ms must be greater than 0".to_string());
+        }
+        if self.execution.default_timeout_ms == 0
+            || self.execution.default_timeout_ms > self.execution.max_timeout_ms
+        {
+            return Err(format!(
+                "execution.default_timeout_ms must be between 1 and execution.max_timeout_ms ({}), got {}",
+                self.execution.max_timeout_ms, self.execution.default_timeout_ms
+            ));
+        }
+        if self.wasm.default_fuel == 0 || self.wasm.default_fuel > self.wasm.max_fuel {
+            return Err(format!(
+                "wasm.default_fuel must be between 1 and wasm.max_fuel ({}), got {}",
+                self.wasm.max_fuel, self.wasm.default_fuel
+            ));
+        }
+        if self.js.default_memory_bytes == 0
+            || self.js.default_memory_bytes > self.js.max_memory_bytes
+        {
+            return Err(format!(
+                "js.default_memory_bytes must be between 1 and js.max_memory_bytes ({}), got {}",
+                self.js.max_memory_bytes, self.js.default_memory_bytes
+            ));
+        }
+        if !(MIN_JS_STACK_BYTES..=MAX_JS_STACK_BYTES).contains(&self.js.max_stack_bytes) {
+            return Err(format!(
+                "js.max_stack_bytes must be between {} and {}, got {}",
+                MIN_JS_STACK_BYTES, MAX_JS_STACK_BYTES, self.js.max_stack_bytes
+            ));
+        }
+        if self.js.runtime_pool_size > MAX_JS_RUNTIME_POOL_SIZE {
+            return Err(format!(
+                "js.runtime_pool_size must be at most {}, got {}",
+                MAX_JS_RUNTIME_POOL_SIZE, self.js.runtime_pool_size
+            ));
+        }
+        if self.js.runtime_pool_size > 0 && self.js.runtime_checkout_timeout_ms == 0 {
+            return Err("js.runtime_checkout_timeout_ms must be greater than 0".to_string());
+        }
+        if self.wasm.default_memory_bytes == 0
+            || self.wasm.default_memory_bytes > self.wasm.max_memory_bytes
+        {
+            return Err(format!(
+                "wasm.default_memory_bytes must be between 1 and wasm.max_memory_bytes ({}), got {}",
+                self.wasm.max_memory_bytes, self.wasm.default_memory_bytes
+            ));
+        }
+        if self.wasm.max_tables == 0 || self.wasm.max_instances == 0 {
+            return Err(
+                "wasm.max_tables and wasm.max_instances must be greater than 0".to_string(),
+            );
+        }
+        if self.wasm.disk_cache_path.is_some() && self.wasm.disk_cache_max_bytes == 0 {
+            return Err(
+                "wasm.disk_cache_max_bytes must be greater than 0 when wasm.disk_cache_path is set"
+                    .to_string(),
+            );
+        }
+        if self.wasm.max_memory_bytes == 0
+            || self.execution.max_output_bytes == 0
+            || self.execution.max_stdout_bytes == 0
+            || self.execution.max_stderr_bytes == 0
+            || self.execution.max_log_bytes == 0
+            || self.execution.max_fetch_calls == 0
+            || self.execution.max_fetch_bytes == 0
+            || self.execution.max_log_entries == 0
+        {
+            return Err(
+                "wasm.max_memory_bytes, execution.max_output_bytes, max_stdout_bytes, max_stderr_bytes, max_log_bytes, max_fetch_calls, max_fetch_bytes and max_log_entries must be greater than 0"
+                    .to_string(),
+            );
+        }
+        if self.execution.max_concurrent == 0 {
+            return Err("execution.max_concurrent must be greater than 0".to_string());
+        }
+        for (index, key) in self.auth.keys.iter().enumerate() {
+            if key.name.is_empty() || key.key.is_empty() {
+                return Err(format!(
+                    "auth.keys[{}] needs a non-empty name and key",
+                    index
+                ));
+            }
+            if key.requests_per_minute == Some(0) || key.max_concurrent == Some(0) {
+                return Err(format!(
+                    "auth key {}: requests_per_minute and max_concurrent must be greater than 0",
+                    key.name
+                ));
+            }
+            let earlier = &self.auth.keys[..index];
+            if earlier.iter().any(|other| other.name == key.name) {
+                return Err(format!("auth key name {} is used more than once", key.name));
+            }
+            if earlier.iter().any(|other| other.key == key.key) {
+                return Err(format!(
+                    "auth key {} has the same secret as another key",
+                    key.name
+                ));
+            }
+        }
+        if let Some(path) = self
+            .auth
+            .public_paths
+            .iter()
+            .find(|path| !path.starts_with('/'))
+        {
+            return Err(format!(
+                "auth.public_paths must start with '/', got {:?}",
+                path
+            ));
+        }
+        let jobs = &self.jobs;
+        if jobs.max_concurrent == 0
+            || jobs.max_queued == 0
+            || jobs.capacity == 0
+            || jobs.ttl_secs == 0
+        {
+            return Err(
+                "jobs.max_concurrent, jobs.max_queued, jobs.capacity and jobs.ttl_secs must be greater than 0"
+                    .to_string(),
+            );
+        }
+        if self.download.max_bytes == 0 || self.download.timeout_secs == 0 {
+            return Err(
+                "download.max_bytes and download.timeout_secs must be greater than 0".to_string(),
+            );
+        }
+        let download = &self.download;
+        if download.allowed_schemes.is_empty() {
+            return Err("download.allowed_schemes cannot be empty".to_string());
+        }
+        if let Some(scheme) = download
+            .allowed_schemes
+            .iter()
+            .find(|scheme| !matches!(scheme.to_ascii_lowercase().as_str(), "http" | "https"))
+        {
+            return Err(format!(
+                "download.allowed_schemes can only list \"http\" and \"https\", got {:?}",
+                scheme
+            ));
+        }
+        if download.allow_hosts.iter().any(String::is_empty)
+            || download.deny_hosts.iter().any(String::is_empty)
+        {
+            return Err(
+                "download.allow_hosts and download.deny_hosts cannot list an empty pattern"
+                    .to_string(),
+            );
+        }
+        if self.warmup.max_attempts == 0 {
+            return Err("warmup.max_attempts must be greater than 0".to_string());
+        }
+        if self.audit.max_bytes == 0 || self.audit.max_files == 0 {
+            return Err("audit.max_bytes and audit.max_files must be greater than 0".to_string());
+        }
+        if self.storage.max_keys == 0 || self.storage.max_bytes == 0 {
+            return Err(
+                "storage.max_keys and storage.max_bytes must be greater than 0".to_string(),
+            );
+        }
+        if self.kv.max_namespaces == 0 || self.kv.max_keys == 0 || self.kv.max_bytes == 0 {
+            return Err(
+                "kv.max_namespaces, kv.max_keys and kv.max_bytes must be greater than 0"
+                    .to_string(),
+            );
+        }
+        if self.circuit_breaker.failure_threshold > 0
+            && (self.circuit_breaker.window_secs == 0
+                || self.circuit_breaker.open_secs == 0
+                || self.circuit_breaker.max_circuits == 0)
+        {
+            return Err(
+                "circuit_breaker.window_secs, open_secs and max_circuits must be greater than 0"
+                    .to_string(),
+            );
+        }
+        if !(0.0..=1.0).contains(&self.tracing.sampling_ratio) {
+            return Err(format!(
+                "tracing.sampling_ratio must be between 0 and 1, got {}",
+                self.tracing.sampling_ratio
+            ));
+        }
+        for webhook in self.reporters.iter().filter_map(|reporter| match reporter {
+            ReporterConfig::Webhook(webhook) => Some(webhook),
+            ReporterConfig::Log => None,
+        }) {
+            url::Url::parse(&webhook.url)
+                .map_err(|e| format!("Invalid webhook reporter url {:?}: {}", webhook.url, e))?;
+            if !(1..=MAX_WEBHOOK_ATTEMPTS).contains(&webhook.max_attempts) {
+                return Err(format!(
+                    "webhook reporter max_attempts must be between 1 and {}, got {}",
+                    MAX_WEBHOOK_ATTEMPTS, webhook.max_attempts
+                ));
+            }
+        }
+        // Validate default headers without echoing their (possibly secret) values
+        download::build_headers(&self.download, None)
+            .map_err(|e| format!("Invalid download.headers: {}", e))?;
+        for name in &self.download.forward_headers {
+            download::validate_header_name(name)
+                .map_err(|e| format!("Invalid download.forward_headers: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Read an override value as TOML, or as a string when it is not valid TOML
+fn parse_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+/// Set a dotted `section.field` key in a TOML table, creating sections as needed
+fn set_key(table: &mut toml::Table, key: &str, value: toml::Value) -> Result<(), String> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let field = parts.pop().filter(|field| !field.is_empty());
+    let Some(field) = field else {
+        return Err("the key is empty".to_string());
+    };
+    let mut section = table;
+    for part in parts {
+        section = section
+            .entry(part)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("{} is not a section", part))?;
+    }
+    section.insert(field.to_string(), value);
+    Ok(())
+}
+
+impl FetchConfig {
+    /// Build the HTTP client shared by all guest fetch calls
+    ///
+    /// # Returns
+    ///
+    /// * `reqwest::Result<reqwest::Client>` - The configured client
+    pub fn build_client(&self) -> reqwest::Result<reqwest::Client> {
+        // `net::fetch` follows redirects itself, checking every hop against the outbound policy.
+        // A proxy from HTTP_PROXY or HTTPS_PROXY would resolve names past the resolver
+        let mut builder = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .no_proxy()
+            .dns_resolver(Arc::new(OutboundPolicy::from_config(self).resolver()))
+            .pool_idle_timeout(Duration::from_secs(self.pool_idle_timeout))
+            .tcp_keepalive(self.tcp_keepalive.map(Duration::from_secs));
+
+        if self.pool_idle_timeout == 0 {
+            builder = builder.pool_max_idle_per_host(0);
+        }
+        if self.http1_only {
+            builder = builder.http1_only();
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+
+        builder.build()
+    }
+}
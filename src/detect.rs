@@ -0,0 +1,180 @@
+//! Code type detection.
+//!
+//! The code type is taken from, in order: the request's `code_type`, the
+//! extension of the URL's path, the response's `Content-Type`, and finally
+//! the payload itself. An explicit `code_type` is used as is. Otherwise an
+//! extension and a `Content-Type` that disagree, or JavaScript or text format
+//! signals for a payload starting with the WebAssembly magic bytes, are
+//! refused with `UNSUPPORTED_CODE_TYPE` rather than guessed between.
+//! JavaScript is run as an ES module when it comes from a `.mjs` URL, or
+//! when it opens `import` or `export` declarations and compiles only as a
+//! module.
+//!
+//! Probes never execute the payload: wasm is recognized by its magic bytes,
+//! the text format by an opening `(module`, and JavaScript by a compile-only
+//! syntax check.
+
+use crate::error::AppError;
+use crate::{js_engine, wat};
+use serde::{Deserialize, Serialize};
+
+/// Magic bytes at the start of every WebAssembly binary
+const WASM_MAGIC: &[u8] = b"\0asm";
+
+/// Type of code to be executed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeType {
+    /// JavaScript code (.js files)
+    JavaScript,
+    /// JavaScript ES modules (.mjs files)
+    Module,
+    /// WebAssembly code (.wasm files)
+    WebAssembly,
+    /// WebAssembly text format (.wat files), assembled before it runs
+    Wat,
+}
+
+impl CodeType {
+    /// Name of the code type, as accepted in `code_type`
+    pub fn name(self) -> &'static str {
+        match self {
+            CodeType::JavaScript => "javascript",
+            CodeType::Module => "module",
+            CodeType::WebAssembly => "webassembly",
+            CodeType::Wat => "wat",
+        }
+    }
+}
+
+/// Determine the code type (and whether the artifact is gzipped) from a URL's extension
+///
+/// Only the path counts, so a query string or fragment does not hide the extension.
+///
+/// # Arguments
+///
+/// * `url` - URL the code is downloaded from
+///
+/// # Returns
+///
+/// * `Option<(CodeType, bool)>` - Code type and gzip flag, or `None` for unrecognized extensions
+pub fn from_extension(url: &str) -> Option<(CodeType, bool)> {
+    let parsed = reqwest::Url::parse(url).ok();
+    let path = parsed.as_ref().map_or(url, |parsed| parsed.path());
+    if path.ends_with(".js") {
+        Some((CodeType::JavaScript, false))
+    } else if path.ends_with(".mjs") {
+        Some((CodeType::Module, false))
+    } else if path.ends_with(".wasm") {
+        Some((CodeType::WebAssembly, false))
+    } else if path.ends_with(".wasm.gz") {
+        Some((CodeType::WebAssembly, true))
+    } else if path.ends_with(".wat") {
+        Some((CodeType::Wat, false))
+    } else {
+        None
+    }
+}
+
+/// Determine the code type from a `Content-Type` header value
+///
+/// # Returns
+///
+/// * `Option<CodeType>` - The code type, or `None` for media types that say nothing about it
+pub fn from_content_type(content_type: &str) -> Option<CodeType> {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    if media_type.eq_ignore_ascii_case("application/wasm") {
+        Some(CodeType::WebAssembly)
+    } else if media_type.eq_ignore_ascii_case("text/javascript")
+        || media_type.eq_ignore_ascii_case("application/javascript")
+    {
+        Some(CodeType::JavaScript)
+    } else {
+        None
+    }
+}
+
+/// Decide the code type of a downloaded payload from every signal available
+///
+/// # Arguments
+///
+/// * `requested` - The request's `code_type`, used as is when set
+/// * `url` - URL the code was downloaded from
+/// * `content_type` - `Content-Type` of the download response
+/// * `code` - The payload, decompressed
+///
+/// # Returns
+///
+/// * `Result<CodeType, AppError>` - The code type, `UnsupportedCodeType` when
+///   the signals conflict, or `CodeTypeUndetected` when there are none and
+///   the payload probes as neither type
+pub fn decide(
+    requested: Option<CodeType>,
+    url: &str,
+    content_type: Option<&str>,
+    code: &[u8],
+) -> Result<CodeType, AppError> {
+    if let Some(code_type) = requested {
+        return Ok(code_type);
+    }
+    let extension = from_extension(url).map(|(code_type, _)| code_type);
+    let declared = content_type.and_then(from_content_type);
+    let wasm_magic = code.starts_with(WASM_MAGIC);
+    let conflict = AppError::UnsupportedCodeType {
+        extension: extension.map(CodeType::name),
+        content_type: content_type.map(str::to_string),
+        wasm_magic,
+    };
+    let code_type = match (extension, declared) {
+        // Modules are served with the JavaScript media types
+        (Some(CodeType::Module), Some(CodeType::JavaScript)) => CodeType::Module,
+        (Some(extension), Some(declared)) if extension != declared => return Err(conflict),
+        (Some(code_type), _) | (None, Some(code_type)) => code_type,
+        (None, None) => return probe(code),
+    };
+    // A binary labeled as a script or as text is mislabeled or not what the caller meant to run
+    if code_type != CodeType::WebAssembly && wasm_magic {
+        return Err(conflict);
+    }
+    if code_type == CodeType::JavaScript
+        && std::str::from_utf8(code).is_ok_and(js_engine::looks_like_module)
+    {
+        return Ok(CodeType::Module);
+    }
+    Ok(code_type)
+}
+
+/// Determine the code type of a payload by inspecting its contents
+///
+/// # Arguments
+///
+/// * `code` - The downloaded payload
+///
+/// # Returns
+///
+/// * `Result<CodeType, AppError>` - Detected code type, or `CodeTypeUndetected`
+///   carrying the reason each probe rejected the payload
+pub fn probe(code: &[u8]) -> Result<CodeType, AppError> {
+    if code.starts_with(WASM_MAGIC) {
+        return Ok(CodeType::WebAssembly);
+    }
+    if wat::looks_like_wat(code) {
+        return Ok(CodeType::Wat);
+    }
+    let wasm_probe =
+        "payload neither starts with the \\0asm magic bytes nor opens with (module".to_string();
+
+    let js_probe = match std::str::from_utf8(code) {
+        Ok(source) => match js_engine::check_syntax(source) {
+            Ok(()) => return Ok(CodeType::JavaScript),
+            Err(_) if js_engine::looks_like_module(source) => return Ok(CodeType::Module),
+            Err(e) => format!("payload is not valid JavaScript: {}", e),
+        },
+        Err(e) => format!("payload is not valid UTF-8: {}", e),
+    };
+
+    Err(AppError::CodeTypeUndetected {
+        wasm_probe,
+        js_probe,
+    })
+}
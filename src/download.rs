@@ -0,0 +1,690 @@
+//! Code download.
+//!
+//! Fetches the code to execute from the request URL. Callers may attach
+//! headers (e.g. `Authorization` for a private registry), limited to the
+//! names in `download.forward_headers`; they go with the download only,
+//! never with guest fetch calls, and their values are redacted wherever
+//! headers are logged. Redirects are followed manually so credentials are
+//! never forwarded to another origin.
+//!
+//! The body is streamed. When the connection breaks partway and the origin
+//! advertised `Accept-Ranges: bytes` together with a strong `ETag`, the
+//! download resumes from the last received byte with a `Range` request
+//! guarded by `If-Range`, so a changed artifact is sent whole instead of
+//! being stitched onto the old bytes. `download.max_bytes` and
+//! `download.timeout_secs` bound the download across all attempts; a body
+//! over the size limit fails with `CODE_TOO_LARGE` as soon as its
+//! `Content-Length` or the bytes received so far cross it.
+//!
+//! Code is only downloaded from where the `[download]` section allows: over
+//! `allowed_schemes` (HTTPS alone by default), from hosts matching
+//! `allow_hosts` when it lists any and matching no `deny_hosts` pattern, and
+//! not from loopback, link-local, private or unique local addresses unless
+//! `allow_private_addresses` is set. The URL is checked before it is
+//! requested and every redirect hop again; names are checked as the download
+//! client resolves them (see `outbound::PublicResolver`). Refused URLs fail
+//! with `CODE_URL_BLOCKED` without being requested.
+
+use crate::config::DownloadConfig;
+use crate::download_cache::{DownloadCache, DownloadCacheStatus, Freshness};
+use crate::error::AppError;
+use crate::outbound;
+use bytes::BytesMut;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, RANGE,
+};
+use reqwest::{Response, StatusCode, Url};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+
+/// Maximum number of redirects followed while downloading code
+const MAX_REDIRECTS: usize = 10;
+
+/// Headers that only make sense for a single connection and may not be set
+const BLOCKED_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "host",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Name fragments identifying headers whose values must never be logged or forwarded cross-origin
+const SECRET_HEADER_MARKERS: &[&str] = &["auth", "cookie", "key", "password", "secret", "token"];
+
+/// Whether a header's value should be treated as a credential
+pub fn is_secret_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_HEADER_MARKERS
+        .iter()
+        .any(|marker| name.contains(marker))
+}
+
+/// Check that a header name is well-formed and allowed on download requests
+///
+/// # Returns
+///
+/// * `Result<HeaderName, String>` - Parsed header name or a description of the problem
+pub fn validate_header_name(name: &str) -> Result<HeaderName, String> {
+    let header_name = HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| format!("Invalid download header name: {}", name))?;
+    if BLOCKED_HEADERS.contains(&header_name.as_str()) {
+        return Err(format!(
+            "Download header {} is a hop-by-hop header and cannot be set",
+            name
+        ));
+    }
+    Ok(header_name)
+}
+
+/// Whether a request's `download_headers` may set `name`, per `download.forward_headers`
+pub fn is_forwardable(config: &DownloadConfig, name: &str) -> bool {
+    config
+        .forward_headers
+        .iter()
+        .any(|forwardable| forwardable.eq_ignore_ascii_case(name))
+}
+
+/// Whether a download header's value is a credential: its name looks like
+/// one, or requests may forward it
+pub fn is_credential_header(config: &DownloadConfig, name: &str) -> bool {
+    is_secret_header(name) || is_forwardable(config, name)
+}
+
+/// Merge server default headers with request headers into a validated header map
+///
+/// Request headers override defaults with the same (case-insensitive) name,
+/// and may only set the names in `download.forward_headers`. Error messages
+/// name the offending header but never include its value.
+///
+/// # Arguments
+///
+/// * `config` - The `[download]` section, with the headers applied to every
+///   download and the names requests may forward
+/// * `overrides` - Headers supplied with the execute request
+///
+/// # Returns
+///
+/// * `Result<HeaderMap, AppError>` - Headers to send, or an `InvalidRequest` error
+pub fn build_headers(
+    config: &DownloadConfig,
+    overrides: Option<&HashMap<String, String>>,
+) -> Result<HeaderMap, AppError> {
+    if let Some(name) = overrides
+        .into_iter()
+        .flatten()
+        .map(|(name, _)| name)
+        .find(|name| !is_forwardable(config, name))
+    {
+        return Err(AppError::InvalidRequest(format!(
+            "Download header {} cannot be forwarded; this server forwards only {}",
+            name,
+            config.forward_headers.join(", ")
+        )));
+    }
+    let mut headers = HeaderMap::new();
+    for (name, value) in config.headers.iter().chain(overrides.into_iter().flatten()) {
+        let header_name = validate_header_name(name).map_err(AppError::InvalidRequest)?;
+        let header_value = HeaderValue::from_str(value).map_err(|_| {
+            AppError::InvalidRequest(format!("Invalid value for download header {}", name))
+        })?;
+        headers.insert(header_name, header_value);
+    }
+    Ok(headers)
+}
+
+/// Describe download headers for logging, with credential values redacted
+pub fn describe_headers(headers: &HeaderMap, config: &DownloadConfig) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            if is_credential_header(config, name.as_str()) {
+                format!("{}: <redacted>", name)
+            } else {
+                format!("{}: {}", name, value.to_str().unwrap_or("<binary>"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Read the `sha256` a request pins its code to
+///
+/// ```
+/// use hoya::download::parse_sha256;
+///
+/// let digest = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+/// assert_eq!(parse_sha256(digest).unwrap(), digest.to_ascii_lowercase());
+/// assert!(parse_sha256("e3b0").is_err());
+/// ```
+///
+/// # Returns
+///
+/// * `Result<String, AppError>` - The digest in lowercase, or `InvalidRequest`
+///   when it is not 64 hexadecimal digits
+pub fn parse_sha256(digest: &str) -> Result<String, AppError> {
+    match digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        true => Ok(digest.to_ascii_lowercase()),
+        false => Err(AppError::InvalidRequest(
+            "sha256 must be 64 hexadecimal digits".to_string(),
+        )),
+    }
+}
+
+/// Where code may be downloaded from, as the `[download]` section says
+#[derive(Debug, Clone)]
+pub struct CodeUrlPolicy {
+    /// Lowercase schemes allowed
+    schemes: Vec<String>,
+    /// Lowercase host patterns allowed; empty allows every host
+    allow_hosts: Vec<String>,
+    /// Lowercase host patterns refused
+    deny_hosts: Vec<String>,
+    /// Skip the address check
+    allow_private_addresses: bool,
+}
+
+impl CodeUrlPolicy {
+    /// Build the policy from the `[download]` section
+    ///
+    /// ```
+    /// use hoya::config::DownloadConfig;
+    /// use hoya::download::CodeUrlPolicy;
+    /// use reqwest::Url;
+    ///
+    /// let config = DownloadConfig {
+    ///     allow_hosts: vec!["*.example.com".to_string()],
+    ///     deny_hosts: vec!["scratch.example.com".to_string()],
+    ///     ..DownloadConfig::default()
+    /// };
+    /// let policy = CodeUrlPolicy::from_config(&config);
+    /// let check = |url: &str| policy.check_url(&Url::parse(url).unwrap()).is_ok();
+    /// assert!(check("https://cdn.example.com/app.wasm"));
+    /// assert!(!check("http://cdn.example.com/app.wasm"));
+    /// assert!(!check("https://example.com/app.wasm"));
+    /// assert!(!check("https://SCRATCH.example.com/app.wasm"));
+    /// ```
+    pub fn from_config(config: &DownloadConfig) -> Self {
+        let lowercase = |values: &[String]| {
+            values
+                .iter()
+                .map(|value| value.trim_end_matches('.').to_ascii_lowercase())
+                .collect()
+        };
+        CodeUrlPolicy {
+            schemes: lowercase(&config.allowed_schemes),
+            allow_hosts: lowercase(&config.allow_hosts),
+            deny_hosts: lowercase(&config.deny_hosts),
+            allow_private_addresses: config.allow_private_addresses,
+        }
+    }
+
+    /// Check the scheme and host of `url` against the patterns, without resolving the host
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AppError>` - `CodeUrlBlocked` if the URL is refused
+    pub fn check_url(&self, url: &Url) -> Result<(), AppError> {
+        let blocked = |host: Option<String>, reason: String| AppError::CodeUrlBlocked {
+            url: crate::record::redact_url(url.as_str()),
+            host,
+            reason,
+        };
+        let host = outbound::normalized_host(url);
+        if !self.schemes.iter().any(|scheme| scheme == url.scheme()) {
+            return Err(blocked(
+                host,
+                format!(
+                    "the {} scheme is not allowed, only {}",
+                    url.scheme(),
+                    self.schemes.join(" and ")
+                ),
+            ));
+        }
+        let Some(host) = host else {
+            return Err(blocked(None, "the URL has no host".to_string()));
+        };
+        if matches_any(&host, &self.deny_hosts) {
+            return Err(blocked(Some(host), "the host is denied".to_string()));
+        }
+        if !self.allow_hosts.is_empty() && !matches_any(&host, &self.allow_hosts) {
+            return Err(blocked(Some(host), "the host is not allowed".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Check whether `url` may be requested: `check_url`, then the host if it is an address
+    ///
+    /// Names are checked by the download client's resolver instead.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AppError>` - `CodeUrlBlocked` if the URL is refused
+    pub fn check(&self, url: &Url) -> Result<(), AppError> {
+        self.check_url(url)?;
+        if self.allow_private_addresses {
+            return Ok(());
+        }
+        match outbound::non_public_literal(url) {
+            Some(address) => Err(AppError::CodeUrlBlocked {
+                url: crate::record::redact_url(url.as_str()),
+                host: outbound::normalized_host(url),
+                reason: format!("the host is the non-public address {}", address),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether `host` matches one of `patterns`
+fn matches_any(host: &str, patterns: &[String]) -> bool {
+    patterns.iter().any(|pattern| glob_matches(pattern, host))
+}
+
+/// Whether `host` matches `pattern`, in which `*` stands for any run of characters
+fn glob_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let host = host.as_bytes();
+    let (mut p, mut h) = (0, 0);
+    // Where the last `*` was, and the host position it currently stands up to
+    let mut star: Option<(usize, usize)> = None;
+    while h < host.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, h));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == host[h] {
+            p += 1;
+            h += 1;
+        } else if let Some((star_p, star_h)) = star {
+            // Let the `*` take one more character
+            p = star_p + 1;
+            h = star_h + 1;
+            star = Some((star_p, star_h + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// One request of a download
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DownloadAttempt {
+    /// Offset of the first body byte received; above 0 for resumed attempts
+    pub offset: u64,
+    /// Body bytes received
+    pub bytes: u64,
+    /// HTTP status of the response (206 when resumed)
+    pub status: u16,
+    /// Why the body broke off; unset for the attempt that completed the download
+    pub error: Option<String>,
+}
+
+/// How an artifact was downloaded, reported in the response metadata
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DownloadReport {
+    /// Size of the downloaded artifact
+    pub bytes: u64,
+    /// Requests made for the body, in order; more than one when the download was resumed
+    pub attempts: Vec<DownloadAttempt>,
+}
+
+/// A downloaded artifact
+pub struct Downloaded {
+    /// The artifact's bytes, stitched together from all attempts
+    pub code: bytes::Bytes,
+    /// `Content-Type` the origin sent with the artifact
+    pub content_type: Option<String>,
+    /// How the artifact was downloaded
+    pub report: DownloadReport,
+    /// Whether the artifact came from the download cache
+    pub cache: DownloadCacheStatus,
+}
+
+/// Whether two URLs share scheme, host and port
+pub fn same_origin(a: &reqwest::Url, b: &reqwest::Url) -> bool {
+    a.scheme() == b.scheme()
+        && a.host_str() == b.host_str()
+        && a.port_or_known_default() == b.port_or_known_default()
+}
+
+/// The validator to resume a response's body with: its strong `ETag`, if the origin accepts byte ranges
+fn resume_validator(response: &Response) -> Option<HeaderValue> {
+    let headers = response.headers();
+    let ranges = headers
+        .get(ACCEPT_RANGES)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("bytes"));
+    // `If-Range` only works with strong validators
+    headers
+        .get(ETAG)
+        .filter(|etag| ranges && !etag.as_bytes().starts_with(b"W/"))
+        .cloned()
+}
+
+/// First byte and complete length of a `Content-Range: bytes first-last/length` header
+fn content_range(response: &Response) -> Option<(u64, Option<u64>)> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, length) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (first, _) = range.split_once('-')?;
+    Some((first.parse().ok()?, length.parse().ok()))
+}
+
+/// Download code from a URL, following redirects and resuming interrupted bodies
+///
+/// Fresh code in `cache` is used without a request, and stale code is
+/// revalidated with a conditional request; completed downloads are cached
+/// as their response allows.
+///
+/// The client must not follow redirects itself. Each hop is followed here so
+/// that it is checked against the `[download]` policy and credential headers
+/// are dropped as soon as a redirect leaves the original origin; resumed
+/// requests go straight to the final location.
+///
+/// # Arguments
+///
+/// * `client` - HTTP client configured with `redirect::Policy::none()`
+/// * `url` - URL of the code to download
+/// * `headers` - Headers to send with the request
+/// * `config` - Size limit, deadline, number of resumes and URL policy of the download
+/// * `cache` - Code downloaded before, by URL and headers
+///
+/// # Returns
+///
+/// * `Result<Downloaded, AppError>` - The downloaded bytes with the attempts
+///   made for them, or an error
+pub async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    headers: HeaderMap,
+    config: &DownloadConfig,
+    cache: &DownloadCache,
+) -> Result<Downloaded, AppError> {
+    let key = DownloadCache::key(url, &headers);
+    let cached = cache.get(&key);
+    let mut request_headers = headers;
+    match &cached {
+        Some(cached) if cached.conditions.is_empty() => {
+            return Ok(Downloaded {
+                code: cached.code.clone(),
+                content_type: cached.content_type.clone(),
+                report: DownloadReport {
+                    bytes: cached.code.len() as u64,
+                    attempts: Vec::new(),
+                },
+                cache: DownloadCacheStatus::Hit,
+            });
+        }
+        Some(cached) => request_headers.extend(cached.conditions.clone()),
+        None => {}
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(config.timeout_secs);
+    let timed_out = |_| {
+        AppError::Download(format!(
+            "Failed to download code: not finished within {} seconds",
+            config.timeout_secs
+        ))
+    };
+    let too_large = |observed_bytes| AppError::CodeTooLarge {
+        limit_bytes: config.max_bytes,
+        observed_bytes,
+    };
+
+    let (mut response, location, mut headers) = timeout_at(
+        deadline,
+        follow_redirects(client, url, request_headers, config),
+    )
+    .await
+    .map_err(timed_out)??;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        let Some(cached) = cached else {
+            return Err(AppError::Download(
+                "Failed to download code: HTTP status 304 Not Modified to an unconditional request"
+                    .to_string(),
+            ));
+        };
+        cache.revalidated(&key, response.headers());
+        return Ok(Downloaded {
+            report: DownloadReport {
+                bytes: cached.code.len() as u64,
+                attempts: vec![DownloadAttempt {
+                    offset: 0,
+                    bytes: 0,
+                    status: response.status().as_u16(),
+                    error: None,
+                }],
+            },
+            code: cached.code,
+            content_type: cached.content_type,
+            cache: DownloadCacheStatus::Revalidated,
+        });
+    }
+    // Resumed requests carry `If-Range` instead
+    headers.remove(IF_NONE_MATCH);
+    headers.remove(IF_MODIFIED_SINCE);
+    let mut freshness = Freshness::from_headers(response.headers());
+    let mut validator = resume_validator(&response);
+    let mut content_type = header_text(&response, CONTENT_TYPE);
+    let mut expected = response.content_length();
+    let mut body = BytesMut::new();
+    let mut attempts = Vec::new();
+
+    loop {
+        // Refuse a declared size over the limit before reading any of the body
+        if let Some(length) = expected.filter(|length| *length > config.max_bytes) {
+            return Err(too_large(length));
+        }
+        let offset = body.len() as u64;
+        let status = response.status().as_u16();
+        let error = loop {
+            match timeout_at(deadline, response.chunk())
+                .await
+                .map_err(timed_out)?
+            {
+                Ok(Some(chunk)) => {
+                    let received = (body.len() + chunk.len()) as u64;
+                    if received > config.max_bytes {
+                        return Err(too_large(received));
+                    }
+                    body.extend_from_slice(&chunk);
+                }
+                Ok(None) => match expected {
+                    Some(length) if (body.len() as u64) < length => {
+                        break Some(format!(
+                            "body ended after {} of {} bytes",
+                            body.len(),
+                            length
+                        ))
+                    }
+                    _ => break None,
+                },
+                Err(e) => break Some(e.to_string()),
+            }
+        };
+        attempts.push(DownloadAttempt {
+            offset,
+            bytes: body.len() as u64 - offset,
+            status,
+            error: error.clone(),
+        });
+        let Some(error) = error else {
+            break;
+        };
+
+        let Some(etag) = validator.clone() else {
+            return Err(AppError::Download(format!(
+                "Failed to download code: {} (the origin does not support resuming)",
+                error
+            )));
+        };
+        if attempts.len() > config.max_resumes as usize {
+            return Err(AppError::Download(format!(
+                "Failed to download code: {} after {} attempts",
+                error,
+                attempts.len()
+            )));
+        }
+        let resume = client
+            .get(location.clone())
+            .headers(headers.clone())
+            .header(RANGE, format!("bytes={}-", body.len()))
+            .header(IF_RANGE, etag)
+            .send();
+        response = timeout_at(deadline, resume)
+            .await
+            .map_err(timed_out)?
+            .map_err(AppError::Reqwest)?;
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let (first, length) = content_range(&response).ok_or_else(|| {
+                    AppError::Download(
+                        "Failed to download code: resumed response without a valid Content-Range"
+                            .to_string(),
+                    )
+                })?;
+                if first != body.len() as u64 {
+                    return Err(AppError::Download(format!(
+                        "Failed to download code: asked to resume at byte {}, got byte {}",
+                        body.len(),
+                        first
+                    )));
+                }
+                expected = length;
+            }
+            // The artifact changed since the first attempt; `If-Range` sent it whole
+            StatusCode::OK => {
+                body.clear();
+                freshness = Freshness::from_headers(response.headers());
+                validator = resume_validator(&response);
+                content_type = header_text(&response, CONTENT_TYPE);
+                expected = response.content_length();
+            }
+            other => {
+                return Err(AppError::Download(format!(
+                    "Failed to download code: HTTP status {} when resuming",
+                    other
+                )))
+            }
+        }
+    }
+
+    let code = body.freeze();
+    cache.insert(&key, &code, content_type.clone(), freshness);
+    Ok(Downloaded {
+        report: DownloadReport {
+            bytes: code.len() as u64,
+            attempts,
+        },
+        code,
+        content_type,
+        cache: DownloadCacheStatus::Miss,
+    })
+}
+
+/// A response header as text, if it is present and printable
+fn header_text(response: &Response, name: HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Request a URL, following redirects up to the first non-redirect response
+/// (or `304 Not Modified`)
+///
+/// Every hop is checked against the policy of `config` before it is requested.
+///
+/// # Returns
+///
+/// * `Result<(Response, Url, HeaderMap), AppError>` - The successful response,
+///   the URL it came from and the headers sent there (without credentials
+///   if a redirect left the original origin)
+async fn follow_redirects(
+    client: &reqwest::Client,
+    url: &str,
+    mut headers: HeaderMap,
+    config: &DownloadConfig,
+) -> Result<(Response, Url, HeaderMap), AppError> {
+    let origin = reqwest::Url::parse(url)
+        .map_err(|e| AppError::InvalidRequest(format!("Invalid code URL: {}", e)))?;
+    let policy = CodeUrlPolicy::from_config(config);
+    let mut current = origin.clone();
+
+    for _ in 0..=MAX_REDIRECTS {
+        policy.check(&current)?;
+        let response = client
+            .get(current.clone())
+            .headers(headers.clone())
+            .send()
+            .await
+            .map_err(|e| match outbound::refused_address(&e) {
+                // The client's resolver found only non-public addresses, so nothing was sent
+                Some(refused) => AppError::CodeUrlBlocked {
+                    url: crate::record::redact_url(current.as_str()),
+                    host: Some(refused.host.clone()),
+                    reason: format!(
+                        "the host resolves to the non-public address {}",
+                        refused.address
+                    ),
+                },
+                None => AppError::Reqwest(e),
+            })?;
+
+        // The answer to a conditional request for cached code
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok((response, current, headers));
+        }
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .ok_or_else(|| {
+                    AppError::Download(format!(
+                        "Failed to download code: HTTP status {} without a Location header",
+                        response.status()
+                    ))
+                })?;
+            let next = current.join(location).map_err(|e| {
+                AppError::Download(format!("Failed to download code: invalid redirect: {}", e))
+            })?;
+
+            if !same_origin(&origin, &next) {
+                let secret_names: Vec<HeaderName> = headers
+                    .keys()
+                    .filter(|name| is_credential_header(config, name.as_str()))
+                    .cloned()
+                    .collect();
+                for name in secret_names {
+                    headers.remove(name);
+                }
+            }
+            current = next;
+            continue;
+        }
+
+        if !response.status().is_success() {
+            return Err(AppError::Download(format!(
+                "Failed to download code: HTTP status {}",
+                response.status()
+            )));
+        }
+        return Ok((response, current, headers));
+    }
+
+    Err(AppError::Download(format!(
+        "Failed to download code: more than {} redirects",
+        MAX_REDIRECTS
+    )))
+}
@@ -0,0 +1,293 @@
+//! Cache of downloaded code.
+//!
+//! Downloads are kept by URL, together with the validators the origin sent
+//! (`ETag`, `Last-Modified`) and how long `Cache-Control: max-age` lets them
+//! be used as they are. A later download of the same URL with the same
+//! headers is answered from the cache while the entry is fresh; once it is
+//! stale, the origin is asked with `If-None-Match` and `If-Modified-Since`,
+//! and a `304 Not Modified` reuses the cached bytes. Responses marked
+//! `Cache-Control: no-store` are never kept, and neither are responses the
+//! origin gave no validator or lifetime for. The least recently used entry
+//! is evicted once the cache holds more than `download.cache_max_bytes`.
+//!
+//! Entries are keyed by the request headers as well as the URL, so code
+//! downloaded with one caller's credentials is never handed to a caller
+//! without them.
+
+use bytes::Bytes;
+use reqwest::header::{
+    HeaderMap, HeaderValue, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How the downloaded code was obtained
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadCacheStatus {
+    /// The cached code was fresh and used without asking the origin
+    Hit,
+    /// The origin answered `304 Not Modified` and the cached code was used
+    Revalidated,
+    /// The code was downloaded
+    Miss,
+}
+
+/// What a response says about caching it
+#[derive(Debug, Clone, Default)]
+pub struct Freshness {
+    /// The response's `ETag`
+    etag: Option<HeaderValue>,
+    /// The response's `Last-Modified`
+    last_modified: Option<HeaderValue>,
+    /// How long the response may be used without revalidation
+    max_age: Option<Duration>,
+    /// `Cache-Control: no-store`
+    no_store: bool,
+}
+
+impl Freshness {
+    /// Read the validators and `Cache-Control` directives of a response
+    ///
+    /// ```
+    /// use hoya::download_cache::Freshness;
+    /// use reqwest::header::{HeaderMap, HeaderValue, CACHE_CONTROL, ETAG};
+    ///
+    /// let mut headers = HeaderMap::new();
+    /// headers.insert(ETAG, HeaderValue::from_static("\"v1\""));
+    /// assert!(Freshness::from_headers(&headers).cacheable());
+    /// headers.insert(CACHE_CONTROL, HeaderValue::from_static("max-age=60, no-store"));
+    /// assert!(!Freshness::from_headers(&headers).cacheable());
+    /// ```
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut freshness = Freshness {
+            etag: headers.get(ETAG).cloned(),
+            last_modified: headers.get(LAST_MODIFIED).cloned(),
+            ..Freshness::default()
+        };
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|directive| directive.trim().to_ascii_lowercase());
+        for directive in directives {
+            match directive.split_once('=') {
+                Some(("max-age", seconds)) => {
+                    freshness.max_age = seconds
+                        .trim_matches('"')
+                        .parse()
+                        .ok()
+                        .map(Duration::from_secs);
+                }
+                // Stored responses must be revalidated every time
+                None if directive == "no-cache" => freshness.max_age = Some(Duration::ZERO),
+                None if directive == "no-store" => freshness.no_store = true,
+                _ => {}
+            }
+        }
+        freshness
+    }
+
+    /// Whether a response with these directives is worth keeping
+    pub fn cacheable(&self) -> bool {
+        !self.no_store
+            && (self.etag.is_some()
+                || self.last_modified.is_some()
+                || self.max_age.is_some_and(|max_age| !max_age.is_zero()))
+    }
+
+    /// Conditional request headers revalidating the response
+    fn conditions(&self) -> HeaderMap {
+        let mut conditions = HeaderMap::new();
+        if let Some(etag) = &self.etag {
+            conditions.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = &self.last_modified {
+            conditions.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+        conditions
+    }
+}
+
+/// Code found in the cache
+pub struct CachedCode {
+    /// The code, as downloaded
+    pub code: Bytes,
+    /// `Content-Type` the origin sent with the code
+    pub content_type: Option<String>,
+    /// Headers asking the origin whether the code changed; empty when the
+    /// entry is fresh and may be used as it is
+    pub conditions: HeaderMap,
+}
+
+/// A cached download
+struct Entry {
+    /// URL and request headers the code was downloaded with
+    key: String,
+    /// The code, as downloaded
+    code: Bytes,
+    /// `Content-Type` the origin sent with the code
+    content_type: Option<String>,
+    /// Validators and lifetime of the code
+    freshness: Freshness,
+    /// Until when the code may be used without revalidation
+    fresh_until: Option<Instant>,
+}
+
+impl Entry {
+    /// Recompute when the entry goes stale, from its directives as of `now`
+    fn refresh(&mut self, now: Instant) {
+        self.fresh_until = self
+            .freshness
+            .max_age
+            .filter(|max_age| !max_age.is_zero())
+            .and_then(|max_age| now.checked_add(max_age));
+    }
+}
+
+/// Bounded least-recently-used cache of downloaded code
+pub struct DownloadCache {
+    /// Most code kept, in bytes; 0 disables caching
+    max_bytes: u64,
+    /// Entries, least recently used first
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+impl DownloadCache {
+    /// Create an empty cache keeping at most `max_bytes` of code
+    pub fn new(max_bytes: u64) -> Self {
+        DownloadCache {
+            max_bytes,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Cache key of a download of `url` with `headers`
+    ///
+    /// Header values are hashed, so the key holds no credentials.
+    pub fn key(url: &str, headers: &HeaderMap) -> String {
+        let mut names: Vec<&str> = headers.keys().map(|name| name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        let mut hasher = Sha256::new();
+        for name in names {
+            for value in headers.get_all(name) {
+                hasher.update(name.as_bytes());
+                hasher.update(b":");
+                hasher.update(value.as_bytes());
+                hasher.update(b"\n");
+            }
+        }
+        format!("{:x} {}", hasher.finalize(), url)
+    }
+
+    /// Look up a download and mark it as most recently used
+    ///
+    /// # Returns
+    ///
+    /// * `Option<CachedCode>` - The cached code with the headers to revalidate
+    ///   it with, or `None` if nothing usable is cached under `key`
+    pub fn get(&self, key: &str) -> Option<CachedCode> {
+        let mut entries = self.entries.lock().ok()?;
+        let index = entries.iter().position(|entry| entry.key == key)?;
+        let entry = entries.remove(index)?;
+        let fresh = entry
+            .fresh_until
+            .is_some_and(|fresh_until| Instant::now() < fresh_until);
+        let conditions = match fresh {
+            true => HeaderMap::new(),
+            false => entry.freshness.conditions(),
+        };
+        // A stale entry without validators cannot be revalidated
+        if !fresh && conditions.is_empty() {
+            return None;
+        }
+        let cached = CachedCode {
+            code: entry.code.clone(),
+            content_type: entry.content_type.clone(),
+            conditions,
+        };
+        entries.push_back(entry);
+        Some(cached)
+    }
+
+    /// Keep a completed download, evicting least recently used entries until it fits
+    ///
+    /// Responses that are not cacheable, or larger than the whole cache,
+    /// are not kept, and drop what was cached under `key` before.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The download's key, from `DownloadCache::key`
+    /// * `code` - The downloaded code
+    /// * `content_type` - `Content-Type` the origin sent with the code
+    /// * `freshness` - Validators and directives of the response
+    pub fn insert(
+        &self,
+        key: &str,
+        code: &Bytes,
+        content_type: Option<String>,
+        freshness: Freshness,
+    ) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        entries.retain(|entry| entry.key != key);
+        let size = code.len() as u64;
+        if !freshness.cacheable() || size > self.max_bytes {
+            return;
+        }
+        let mut bytes: u64 = entries.iter().map(|entry| entry.code.len() as u64).sum();
+        while bytes + size > self.max_bytes {
+            match entries.pop_front() {
+                Some(evicted) => bytes -= evicted.code.len() as u64,
+                None => break,
+            }
+        }
+        let mut entry = Entry {
+            key: key.to_string(),
+            code: code.clone(),
+            content_type,
+            freshness,
+            fresh_until: None,
+        };
+        entry.refresh(Instant::now());
+        entries.push_back(entry);
+    }
+
+    /// Record that the origin confirmed the entry under `key` is unchanged
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The download's key, from `DownloadCache::key`
+    /// * `headers` - Headers of the `304 Not Modified` response, whose
+    ///   validators and directives replace the cached ones
+    pub fn revalidated(&self, key: &str, headers: &HeaderMap) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        let Some(index) = entries.iter().position(|entry| entry.key == key) else {
+            return;
+        };
+        let update = Freshness::from_headers(headers);
+        if update.no_store {
+            entries.remove(index);
+            return;
+        }
+        let entry = &mut entries[index];
+        if update.etag.is_some() {
+            entry.freshness.etag = update.etag;
+        }
+        if update.last_modified.is_some() {
+            entry.freshness.last_modified = update.last_modified;
+        }
+        if update.max_age.is_some() {
+            entry.freshness.max_age = update.max_age;
+        }
+        entry.refresh(Instant::now());
+    }
+}
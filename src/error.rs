@@ -4,16 +4,31 @@
 //! It includes error handling for JavaScript execution, WebAssembly execution,
 //! HTTP requests, and general application errors.
 
+use crate::circuit::FailureSummary;
+use crate::download::DownloadReport;
+use crate::download_cache::DownloadCacheStatus;
+use crate::executor::EffectiveOptions;
+use crate::limits::OptionViolation;
+use crate::manifest::AppliedManifest;
+use crate::net::NetworkStats;
+use crate::output::{self, Channel, LogEntry, OutputUsage};
+use crate::record::ExecutionRecord;
+use crate::wasm_engine::{
+    CacheStatus, GuestAbort, MissingImport, OutputEncoding, StubbedImportCalled, TrapFrame,
+    TrapKind,
+};
+use crate::wat::SourceFormat;
 use anyhow::Error as AnyhowError;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 use std::collections::HashMap;
+use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 // Define these types directly in this module
 /// Error information returned to API clients
-#[derive(serde::Serialize, Debug)]
+#[derive(serde::Serialize, Debug, Clone)]
 pub struct ErrorInfo {
     /// Error code identifier
     pub code: String,
@@ -23,17 +38,254 @@ pub struct ErrorInfo {
     pub details: Option<HashMap<String, serde_json::Value>>,
 }
 
+/// A failed execution, as handed to reporters
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct AppErrorContext {
+    /// Identifier of the execution
+    pub execution_id: String,
+    /// URL the code was requested from, without credentials, query or fragment
+    pub source_url: String,
+    /// Hex SHA-256 digest of the code, if it was downloaded
+    pub code_sha256: Option<String>,
+    /// HTTP status code of the response
+    pub http_status: u16,
+    /// The error as returned to the caller
+    pub error: ErrorInfo,
+}
+
+impl AppErrorContext {
+    /// Describe the failure of an execution, if it failed
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - Summary of the execution
+    /// * `response` - The response body sent to the caller
+    pub fn from_response(record: &ExecutionRecord, response: &ExecuteResponse) -> Option<Self> {
+        response.error.as_ref().map(|error| AppErrorContext {
+            execution_id: record.request_id.clone(),
+            source_url: record.source_url.clone(),
+            code_sha256: record.code_sha256.clone(),
+            http_status: record.http_status,
+            error: error.clone(),
+        })
+    }
+}
+
 /// Metadata about code execution
 #[derive(serde::Serialize, Debug)]
 pub struct ExecutionMetadata {
-    /// Execution time in milliseconds
+    /// Execution time in milliseconds, from the start of the download to the end of the run
     pub execution_time: u64,
+    /// Time spent downloading the code, in milliseconds
+    pub download_ms: Option<u64>,
+    /// Time spent compiling the WebAssembly module or script, in milliseconds
+    pub compile_ms: Option<u64>,
+    /// Time spent instantiating the module or setting up the script's context, in milliseconds
+    pub instantiate_ms: Option<u64>,
+    /// Time the guest code ran, in milliseconds
+    pub run_ms: Option<u64>,
     /// Type of code executed ("javascript" or "webassembly")
     pub code_type: String,
+    /// Format the WebAssembly module was written in, when it was not the binary format
+    pub source_format: Option<SourceFormat>,
     /// ISO timestamp of execution
     pub timestamp: String,
     /// Size of the executed code in bytes
     pub resource_size: usize,
+    /// Size of the downloaded artifact in bytes, if it was compressed
+    pub compressed_size: Option<usize>,
+    /// Attempts made to download the code, including resumed ones
+    pub download: Option<DownloadReport>,
+    /// Whether the code came from the download cache
+    pub download_cache: Option<DownloadCacheStatus>,
+    /// Hex SHA-256 digest of the executed code (after decompression)
+    pub code_sha256: Option<String>,
+    /// Identifier of the execution, used to look it up in the history
+    pub execution_id: Option<String>,
+    /// Name of the API key the request was made with, never the key itself
+    pub api_key: Option<String>,
+    /// Name of the registered module that ran, for `module` requests
+    pub registered_module: Option<String>,
+    /// Hex id of the execution's trace, when trace export is enabled
+    pub trace_id: Option<String>,
+    /// Whether the compiled WebAssembly module came from the module cache
+    pub module_cache: Option<CacheStatus>,
+    /// Fuel the WebAssembly module consumed
+    pub fuel_consumed: Option<u64>,
+    /// Largest size the WebAssembly module's memory reached, in 64 KiB pages
+    pub peak_memory_pages: Option<u64>,
+    /// Heap the script's runtime held when it finished, in bytes
+    pub memory_used_bytes: Option<u64>,
+    /// How the result a WebAssembly module set with `set_output` is encoded in `output`
+    pub output_encoding: Option<OutputEncoding>,
+    /// JavaScript console/capture calls dropped after `js.max_console_calls` was reached
+    pub console_calls_dropped: Option<u64>,
+    /// Summary of guest fetch calls made during execution
+    pub network: Option<NetworkStats>,
+    /// Output budget usage, including output dropped once it was exhausted
+    pub output: Option<OutputUsage>,
+    /// Limits applied from the WebAssembly module's manifest, if it has one
+    pub manifest: Option<AppliedManifest>,
+    /// Seed of the guest's random stream, in deterministic mode
+    pub seed: Option<u64>,
+    /// Limits and options the execution ran with, after the server's caps and any manifest
+    pub effective_options: Option<EffectiveOptions>,
+}
+
+impl ExecutionMetadata {
+    /// Metadata timestamped now, with every optional field unset
+    ///
+    /// # Arguments
+    ///
+    /// * `code_type` - Type of code executed, or "unknown" before it is known
+    /// * `resource_size` - Size of the executed code in bytes
+    /// * `execution_time` - Execution time in milliseconds
+    pub fn new(code_type: &str, resource_size: usize, execution_time: u64) -> Self {
+        // Generate current timestamp in ISO format
+        let now = SystemTime::now();
+        let timestamp = match now.duration_since(UNIX_EPOCH) {
+            Ok(duration) => {
+                let datetime = chrono::DateTime::<chrono::Utc>::from_timestamp(
+                    duration.as_secs() as i64,
+                    duration.subsec_nanos(),
+                )
+                .unwrap_or_else(chrono::Utc::now);
+                datetime.to_rfc3339()
+            }
+            Err(_) => chrono::Utc::now().to_rfc3339(),
+        };
+
+        ExecutionMetadata {
+            execution_time,
+            download_ms: None,
+            compile_ms: None,
+            instantiate_ms: None,
+            run_ms: None,
+            code_type: code_type.to_string(),
+            source_format: None,
+            timestamp,
+            resource_size,
+            compressed_size: None,
+            download: None,
+            download_cache: None,
+            code_sha256: None,
+            execution_id: None,
+            api_key: None,
+            registered_module: None,
+            trace_id: None,
+            module_cache: None,
+            fuel_consumed: None,
+            peak_memory_pages: None,
+            memory_used_bytes: None,
+            output_encoding: None,
+            console_calls_dropped: None,
+            network: None,
+            output: None,
+            manifest: None,
+            seed: None,
+            effective_options: None,
+        }
+    }
+
+    /// Record the time the engine spent in each phase
+    pub fn set_phases(&mut self, phases: EnginePhases) {
+        self.compile_ms = phases.compile_ms;
+        self.instantiate_ms = phases.instantiate_ms;
+        self.run_ms = phases.run_ms;
+    }
+
+    /// Whether output of `channel` was dropped, by its own cap or the shared budget
+    pub fn truncated(&self, channel: Channel) -> bool {
+        self.output
+            .as_ref()
+            .is_some_and(|usage| usage.dropped.contains_key(channel.name()))
+    }
+}
+
+/// Time an engine spent in each phase of an execution, in milliseconds
+///
+/// Phases the execution did not reach are unset.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnginePhases {
+    /// Compiling the module or script
+    pub compile_ms: Option<u64>,
+    /// Instantiating the module or setting up the script's context
+    pub instantiate_ms: Option<u64>,
+    /// Running the guest code
+    pub run_ms: Option<u64>,
+}
+
+/// What an execution produced before it failed
+#[derive(Debug)]
+pub struct ExecutionArtifacts {
+    /// Standard output captured before the failure
+    pub stdout: String,
+    /// Standard error captured before the failure
+    pub stderr: String,
+    /// Console calls and `app_log` messages captured before the failure
+    pub logs: Option<Vec<LogEntry>>,
+    /// Metadata of the execution up to the failure
+    pub metadata: ExecutionMetadata,
+}
+
+impl ExecutionArtifacts {
+    /// Snapshot the output an execution has captured so far
+    ///
+    /// # Arguments
+    ///
+    /// * `stdout` - The execution's stdout buffer
+    /// * `stderr` - The execution's stderr buffer
+    /// * `metadata` - Metadata of the execution up to the failure
+    pub fn capture(
+        stdout: &Mutex<String>,
+        stderr: &Mutex<String>,
+        metadata: ExecutionMetadata,
+    ) -> Self {
+        ExecutionArtifacts {
+            stdout: output::lock(stdout).clone(),
+            stderr: output::lock(stderr).clone(),
+            logs: None,
+            metadata,
+        }
+    }
+}
+
+/// What a successful execution produced, as returned by the engines
+///
+/// The execute endpoint wraps it in an `ExecuteResponse`; code embedding the
+/// engines can read it directly.
+#[derive(serde::Serialize, Debug)]
+pub struct ExecutionResult {
+    /// The script's result, or a description of what the module ran
+    pub output: String,
+    /// The JavaScript result as JSON (if not `undefined`)
+    pub output_json: Option<serde_json::Value>,
+    /// Standard output content captured during execution
+    pub stdout: String,
+    /// Standard error content captured during execution
+    pub stderr: String,
+    /// Console calls and `app_log` messages with their severity, in order
+    pub logs: Vec<LogEntry>,
+    /// Metadata about the execution
+    pub metadata: ExecutionMetadata,
+}
+
+impl From<ExecutionResult> for ExecuteResponse {
+    fn from(result: ExecutionResult) -> Self {
+        ExecuteResponse {
+            status: "success".to_string(),
+            output: Some(result.output),
+            output_json: result.output_json,
+            stdout: Some(result.stdout),
+            stderr: Some(result.stderr),
+            logs: Some(result.logs),
+            stdout_truncated: result.metadata.truncated(Channel::Stdout),
+            stderr_truncated: result.metadata.truncated(Channel::Stderr),
+            logs_truncated: result.metadata.truncated(Channel::Log),
+            error: None,
+            metadata: result.metadata,
+        }
+    }
 }
 
 /// Response for the execute endpoint
@@ -43,16 +295,41 @@ pub struct ExecuteResponse {
     pub status: String,
     /// Output from code execution (if successful)
     pub output: Option<String>,
+    /// The JavaScript result as JSON (if successful and not `undefined`)
+    pub output_json: Option<serde_json::Value>,
     /// Standard output content captured during execution
     pub stdout: Option<String>,
     /// Standard error content captured during execution
     pub stderr: Option<String>,
+    /// Console calls and `app_log` messages with their severity, in order
+    pub logs: Option<Vec<LogEntry>>,
+    /// Whether stdout lost output to `max_stdout_bytes` or the shared output budget
+    pub stdout_truncated: bool,
+    /// Whether stderr lost output to `max_stderr_bytes` or the shared output budget
+    pub stderr_truncated: bool,
+    /// Whether `app_log` messages were dropped by `max_log_bytes` or the shared output budget
+    pub logs_truncated: bool,
     /// Error information (if execution failed)
     pub error: Option<ErrorInfo>,
     /// Metadata about the execution
     pub metadata: ExecutionMetadata,
 }
 
+/// What is known about a value thrown by JavaScript besides its message
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JsErrorDetails {
+    /// The error's `name`, e.g. "TypeError"; `None` for thrown values that are not errors
+    pub name: Option<String>,
+    /// The error's stack trace, if it has one
+    pub stack: Option<String>,
+    /// One-based line the error was thrown at
+    pub line: Option<u32>,
+    /// One-based column the error was thrown at
+    pub column: Option<u32>,
+    /// A thrown value that is not an error, serialized as JSON
+    pub thrown: Option<serde_json::Value>,
+}
+
 /// Application error types
 ///
 /// This enum represents the different kinds of errors that can occur
@@ -61,12 +338,224 @@ pub struct ExecuteResponse {
 pub enum AppError {
     /// QuickJS JavaScript engine errors
     QuickJs(rquickjs::Error),
+    /// The JavaScript being executed does not compile
+    JsSyntaxError {
+        /// The syntax error's message
+        message: String,
+        /// The syntax error's stack trace, if it has one
+        stack: Option<String>,
+        /// File name the script was compiled under: its URL, or `inline:<digest prefix>`
+        source: String,
+    },
+    /// An ES module imports another module by a specifier other than a data URL
+    ModuleResolutionDisabled {
+        /// The specifier of the refused import
+        specifier: String,
+        /// File name of the importing module: its URL, or `inline:<digest prefix>`
+        source: String,
+    },
+    /// An error escaped the JavaScript being executed
+    JsException {
+        /// The thrown error's message
+        message: String,
+        /// The thrown error's name, stack and location, or the thrown value
+        details: JsErrorDetails,
+        /// File name the script ran under: its URL, or `inline:<digest prefix>`
+        source: String,
+    },
     /// Wasmtime WebAssembly engine errors
     Wasmtime(AnyhowError),
+    /// The WebAssembly guest trapped
+    WasmTrap {
+        /// Kind of the trap, which picks the error code
+        kind: TrapKind,
+        /// Description of the trap, e.g. "wasm `unreachable` instruction executed"
+        trap: String,
+        /// The guest's stack when it trapped, innermost frame first
+        backtrace: Vec<TrapFrame>,
+    },
+    /// The WebAssembly binary does not compile, or cannot be linked against the host imports
+    InvalidModule(String),
+    /// The WebAssembly module imports what the host does not provide
+    MissingImports {
+        /// Every unsatisfied import, in the module's order
+        imports: Vec<MissingImport>,
+        /// The `env` functions the host does provide, as `env::name`
+        provided: Vec<String>,
+    },
+    /// The WebAssembly guest called a placeholder of `stub_missing_imports`
+    StubbedImportCalled(StubbedImportCalled),
+    /// The WebAssembly module exports an entrypoint, such as `_start`, of the wrong type
+    InvalidEntrypoint {
+        /// Name of the export
+        export: String,
+        /// Its actual type, e.g. `func(i32) -> i32`
+        signature: String,
+        /// The type its name calls for
+        expected: String,
+    },
+    /// The WebAssembly module exports no memory host functions can use
+    NoUsableMemory {
+        /// Names of the memories it exports, in export order
+        memory_exports: Vec<String>,
+    },
+    /// The WebAssembly text format module does not assemble
+    WatSyntaxError {
+        /// The parser's message, without the location
+        message: String,
+        /// One-based line of the error
+        line: usize,
+        /// One-based column of the error, in characters
+        column: usize,
+    },
     /// HTTP request errors
     Reqwest(reqwest::Error),
+    /// The code could not be downloaded from its URL
+    Download(String),
+    /// The code is larger than `download.max_bytes`
+    CodeTooLarge {
+        /// The download size limit
+        limit_bytes: u64,
+        /// The size the origin declared, or the bytes received when the limit was crossed
+        observed_bytes: u64,
+    },
+    /// The code URL, or a redirect followed while downloading it, is refused by the `[download]` policy
+    CodeUrlBlocked {
+        /// The refused URL, without credentials, query or fragment
+        url: String,
+        /// The URL's host, if it has one
+        host: Option<String>,
+        /// Why the URL is refused
+        reason: String,
+    },
+    /// The downloaded code does not hash to the request's `sha256`
+    ChecksumMismatch {
+        /// The digest the request pinned
+        expected: String,
+        /// The digest of the downloaded code
+        actual: String,
+    },
+    /// Invalid request parameters
+    InvalidRequest(String),
+    /// Requested limits that are 0 or above the server's caps, one per field
+    InvalidOptions(Vec<OptionViolation>),
+    /// The guest ran past its execution deadline
+    Timeout {
+        /// The timeout that was exceeded, in milliseconds
+        timeout_ms: u64,
+    },
+    /// The execution was cancelled through `POST /executions/{id}/cancel`
+    Cancelled,
+    /// The WebAssembly guest called WASI `proc_exit` with a nonzero code
+    WasiExit {
+        /// The code passed to `proc_exit`
+        exit_code: i32,
+    },
+    /// The WebAssembly guest called `env.abort`, as AssemblyScript modules do
+    GuestAbort(GuestAbort),
+    /// The WebAssembly guest consumed all the fuel it was given
+    FuelExhausted {
+        /// The fuel budget, `limits.max_fuel`
+        max_fuel: u64,
+    },
+    /// A WebAssembly memory was about to grow past `limits.memory_bytes`, or
+    /// a script ran out of the heap it was given
+    MemoryLimitExceeded {
+        /// The memory limit, in bytes
+        limit_bytes: u64,
+        /// The size the memory would have grown to, in bytes (WebAssembly only)
+        requested_bytes: Option<u64>,
+    },
+    /// The guest exhausted its output budget with strict output enabled
+    OutputBudgetExceeded {
+        /// The output budget, in bytes
+        limit_bytes: usize,
+    },
+    /// The requested resource does not exist
+    NotFound(String),
+    /// The execution to cancel has already finished
+    ExecutionFinished(String),
+    /// The code of an execution being replayed can no longer be obtained unchanged
+    ReplaySourceUnavailable(String),
+    /// Errors while decompressing a downloaded artifact
+    Decompression(String),
+    /// The request asked for a response version this server does not know
+    UnsupportedResponseVersion(String),
+    /// The source failed repeatedly and its circuit is open
+    CircuitOpen {
+        /// The source URL, without credentials, query or fragment
+        url: String,
+        /// Seconds until the next request for the source is let through
+        retry_after_secs: u64,
+        /// The failures that opened the circuit, oldest first
+        recent_failures: Vec<FailureSummary>,
+    },
+    /// `jobs.max_queued` jobs are queued or running already
+    JobQueueFull {
+        /// The queue limit
+        max_queued: usize,
+    },
+    /// Registering the module would take the registry past one of its limits
+    ModuleRegistryFull {
+        /// The limit: "max_modules" or "max_bytes"
+        limit: &'static str,
+        /// Value of the limit
+        maximum: u64,
+    },
+    /// The request carries no API key, or one the server does not know
+    Unauthorized(String),
+    /// The request's API key used up one of its quotas
+    QuotaExceeded {
+        /// Name of the key
+        key: String,
+        /// The quota used up: "requests_per_minute" or "max_concurrent"
+        quota: &'static str,
+        /// The key's limit
+        limit: u64,
+        /// Seconds until the quota allows another request, for per-minute quotas
+        retry_after_secs: Option<u64>,
+    },
+    /// Every execution slot stayed busy for `execution.queue_timeout_ms`
+    ServerBusy {
+        /// Executions allowed to run at once
+        max_concurrent: usize,
+        /// Milliseconds the request waited for a slot
+        waited_ms: u64,
+    },
+    /// Every pooled JavaScript runtime stayed busy for `js.runtime_checkout_timeout_ms`
+    RuntimePoolExhausted {
+        /// Runtimes in the pool
+        pool_size: usize,
+        /// Milliseconds the execution waited for a runtime
+        waited_ms: u64,
+    },
+    /// The WebAssembly module's `hoya.manifest` section is malformed
+    InvalidManifest(String),
+    /// The URL extension, `Content-Type` and payload disagree about the code type
+    UnsupportedCodeType {
+        /// Code type named by the URL extension
+        extension: Option<&'static str>,
+        /// `Content-Type` of the download response
+        content_type: Option<String>,
+        /// Whether the payload starts with the WebAssembly magic bytes
+        wasm_magic: bool,
+    },
+    /// Content probing could not determine the code type
+    CodeTypeUndetected {
+        /// Why the payload was rejected as WebAssembly
+        wasm_probe: String,
+        /// Why the payload was rejected as JavaScript
+        js_probe: String,
+    },
     /// Internal application errors
     Internal(String),
+    /// An engine failed after the execution started; the response carries what it produced
+    FailedExecution {
+        /// Why the execution failed
+        error: Box<AppError>,
+        /// Output and metadata of the execution up to the failure
+        partial: ExecutionArtifacts,
+    },
 }
 
 impl From<rquickjs::Error> for AppError {
@@ -99,9 +588,278 @@ impl From<&str> for AppError {
     }
 }
 
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::QuickJs(e) => write!(f, "JavaScript Execution Error: {}", e),
+            AppError::JsException { message, .. } => {
+                write!(f, "JavaScript Execution Error: {}", message)
+            }
+            AppError::JsSyntaxError { message, .. } => {
+                write!(f, "JavaScript Syntax Error: {}", message)
+            }
+            AppError::ModuleResolutionDisabled { specifier, source } => write!(
+                f,
+                "Module imports are disabled: {} cannot import {}",
+                source, specifier
+            ),
+            AppError::Wasmtime(e) => write!(f, "WebAssembly Execution Error: {}", e),
+            AppError::WasmTrap { trap, .. } => write!(f, "WebAssembly module trapped: {}", trap),
+            AppError::InvalidModule(s) => write!(f, "Invalid WebAssembly module: {}", s),
+            AppError::MissingImports { imports, .. } => {
+                write!(f, "{}", describe_missing_imports(imports))
+            }
+            AppError::StubbedImportCalled(called) => {
+                write!(f, "WebAssembly module {}", called)
+            }
+            AppError::InvalidEntrypoint {
+                export,
+                signature,
+                expected,
+            } => write!(
+                f,
+                "Invalid WebAssembly module: {} is {}, expected {}",
+                export, signature, expected
+            ),
+            AppError::NoUsableMemory { memory_exports } => write!(
+                f,
+                "Invalid WebAssembly module: {}",
+                describe_memory_exports(memory_exports)
+            ),
+            AppError::WatSyntaxError {
+                message,
+                line,
+                column,
+            } => write!(
+                f,
+                "WebAssembly Text Syntax Error at line {}, column {}: {}",
+                line, column, message
+            ),
+            AppError::Reqwest(e) => write!(f, "Failed to fetch resource: {}", e),
+            AppError::InvalidRequest(s)
+            | AppError::Download(s)
+            | AppError::Decompression(s)
+            | AppError::Internal(s) => write!(f, "{}", s),
+            AppError::InvalidOptions(violations) => {
+                write!(f, "{}", describe_violations(violations))
+            }
+            AppError::Timeout { timeout_ms, .. } => {
+                write!(f, "Execution exceeded the timeout of {} ms", timeout_ms)
+            }
+            AppError::WasiExit { exit_code, .. } => {
+                write!(f, "WebAssembly module exited with code {}", exit_code)
+            }
+            AppError::GuestAbort(abort) => write!(f, "WebAssembly module aborted: {}", abort),
+            AppError::FuelExhausted { max_fuel } => {
+                write!(f, "Execution ran out of fuel after {} units", max_fuel)
+            }
+            AppError::MemoryLimitExceeded {
+                limit_bytes,
+                requested_bytes: Some(requested_bytes),
+            } => write!(
+                f,
+                "Memory growth to {} bytes exceeds the limit of {} bytes",
+                requested_bytes, limit_bytes
+            ),
+            AppError::MemoryLimitExceeded {
+                limit_bytes,
+                requested_bytes: None,
+            } => write!(
+                f,
+                "The script ran out of memory, its heap is limited to {} bytes",
+                limit_bytes
+            ),
+            AppError::NotFound(s) | AppError::ExecutionFinished(s) => write!(f, "{}", s),
+            AppError::Cancelled => write!(f, "The execution was cancelled"),
+            AppError::ReplaySourceUnavailable(s) => write!(f, "{}", s),
+            AppError::OutputBudgetExceeded { limit_bytes } => {
+                write!(
+                    f,
+                    "Execution exceeded the output budget of {} bytes",
+                    limit_bytes
+                )
+            }
+            AppError::UnsupportedResponseVersion(version) => {
+                write!(f, "Unsupported response version {:?}", version)
+            }
+            AppError::CircuitOpen {
+                url,
+                retry_after_secs,
+                ..
+            } => write!(
+                f,
+                "{} failed repeatedly; retry in {} s",
+                url, retry_after_secs
+            ),
+            AppError::JobQueueFull { max_queued } => {
+                write!(f, "{} jobs are queued or running already", max_queued)
+            }
+            AppError::ModuleRegistryFull { limit, maximum } => {
+                write!(
+                    f,
+                    "The module registry is at modules.{} ({})",
+                    limit, maximum
+                )
+            }
+            AppError::Unauthorized(msg) => write!(f, "{}", msg),
+            AppError::QuotaExceeded {
+                key, quota, limit, ..
+            } => write!(
+                f,
+                "API key {} is over its {} quota of {}",
+                key, quota, limit
+            ),
+            AppError::ServerBusy {
+                max_concurrent,
+                waited_ms,
+            } => write!(
+                f,
+                "All {} execution slots stayed busy for {} ms",
+                max_concurrent, waited_ms
+            ),
+            AppError::RuntimePoolExhausted {
+                pool_size,
+                waited_ms,
+            } => write!(
+                f,
+                "All {} JavaScript runtimes stayed busy for {} ms",
+                pool_size, waited_ms
+            ),
+            AppError::CodeTooLarge {
+                limit_bytes,
+                observed_bytes,
+            } => write!(
+                f,
+                "The code is larger than {} bytes (at least {} bytes)",
+                limit_bytes, observed_bytes
+            ),
+            AppError::CodeUrlBlocked { url, reason, .. } => {
+                write!(f, "Code cannot be downloaded from {}: {}", url, reason)
+            }
+            AppError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "The code has SHA-256 digest {}, expected {}",
+                actual, expected
+            ),
+            AppError::InvalidManifest(s) => write!(f, "{}", s),
+            AppError::UnsupportedCodeType { .. } => {
+                write!(f, "The signals about the code type of the payload conflict")
+            }
+            AppError::CodeTypeUndetected { .. } => {
+                write!(
+                    f,
+                    "Could not determine the code type of the downloaded payload"
+                )
+            }
+            AppError::FailedExecution { error, .. } => write!(f, "{}", error),
+        }
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status_code, error_info) = match self {
+        let (status_code, body) = self.into_execute_response(|_| {});
+        (status_code, Json(body)).into_response()
+    }
+}
+
+impl AppError {
+    /// The code identifying this kind of error in responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::QuickJs(_) | AppError::JsException { .. } => "JAVASCRIPT_EXECUTION_ERROR",
+            AppError::JsSyntaxError { .. } => "JS_SYNTAX_ERROR",
+            AppError::ModuleResolutionDisabled { .. } => "MODULE_RESOLUTION_DISABLED",
+            AppError::Wasmtime(_) | AppError::WasiExit { .. } => "WEBASSEMBLY_EXECUTION_ERROR",
+            AppError::GuestAbort(_) => "GUEST_ABORT",
+            AppError::MissingImports { .. } => "MISSING_IMPORTS",
+            AppError::InvalidEntrypoint { .. } => "INVALID_ENTRYPOINT",
+            AppError::StubbedImportCalled(_) => "STUBBED_IMPORT_CALLED",
+            AppError::WasmTrap { kind, .. } => kind.code(),
+            AppError::InvalidModule(_) | AppError::NoUsableMemory { .. } => "INVALID_MODULE",
+            AppError::WatSyntaxError { .. } => "WAT_SYNTAX_ERROR",
+            AppError::Reqwest(_) => "FETCH_ERROR",
+            AppError::Download(_) => "DOWNLOAD_ERROR",
+            AppError::InvalidRequest(_) => "INVALID_REQUEST",
+            AppError::InvalidOptions(_) => "INVALID_OPTIONS",
+            AppError::Timeout { .. } => "EXECUTION_TIMEOUT",
+            AppError::FuelExhausted { .. } => "RESOURCE_EXHAUSTED",
+            AppError::MemoryLimitExceeded { .. } => "MEMORY_LIMIT_EXCEEDED",
+            AppError::Cancelled => "EXECUTION_CANCELLED",
+            AppError::NotFound(_) => "NOT_FOUND",
+            AppError::ExecutionFinished(_) => "EXECUTION_FINISHED",
+            AppError::ReplaySourceUnavailable(_) => "REPLAY_SOURCE_UNAVAILABLE",
+            AppError::OutputBudgetExceeded { .. } => "OUTPUT_BUDGET_EXCEEDED",
+            AppError::Decompression(_) => "DECOMPRESSION_ERROR",
+            AppError::UnsupportedResponseVersion(_) => "UNSUPPORTED_RESPONSE_VERSION",
+            AppError::CircuitOpen { .. } => "CIRCUIT_OPEN",
+            AppError::JobQueueFull { .. } => "JOB_QUEUE_FULL",
+            AppError::ModuleRegistryFull { .. } => "MODULE_REGISTRY_FULL",
+            AppError::Unauthorized(_) => "UNAUTHORIZED",
+            AppError::QuotaExceeded { .. } => "QUOTA_EXCEEDED",
+            AppError::ServerBusy { .. } => "SERVER_BUSY",
+            AppError::RuntimePoolExhausted { .. } => "RUNTIME_POOL_EXHAUSTED",
+            AppError::CodeTooLarge { .. } => "CODE_TOO_LARGE",
+            AppError::CodeUrlBlocked { .. } => "CODE_URL_BLOCKED",
+            AppError::ChecksumMismatch { .. } => "CHECKSUM_MISMATCH",
+            AppError::InvalidManifest(_) => "INVALID_MANIFEST",
+            AppError::UnsupportedCodeType { .. } => "UNSUPPORTED_CODE_TYPE",
+            AppError::CodeTypeUndetected { .. } => "CODE_TYPE_UNDETECTED",
+            AppError::Internal(_) => "INTERNAL_ERROR",
+            AppError::FailedExecution { error, .. } => error.code(),
+        }
+    }
+
+    /// Metadata of the failed execution this error carries, if the execution had started
+    pub fn partial_metadata(&mut self) -> Option<&mut ExecutionMetadata> {
+        match self {
+            AppError::FailedExecution { partial, .. } => Some(&mut partial.metadata),
+            _ => None,
+        }
+    }
+
+    /// The error itself, without what a failed execution produced
+    pub fn cause(&self) -> &AppError {
+        match self {
+            AppError::FailedExecution { error, .. } => error.cause(),
+            error => error,
+        }
+    }
+
+    /// Convert the error into a response body, letting the caller fill in metadata it already knows
+    ///
+    /// # Arguments
+    ///
+    /// * `update` - Called with the error response metadata before the body is returned
+    ///
+    /// # Returns
+    ///
+    /// * `(StatusCode, ExecuteResponse)` - HTTP status and body of the error response
+    pub fn into_execute_response(
+        self,
+        update: impl FnOnce(&mut ExecutionMetadata),
+    ) -> (StatusCode, ExecuteResponse) {
+        // Executions that failed after they started still report what they
+        // printed, with their metadata
+        let (error, stdout, stderr, logs, mut metadata) = match self {
+            AppError::FailedExecution { error, partial } => (
+                *error,
+                partial.stdout,
+                partial.stderr,
+                partial.logs,
+                partial.metadata,
+            ),
+            error => (
+                error,
+                String::new(),
+                String::new(),
+                None,
+                // No resource size for errors before loading
+                ExecutionMetadata::new("unknown", 0, 0),
+            ),
+        };
+        let code = error.code().to_string();
+        let (status_code, error_info) = match error {
             AppError::QuickJs(e) => {
                 let mut details = HashMap::new();
                 details.insert(
@@ -110,12 +868,68 @@ impl IntoResponse for AppError {
                 );
 
                 let error = ErrorInfo {
-                    code: "JAVASCRIPT_EXECUTION_ERROR".to_string(),
+                    code,
                     message: format!("JavaScript Execution Error: {}", e),
                     details: Some(details),
                 };
                 (StatusCode::INTERNAL_SERVER_ERROR, error)
             }
+            AppError::JsException {
+                message,
+                details: thrown,
+                source,
+            } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "errorType".to_string(),
+                    serde_json::Value::String("QuickJS".to_string()),
+                );
+                details.insert("source".to_string(), serde_json::Value::String(source));
+                if let Some(name) = thrown.name {
+                    details.insert("name".to_string(), serde_json::Value::String(name));
+                }
+                if let Some(stack) = thrown.stack {
+                    details.insert("stack".to_string(), serde_json::Value::String(stack));
+                }
+                if let Some(line) = thrown.line {
+                    details.insert("line".to_string(), serde_json::Value::from(line));
+                }
+                if let Some(column) = thrown.column {
+                    details.insert("column".to_string(), serde_json::Value::from(column));
+                }
+                if let Some(value) = thrown.thrown {
+                    details.insert("thrown".to_string(), value);
+                }
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!("JavaScript Execution Error: {}", message),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::JsSyntaxError {
+                message,
+                stack,
+                source,
+            } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "errorType".to_string(),
+                    serde_json::Value::String("SyntaxError".to_string()),
+                );
+                details.insert("source".to_string(), serde_json::Value::String(source));
+                if let Some(stack) = stack {
+                    details.insert("stack".to_string(), serde_json::Value::String(stack));
+                }
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!("JavaScript Syntax Error: {}", message),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
             AppError::Wasmtime(e) => {
                 let mut details = HashMap::new();
                 details.insert(
@@ -124,12 +938,219 @@ impl IntoResponse for AppError {
                 );
 
                 let error = ErrorInfo {
-                    code: "WEBASSEMBLY_EXECUTION_ERROR".to_string(),
+                    code,
                     message: format!("WebAssembly Execution Error: {}", e),
                     details: Some(details),
                 };
                 (StatusCode::INTERNAL_SERVER_ERROR, error)
             }
+            AppError::WasmTrap {
+                kind,
+                trap,
+                backtrace,
+            } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "errorType".to_string(),
+                    serde_json::Value::String("Trap".to_string()),
+                );
+                details.insert(
+                    "trap".to_string(),
+                    serde_json::json!({
+                        "kind": kind.name(),
+                        "message": trap,
+                        "backtrace": backtrace,
+                    }),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!("WebAssembly module trapped: {}", trap),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::InvalidModule(s) => {
+                let error = ErrorInfo {
+                    code,
+                    message: format!("Invalid WebAssembly module: {}", s),
+                    details: None,
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::MissingImports { imports, provided } => {
+                let message = describe_missing_imports(&imports);
+                let mut details = HashMap::new();
+                details.insert(
+                    "imports".to_string(),
+                    serde_json::to_value(&imports).unwrap_or_default(),
+                );
+                details.insert(
+                    "hint".to_string(),
+                    serde_json::Value::String(format!(
+                        "The host provides {}, and WASI preview1 as wasi_snapshot_preview1",
+                        provided.join(", ")
+                    )),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message,
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::StubbedImportCalled(called) => {
+                let message = format!("WebAssembly module {}", called);
+                let mut details = HashMap::new();
+                details.insert(
+                    "module".to_string(),
+                    serde_json::Value::String(called.module),
+                );
+                details.insert("name".to_string(), serde_json::Value::String(called.name));
+
+                let error = ErrorInfo {
+                    code,
+                    message,
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::InvalidEntrypoint {
+                export,
+                signature,
+                expected,
+            } => {
+                let message = format!(
+                    "Invalid WebAssembly module: {} is {}, expected {}",
+                    export, signature, expected
+                );
+                let mut details = HashMap::new();
+                details.insert("export".to_string(), serde_json::Value::String(export));
+                details.insert(
+                    "signature".to_string(),
+                    serde_json::Value::String(signature),
+                );
+                details.insert("expected".to_string(), serde_json::Value::String(expected));
+
+                let error = ErrorInfo {
+                    code,
+                    message,
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::NoUsableMemory { memory_exports } => {
+                let message = format!(
+                    "Invalid WebAssembly module: {}",
+                    describe_memory_exports(&memory_exports)
+                );
+                let mut details = HashMap::new();
+                details.insert(
+                    "memoryExports".to_string(),
+                    serde_json::Value::from(memory_exports),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message,
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::ModuleResolutionDisabled { specifier, source } => {
+                let message = format!(
+                    "Module imports are disabled: {} cannot import {}",
+                    source, specifier
+                );
+                let mut details = HashMap::new();
+                details.insert(
+                    "specifier".to_string(),
+                    serde_json::Value::String(specifier),
+                );
+                details.insert("source".to_string(), serde_json::Value::String(source));
+
+                let error = ErrorInfo {
+                    code,
+                    message,
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::WatSyntaxError {
+                message,
+                line,
+                column,
+            } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "line".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(line)),
+                );
+                details.insert(
+                    "column".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(column)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "WebAssembly Text Syntax Error at line {}, column {}: {}",
+                        line, column, message
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::WasiExit { exit_code, .. } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "errorType".to_string(),
+                    serde_json::Value::String("WasiExit".to_string()),
+                );
+                details.insert(
+                    "exitCode".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(exit_code)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!("WebAssembly module exited with code {}", exit_code),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::GuestAbort(abort) => {
+                let message = format!("WebAssembly module aborted: {}", abort);
+                let mut details = HashMap::new();
+                details.insert(
+                    "message".to_string(),
+                    abort
+                        .message
+                        .map_or(serde_json::Value::Null, serde_json::Value::String),
+                );
+                details.insert(
+                    "file".to_string(),
+                    abort
+                        .file
+                        .map_or(serde_json::Value::Null, serde_json::Value::String),
+                );
+                details.insert(
+                    "line".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(abort.line)),
+                );
+                details.insert(
+                    "column".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(abort.column)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message,
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
             AppError::Reqwest(e) => {
                 let mut details = HashMap::new();
                 if let Some(url) = e.url().map(|u| u.to_string()) {
@@ -143,52 +1164,533 @@ impl IntoResponse for AppError {
                 }
 
                 let error = ErrorInfo {
-                    code: "FETCH_ERROR".to_string(),
+                    code,
                     message: format!("Failed to fetch resource: {}", e),
                     details: Some(details),
                 };
                 (StatusCode::BAD_GATEWAY, error)
             }
+            AppError::Download(s) => {
+                let error = ErrorInfo {
+                    code,
+                    message: s,
+                    details: None,
+                };
+                (StatusCode::BAD_GATEWAY, error)
+            }
+            AppError::InvalidRequest(s) => {
+                let error = ErrorInfo {
+                    code,
+                    message: s,
+                    details: None,
+                };
+                (StatusCode::BAD_REQUEST, error)
+            }
+            AppError::InvalidOptions(violations) => {
+                let fields = violations
+                    .iter()
+                    .map(|violation| {
+                        serde_json::json!({
+                            "field": violation.field,
+                            "requested": violation.requested,
+                            "maximum": violation.maximum,
+                            "message": violation.to_string(),
+                        })
+                    })
+                    .collect();
+                let mut details = HashMap::new();
+                details.insert("fields".to_string(), serde_json::Value::Array(fields));
+
+                let error = ErrorInfo {
+                    code,
+                    message: describe_violations(&violations),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::Timeout { timeout_ms, .. } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "timeoutMs".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(timeout_ms)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!("Execution exceeded the timeout of {} ms", timeout_ms),
+                    details: Some(details),
+                };
+                (StatusCode::GATEWAY_TIMEOUT, error)
+            }
+            AppError::FuelExhausted { max_fuel } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "maxFuel".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(max_fuel)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!("Execution ran out of fuel after {} units", max_fuel),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::MemoryLimitExceeded {
+                limit_bytes,
+                requested_bytes,
+            } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "limitBytes".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(limit_bytes)),
+                );
+                let message = match requested_bytes {
+                    Some(requested_bytes) => {
+                        details.insert(
+                            "requestedBytes".to_string(),
+                            serde_json::Value::Number(serde_json::Number::from(requested_bytes)),
+                        );
+                        format!(
+                            "Memory growth to {} bytes exceeds the limit of {} bytes",
+                            requested_bytes, limit_bytes
+                        )
+                    }
+                    None => format!(
+                        "The script ran out of memory, its heap is limited to {} bytes",
+                        limit_bytes
+                    ),
+                };
+
+                let error = ErrorInfo {
+                    code,
+                    message,
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::Cancelled => {
+                let error = ErrorInfo {
+                    code,
+                    message: "The execution was cancelled".to_string(),
+                    details: None,
+                };
+                // nginx's "client closed request", as `http` has no constant for it
+                let status = StatusCode::from_u16(499).unwrap_or(StatusCode::CONFLICT);
+                (status, error)
+            }
+            AppError::NotFound(s) => {
+                let error = ErrorInfo {
+                    code,
+                    message: s,
+                    details: None,
+                };
+                (StatusCode::NOT_FOUND, error)
+            }
+            AppError::ExecutionFinished(s) => {
+                let error = ErrorInfo {
+                    code,
+                    message: s,
+                    details: None,
+                };
+                (StatusCode::CONFLICT, error)
+            }
+            AppError::ReplaySourceUnavailable(s) => {
+                let error = ErrorInfo {
+                    code,
+                    message: s,
+                    details: None,
+                };
+                (StatusCode::GONE, error)
+            }
+            AppError::OutputBudgetExceeded { limit_bytes } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "limitBytes".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(limit_bytes)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "Execution exceeded the output budget of {} bytes",
+                        limit_bytes
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::Decompression(s) => {
+                let error = ErrorInfo {
+                    code,
+                    message: s,
+                    details: None,
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::UnsupportedResponseVersion(version) => {
+                let mut details = HashMap::new();
+                details.insert("supportedVersions".to_string(), serde_json::json!([1, 2]));
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "Unsupported response version {:?}; supported versions are 1 and 2",
+                        version
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::BAD_REQUEST, error)
+            }
+            AppError::CircuitOpen {
+                url,
+                retry_after_secs,
+                recent_failures,
+            } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "retryAfterSecs".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(retry_after_secs)),
+                );
+                details.insert(
+                    "recentFailures".to_string(),
+                    serde_json::to_value(&recent_failures).unwrap_or_default(),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "{} failed {} times recently; requests for it are rejected for {} s",
+                        url,
+                        recent_failures.len(),
+                        retry_after_secs
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::SERVICE_UNAVAILABLE, error)
+            }
+            AppError::JobQueueFull { max_queued } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "maxQueued".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(max_queued)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "{} jobs are queued or running already; retry once one finishes",
+                        max_queued
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::TOO_MANY_REQUESTS, error)
+            }
+            AppError::ModuleRegistryFull { limit, maximum } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "limit".to_string(),
+                    serde_json::Value::String(limit.to_string()),
+                );
+                details.insert(
+                    "maximum".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(maximum)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "The module registry is at modules.{} ({}); delete a module first",
+                        limit, maximum
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::INSUFFICIENT_STORAGE, error)
+            }
+            AppError::Unauthorized(msg) => {
+                let error = ErrorInfo {
+                    code,
+                    message: msg,
+                    details: None,
+                };
+                (StatusCode::UNAUTHORIZED, error)
+            }
+            AppError::QuotaExceeded {
+                key,
+                quota,
+                limit,
+                retry_after_secs,
+            } => {
+                let mut details = HashMap::new();
+                details.insert("key".to_string(), serde_json::Value::String(key.clone()));
+                details.insert(
+                    "quota".to_string(),
+                    serde_json::Value::String(quota.to_string()),
+                );
+                details.insert(
+                    "limit".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(limit)),
+                );
+                details.insert(
+                    "retryAfterSecs".to_string(),
+                    retry_after_secs
+                        .map(|secs| serde_json::Value::Number(serde_json::Number::from(secs)))
+                        .unwrap_or(serde_json::Value::Null),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "API key {} is over its {} quota of {}; retry later",
+                        key, quota, limit
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::TOO_MANY_REQUESTS, error)
+            }
+            AppError::ServerBusy {
+                max_concurrent,
+                waited_ms,
+            } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "maxConcurrent".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(max_concurrent)),
+                );
+                details.insert(
+                    "waitedMs".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(waited_ms)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "All {} execution slots stayed busy for {} ms; retry later",
+                        max_concurrent, waited_ms
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::TOO_MANY_REQUESTS, error)
+            }
+            AppError::RuntimePoolExhausted {
+                pool_size,
+                waited_ms,
+            } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "poolSize".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(pool_size)),
+                );
+                details.insert(
+                    "waitedMs".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(waited_ms)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "All {} JavaScript runtimes stayed busy for {} ms; retry later",
+                        pool_size, waited_ms
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::SERVICE_UNAVAILABLE, error)
+            }
+            AppError::CodeTooLarge {
+                limit_bytes,
+                observed_bytes,
+            } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "limitBytes".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(limit_bytes)),
+                );
+                details.insert(
+                    "observedBytes".to_string(),
+                    serde_json::Value::Number(serde_json::Number::from(observed_bytes)),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "The code is larger than the {} byte download limit (at least {} bytes)",
+                        limit_bytes, observed_bytes
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::CodeUrlBlocked { url, host, reason } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "host".to_string(),
+                    host.map_or(serde_json::Value::Null, serde_json::Value::String),
+                );
+                details.insert(
+                    "reason".to_string(),
+                    serde_json::Value::String(reason.clone()),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!("Code cannot be downloaded from {}: {}", url, reason),
+                    details: Some(details),
+                };
+                (StatusCode::FORBIDDEN, error)
+            }
+            AppError::ChecksumMismatch { expected, actual } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "expected".to_string(),
+                    serde_json::Value::String(expected.clone()),
+                );
+                details.insert(
+                    "actual".to_string(),
+                    serde_json::Value::String(actual.clone()),
+                );
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "The code has SHA-256 digest {}, but the request expects {}",
+                        actual, expected
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::InvalidManifest(s) => {
+                let error = ErrorInfo {
+                    code,
+                    message: s,
+                    details: None,
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::UnsupportedCodeType {
+                extension,
+                content_type,
+                wasm_magic,
+            } => {
+                let observed = [
+                    extension.map(|code_type| format!("the URL extension names {}", code_type)),
+                    content_type
+                        .as_ref()
+                        .map(|content_type| format!("Content-Type is {}", content_type)),
+                    Some(if wasm_magic {
+                        "the payload starts with the \\0asm magic bytes".to_string()
+                    } else {
+                        "the payload does not start with the \\0asm magic bytes".to_string()
+                    }),
+                ];
+                let mut details = HashMap::new();
+                details.insert(
+                    "extension".to_string(),
+                    extension.map_or(serde_json::Value::Null, |code_type| {
+                        serde_json::Value::String(code_type.to_string())
+                    }),
+                );
+                details.insert(
+                    "contentType".to_string(),
+                    content_type.map_or(serde_json::Value::Null, serde_json::Value::String),
+                );
+                details.insert("wasmMagic".to_string(), serde_json::Value::Bool(wasm_magic));
+
+                let error = ErrorInfo {
+                    code,
+                    message: format!(
+                        "Conflicting code type signals: {}; set code_type to choose",
+                        observed
+                            .into_iter()
+                            .flatten()
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
+            AppError::CodeTypeUndetected {
+                wasm_probe,
+                js_probe,
+            } => {
+                let mut details = HashMap::new();
+                details.insert(
+                    "wasmProbe".to_string(),
+                    serde_json::Value::String(wasm_probe),
+                );
+                details.insert("jsProbe".to_string(), serde_json::Value::String(js_probe));
+
+                let error = ErrorInfo {
+                    code,
+                    message: "Could not determine the code type of the downloaded payload"
+                        .to_string(),
+                    details: Some(details),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, error)
+            }
             AppError::Internal(s) => {
                 let error = ErrorInfo {
-                    code: "INTERNAL_ERROR".to_string(),
+                    code,
                     message: s,
                     details: None,
                 };
                 (StatusCode::INTERNAL_SERVER_ERROR, error)
             }
-        };
-
-        // Generate current timestamp in ISO format
-        let now = SystemTime::now();
-        let timestamp = match now.duration_since(UNIX_EPOCH) {
-            Ok(duration) => {
-                let datetime = chrono::DateTime::<chrono::Utc>::from_timestamp(
-                    duration.as_secs() as i64,
-                    duration.subsec_nanos(),
-                )
-                .unwrap_or_else(|| chrono::Utc::now());
-                datetime.to_rfc3339()
+            // Engines wrap an error once; a nested one is reported by its innermost error
+            nested @ AppError::FailedExecution { .. } => {
+                return nested.into_execute_response(update)
             }
-            Err(_) => chrono::Utc::now().to_rfc3339(),
         };
 
-        let metadata = ExecutionMetadata {
-            execution_time: 0, // We don't have execution time for errors before execution
-            code_type: "unknown".to_string(),
-            timestamp,
-            resource_size: 0, // No resource size for errors before loading
-        };
+        update(&mut metadata);
 
-        let body = Json(ExecuteResponse {
+        let body = ExecuteResponse {
             status: "error".to_string(),
             output: None,
-            stdout: Some("".to_string()), // No stdout for errors before execution
-            stderr: Some("".to_string()), // No stderr for errors before execution
+            output_json: None,
+            stdout: Some(stdout),
+            stderr: Some(stderr),
+            logs,
+            stdout_truncated: metadata.truncated(Channel::Stdout),
+            stderr_truncated: metadata.truncated(Channel::Stderr),
+            logs_truncated: metadata.truncated(Channel::Log),
             error: Some(error_info),
             metadata,
-        });
+        };
+
+        (status_code, body)
+    }
+}
+
+/// One message naming every rejected option, e.g. "limits.timeout_ms exceeds maximum 60000"
+fn describe_violations(violations: &[OptionViolation]) -> String {
+    violations
+        .iter()
+        .map(|violation| violation.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// One message naming every import a module lacks, e.g.
+/// "Module imports 2 items the host does not provide: env::a, env::b"
+fn describe_missing_imports(imports: &[MissingImport]) -> String {
+    let names: Vec<String> = imports.iter().map(|import| import.to_string()).collect();
+    format!(
+        "Module imports {} item{} the host does not provide: {}",
+        imports.len(),
+        if imports.len() == 1 { "" } else { "s" },
+        names.join(", ")
+    )
+}
 
-        (status_code, body).into_response()
+/// Why none of a module's memory exports is usable, naming them
+fn describe_memory_exports(memory_exports: &[String]) -> String {
+    if memory_exports.is_empty() {
+        return "the module exports no memory".to_string();
     }
+    format!(
+        "the module exports memories {}, none named 'memory' or memory 0",
+        memory_exports.join(", ")
+    )
 }
@@ -0,0 +1,348 @@
+//! # Executors
+//!
+//! An `Executor` runs downloaded code of one type. The handlers pick one with
+//! `for_code_type` once the code type is detected and hand it the code with
+//! the execution's `ExecOptions`; nothing past that point depends on which
+//! engine runs the code, so a new code type only needs an executor and an arm
+//! in `for_code_type`.
+//!
+//! What an execution ended up running with, once the request's options were
+//! checked against the server's caps and a manifest applied, is reported
+//! back as `metadata.effective_options`.
+//!
+//! Executions are futures. WebAssembly runs on the runtime polling the
+//! future, suspended while its guest waits on the network; QuickJS is
+//! synchronous, so scripts run on the blocking pool and the future waits for
+//! them there.
+
+use crate::config::{Config, JsConfig};
+use crate::detect::CodeType;
+use crate::error::{AppError, ExecutionResult};
+use crate::guest_env::GuestEnv;
+use crate::js_engine::{self, JsExecution, JsLimits, RuntimePool, Script};
+use crate::kv::KvNamespace;
+use crate::limits::Limits;
+use crate::net::FetchContext;
+use crate::output::OutputBudget;
+use crate::random::DeterministicMode;
+use crate::storage::Namespace;
+use crate::wasm_engine::{self, Compat, SharedEngine};
+use bytes::Bytes;
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Everything an execution runs with besides its code
+pub struct ExecOptions {
+    /// Hex SHA-256 digest of the code, as downloaded
+    pub sha256: String,
+    /// URL the code was downloaded from; scripts without one are named after their digest
+    pub url: Option<String>,
+    /// Per-execution state backing guest fetch calls; the engine runs with a clone
+    pub fetch_ctx: FetchContext,
+    /// Byte budget shared by all captured output
+    pub output_budget: Arc<OutputBudget>,
+    /// Seed and options of deterministic mode, if enabled
+    pub deterministic: Option<DeterministicMode>,
+    /// Zone of the guest's local-time functions
+    pub timezone: Tz,
+    /// Resolved limits of the execution
+    pub limits: Limits,
+    /// The code's storage namespace, when the execution was granted `storage`
+    pub storage: Option<Namespace>,
+    /// The request's key-value namespace, or one of the execution's own
+    pub kv: KvNamespace,
+    /// Variables of the request's `env`
+    pub env: Arc<GuestEnv>,
+    /// The request's `input`, serialized as JSON
+    pub input: Option<Bytes>,
+    /// Host imports shimmed for the toolchain that built a WebAssembly module
+    pub compat: Option<Compat>,
+    /// Define a module's missing function imports as placeholders instead of failing
+    pub stub_missing_imports: bool,
+    /// Replace invalid UTF-8 in `app_log` text instead of failing
+    pub lossy_app_log: bool,
+}
+
+/// The options an execution ran with, as reported in `metadata.effective_options`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct EffectiveOptions {
+    /// Execution timeout in milliseconds
+    pub timeout_ms: u64,
+    /// Largest size WebAssembly memories may grow to, or heap the script may allocate, in bytes
+    pub memory_bytes: u64,
+    /// Bytes of output captured across all channels
+    pub max_output_bytes: usize,
+    /// Bytes of stdout captured
+    pub max_stdout_bytes: usize,
+    /// Bytes of stderr captured
+    pub max_stderr_bytes: usize,
+    /// Bytes of `app_log` messages captured
+    pub max_log_bytes: usize,
+    /// Guest fetch calls sent
+    pub max_fetch_calls: u64,
+    /// Response body bytes received by guest fetch calls
+    pub max_fetch_bytes: u64,
+    /// Messages captured across stdout, stderr and `app_log`
+    pub max_log_entries: u64,
+    /// Fuel the WebAssembly module was given; null for scripts, which use none
+    pub max_fuel: Option<u64>,
+    /// Whether exhausting the output budget terminates the execution
+    pub strict_output: bool,
+    /// Zone of the guest's local-time functions
+    pub timezone: String,
+    /// Whether clocks and random values were deterministic
+    pub deterministic: bool,
+    /// Number of variables in the guest's `env`
+    pub env_vars: usize,
+}
+
+impl ExecOptions {
+    /// The options as they apply to code of `code_type`
+    pub fn effective(&self, code_type: CodeType) -> EffectiveOptions {
+        let wasm = matches!(code_type, CodeType::WebAssembly | CodeType::Wat);
+        EffectiveOptions {
+            timeout_ms: self.limits.timeout.as_millis() as u64,
+            memory_bytes: self.limits.memory_bytes,
+            max_output_bytes: self.limits.max_output_bytes,
+            max_stdout_bytes: self.limits.max_stdout_bytes,
+            max_stderr_bytes: self.limits.max_stderr_bytes,
+            max_log_bytes: self.limits.max_log_bytes,
+            max_fetch_calls: self.limits.max_fetch_calls,
+            max_fetch_bytes: self.limits.max_fetch_bytes,
+            max_log_entries: self.limits.max_log_entries,
+            max_fuel: wasm.then_some(self.limits.max_fuel),
+            strict_output: self.output_budget.is_strict(),
+            timezone: self.timezone.name().to_string(),
+            deterministic: self.deterministic.is_some(),
+            env_vars: self.env.len(),
+        }
+    }
+}
+
+/// A running execution, resolving to its result
+pub type Execution = Pin<Box<dyn Future<Output = Result<ExecutionResult, AppError>> + Send>>;
+
+/// Runs code of one type
+///
+/// A script and a module computing the same answer, each run through the trait:
+///
+/// ```
+/// use hoya::config::Config;
+/// use hoya::executor::{ExecOptions, Executor, JsExecutor, WasmExecutor};
+/// use hoya::fetch_runtime::FetchRuntime;
+/// use hoya::kv::KvStore;
+/// use hoya::limits::Limits;
+/// use hoya::metrics::Metrics;
+/// use hoya::net::FetchContext;
+/// use hoya::outbound::OutboundPolicy;
+/// use hoya::wasm_engine::SharedEngine;
+/// use std::sync::Arc;
+///
+/// let config = Config::default();
+/// let fetch_runtime = Arc::new(FetchRuntime::start().unwrap());
+/// let options = || {
+///     let limits = Limits::defaults(&config);
+///     ExecOptions {
+///         sha256: String::new(),
+///         url: None,
+///         fetch_ctx: FetchContext::new(
+///             reqwest::Client::new(),
+///             fetch_runtime.clone(),
+///             Arc::new(OutboundPolicy::unrestricted()),
+///             false,
+///             Arc::new(Metrics::new(&[]).unwrap()),
+///         ),
+///         output_budget: Arc::new(limits.output_budget(false, false)),
+///         deterministic: None,
+///         timezone: chrono_tz::Tz::UTC,
+///         limits,
+///         storage: None,
+///         kv: KvStore::new(&config.kv).isolated(),
+///         env: Arc::default(),
+///         input: None,
+///         compat: None,
+///         stub_missing_imports: false,
+///         lossy_app_log: false,
+///     }
+/// };
+///
+/// let module = hoya::wat::assemble(br#"(module
+///   (import "env" "set_output" (func $set_output (param i32 i32)))
+///   (memory (export "memory") 1)
+///   (data (i32.const 0) "42")
+///   (func (export "_start") (call $set_output (i32.const 0) (i32.const 2))))"#)
+/// .unwrap();
+/// let runs: Vec<(Box<dyn Executor>, bytes::Bytes)> = vec![
+///     (Box::new(JsExecutor::new(&config.js)), "6 * 7".into()),
+///     (
+///         Box::new(WasmExecutor::new(Arc::new(SharedEngine::new(&config.wasm).unwrap()))),
+///         module.into(),
+///     ),
+/// ];
+///
+/// // Scripts run on the blocking pool, so the executions need a Tokio runtime
+/// let runtime = tokio::runtime::Builder::new_current_thread()
+///     .enable_all()
+///     .build()
+///     .unwrap();
+/// for (executor, code) in runs {
+///     let result = runtime.block_on(executor.execute(code, options())).unwrap();
+///     assert_eq!(result.output, "42");
+/// }
+/// ```
+pub trait Executor: Send + Sync {
+    /// Start running `code`; the execution makes progress while it is polled
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The code to run, decompressed and, for text formats, assembled
+    /// * `opts` - Digest, fetch context, output budget, limits and the rest
+    ///
+    /// # Returns
+    ///
+    /// * `Execution` - Resolves to the execution result or error, once the code has run
+    fn execute(&self, code: Bytes, opts: ExecOptions) -> Execution;
+}
+
+/// Convert an engine task that did not finish into an error instead of taking the server down
+///
+/// # Arguments
+///
+/// * `error` - Why the task failed, usually a panic in the engine
+///
+/// # Returns
+///
+/// * `AppError` - An `Internal` error carrying the panic message
+pub fn engine_failure(error: tokio::task::JoinError) -> AppError {
+    let message = match error.try_into_panic() {
+        Ok(panic) => panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string()),
+        Err(error) => error.to_string(),
+    };
+    AppError::Internal(format!("Execution panicked: {}", message))
+}
+
+/// Runs scripts and ES modules on QuickJS
+#[derive(Clone)]
+pub struct JsExecutor {
+    /// Capture calls allowed before further calls are dropped
+    pub max_console_calls: u64,
+    /// Native stack the interpreter may use, in bytes
+    pub max_stack_bytes: usize,
+    /// Evaluate the code as an ES module instead of a classic script
+    pub module: bool,
+    /// Pooled runtimes to run on; without them every execution builds its own
+    pub runtimes: Option<Arc<RuntimePool>>,
+}
+
+impl JsExecutor {
+    /// An executor of classic scripts with the `[js]` section's engine settings,
+    /// building a runtime per execution
+    pub fn new(config: &JsConfig) -> Self {
+        JsExecutor {
+            max_console_calls: config.max_console_calls,
+            max_stack_bytes: config.max_stack_bytes,
+            module: false,
+            runtimes: None,
+        }
+    }
+
+    /// An executor of ES modules with the `[js]` section's engine settings
+    pub fn module(config: &JsConfig) -> Self {
+        JsExecutor {
+            module: true,
+            ..JsExecutor::new(config)
+        }
+    }
+}
+
+impl Executor for JsExecutor {
+    fn execute(&self, code: Bytes, opts: ExecOptions) -> Execution {
+        let mut script = match &opts.url {
+            Some(url) => Script::remote(code, &opts.sha256, url),
+            None => Script::inline(code, &opts.sha256),
+        };
+        script.module = self.module;
+        let execution = JsExecution {
+            script,
+            fetch_ctx: opts.fetch_ctx,
+            output_budget: opts.output_budget,
+            deterministic: opts.deterministic,
+            timezone: opts.timezone,
+            limits: JsLimits {
+                timeout: opts.limits.timeout,
+                max_console_calls: self.max_console_calls,
+                memory_bytes: opts.limits.memory_bytes,
+                max_stack_bytes: self.max_stack_bytes,
+            },
+            storage: opts.storage,
+            kv: opts.kv,
+            env: opts.env,
+            input: opts.input,
+            runtimes: self.runtimes.clone(),
+        };
+        // Scripts run synchronously, on the blocking pool and in the caller's span
+        let span = tracing::Span::current();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || span.in_scope(|| js_engine::execute_js(execution)))
+                .await
+                .unwrap_or_else(|e| Err(engine_failure(e)))
+        })
+    }
+}
+
+/// Runs WebAssembly modules on the shared Wasmtime engine
+#[derive(Clone)]
+pub struct WasmExecutor {
+    /// Engine, module cache and epoch ticker shared by all executions
+    pub engine: Arc<SharedEngine>,
+}
+
+impl WasmExecutor {
+    /// An executor running modules on `engine`
+    pub fn new(engine: Arc<SharedEngine>) -> Self {
+        WasmExecutor { engine }
+    }
+}
+
+impl Executor for WasmExecutor {
+    fn execute(&self, code: Bytes, opts: ExecOptions) -> Execution {
+        let engine = self.engine.clone();
+        Box::pin(async move { wasm_engine::execute_wasm(&engine, code, &opts).await })
+    }
+}
+
+/// The executor for code of a detected type
+///
+/// # Arguments
+///
+/// * `code_type` - The detected code type; text formats arrive assembled
+/// * `config` - Server configuration, for the engines' own settings
+/// * `wasm` - The shared WebAssembly engine
+/// * `js_runtimes` - The pooled QuickJS runtimes, unless `js.runtime_pool_size` is 0
+///
+/// # Returns
+///
+/// * `Box<dyn Executor>` - The executor to hand the code to
+pub fn for_code_type(
+    code_type: CodeType,
+    config: &Config,
+    wasm: &Arc<SharedEngine>,
+    js_runtimes: Option<&Arc<RuntimePool>>,
+) -> Box<dyn Executor> {
+    let pooled = |executor: JsExecutor| JsExecutor {
+        runtimes: js_runtimes.cloned(),
+        ..executor
+    };
+    match code_type {
+        CodeType::JavaScript => Box::new(pooled(JsExecutor::new(&config.js))),
+        CodeType::Module => Box::new(pooled(JsExecutor::module(&config.js))),
+        CodeType::WebAssembly | CodeType::Wat => Box::new(WasmExecutor::new(wasm.clone())),
+    }
+}
@@ -0,0 +1,127 @@
+//! Runtime for guest fetch calls.
+//!
+//! Guest code runs on whatever thread or runtime the embedder drives it from:
+//! a blocking pool or runtime pool thread when serving requests, the thread of
+//! the embedder's own runtime, which may be a current-thread runtime that
+//! cannot be blocked from inside, or no runtime at all. Guest fetch calls
+//! therefore never run on the caller's runtime. Their futures are sent over a
+//! channel to a dedicated thread driving a runtime of its own. JavaScript host
+//! functions are synchronous and wait for the output on a `std::sync::mpsc`
+//! channel; WebAssembly imports are async and await it on a
+//! `tokio::sync::oneshot` channel, which any executor can poll.
+//!
+//! Fetch calls work from a script running inside a current-thread runtime:
+//!
+//! ```
+//! use hoya::js_engine::{execute_js, JsExecution};
+//! use hoya::outbound::OutboundPolicy;
+//! use std::sync::Arc;
+//!
+//! // Fetches a closed port and returns the code of the error envelope
+//! let mut execution = JsExecution::from(
+//!     br#"JSON.parse(hoyaFetch(JSON.stringify({
+//!       url: "http://127.0.0.1:9/", method: "GET", headers: {}
+//!     }))).error.code"#,
+//! );
+//! execution.fetch_ctx.policy = Arc::new(OutboundPolicy::unrestricted());
+//!
+//! let runtime = tokio::runtime::Builder::new_current_thread()
+//!     .enable_all()
+//!     .build()
+//!     .unwrap();
+//! let result = runtime.block_on(async { execute_js(execution) });
+//! assert!(result.unwrap().output.contains("FETCH_FAILED"));
+//! ```
+
+use anyhow::{anyhow, Result as AnyhowResult};
+use std::future::Future;
+use std::pin::Pin;
+
+/// A future handed to the runtime thread
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A runtime on a thread of its own that guest fetch calls run on
+///
+/// The thread ends once the runtime is dropped.
+pub struct FetchRuntime {
+    /// Futures to spawn on the runtime thread
+    jobs: tokio::sync::mpsc::UnboundedSender<Job>,
+}
+
+impl FetchRuntime {
+    /// Start the runtime thread
+    ///
+    /// # Returns
+    ///
+    /// * `std::io::Result<FetchRuntime>` - The runtime, or why its thread could not be started
+    pub fn start() -> std::io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (jobs, mut receiver) = tokio::sync::mpsc::unbounded_channel::<Job>();
+        std::thread::Builder::new()
+            .name("hoya-fetch".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    while let Some(job) = receiver.recv().await {
+                        tokio::spawn(job);
+                    }
+                })
+            })?;
+        Ok(FetchRuntime { jobs })
+    }
+
+    /// Run `future` on the runtime thread and block until it completes
+    ///
+    /// The future is first polled on the runtime thread, so everything it
+    /// creates there, such as timers and connections, belongs to that runtime.
+    /// Blocking is fine from any thread but the runtime's own.
+    ///
+    /// # Returns
+    ///
+    /// * `AnyhowResult<F::Output>` - The future's output, or an error if the
+    ///   runtime thread is gone or the future panicked
+    pub fn block_on<F>(&self, future: F) -> AnyhowResult<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        self.jobs
+            .send(Box::pin(async move {
+                // Fails only if the caller is gone
+                let _ = sender.send(future.await);
+            }))
+            .map_err(|_| anyhow!("fetch: the fetch runtime has stopped"))?;
+        receiver
+            .recv()
+            .map_err(|_| anyhow!("fetch: the request was dropped by the fetch runtime"))
+    }
+
+    /// Run `future` on the runtime thread and wait for it without blocking
+    ///
+    /// Like `block_on`, but the caller awaits the output, so the calling
+    /// future may be polled by any executor, a Tokio runtime or none.
+    /// Dropping the returned future does not stop `future`.
+    ///
+    /// # Returns
+    ///
+    /// * `AnyhowResult<F::Output>` - The future's output, or an error if the
+    ///   runtime thread is gone or the future panicked
+    pub async fn run<F>(&self, future: F) -> AnyhowResult<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.jobs
+            .send(Box::pin(async move {
+                // Fails only if the caller is gone
+                let _ = sender.send(future.await);
+            }))
+            .map_err(|_| anyhow!("fetch: the fetch runtime has stopped"))?;
+        receiver
+            .await
+            .map_err(|_| anyhow!("fetch: the request was dropped by the fetch runtime"))
+    }
+}
@@ -0,0 +1,109 @@
+//! # Guest environment variables
+//!
+//! A request's `env` map is handed to the guest as configuration: a frozen
+//! `env` object in JavaScript, and the `env_get`, `env_count` and
+//! `env_key_at` imports in WebAssembly. The variables only ever live in the
+//! execution; they are never set on the server process, so guests cannot
+//! see the server's environment through them and executions cannot see each
+//! other's.
+//!
+//! Names follow the usual environment variable syntax (`[A-Za-z_][A-Za-z0-9_]*`).
+//! Names are limited to `execution.max_env_key_bytes`, values to
+//! `execution.max_env_value_bytes`, and all names and values together to
+//! `execution.max_env_bytes`; requests past any limit are refused.
+
+use crate::config::ExecutionConfig;
+use crate::error::AppError;
+use std::collections::{BTreeMap, HashMap};
+
+/// Environment variables of one execution, ordered by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GuestEnv {
+    /// Variables by name; the order is the enumeration order of `env_key_at`
+    vars: BTreeMap<String, String>,
+}
+
+impl GuestEnv {
+    /// Check a request's variables against the server's limits
+    ///
+    /// # Arguments
+    ///
+    /// * `vars` - The request's `env`, if it has one
+    /// * `config` - Execution settings holding the size limits
+    ///
+    /// # Returns
+    ///
+    /// * `Result<GuestEnv, AppError>` - The variables, or `InvalidRequest`
+    ///   naming the first invalid name or exceeded limit
+    pub fn new(
+        vars: Option<&HashMap<String, String>>,
+        config: &ExecutionConfig,
+    ) -> Result<Self, AppError> {
+        let vars: BTreeMap<String, String> = vars
+            .map(|vars| vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default();
+        let mut total_bytes = 0;
+        for (name, value) in &vars {
+            if !is_valid_name(name) {
+                return Err(AppError::InvalidRequest(format!(
+                    "env name {:?} must start with a letter or underscore and hold only letters, digits and underscores",
+                    name
+                )));
+            }
+            if name.len() > config.max_env_key_bytes {
+                return Err(AppError::InvalidRequest(format!(
+                    "env name {} is longer than {} bytes",
+                    name, config.max_env_key_bytes
+                )));
+            }
+            if value.len() > config.max_env_value_bytes {
+                return Err(AppError::InvalidRequest(format!(
+                    "env value of {} is longer than {} bytes",
+                    name, config.max_env_value_bytes
+                )));
+            }
+            total_bytes += name.len() + value.len();
+        }
+        if total_bytes > config.max_env_bytes {
+            return Err(AppError::InvalidRequest(format!(
+                "env holds {} bytes of names and values, more than {}",
+                total_bytes, config.max_env_bytes
+            )));
+        }
+        Ok(GuestEnv { vars })
+    }
+
+    /// Value of the variable `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+
+    /// Number of variables
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+
+    /// Whether there are no variables
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    /// Name of the variable at `index`, counting in name order
+    pub fn name_at(&self, index: usize) -> Option<&str> {
+        self.vars.keys().nth(index).map(String::as_str)
+    }
+
+    /// Variables as name/value pairs, in name order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+/// Whether `name` is a valid environment variable name
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
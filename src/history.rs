@@ -0,0 +1,202 @@
+//! # Execution history
+//!
+//! A bounded in-memory record of recent executions, kept so that an
+//! execution can be replayed by id. Each record holds the request needed to
+//! run it again (without its download headers, which carry credentials) and a
+//! summary of its outcome. Captured output is stored as digests only, which
+//! is enough to tell whether a replay produced the same output. Records are
+//! served with the request's URL and `env` values redacted; replays use the
+//! request as received.
+
+use crate::error::ExecuteResponse;
+use crate::record;
+use crate::server::ExecuteRequest;
+use serde::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// How an execution ended
+#[derive(Serialize, Debug, Clone)]
+pub struct Outcome {
+    /// "success" or "error"
+    pub status: String,
+    /// HTTP status code of the response
+    pub http_status: u16,
+    /// Error code of a failed execution
+    pub error_code: Option<String>,
+    /// Hex SHA-256 digest of the executed code, if it was downloaded
+    pub code_sha256: Option<String>,
+    /// Digest of the execution result
+    pub output_sha256: Option<String>,
+    /// Digest of the captured stdout
+    pub stdout_sha256: Option<String>,
+    /// Digest of the captured stderr
+    pub stderr_sha256: Option<String>,
+    /// Time spent executing the code, in milliseconds
+    pub execution_time: u64,
+}
+
+impl Outcome {
+    /// Summarize a response
+    pub fn from_response(http_status: u16, response: &ExecuteResponse) -> Self {
+        let digest = |text: &Option<String>| {
+            text.as_ref()
+                .map(|text| format!("{:x}", Sha256::digest(text.as_bytes())))
+        };
+        Outcome {
+            status: response.status.clone(),
+            http_status,
+            error_code: response.error.as_ref().map(|error| error.code.clone()),
+            code_sha256: response.metadata.code_sha256.clone(),
+            output_sha256: digest(&response.output),
+            stdout_sha256: digest(&response.stdout),
+            stderr_sha256: digest(&response.stderr),
+            execution_time: response.metadata.execution_time,
+        }
+    }
+}
+
+/// Value shown in place of each `env` value of a recorded request
+const REDACTED: &str = "<redacted>";
+
+/// One past execution
+#[derive(Serialize, Debug, Clone)]
+pub struct HistoryRecord {
+    /// Identifier returned in the execution's metadata
+    pub execution_id: String,
+    /// The request, without its download headers; serialized with its URL and `env` values redacted
+    #[serde(serialize_with = "serialize_request")]
+    pub request: ExecuteRequest,
+    /// How the execution ended
+    pub outcome: Outcome,
+    /// Execution this one replayed, if it was a replay
+    pub replay_of: Option<String>,
+}
+
+impl HistoryRecord {
+    /// Record an execution
+    ///
+    /// # Arguments
+    ///
+    /// * `execution_id` - Identifier of the execution
+    /// * `request` - The request as received; its download headers are dropped
+    /// * `outcome` - How the execution ended
+    /// * `replay_of` - Execution this one replayed, if any
+    pub fn new(
+        execution_id: String,
+        mut request: ExecuteRequest,
+        outcome: Outcome,
+        replay_of: Option<String>,
+    ) -> Self {
+        // Requests may only forward credentials, which are never kept
+        request.download_headers = None;
+        HistoryRecord {
+            execution_id,
+            request,
+            outcome,
+            replay_of,
+        }
+    }
+}
+
+/// Serialize a recorded request with its URL stripped of credentials, query
+/// and fragment, as `record::redact_url` does for the audit log, and its `env`
+/// values replaced by `<redacted>`, since variables may hold secrets
+fn serialize_request<S: Serializer>(
+    request: &ExecuteRequest,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut shown = request.clone();
+    // Requests for registered modules have no URL
+    if !shown.url.is_empty() {
+        shown.url = record::redact_url(&shown.url);
+    }
+    if let Some(env) = shown.env.as_mut() {
+        for value in env.values_mut() {
+            *value = REDACTED.to_string();
+        }
+    }
+    shown.serialize(serializer)
+}
+
+/// Bounded store of recent executions; the oldest record is evicted first
+pub struct History {
+    /// Maximum number of records kept
+    capacity: usize,
+    /// Records, oldest first
+    records: Mutex<VecDeque<HistoryRecord>>,
+}
+
+impl History {
+    /// Create an empty store keeping at most `capacity` records
+    pub fn new(capacity: usize) -> Self {
+        History {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Add a record, evicting the oldest one when full
+    pub fn insert(&self, record: HistoryRecord) {
+        if let Ok(mut records) = self.records.lock() {
+            while records.len() >= self.capacity {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+    }
+
+    /// Look up a record by execution id
+    pub fn get(&self, execution_id: &str) -> Option<HistoryRecord> {
+        let records = self.records.lock().ok()?;
+        records
+            .iter()
+            .find(|record| record.execution_id == execution_id)
+            .cloned()
+    }
+}
+
+/// Structural comparison of a replay against the execution it replayed
+#[derive(Serialize, Debug)]
+pub struct ReplayDiff {
+    /// Whether nothing but timing differs
+    pub identical: bool,
+    /// Whether the status or HTTP status changed
+    pub status_changed: bool,
+    /// Whether the error code changed
+    pub error_code_changed: bool,
+    /// Whether the execution result changed
+    pub output_changed: bool,
+    /// Whether the captured stdout changed
+    pub stdout_changed: bool,
+    /// Whether the captured stderr changed
+    pub stderr_changed: bool,
+    /// Replay execution time minus the original's, in milliseconds
+    pub duration_delta_ms: i64,
+}
+
+impl ReplayDiff {
+    /// Compare a replay's outcome with the original's
+    pub fn between(original: &Outcome, replay: &Outcome) -> Self {
+        let status_changed =
+            original.status != replay.status || original.http_status != replay.http_status;
+        let error_code_changed = original.error_code != replay.error_code;
+        let output_changed = original.output_sha256 != replay.output_sha256;
+        let stdout_changed = original.stdout_sha256 != replay.stdout_sha256;
+        let stderr_changed = original.stderr_sha256 != replay.stderr_sha256;
+        ReplayDiff {
+            identical: !(status_changed
+                || error_code_changed
+                || output_changed
+                || stdout_changed
+                || stderr_changed),
+            status_changed,
+            error_code_changed,
+            output_changed,
+            stdout_changed,
+            stderr_changed,
+            duration_delta_ms: replay.execution_time as i64 - original.execution_time as i64,
+        }
+    }
+}
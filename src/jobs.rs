@@ -0,0 +1,187 @@
+//! # Asynchronous jobs
+//!
+//! `POST /jobs` accepts an execute request and answers at once with a job id;
+//! the execution runs on a background task and `GET /jobs/{id}` reports it as
+//! `queued`, `running` or `completed`, with the full execute response once it
+//! has finished. This keeps long executions from holding a connection open
+//! through proxies with short timeouts.
+//!
+//! At most `jobs.max_concurrent` jobs run at once; the others wait in the
+//! queue. A job leaving the queue still takes its API key's and the server's
+//! execution slots like an `/execute` request, or completes with the refusal.
+//! Once `jobs.max_queued` jobs are queued or running, submissions are
+//! refused with `JOB_QUEUE_FULL` (429). Completed jobs are kept for
+//! `jobs.ttl_secs`, and at most `jobs.capacity` of them; the oldest go first.
+
+use crate::config::JobsConfig;
+use crate::error::AppError;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Where a job is in its life
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for one of the `max_concurrent` slots
+    Queued,
+    /// The execution is running
+    Running,
+    /// The execution finished, successfully or not
+    Completed,
+}
+
+/// A job as reported by `GET /jobs/{id}`
+#[derive(Serialize, Debug, Clone)]
+pub struct JobRecord {
+    /// Identifier returned when the job was submitted
+    pub job_id: String,
+    /// Where the job is in its life
+    pub status: JobStatus,
+    /// ISO timestamp of the submission
+    pub submitted_at: String,
+    /// ISO timestamp of the start of the execution
+    pub started_at: Option<String>,
+    /// ISO timestamp of the end of the execution
+    pub finished_at: Option<String>,
+    /// Status `/execute` would have answered the request with
+    pub http_status: Option<u16>,
+    /// The execute response, in the response version the job was submitted with
+    pub result: Option<serde_json::Value>,
+}
+
+/// A job and when it finished
+struct Job {
+    /// What `GET /jobs/{id}` reports
+    record: JobRecord,
+    /// When the job completed, for eviction
+    finished: Option<Instant>,
+}
+
+/// Jobs submitted to this server, by id
+pub struct Jobs {
+    /// Jobs queued or running before submissions are refused
+    max_queued: usize,
+    /// Completed jobs kept
+    capacity: usize,
+    /// How long a completed job is kept
+    ttl: Duration,
+    /// All jobs not evicted yet
+    jobs: Mutex<HashMap<String, Job>>,
+    /// One permit per job allowed to run at once
+    slots: Arc<Semaphore>,
+}
+
+impl Jobs {
+    /// Create an empty store from the `[jobs]` section
+    pub fn new(config: &JobsConfig) -> Self {
+        Jobs {
+            max_queued: config.max_queued,
+            capacity: config.capacity,
+            ttl: Duration::from_secs(config.ttl_secs),
+            jobs: Mutex::new(HashMap::new()),
+            slots: Arc::new(Semaphore::new(config.max_concurrent)),
+        }
+    }
+
+    /// Accept a job into the queue
+    ///
+    /// # Returns
+    ///
+    /// * `Result<String, AppError>` - The new job's id, or `JobQueueFull` when
+    ///   `max_queued` jobs are queued or running already
+    pub fn submit(&self) -> Result<String, AppError> {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .map_err(|_| AppError::Internal("The job store is unavailable".to_string()))?;
+        self.evict(&mut jobs);
+        let pending = jobs.values().filter(|job| job.finished.is_none()).count();
+        if pending >= self.max_queued {
+            return Err(AppError::JobQueueFull {
+                max_queued: self.max_queued,
+            });
+        }
+        let job_id = uuid::Uuid::new_v4().to_string();
+        jobs.insert(
+            job_id.clone(),
+            Job {
+                record: JobRecord {
+                    job_id: job_id.clone(),
+                    status: JobStatus::Queued,
+                    submitted_at: chrono::Utc::now().to_rfc3339(),
+                    started_at: None,
+                    finished_at: None,
+                    http_status: None,
+                    result: None,
+                },
+                finished: None,
+            },
+        );
+        Ok(job_id)
+    }
+
+    /// Wait until a job may run; the job runs until the permit is dropped
+    pub async fn wait_for_slot(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().acquire_owned().await.ok()
+    }
+
+    /// Mark a job as running
+    pub fn start(&self, job_id: &str) {
+        let Ok(mut jobs) = self.jobs.lock() else {
+            return;
+        };
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.record.status = JobStatus::Running;
+            job.record.started_at = Some(chrono::Utc::now().to_rfc3339());
+        }
+    }
+
+    /// Store the outcome of a job
+    ///
+    /// # Arguments
+    ///
+    /// * `job_id` - The job that finished
+    /// * `http_status` - Status `/execute` would have answered with
+    /// * `result` - The execute response, already in the job's response version
+    pub fn complete(&self, job_id: &str, http_status: u16, result: serde_json::Value) {
+        let Ok(mut jobs) = self.jobs.lock() else {
+            return;
+        };
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.record.status = JobStatus::Completed;
+            job.record.finished_at = Some(chrono::Utc::now().to_rfc3339());
+            job.record.http_status = Some(http_status);
+            job.record.result = Some(result);
+            job.finished = Some(Instant::now());
+        }
+        self.evict(&mut jobs);
+    }
+
+    /// Look up a job that has not been evicted
+    pub fn get(&self, job_id: &str) -> Option<JobRecord> {
+        let mut jobs = self.jobs.lock().ok()?;
+        self.evict(&mut jobs);
+        jobs.get(job_id).map(|job| job.record.clone())
+    }
+
+    /// Drop completed jobs past their TTL, then the oldest beyond `capacity`
+    fn evict(&self, jobs: &mut HashMap<String, Job>) {
+        jobs.retain(|_, job| {
+            job.finished
+                .is_none_or(|finished| finished.elapsed() < self.ttl)
+        });
+        let mut completed: Vec<(Instant, String)> = jobs
+            .iter()
+            .filter_map(|(job_id, job)| job.finished.map(|finished| (finished, job_id.clone())))
+            .collect();
+        if completed.len() > self.capacity {
+            completed.sort();
+            for (_, job_id) in &completed[..completed.len() - self.capacity] {
+                jobs.remove(job_id);
+            }
+        }
+    }
+}
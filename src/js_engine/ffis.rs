@@ -1,50 +1,212 @@
-use rquickjs::{Ctx, Function, Object, Result as QuickJsResult, Value};
+use crate::clock::GuestClock;
+use crate::compression::{self, InflateError};
+use crate::guest_env::GuestEnv;
+use crate::kv::{KvError, KvNamespace};
+use crate::limits::MAX_SLEEP_MS_PER_CALL;
+use crate::net::{self, FetchContext};
+use crate::output::{self, Channel, LogEntry, LogLevel, LogSource, OutputBudget};
+use crate::random::{DeterministicMode, GuestRandom, RandomError, MAX_RANDOM_BYTES_PER_CALL};
+use crate::rfc3339;
+use crate::storage::{Namespace, StorageError};
+use crate::types::{BodyEncoding, WasmFetchOptions, WasmFetchResponse};
+use chrono_tz::Tz;
+use rquickjs::function::Opt;
+use rquickjs::{
+    Coerced, Ctx, Exception, Function, Object, Result as QuickJsResult, TypedArray, Value,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// Output buffers for capturing stdout and stderr
 pub struct OutputBuffers {
     pub stdout: Arc<Mutex<String>>,
     pub stderr: Arc<Mutex<String>>,
+    /// Console calls admitted to the buffers, with their severity
+    pub logs: Arc<Mutex<Vec<LogEntry>>>,
+    /// Byte budget shared by both buffers
+    pub budget: Arc<OutputBudget>,
+    /// Calls made to the capture functions
+    pub console_calls: Arc<ConsoleCalls>,
+}
+
+/// Per-execution count of capture calls (console.log, console.error, app_log and the internals)
+///
+/// Empty messages cost nothing against the byte budget but every call still
+/// crosses into the host, so the number of calls is limited separately.
+pub struct ConsoleCalls {
+    /// Calls captured before further calls are dropped
+    max: u64,
+    /// Calls admitted or dropped so far
+    calls: AtomicU64,
+    /// Calls dropped after the limit was reached
+    dropped: AtomicU64,
+}
+
+impl ConsoleCalls {
+    /// Create a counter allowing `max` calls
+    pub fn new(max: u64) -> Self {
+        ConsoleCalls {
+            max,
+            calls: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a call, returning `false` if it must be dropped
+    fn admit(&self) -> bool {
+        if self.calls.fetch_add(1, Ordering::Relaxed) < self.max {
+            true
+        } else {
+            self.drop_call();
+            false
+        }
+    }
+
+    /// Count a call that was dropped without reaching the capture functions
+    fn drop_call(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of dropped calls
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Append a line to a capture buffer, and publish it live, if the output budget admits it
+///
+/// Throws once a strict budget is exhausted, otherwise drops the line silently.
+fn capture_line(
+    ctx: &Ctx<'_>,
+    budget: &OutputBudget,
+    channel: Channel,
+    buffer: &Mutex<String>,
+    message: &str,
+) -> QuickJsResult<bool> {
+    if !budget.admit(channel, message.len() + 1) {
+        if budget.should_terminate() {
+            return Err(Exception::throw_message(ctx, "output budget exceeded"));
+        }
+        return Ok(false);
+    }
+    {
+        let mut buffer = output::lock(buffer);
+        buffer.push_str(message);
+        buffer.push('\n');
+    }
+    budget.publish(channel, message);
+    Ok(true)
+}
+
+/// Record a captured console call in the structured logs
+///
+/// Calls without a known level, such as direct calls to the internal capture
+/// functions, are not console calls and are not recorded.
+fn record_log(logs: &Mutex<Vec<LogEntry>>, level: Option<String>, message: &str) {
+    let Some(level) = level.as_deref().and_then(LogLevel::parse) else {
+        return;
+    };
+    output::lock(logs).push(LogEntry::now(level, LogSource::Console, message));
 }
 
 /// Register JavaScript functions directly to the global object with output capturing
 ///
 /// This approach attaches functions directly to the global object and
-/// captures console.log and console.error output.
+/// captures console output: `log`, `info` and `debug` go to stdout, `warn`,
+/// `error` and `trace` to stderr, and every captured call is also recorded
+/// with its level. `fetch_ctx` backs the `fetch` and `hoyaFetch` globals and
+/// may be shared with other users of the execution's fetch budget. `atob`,
+/// `btoa`, `TextEncoder` and `TextDecoder` (UTF-8 only) are defined too.
 pub fn register_to_globals_with_capture<'js>(
     ctx: &Ctx<'js>,
     output_buffers: OutputBuffers,
+    fetch_ctx: Arc<FetchContext>,
 ) -> QuickJsResult<()> {
     // Get the global object
     let globals = ctx.globals();
 
-    // Capture stdout buffer for console.log
+    // Register internal capture functions; every call counts against the call limit
     let stdout = output_buffers.stdout.clone();
-    let console_log_str = format!(
-        r#"
-        function(...args) {{
-            const message = args.map(arg => 
-                typeof arg === 'object' ? JSON.stringify(arg) : String(arg)
-            ).join(' ');
-            __internal_capture_stdout(message);
-        }}
-        "#
-    );
-    let console_log_fn: Value = ctx.eval(console_log_str)?;
-
-    // Capture stderr buffer for console.error
+    let stdout_budget = output_buffers.budget.clone();
+    let stdout_calls = output_buffers.console_calls.clone();
+    let stdout_logs = output_buffers.logs.clone();
+    let capture_stdout_fn = Function::new(
+        ctx.clone(),
+        move |ctx: Ctx<'js>,
+              message: String,
+              is_log: Opt<bool>,
+              level: Opt<String>|
+              -> QuickJsResult<bool> {
+            if !stdout_calls.admit() {
+                return Ok(false);
+            }
+            let channel = if is_log.0.unwrap_or(false) {
+                Channel::Log
+            } else {
+                Channel::Stdout
+            };
+            if capture_line(&ctx, &stdout_budget, channel, &stdout, &message)? {
+                record_log(&stdout_logs, level.0, &message);
+                if stdout_budget.echo_to_host() {
+                    output::echo_line("javascript", channel, &message);
+                }
+            }
+            Ok(true)
+        },
+    )?;
+    globals.set("__internal_capture_stdout", capture_stdout_fn.clone())?;
+
     let stderr = output_buffers.stderr.clone();
-    let console_error_str = format!(
-        r#"
-        function(...args) {{
-            const message = args.map(arg => 
-                typeof arg === 'object' ? JSON.stringify(arg) : String(arg)
-            ).join(' ');
-            __internal_capture_stderr(message);
-        }}
-        "#
-    );
-    let console_error_fn: Value = ctx.eval(console_error_str)?;
+    let stderr_budget = output_buffers.budget.clone();
+    let stderr_calls = output_buffers.console_calls.clone();
+    let stderr_logs = output_buffers.logs.clone();
+    let capture_stderr_fn = Function::new(
+        ctx.clone(),
+        move |ctx: Ctx<'js>, message: String, level: Opt<String>| -> QuickJsResult<bool> {
+            if !stderr_calls.admit() {
+                return Ok(false);
+            }
+            if capture_line(&ctx, &stderr_budget, Channel::Stderr, &stderr, &message)? {
+                record_log(&stderr_logs, level.0, &message);
+                if stderr_budget.echo_to_host() {
+                    output::echo_line("javascript", Channel::Stderr, &message);
+                }
+            }
+            Ok(true)
+        },
+    )?;
+    globals.set("__internal_capture_stderr", capture_stderr_fn.clone())?;
+
+    // Once the call limit is reached the wrappers below stop formatting their
+    // arguments and only count the dropped call with this zero-argument function
+    let drop_calls = output_buffers.console_calls.clone();
+    let drop_call_fn = Function::new(ctx.clone(), move || drop_calls.drop_call())?;
+
+    // The console functions format their arguments and capture them with their level;
+    // console.trace appends the caller's stack, without the frame of the wrapper itself
+    let console_fn_str = r#"
+        (function(capture, drop, level, toStdout) {
+            let muted = false;
+            return function(...args) {
+                if (muted) {
+                    drop();
+                    return;
+                }
+                let message = args.map(arg => 
+                    typeof arg === 'object' ? JSON.stringify(arg) : String(arg)
+                ).join(' ');
+                if (level === 'trace') {
+                    const frames = String(new Error().stack || '').split('\n')
+                        .filter(line => line.trim()).slice(1);
+                    message = ['Trace' + (message ? ': ' + message : '')].concat(frames).join('\n');
+                }
+                muted = !(toStdout ? capture(message, false, level) : capture(message, level));
+            };
+        })
+        "#;
+    let console_fn_factory: Function = ctx.eval(console_fn_str)?;
 
     // Create console object if it doesn't exist
     let console_exists: bool = ctx.eval("typeof console !== 'undefined'")?;
@@ -52,68 +214,1006 @@ pub fn register_to_globals_with_capture<'js>(
         ctx.eval::<(), _>("var console = {};")?;
     }
 
-    // Set the console.log and console.error functions
+    // log, info and debug go to stdout; warn, error and trace to stderr
     let console: Object = ctx.eval("console")?;
-    console.set("log", console_log_fn)?;
-    console.set("error", console_error_fn)?;
+    for (level, to_stdout) in [
+        ("log", true),
+        ("info", true),
+        ("debug", true),
+        ("warn", false),
+        ("error", false),
+        ("trace", false),
+    ] {
+        let capture = if to_stdout {
+            capture_stdout_fn.clone()
+        } else {
+            capture_stderr_fn.clone()
+        };
+        let console_fn: Value =
+            console_fn_factory.call((capture, drop_call_fn.clone(), level, to_stdout))?;
+        console.set(level, console_fn)?;
+    }
 
-    // Register internal capture functions
-    let stdout_clone = stdout.clone();
+    // Register the app_log host function; it counts against the call limit like
+    // the capture functions, and records the message with its normalized level
+    let app_log_stdout = output_buffers.stdout.clone();
+    let app_log_budget = output_buffers.budget.clone();
+    let app_log_calls = output_buffers.console_calls.clone();
+    let app_log_logs = output_buffers.logs.clone();
+    let capture_app_log_fn = Function::new(
+        ctx.clone(),
+        move |ctx: Ctx<'js>, level: String, message: String| -> QuickJsResult<bool> {
+            if !app_log_calls.admit() {
+                return Ok(false);
+            }
+            let entry = LogEntry::now(LogLevel::from_app_log(&level), LogSource::AppLog, &message);
+            let line = format!("[JS LOG - {}]: {}", entry.level.label(), message);
+            if capture_line(&ctx, &app_log_budget, Channel::Log, &app_log_stdout, &line)? {
+                if app_log_budget.echo_to_host() {
+                    entry.echo("javascript");
+                }
+                output::lock(&app_log_logs).push(entry);
+            }
+            Ok(true)
+        },
+    )?;
+
+    // Create app_log function
+    let app_log_str = r#"
+    (function(capture, drop) {
+        let muted = false;
+        return function(level, message) {
+            if (muted) {
+                drop();
+                return;
+            }
+            muted = !capture(String(level || 'INFO'), String(message || ''));
+        };
+    })
+    "#;
+    let app_log_factory: Function = ctx.eval(app_log_str)?;
+    let app_log_fn: Value = app_log_factory.call((capture_app_log_fn, drop_call_fn))?;
+    globals.set("app_log", app_log_fn)?;
+
+    // Create hoyaFormatRfc3339 function, the same UTC formatting as the wasm format_rfc3339 import
     globals.set(
-        "__internal_capture_stdout",
-        Function::new(ctx.clone(), move |message: String| -> QuickJsResult<()> {
-            println!("{}", &message); // Also print to host stdout for debugging
-            if let Ok(mut buffer) = stdout_clone.lock() {
-                buffer.push_str(&message);
-                buffer.push('\n');
-            }
-            Ok(())
-        })?,
+        "hoyaFormatRfc3339",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, seconds: f64| -> QuickJsResult<String> {
+                // Casting saturates, so huge values land outside chrono's range
+                let formatted = seconds.is_finite().then(|| seconds.floor() as i64);
+                formatted.and_then(rfc3339::format).ok_or_else(|| {
+                    Exception::throw_range(
+                        &ctx,
+                        &format!("{} is outside the representable time range", seconds),
+                    )
+                })
+            },
+        )?,
     )?;
 
-    let stderr_clone = stderr.clone();
+    // Create fetch function, taking and returning plain objects; the script waits
+    // for the request, which runs on the fetch runtime
+    let object_fetch_ctx = fetch_ctx.clone();
     globals.set(
-        "__internal_capture_stderr",
-        Function::new(ctx.clone(), move |message: String| -> QuickJsResult<()> {
-            eprintln!("{}", &message); // Also print to host stderr for debugging
-            if let Ok(mut buffer) = stderr_clone.lock() {
-                buffer.push_str(&message);
-                buffer.push('\n');
-            }
-            Ok(())
-        })?,
+        "fetch",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, options: Object<'js>| -> QuickJsResult<Object<'js>> {
+                let options = fetch_options(&ctx, &options)?;
+                let response = net::fetch_blocking(&object_fetch_ctx, options)
+                    .map_err(|e| throw_with_code(&ctx, "FETCH_INVALID_REQUEST", &e.to_string()))?;
+                fetch_response(&ctx, response)
+            },
+        )?,
     )?;
 
-    // Create app_log function
-    let app_log_str = r#"
-    function(level, message) {
-        console.log("[JS LOG - " + (level || 'INFO').toUpperCase() + "]: " + (message || ''));
-    }
+    // Create hoyaFetch function, speaking the same JSON protocol as the wasm fetch import
+    globals.set(
+        "hoyaFetch",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, options_json: String| -> QuickJsResult<String> {
+                let response_json = net::fetch_json_blocking(&fetch_ctx, options_json.as_bytes())
+                    .map_err(|e| Exception::throw_message(&ctx, &e.to_string()))?;
+                String::from_utf8(response_json)
+                    .map_err(|e| Exception::throw_message(&ctx, &e.to_string()))
+            },
+        )?,
+    )?;
+
+    // Create hoyaGzip/hoyaGunzip functions, backed by the same helpers as the wasm gzip imports
+    globals.set(
+        "hoyaGzip",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, data: TypedArray<'js, u8>| -> QuickJsResult<TypedArray<'js, u8>> {
+                let source = data.as_bytes().unwrap_or_default();
+                let compressed = compression::gzip(source)
+                    .map_err(|e| Exception::throw_message(&ctx, &e.to_string()))?;
+                TypedArray::new(ctx, compressed)
+            },
+        )?,
+    )?;
+    globals.set(
+        "hoyaGunzip",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, data: TypedArray<'js, u8>| -> QuickJsResult<TypedArray<'js, u8>> {
+                let source = data.as_bytes().unwrap_or_default();
+                match compression::inflate(source, compression::MAX_GUEST_DECOMPRESSED_SIZE) {
+                    Ok(decompressed) => TypedArray::new(ctx, decompressed),
+                    Err(InflateError::Corrupt(e)) => Err(throw_with_code(
+                        &ctx,
+                        "GZIP_CORRUPT",
+                        &format!("Invalid gzip stream: {}", e),
+                    )),
+                    Err(InflateError::TooLarge) => Err(throw_with_code(
+                        &ctx,
+                        "GZIP_TOO_LARGE",
+                        &format!(
+                            "Decompressed size exceeds the limit of {} bytes",
+                            compression::MAX_GUEST_DECOMPRESSED_SIZE
+                        ),
+                    )),
+                }
+            },
+        )?,
+    )?;
+
+    // Create atob/btoa and the UTF-8 TextEncoder/TextDecoder; the conversions
+    // and their validation are native, the classes only hold the options
+    globals.set(
+        "btoa",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, data: Coerced<String>| -> QuickJsResult<String> {
+                let bytes = data
+                    .chars()
+                    .map(|c| u8::try_from(u32::from(c)).ok())
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| {
+                        throw_dom_exception(
+                            &ctx,
+                            "InvalidCharacterError",
+                            "The string to encode contains characters outside of the Latin1 range",
+                        )
+                    })?;
+                Ok(crate::base64::encode(&bytes))
+            },
+        )?,
+    )?;
+    globals.set(
+        "atob",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, data: Coerced<String>| -> QuickJsResult<String> {
+                let bytes = forgiving_base64_decode(&data).ok_or_else(|| {
+                    throw_dom_exception(
+                        &ctx,
+                        "InvalidCharacterError",
+                        "The string to decode is not correctly encoded",
+                    )
+                })?;
+                Ok(bytes.into_iter().map(char::from).collect())
+            },
+        )?,
+    )?;
+    let encode_utf8 = Function::new(
+        ctx.clone(),
+        move |ctx: Ctx<'js>, text: Coerced<String>| -> QuickJsResult<TypedArray<'js, u8>> {
+            TypedArray::new(ctx, text.0.into_bytes())
+        },
+    )?;
+    let decode_utf8 = Function::new(
+        ctx.clone(),
+        move |ctx: Ctx<'js>,
+              data: TypedArray<'js, u8>,
+              fatal: bool,
+              ignore_bom: bool|
+              -> QuickJsResult<String> {
+            let mut bytes = data.as_bytes().unwrap_or_default();
+            if !ignore_bom {
+                bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+            }
+            if fatal {
+                return std::str::from_utf8(bytes).map(str::to_string).map_err(|_| {
+                    Exception::throw_type(&ctx, "The encoded data is not valid UTF-8")
+                });
+            }
+            Ok(String::from_utf8_lossy(bytes).into_owned())
+        },
+    )?;
+    let text_codecs_str = r#"
+    (function(encodeUtf8, decodeUtf8) {
+        const labels = ["utf-8", "utf8", "unicode-1-1-utf-8", "unicode11utf8", "unicode20utf8",
+                        "x-unicode20utf8"];
+        class TextEncoder {
+            get encoding() { return "utf-8"; }
+            encode(input = "") { return encodeUtf8(input); }
+        }
+        class TextDecoder {
+            #fatal;
+            #ignoreBOM;
+            constructor(label = "utf-8", options = {}) {
+                if (!labels.includes(String(label).trim().toLowerCase())) {
+                    throw new RangeError(`The encoding label "${label}" is not supported`);
+                }
+                this.#fatal = Boolean(options.fatal);
+                this.#ignoreBOM = Boolean(options.ignoreBOM);
+            }
+            get encoding() { return "utf-8"; }
+            get fatal() { return this.#fatal; }
+            get ignoreBOM() { return this.#ignoreBOM; }
+            decode(input) {
+                if (input === undefined) {
+                    return "";
+                }
+                let bytes;
+                if (input instanceof ArrayBuffer) {
+                    bytes = new Uint8Array(input);
+                } else if (ArrayBuffer.isView(input)) {
+                    bytes = new Uint8Array(input.buffer, input.byteOffset, input.byteLength);
+                } else {
+                    throw new TypeError("The input must be an ArrayBuffer or a view of one");
+                }
+                return decodeUtf8(bytes, this.#fatal, this.#ignoreBOM);
+            }
+        }
+        globalThis.TextEncoder = TextEncoder;
+        globalThis.TextDecoder = TextDecoder;
+    })
     "#;
-    let app_log_fn: Value = ctx.eval(app_log_str)?;
-    globals.set("app_log", app_log_fn)?;
+    let text_codecs_fn: Function = ctx.eval(text_codecs_str)?;
+    text_codecs_fn.call::<_, ()>((encode_utf8, decode_utf8))?;
+
+    Ok(())
+}
+
+/// Read the `{url, method, headers, body, body_encoding, timeout_ms,
+/// max_redirects, max_response_bytes}` options of a `fetch` call
+///
+/// `url` is required; `method` defaults to `GET`, and header values and the
+/// body are converted to strings. `body_encoding` is `"utf8"` (the default) or
+/// `"base64"`, and the limits are non-negative numbers, as in the JSON options
+/// of `hoyaFetch`.
+fn fetch_options<'js>(ctx: &Ctx<'js>, options: &Object<'js>) -> QuickJsResult<WasmFetchOptions> {
+    let url: Option<String> = options.get("url")?;
+    let url = url.ok_or_else(|| {
+        throw_with_code(
+            ctx,
+            "FETCH_INVALID_REQUEST",
+            "fetch: options.url must be a string",
+        )
+    })?;
+    let method: Option<String> = options.get("method")?;
+    let mut headers = HashMap::new();
+    if let Some(header_object) = options.get::<_, Option<Object<'js>>>("headers")? {
+        for header in header_object.props::<String, Coerced<String>>() {
+            let (name, value) = header?;
+            headers.insert(name, value.0);
+        }
+    }
+    let body: Option<Coerced<String>> = options.get("body")?;
+    let body_encoding: Option<String> = options.get("body_encoding")?;
+    let body_encoding = match body_encoding.as_deref() {
+        None | Some("utf8") => BodyEncoding::Utf8,
+        Some("base64") => BodyEncoding::Base64,
+        Some(other) => {
+            return Err(throw_with_code(
+                ctx,
+                "FETCH_INVALID_REQUEST",
+                &format!("fetch: unknown body_encoding {:?}", other),
+            ))
+        }
+    };
+    let limit = |name: &str| -> QuickJsResult<Option<u64>> {
+        match options.get::<_, Option<f64>>(name)? {
+            Some(value) if value.is_nan() || value < 0.0 => Err(throw_with_code(
+                ctx,
+                "FETCH_INVALID_REQUEST",
+                &format!("fetch: options.{} must be a non-negative number", name),
+            )),
+            value => Ok(value.map(|value| value as u64)),
+        }
+    };
+    Ok(WasmFetchOptions {
+        url,
+        method: method.unwrap_or_else(|| "GET".to_string()),
+        headers,
+        body: body.map(|body| body.0),
+        body_encoding,
+        cookies: None,
+        timeout_ms: limit("timeout_ms")?,
+        max_redirects: limit("max_redirects")?.map(|max| max as usize),
+        max_response_bytes: limit("max_response_bytes")?,
+    })
+}
+
+/// Convert a fetch response into the `{status, headers, body, body_encoding}` object returned
+/// to scripts, throwing its error envelope instead if it has one
+fn fetch_response<'js>(ctx: &Ctx<'js>, response: WasmFetchResponse) -> QuickJsResult<Object<'js>> {
+    if let Some(error) = response.error {
+        return Err(throw_with_code(ctx, &error.code, &error.message));
+    }
+    let headers = Object::new(ctx.clone())?;
+    for (name, value) in response.headers {
+        headers.set(name, value)?;
+    }
+    let result = Object::new(ctx.clone())?;
+    result.set("status", response.status)?;
+    result.set("headers", headers)?;
+    result.set("body", response.body)?;
+    result.set("body_encoding", response.body_encoding.name())?;
+    Ok(result)
+}
 
-    // Create get_unixtime function
-    let get_unixtime_str = r#"
-    function() {
-        return Date.now() / 1000;
+/// Throw an `Error` carrying a machine-readable `code` property
+fn throw_with_code(ctx: &Ctx<'_>, code: &str, message: &str) -> rquickjs::Error {
+    let exception = match Exception::from_message(ctx.clone(), message) {
+        Ok(exception) => exception,
+        Err(e) => return e,
+    };
+    if let Err(e) = exception.as_object().set("code", code) {
+        return e;
     }
+    ctx.throw(exception.into_value())
+}
+
+/// Decode base64 the way `atob` does
+///
+/// ASCII whitespace is ignored and the padding is optional, but where there
+/// is padding it must complete the last group.
+///
+/// # Returns
+///
+/// * `Option<Vec<u8>>` - The bytes, or `None` for input that is not base64
+fn forgiving_base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut text: String = text
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' '))
+        .collect();
+    if text.len() % 4 == 0 {
+        let unpadded = text.trim_end_matches('=').len();
+        if text.len() - unpadded > 2 {
+            return None;
+        }
+        text.truncate(unpadded);
+    }
+    if text.len() % 4 == 1 || text.contains('=') {
+        return None;
+    }
+    crate::base64::decode(&text)
+}
+
+/// Throw an `Error` shaped like a `DOMException`, with `name` as its name
+fn throw_dom_exception(ctx: &Ctx<'_>, name: &str, message: &str) -> rquickjs::Error {
+    let exception = match Exception::from_message(ctx.clone(), message) {
+        Ok(exception) => exception,
+        Err(e) => return e,
+    };
+    if let Err(e) = exception.as_object().set("name", name) {
+        return e;
+    }
+    ctx.throw(exception.into_value())
+}
+
+/// Replace `Math.random` with a host function drawing from the execution's seeded stream
+///
+/// Installed before user code runs. `random` is the execution's source,
+/// seeded from `mode`, which `crypto` draws from too. With
+/// `lock_math_random`, both `Math.random` and the global `Math` binding
+/// become non-writable and non-configurable, so scripts cannot swap in
+/// another generator.
+pub fn install_deterministic_random(
+    ctx: &Ctx<'_>,
+    mode: DeterministicMode,
+    random: Arc<Mutex<GuestRandom>>,
+) -> QuickJsResult<()> {
+    let random = Function::new(ctx.clone(), move || -> f64 {
+        random
+            .lock()
+            .ok()
+            .and_then(|mut random| random.next_f64())
+            .unwrap_or(0.0)
+    })?;
+
+    let install_str = r#"
+    (function(random, lock) {
+        Object.defineProperty(Math, "random", {
+            value: random,
+            writable: !lock,
+            configurable: !lock,
+            enumerable: false
+        });
+        if (lock) {
+            Object.defineProperty(globalThis, "Math", { writable: false, configurable: false });
+        }
+    })
     "#;
-    let get_unixtime_fn: Value = ctx.eval(get_unixtime_str)?;
-    globals.set("get_unixtime", get_unixtime_fn)?;
-
-    // Create fetch function
-    let fetch_str = r#"
-    function(options) {
-        throw {
-            code: "FETCH_NOT_IMPLEMENTED",
-            message: "fetch is not fully implemented in this runtime",
-            details: { requestedUrl: options && options.url }
+    let install_fn: Function = ctx.eval(install_str)?;
+    install_fn.call::<_, ()>((random, mode.lock_math_random))?;
+
+    Ok(())
+}
+
+/// Expose `crypto.getRandomValues(array)` and `crypto.randomUUID()`, drawing from `random`
+///
+/// `getRandomValues` fills an integer typed array in place and returns it;
+/// other arrays throw an `Error` named `TypeMismatchError`, and requests past
+/// the per-call or per-execution budget one named `QuotaExceededError`.
+/// `randomUUID` returns a version 4 UUID string.
+pub fn install_crypto<'js>(ctx: &Ctx<'js>, random: Arc<Mutex<GuestRandom>>) -> QuickJsResult<()> {
+    let draw = move |ctx: &Ctx<'js>, len: usize| -> QuickJsResult<Vec<u8>> {
+        // One byte past the limit is enough for `fill` to refuse an oversized request
+        let mut bytes = vec![0u8; len.min(MAX_RANDOM_BYTES_PER_CALL + 1)];
+        let filled = match random.lock() {
+            Ok(mut random) => random.fill(&mut bytes),
+            Err(_) => Err(RandomError::Unavailable),
         };
+        match filled {
+            Ok(()) => Ok(bytes),
+            Err(RandomError::Unavailable) => Err(Exception::throw_message(
+                ctx,
+                &RandomError::Unavailable.to_string(),
+            )),
+            Err(e) => Err(throw_dom_exception(
+                ctx,
+                "QuotaExceededError",
+                &e.to_string(),
+            )),
+        }
+    };
+    let uuid_draw = draw.clone();
+    let random_bytes = Function::new(
+        ctx.clone(),
+        move |ctx: Ctx<'js>, len: u32| -> QuickJsResult<TypedArray<'js, u8>> {
+            TypedArray::new(ctx.clone(), draw(&ctx, len as usize)?)
+        },
+    )?;
+    let random_uuid = Function::new(ctx.clone(), move |ctx: Ctx<'js>| -> QuickJsResult<String> {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&uuid_draw(&ctx, bytes.len())?);
+        Ok(uuid::Builder::from_random_bytes(bytes)
+            .into_uuid()
+            .to_string())
+    })?;
+
+    let install_str = r#"
+    (function(randomBytes, randomUUID) {
+        const integerArrays = [Int8Array, Uint8Array, Uint8ClampedArray, Int16Array, Uint16Array,
+                               Int32Array, Uint32Array, BigInt64Array, BigUint64Array];
+        const crypto = {
+            getRandomValues(array) {
+                if (!integerArrays.some((type) => array instanceof type)) {
+                    const error = new TypeError("getRandomValues needs an integer typed array");
+                    error.name = "TypeMismatchError";
+                    throw error;
+                }
+                new Uint8Array(array.buffer, array.byteOffset, array.byteLength)
+                    .set(randomBytes(array.byteLength));
+                return array;
+            },
+            randomUUID() {
+                return randomUUID();
+            }
+        };
+        Object.defineProperty(globalThis, "crypto", {
+            value: crypto, writable: true, configurable: true, enumerable: false
+        });
+    })
+    "#;
+    let install_fn: Function = ctx.eval(install_str)?;
+    install_fn.call::<_, ()>((random_bytes, random_uuid))
+}
+
+/// Expose the execution's time zone and make `Date`'s local-time helpers use it
+///
+/// Sets `HOYA_TIMEZONE`, `hoyaLocalTime()` (the current time as RFC 3339 with
+/// the zone's offset) and `hoyaUtcOffsetSeconds(ms?)`, and replaces the local
+/// getters (`getHours`, `getDate`, ...), `getTimezoneOffset`, `toString`,
+/// `toDateString` and `toTimeString` of `Date.prototype` so they follow the
+/// zone rather than the host's. Local setters still use the host's zone. The
+/// current time is read from `clock`.
+pub fn install_timezone(ctx: &Ctx<'_>, tz: Tz, clock: Arc<GuestClock>) -> QuickJsResult<()> {
+    let globals = ctx.globals();
+    globals.set("HOYA_TIMEZONE", tz.name())?;
+    let local_clock = clock.clone();
+    globals.set(
+        "hoyaLocalTime",
+        Function::new(ctx.clone(), move || -> Option<String> {
+            let ms = local_clock.unix_ms()?;
+            rfc3339::format_local((ms / 1000) as i64, tz)
+        })?,
+    )?;
+    let offset_fn = Function::new(ctx.clone(), move |millis: Opt<f64>| -> f64 {
+        let millis = match millis.0 {
+            Some(millis) if millis.is_finite() => millis as i64,
+            Some(_) => return f64::NAN,
+            None => match clock.unix_ms() {
+                Some(ms) => ms as i64,
+                None => return f64::NAN,
+            },
+        };
+        rfc3339::utc_offset_seconds(millis, tz).map_or(f64::NAN, f64::from)
+    })?;
+    globals.set("hoyaUtcOffsetSeconds", offset_fn.clone())?;
+
+    let install_str = r#"
+    (function(offsetAt, zone) {
+        const proto = Date.prototype;
+        const define = (name, value) => Object.defineProperty(proto, name, {
+            value: value, writable: true, configurable: true, enumerable: false
+        });
+        const getters = {
+            getFullYear: proto.getUTCFullYear, getMonth: proto.getUTCMonth,
+            getDate: proto.getUTCDate, getDay: proto.getUTCDay,
+            getHours: proto.getUTCHours, getMinutes: proto.getUTCMinutes,
+            getSeconds: proto.getUTCSeconds, getMilliseconds: proto.getUTCMilliseconds
+        };
+        for (const name in getters) {
+            const utcGetter = getters[name];
+            define(name, function() {
+                const time = this.getTime();
+                return isNaN(time) ? NaN : utcGetter.call(new Date(time + offsetAt(time) * 1000));
+            });
+        }
+        define("getTimezoneOffset", function() {
+            const time = this.getTime();
+            return isNaN(time) ? NaN : -offsetAt(time) / 60;
+        });
+
+        const days = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+        const months = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                        "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+        const pad = (value, width) => String(value).padStart(width || 2, "0");
+        const dateString = (date) => days[date.getDay()] + " " + months[date.getMonth()] + " " +
+            pad(date.getDate()) + " " + pad(date.getFullYear(), 4);
+        const timeString = (date) => {
+            const offset = -date.getTimezoneOffset();
+            const abs = Math.abs(offset);
+            return pad(date.getHours()) + ":" + pad(date.getMinutes()) + ":" + pad(date.getSeconds()) +
+                " GMT" + (offset < 0 ? "-" : "+") + pad(Math.floor(abs / 60)) + pad(abs % 60) +
+                " (" + zone + ")";
+        };
+        define("toString", function() {
+            return isNaN(this.getTime()) ? "Invalid Date" : dateString(this) + " " + timeString(this);
+        });
+        define("toDateString", function() {
+            return isNaN(this.getTime()) ? "Invalid Date" : dateString(this);
+        });
+        define("toTimeString", function() {
+            return isNaN(this.getTime()) ? "Invalid Date" : timeString(this);
+        });
+    })
+    "#;
+    let install_fn: Function = ctx.eval(install_str)?;
+    install_fn.call::<_, ()>((offset_fn, tz.name()))?;
+
+    Ok(())
+}
+
+/// Timers that may be pending at once; scheduling one more throws a `RangeError`
+const MAX_PENDING_TIMERS: usize = 10_000;
+
+/// Shortest period of an interval, so a 0 ms interval does not spin the event loop
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Timers scheduled by `setTimeout` and `setInterval`, shared by the timer
+/// globals and the event loop that fires them
+///
+/// The queue holds ids and due times only; the callbacks stay in the
+/// context, with the function `install_timers` returns.
+#[derive(Clone, Default)]
+pub struct Timers(Arc<Mutex<TimerQueue>>);
+
+/// Contents of `Timers`
+#[derive(Default)]
+struct TimerQueue {
+    /// The id handed out last
+    last_id: u32,
+    /// Pending timers by due time, then id, with the period of intervals
+    due: BTreeMap<(Instant, u32), Option<Duration>>,
+    /// Due time of each pending timer
+    by_id: HashMap<u32, Instant>,
+}
+
+impl Timers {
+    /// Schedule a timer firing after `delay`, and every `delay` after that if
+    /// `repeat`; `None` once `MAX_PENDING_TIMERS` are pending
+    fn schedule(&self, delay: Duration, repeat: bool) -> Option<u32> {
+        let mut queue = self.0.lock().ok()?;
+        if queue.by_id.len() >= MAX_PENDING_TIMERS {
+            return None;
+        }
+        queue.last_id = queue.last_id.wrapping_add(1).max(1);
+        let id = queue.last_id;
+        let due = Instant::now() + delay;
+        let period = repeat.then(|| delay.max(MIN_INTERVAL));
+        queue.due.insert((due, id), period);
+        queue.by_id.insert(id, due);
+        Some(id)
     }
+
+    /// Forget the timer `id`, if it is pending
+    fn clear(&self, id: u32) {
+        if let Ok(mut queue) = self.0.lock() {
+            if let Some(due) = queue.by_id.remove(&id) {
+                queue.due.remove(&(due, id));
+            }
+        }
+    }
+
+    /// When the next pending timer is due, or `None` when no timer is pending
+    pub fn next_due(&self) -> Option<Instant> {
+        let queue = self.0.lock().ok()?;
+        queue.due.keys().next().map(|(due, _)| *due)
+    }
+
+    /// Take the next timer due by `now`
+    ///
+    /// Intervals are scheduled again a period after `now`, before their
+    /// callback runs, so the callback can clear them.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<(u32, bool)>` - The timer's id and whether it fired for the
+    ///   last time, or `None` when no timer is due
+    pub fn take_due(&self, now: Instant) -> Option<(u32, bool)> {
+        let mut queue = self.0.lock().ok()?;
+        let (&(due, id), &period) = queue.due.iter().next()?;
+        if due > now {
+            return None;
+        }
+        queue.due.remove(&(due, id));
+        match period {
+            Some(period) => {
+                queue.due.insert((now + period, id), Some(period));
+                queue.by_id.insert(id, now + period);
+                Some((id, false))
+            }
+            None => {
+                queue.by_id.remove(&id);
+                Some((id, true))
+            }
+        }
+    }
+}
+
+/// Install `setTimeout`, `clearTimeout`, `setInterval`, `clearInterval`,
+/// `queueMicrotask` and `sleep`
+///
+/// Timers are scheduled in `timers` and fire when the engine's event loop
+/// calls the returned function with a timer's id and whether it fired for
+/// the last time; the function calls the timer's callback with its extra
+/// arguments. Delays are in milliseconds; negative or non-numeric ones count
+/// as 0. An exception thrown by a microtask is thrown again from a timer, so
+/// it ends the execution like one thrown by a timer callback. `sleep(ms)`
+/// returns a promise resolved by a timer after `ms` milliseconds, at most
+/// `MAX_SLEEP_MS_PER_CALL`: with 0, or with 2 when the sleep was capped, the
+/// same values as the WebAssembly `sleep_ms` import.
+///
+/// # Returns
+///
+/// * `QuickJsResult<Function>` - The function firing a timer's callback
+pub fn install_timers<'js>(ctx: &Ctx<'js>, timers: Timers) -> QuickJsResult<Function<'js>> {
+    let schedule_timers = timers.clone();
+    let schedule = Function::new(
+        ctx.clone(),
+        move |ctx: Ctx<'js>, delay: f64, repeat: bool| -> QuickJsResult<u32> {
+            let millis = if delay.is_nan() { 0.0 } else { delay.max(0.0) };
+            let delay = Duration::from_secs_f64(millis.min(i32::MAX as f64) / 1000.0);
+            schedule_timers.schedule(delay, repeat).ok_or_else(|| {
+                Exception::throw_range(
+                    &ctx,
+                    &format!("More than {} timers are pending", MAX_PENDING_TIMERS),
+                )
+            })
+        },
+    )?;
+    let clear = Function::new(ctx.clone(), move |id: u32| timers.clear(id))?;
+
+    let install_str = r#"
+    (function(schedule, clear, maxSleepMs) {
+        const callbacks = new Map();
+        const add = (repeat) => function(callback, delay, ...args) {
+            if (typeof callback !== "function") {
+                throw new TypeError("The timer callback must be a function");
+            }
+            const id = schedule(Number(delay) || 0, repeat);
+            callbacks.set(id, [callback, args]);
+            return id;
+        };
+        const remove = function(id) {
+            id = Number(id);
+            if (callbacks.delete(id)) {
+                clear(id);
+            }
+        };
+        const setTimeout = add(false);
+        globalThis.setTimeout = setTimeout;
+        globalThis.setInterval = add(true);
+        globalThis.clearTimeout = remove;
+        globalThis.clearInterval = remove;
+        globalThis.queueMicrotask = function(callback) {
+            if (typeof callback !== "function") {
+                throw new TypeError("The microtask callback must be a function");
+            }
+            Promise.resolve().then(() => callback()).catch((error) => {
+                setTimeout(() => { throw error; }, 0);
+            });
+        };
+        globalThis.sleep = function(ms) {
+            const delay = Number(ms) || 0;
+            const capped = delay > maxSleepMs;
+            return new Promise((resolve) => {
+                setTimeout(resolve, capped ? maxSleepMs : delay, capped ? 2 : 0);
+            });
+        };
+        return function(id, last) {
+            const entry = callbacks.get(id);
+            if (entry === undefined) {
+                return;
+            }
+            if (last) {
+                callbacks.delete(id);
+            }
+            entry[0].apply(globalThis, entry[1]);
+        };
+    })
     "#;
-    let fetch_fn: Value = ctx.eval(fetch_str)?;
-    globals.set("fetch", fetch_fn)?;
+    let install_fn: Function = ctx.eval(install_str)?;
+    install_fn.call((schedule, clear, MAX_SLEEP_MS_PER_CALL))
+}
 
+/// Expose the request's variables as the frozen `env` global
+///
+/// Each variable is an own, enumerable string property, defined rather than
+/// assigned so names such as `__proto__` hold their value too.
+pub fn install_env(ctx: &Ctx<'_>, env: &GuestEnv) -> QuickJsResult<()> {
+    let (names, values): (Vec<&str>, Vec<&str>) = env.iter().unzip();
+    let install_str = r#"
+    (function(names, values) {
+        const env = {};
+        for (let i = 0; i < names.length; i++) {
+            Object.defineProperty(env, names[i], { value: values[i], enumerable: true });
+        }
+        globalThis.env = Object.freeze(env);
+    })
+    "#;
+    let install_fn: Function = ctx.eval(install_str)?;
+    install_fn.call::<_, ()>((names, values))
+}
+
+/// Parse the request's `input` into the `input` global, or set it to `null` without one
+pub fn install_input(ctx: &Ctx<'_>, input: Option<&[u8]>) -> QuickJsResult<()> {
+    let value = match input {
+        Some(json) => ctx.json_parse(json)?,
+        None => Value::new_null(ctx.clone()),
+    };
+    ctx.globals().set("input", value)
+}
+
+/// Expose the execution's storage namespace as the `hoyaStore` global
+///
+/// `hoyaStore.get(key)` returns the stored string or `null`,
+/// `hoyaStore.set(key, value)` stores a string and `hoyaStore.delete(key)`
+/// returns whether the key was present. Refused operations throw an `Error`
+/// whose `code` is `STORAGE_INVALID_KEY`, `STORAGE_QUOTA_EXCEEDED` or
+/// `STORAGE_FAILED`.
+pub fn install_store<'js>(ctx: &Ctx<'js>, namespace: Namespace) -> QuickJsResult<()> {
+    let store = Object::new(ctx.clone())?;
+
+    let get_namespace = namespace.clone();
+    store.set(
+        "get",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, key: String| -> QuickJsResult<Option<String>> {
+                let value = get_namespace
+                    .get(key.as_bytes())
+                    .map_err(|e| throw_storage_error(&ctx, e))?;
+                Ok(value.map(|value| String::from_utf8_lossy(&value).into_owned()))
+            },
+        )?,
+    )?;
+
+    let set_namespace = namespace.clone();
+    store.set(
+        "set",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, key: String, value: String| -> QuickJsResult<()> {
+                set_namespace
+                    .set(key.as_bytes(), value.as_bytes())
+                    .map_err(|e| throw_storage_error(&ctx, e))
+            },
+        )?,
+    )?;
+
+    store.set(
+        "delete",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, key: String| -> QuickJsResult<bool> {
+                namespace
+                    .delete(key.as_bytes())
+                    .map_err(|e| throw_storage_error(&ctx, e))
+            },
+        )?,
+    )?;
+
+    ctx.globals().set("hoyaStore", store)?;
     Ok(())
 }
+
+/// Install the clock functions `get_unixtime`, `get_unixtime_ms` and `performance.now`
+///
+/// `get_unixtime()` returns whole seconds and `get_unixtime_ms()` whole
+/// milliseconds since the Unix epoch, like the WebAssembly imports of the
+/// same names; `performance.now()` returns milliseconds since the start of
+/// the execution, with sub-millisecond precision, and `performance.timeOrigin`
+/// the time it counts from. All of them read `clock`, so they are linearized
+/// in deterministic mode, where `Date.now()`, `Date()` and `new Date()`
+/// without arguments read it too. The host's clock being set before 1970 throws.
+pub fn install_clock<'js>(ctx: &Ctx<'js>, clock: Arc<GuestClock>) -> QuickJsResult<()> {
+    let globals = ctx.globals();
+    let unix_ms = move |ctx: &Ctx<'js>, clock: &GuestClock| -> QuickJsResult<f64> {
+        clock
+            .unix_ms()
+            .map(|ms| ms as f64)
+            .ok_or_else(|| Exception::throw_message(ctx, "Failed to get system time"))
+    };
+
+    let seconds_clock = clock.clone();
+    globals.set(
+        "get_unixtime",
+        Function::new(ctx.clone(), move |ctx: Ctx<'js>| -> QuickJsResult<f64> {
+            Ok((unix_ms(&ctx, &seconds_clock)? / 1000.0).floor())
+        })?,
+    )?;
+
+    let millis_clock = clock.clone();
+    globals.set(
+        "get_unixtime_ms",
+        Function::new(ctx.clone(), move |ctx: Ctx<'js>| -> QuickJsResult<f64> {
+            unix_ms(&ctx, &millis_clock)
+        })?,
+    )?;
+
+    if clock.is_deterministic() {
+        let date_clock = clock.clone();
+        let now = Function::new(ctx.clone(), move |ctx: Ctx<'js>| -> QuickJsResult<f64> {
+            unix_ms(&ctx, &date_clock)
+        })?;
+        // The current time comes from `now`; a Date built from arguments is the host's own
+        let install_str = r#"
+        (function(now) {
+            const HostDate = Date;
+            function GuestDate(...args) {
+                if (!new.target) {
+                    return new HostDate(now()).toString();
+                }
+                return Reflect.construct(HostDate, args.length === 0 ? [now()] : args, new.target);
+            }
+            Object.defineProperty(GuestDate, "name", { value: "Date" });
+            Object.defineProperty(GuestDate, "length", { value: HostDate.length });
+            GuestDate.prototype = HostDate.prototype;
+            GuestDate.now = now;
+            GuestDate.parse = HostDate.parse;
+            GuestDate.UTC = HostDate.UTC;
+            Object.defineProperty(HostDate.prototype, "constructor", {
+                value: GuestDate, writable: true, configurable: true, enumerable: false
+            });
+            Object.defineProperty(globalThis, "Date", {
+                value: GuestDate, writable: true, configurable: true, enumerable: false
+            });
+        })
+        "#;
+        let install_fn: Function = ctx.eval(install_str)?;
+        install_fn.call::<_, ()>((now,))?;
+    }
+
+    let performance = Object::new(ctx.clone())?;
+    performance.set("timeOrigin", clock.origin_unix_ms() as f64)?;
+    performance.set(
+        "now",
+        Function::new(ctx.clone(), move || -> f64 {
+            clock.monotonic_ns() as f64 / 1_000_000.0
+        })?,
+    )?;
+    globals.set("performance", performance)
+}
+
+/// Expose the execution's key-value namespace as `hoya.kv`
+///
+/// `hoya.kv.get(key)` returns the stored string or `null`,
+/// `hoya.kv.set(key, value, options)` stores a string, for `options.ttl_ms`
+/// milliseconds when given, and `hoya.kv.delete(key)` returns whether the key
+/// was present. Refused operations throw an `Error` whose `code` is
+/// `KV_INVALID_KEY` or `KV_QUOTA_EXCEEDED`.
+pub fn install_kv<'js>(ctx: &Ctx<'js>, namespace: KvNamespace) -> QuickJsResult<()> {
+    let kv = Object::new(ctx.clone())?;
+
+    let get_namespace = namespace.clone();
+    kv.set(
+        "get",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, key: String| -> QuickJsResult<Option<String>> {
+                let value = get_namespace
+                    .get(key.as_bytes())
+                    .map_err(|e| throw_kv_error(&ctx, e))?;
+                Ok(value.map(|value| String::from_utf8_lossy(&value).into_owned()))
+            },
+        )?,
+    )?;
+
+    let set_namespace = namespace.clone();
+    kv.set(
+        "set",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>,
+                  key: String,
+                  value: String,
+                  options: Opt<Option<Object<'js>>>|
+                  -> QuickJsResult<()> {
+                let ttl_ms = match options.0.flatten() {
+                    Some(options) => options.get::<_, Option<f64>>("ttl_ms")?,
+                    None => None,
+                };
+                let ttl = match ttl_ms.map(|ttl_ms| Duration::try_from_secs_f64(ttl_ms / 1000.0)) {
+                    None => None,
+                    Some(Ok(ttl)) if !ttl.is_zero() => Some(ttl),
+                    Some(_) => {
+                        return Err(Exception::throw_range(
+                            &ctx,
+                            "ttl_ms must be a positive number of milliseconds",
+                        ));
+                    }
+                };
+                set_namespace
+                    .set(key.as_bytes(), value.as_bytes(), ttl)
+                    .map_err(|e| throw_kv_error(&ctx, e))
+            },
+        )?,
+    )?;
+
+    kv.set(
+        "delete",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, key: String| -> QuickJsResult<bool> {
+                namespace
+                    .delete(key.as_bytes())
+                    .map_err(|e| throw_kv_error(&ctx, e))
+            },
+        )?,
+    )?;
+
+    let hoya = Object::new(ctx.clone())?;
+    hoya.set("kv", kv)?;
+    ctx.globals().set("hoya", hoya)?;
+    Ok(())
+}
+
+/// Throw a refused key-value operation as an `Error` with a `code`
+fn throw_kv_error(ctx: &Ctx<'_>, error: KvError) -> rquickjs::Error {
+    let code = match error {
+        KvError::InvalidKey => "KV_INVALID_KEY",
+        KvError::QuotaExceeded { .. } | KvError::TooManyNamespaces { .. } => "KV_QUOTA_EXCEEDED",
+    };
+    throw_with_code(ctx, code, &error.to_string())
+}
+
+/// Throw a refused storage operation as an `Error` with a `code`
+fn throw_storage_error(ctx: &Ctx<'_>, error: StorageError) -> rquickjs::Error {
+    let code = match error {
+        StorageError::InvalidKey => "STORAGE_INVALID_KEY",
+        StorageError::QuotaExceeded { .. } => "STORAGE_QUOTA_EXCEEDED",
+        StorageError::Backend(_) => "STORAGE_FAILED",
+    };
+    throw_with_code(ctx, code, &error.to_string())
+}
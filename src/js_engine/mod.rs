@@ -1,111 +1,1067 @@
 mod ffis;
+mod modules;
+mod pool;
 
-use crate::error::{AppError, ExecuteResponse, ExecutionMetadata};
-use axum::Json;
+pub use ffis::{
+    install_clock, install_crypto, install_deterministic_random, install_env, install_input,
+    install_kv, install_store, install_timers, install_timezone, register_to_globals_with_capture,
+    ConsoleCalls, OutputBuffers, Timers,
+};
+pub use pool::RuntimePool;
+
+use crate::cancel::CancelToken;
+use crate::clock::GuestClock;
+use crate::config::Config;
+use crate::error::{
+    AppError, EnginePhases, ExecutionArtifacts, ExecutionMetadata, ExecutionResult, JsErrorDetails,
+};
+use crate::fetch_runtime::FetchRuntime;
+use crate::guest_env::GuestEnv;
+use crate::kv::{KvNamespace, KvStore};
+use crate::limits::Limits;
+use crate::metrics::Metrics;
+use crate::net::FetchContext;
+use crate::outbound::OutboundPolicy;
+use crate::output::{self, OutputBudget};
+use crate::random::{DeterministicMode, GuestRandom};
+use crate::storage::Namespace;
+use chrono_tz::Tz;
 use ffis as js_ffis; // Adjusted import path
-use rquickjs::{Context, Result as QuickJsResult, Runtime, Value};
+use rquickjs::promise::PromiseState;
+use rquickjs::{qjs, Coerced, Context, Ctx, Result as QuickJsResult, Runtime, Value};
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant};
+
+/// Number of digest characters in the name of inline scripts
+const INLINE_NAME_DIGEST_CHARS: usize = 12;
+
+/// A script's `output` string and its JSON form
+type ScriptOutput = (String, Option<serde_json::Value>);
+
+/// `JSON.stringify` replacer serializing values JSON has no form for: `BigInt`s
+/// and symbols as their string form, functions as a `[Function name]`
+/// placeholder, and typed arrays as arrays of their elements. `Date`s already
+/// serialize as ISO strings through their `toJSON` method.
+const JSON_REPLACER: &str = r#"(function (key, value) {
+    switch (typeof value) {
+        case "bigint":
+            return value.toString();
+        case "symbol":
+            return value.toString();
+        case "function":
+            return "[Function " + (value.name || "anonymous") + "]";
+    }
+    if (ArrayBuffer.isView(value) && !(value instanceof DataView)) {
+        return Array.from(value);
+    }
+    return value;
+})"#;
+
+/// JavaScript code handed to the engine
+pub struct Script {
+    /// The code to execute
+    pub code: bytes::Bytes,
+    /// Hex SHA-256 digest of the code, exposed as `HOYA_CODE_SHA256`
+    pub sha256: String,
+    /// File name of the script in stack traces and error details
+    pub name: String,
+    /// Evaluate the code as an ES module instead of a classic script
+    pub module: bool,
+}
+
+impl Script {
+    /// A script downloaded from a URL, named after the URL without credentials, query or fragment
+    pub fn remote(code: bytes::Bytes, sha256: &str, url: &str) -> Self {
+        Script {
+            code,
+            sha256: sha256.to_string(),
+            name: crate::record::redact_url(url),
+            module: false,
+        }
+    }
+
+    /// A script that has no URL, named `inline:<digest prefix>`
+    pub fn inline(code: bytes::Bytes, sha256: &str) -> Self {
+        let prefix = &sha256[..sha256.len().min(INLINE_NAME_DIGEST_CHARS)];
+        Script {
+            code,
+            sha256: sha256.to_string(),
+            name: format!("inline:{}", prefix),
+            module: false,
+        }
+    }
+}
+
+impl From<&[u8]> for Script {
+    /// An inline script, digested here
+    fn from(code: &[u8]) -> Self {
+        let sha256 = format!("{:x}", Sha256::digest(code));
+        Script::inline(bytes::Bytes::copy_from_slice(code), &sha256)
+    }
+}
+
+/// Limits enforced by the JavaScript engine itself
+#[derive(Debug, Clone, Copy)]
+pub struct JsLimits {
+    /// Wall-clock limit of the script, checked by the QuickJS interrupt handler
+    pub timeout: Duration,
+    /// Capture calls allowed before further calls are dropped
+    pub max_console_calls: u64,
+    /// Heap the script may allocate, in bytes, on top of what the globals take
+    pub memory_bytes: u64,
+    /// Native stack the interpreter may use, in bytes
+    pub max_stack_bytes: usize,
+}
+
+/// A script and everything it runs with
+pub struct JsExecution {
+    /// The code to execute, its digest and its name in stack traces
+    pub script: Script,
+    /// Per-execution state backing `hoyaFetch` calls; its cancel token
+    /// interrupts the script like the timeout does
+    pub fetch_ctx: FetchContext,
+    /// Byte budget shared by all captured output
+    pub output_budget: Arc<OutputBudget>,
+    /// Seed and options of deterministic mode, if enabled
+    pub deterministic: Option<DeterministicMode>,
+    /// Zone of `HOYA_TIMEZONE` and `Date`'s local-time helpers
+    pub timezone: Tz,
+    /// Timeout, console call limit, heap and stack; a host call in progress
+    /// (e.g. `hoyaFetch`) finishes before the timeout interrupts the script
+    pub limits: JsLimits,
+    /// Namespace exposed as `hoyaStore`; without it the global is not defined
+    pub storage: Option<Namespace>,
+    /// Namespace exposed as `hoya.kv`
+    pub kv: KvNamespace,
+    /// Variables exposed as the frozen `env` object
+    pub env: Arc<GuestEnv>,
+    /// JSON document parsed into the `input` global; without it `input` is `null`
+    pub input: Option<bytes::Bytes>,
+    /// Pool to run the script on; without one it gets a runtime of its own
+    pub runtimes: Option<Arc<RuntimePool>>,
+}
+
+impl JsExecution {
+    /// Run `script` with the limits and fetch policy of a server with the default
+    /// configuration, in UTC, without deterministic mode, storage, variables or input,
+    /// and with an isolated key-value namespace
+    ///
+    /// Fetch calls made by the script run on a fetch runtime of its own, and the
+    /// script on a QuickJS runtime of its own.
+    pub fn new(script: Script) -> Self {
+        let config = Config::default();
+        let limits = Limits::defaults(&config);
+        // Registering into a fresh registry only fails for duplicate metric names
+        let metrics = Metrics::new(&[]).expect("the service metrics register once");
+        let runtime = FetchRuntime::start().expect("the fetch runtime thread starts");
+        JsExecution {
+            script,
+            fetch_ctx: FetchContext::new(
+                config
+                    .fetch
+                    .build_client()
+                    .expect("the default fetch client builds"),
+                Arc::new(runtime),
+                Arc::new(OutboundPolicy::from_config(&config.fetch)),
+                false,
+                Arc::new(metrics),
+            ),
+            output_budget: Arc::new(limits.output_budget(false, false)),
+            deterministic: None,
+            timezone: Tz::UTC,
+            limits: JsLimits {
+                timeout: limits.timeout,
+                max_console_calls: config.js.max_console_calls,
+                memory_bytes: config.js.default_memory_bytes,
+                max_stack_bytes: config.js.max_stack_bytes,
+            },
+            storage: None,
+            kv: KvStore::new(&config.kv).isolated(),
+            env: Arc::default(),
+            input: None,
+            runtimes: None,
+        }
+    }
+}
+
+impl From<Script> for JsExecution {
+    fn from(script: Script) -> Self {
+        JsExecution::new(script)
+    }
+}
+
+impl From<&[u8]> for JsExecution {
+    fn from(code: &[u8]) -> Self {
+        JsExecution::new(Script::from(code))
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for JsExecution {
+    fn from(code: &[u8; N]) -> Self {
+        JsExecution::new(Script::from(&code[..]))
+    }
+}
 
 /// Execute JavaScript code and return the execution result
 ///
 /// # Arguments
 ///
-/// * `js_code` - JavaScript code to execute as a byte array
+/// * `execution` - The script and its fetch context, output budget, mode, time
+///   zone, limits, storage, variables and input
 ///
 /// # Returns
 ///
-/// * `Result<Json<ExecuteResponse>, AppError>` - Execution result or error
-pub fn execute_js(downloaded_code: bytes::Bytes) -> Result<Json<ExecuteResponse>, AppError> {
-    println!(
-        "Code type: JavaScript, size: {} bytes",
-        downloaded_code.len()
-    );
-
-    let start_time = std::time::Instant::now();
-    let resource_size = downloaded_code.len();
+/// * `Result<ExecutionResult, AppError>` - Execution result or error
+///
+/// # Examples
+///
+/// ```
+/// use hoya::js_engine::execute_js;
+///
+/// let result = execute_js(b"1+1".into()).unwrap();
+/// assert_eq!(result.output, "2");
+/// assert_eq!(result.metadata.code_type, "javascript");
+/// ```
+///
+/// Console output is captured rather than printed:
+///
+/// ```
+/// use hoya::js_engine::{execute_js, JsExecution, Script};
+///
+/// let mut execution = JsExecution::new(Script::from(&b"console.log('hi'); 'done'"[..]));
+/// execution.limits.timeout = std::time::Duration::from_secs(1);
+/// let result = execute_js(execution).unwrap();
+/// assert_eq!(result.stdout, "hi\n");
+/// assert_eq!(result.output, "done");
+/// ```
+///
+/// Timers fire after the script has run, and the output is taken once none is left:
+///
+/// ```
+/// use hoya::js_engine::execute_js;
+///
+/// let result = execute_js(
+///     b"setTimeout(() => console.log('tick'), 5); new Promise((done) => setTimeout(done, 10, 'late'))"
+///         .into(),
+/// )
+/// .unwrap();
+/// assert_eq!(result.stdout, "tick\n");
+/// assert_eq!(result.output, "late");
+/// ```
+///
+/// ES modules output their default export, or what their `main` export
+/// returns, and can import `data:` and `http(s)` URLs, the latter through
+/// the execution's fetch context and its outbound policy:
+///
+/// ```
+/// use hoya::js_engine::{execute_js, Script};
+///
+/// let mut script = Script::from(
+///     &br#"
+///     const { twice } = await import("data:text/javascript,export const twice = (n) => n * 2");
+///     export const main = () => twice(21);
+///     "#[..],
+/// );
+/// script.module = true;
+/// assert_eq!(execute_js(script.into()).unwrap().output, "42");
+///
+/// let mut script = Script::from(&b"import './helper.mjs'; export default 1;"[..]);
+/// script.module = true;
+/// let error = execute_js(script.into()).unwrap_err();
+/// assert_eq!(error.code(), "MODULE_RESOLUTION_DISABLED");
+///
+/// let mut script = Script::from(&b"import 'http://127.0.0.1:9/lib.mjs'; export default 1;"[..]);
+/// script.module = true;
+/// let error = execute_js(script.into()).unwrap_err().to_string();
+/// assert!(error.contains("http://127.0.0.1:9/lib.mjs"));
+/// assert!(error.contains("FETCH_BLOCKED"));
+/// ```
+pub fn execute_js(execution: JsExecution) -> Result<ExecutionResult, AppError> {
+    match execution.runtimes.clone() {
+        Some(pool) => {
+            // The pooled runtime's thread traces the execution under the caller's span
+            let span = tracing::Span::current();
+            pool.run(move |runtime| span.in_scope(|| run_js(runtime, execution)))
+        }
+        None => {
+            let runtime = Runtime::new()?;
+            run_js(&runtime, execution)
+        }
+    }
+}
 
-    let js_code = String::from_utf8(downloaded_code.to_vec()).map_err(|e| {
-        AppError::Internal(format!(
-            "Failed to convert downloaded code to string: {}",
-            e
-        ))
-    })?;
+/// Execute JavaScript code on `runtime`, in a context created for it
+///
+/// The handlers, loader and limits of the execution are installed on the
+/// runtime; a pooled runtime has them reset once the execution returns.
+fn run_js(runtime: &Runtime, execution: JsExecution) -> Result<ExecutionResult, AppError> {
+    let JsExecution {
+        script,
+        mut fetch_ctx,
+        output_budget,
+        deterministic,
+        timezone,
+        limits,
+        storage,
+        kv,
+        env,
+        input,
+        runtimes: _,
+    } = execution;
+    tracing::info!(size_bytes = script.code.len(), "Running JavaScript");
 
-    let runtime = Runtime::new()?;
-    let context = Context::full(&runtime)?;
+    let start_time = Instant::now();
+    let deadline = start_time + limits.timeout;
+    let resource_size = script.code.len();
 
     // Create buffers for stdout and stderr
     let stdout_buffer = Arc::new(Mutex::new(String::new()));
     let stderr_buffer = Arc::new(Mutex::new(String::new()));
+    let logs = Arc::new(Mutex::new(Vec::new()));
+    let console_calls = Arc::new(js_ffis::ConsoleCalls::new(limits.max_console_calls));
+    // Phases are timed from inside the context, and reported with errors too
+    let phases = Cell::new(EnginePhases::default());
+
+    // Errors carry what the script printed before them, and the execution's metadata
+    let failed = |error: AppError| {
+        let mut metadata = ExecutionMetadata::new(
+            "javascript",
+            resource_size,
+            start_time.elapsed().as_millis() as u64,
+        );
+        metadata.set_phases(phases.get());
+        metadata.console_calls_dropped = Some(console_calls.dropped());
+        let mut partial = ExecutionArtifacts::capture(&stdout_buffer, &stderr_buffer, metadata);
+        partial.logs = Some(output::lock(&logs).clone());
+        AppError::FailedExecution {
+            error: Box::new(error),
+            partial,
+        }
+    };
+    let invalid_code = |message: String| {
+        failed(AppError::JsSyntaxError {
+            message,
+            stack: None,
+            source: script.name.clone(),
+        })
+    };
+    let js_code = String::from_utf8(script.code.to_vec())
+        .map_err(|e| invalid_code(format!("JavaScript code is not UTF-8: {}", e)))?;
+    let js_code = CString::new(js_code)
+        .map_err(|_| invalid_code("JavaScript code contains a NUL byte".to_string()))?;
+    let script_name = CString::new(script.name.as_str()).map_err(|_| {
+        failed(AppError::Internal(
+            "Script name contains a NUL byte".to_string(),
+        ))
+    })?;
+
+    runtime.set_max_stack_size(limits.max_stack_bytes);
+    // Capture functions throw once a strict budget is exhausted, but scripts can
+    // catch that; interrupting the runtime ends the script for good. The same
+    // handler ends scripts that were cancelled or run past their deadline.
+    let interrupt_budget = output_budget.clone();
+    let cancel = fetch_ctx.cancel.clone();
+    let loop_cancel = cancel.clone();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let interrupt_cancelled = cancelled.clone();
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let interrupt_timed_out = timed_out.clone();
+    runtime.set_interrupt_handler(Some(Box::new(move || {
+        if interrupt_budget.should_terminate() {
+            return true;
+        }
+        if cancel.is_cancelled() {
+            interrupt_cancelled.store(true, Ordering::Relaxed);
+            return true;
+        }
+        if Instant::now() >= deadline {
+            interrupt_timed_out.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    })));
+    let instantiate_started = Instant::now();
+    let context = Context::full(runtime).map_err(|e| failed(e.into()))?;
 
     // It seems register_context_properties was intended to set up global functions and capture.
     // We will use register_to_globals_with_capture for this.
     // The actual registration will happen inside context.with() where Ctx is available.
 
+    // Guest fetch calls made by the script are traced under the run span
+    let run_span = tracing::info_span!("run");
+    fetch_ctx.span = run_span.clone();
+    let fetch_ctx = Arc::new(fetch_ctx);
+
+    // Imports are fetched with the same context as the `fetch` globals, so they
+    // count against the same call limit and quota, in scripts and modules alike
+    let import_log = modules::ImportLog::default();
+    runtime.set_loader(
+        modules::ImportResolver {
+            log: import_log.clone(),
+        },
+        modules::ModuleLoader {
+            fetch_ctx: fetch_ctx.clone(),
+            log: import_log.clone(),
+            sources: HashMap::new(),
+        },
+    );
+
+    // Timers scheduled by the script, fired by the event loop once it has run
+    let timers = js_ffis::Timers::default();
+
+    // Promises rejected without a handler, reported once the event loop is done
+    let rejections = Rejections::default();
+    let tracked = rejections.clone();
+    let tracked_source = script.name.clone();
+    runtime.set_host_promise_rejection_tracker(Some(Box::new(
+        move |_ctx, promise, reason, is_handled| {
+            tracked.track(&promise, &reason, is_handled, &tracked_source)
+        },
+    )));
+
     // Execute JavaScript with output capturing
-    let result = context.with(|ctx| -> QuickJsResult<String> {
-        // Register JavaScript functions with stdout/stderr capture
-        let output_buffers = js_ffis::OutputBuffers {
-            stdout: stdout_buffer.clone(),
-            stderr: stderr_buffer.clone(),
-        };
-        // Corrected: Use the alias js_ffis
-        js_ffis::register_to_globals_with_capture(&ctx, output_buffers)?;
-
-        // Execute the JS code
-        let result = ctx.eval::<Value, _>(js_code.as_str())?;
-
-        // Convert the result to a string
-        let output = match result.type_of() {
-            rquickjs::Type::String => result.as_string().unwrap().to_string()?,
-            rquickjs::Type::Int => result.as_int().unwrap().to_string(),
-            rquickjs::Type::Bool => result.as_bool().unwrap().to_string(),
-            rquickjs::Type::Float => result.as_float().unwrap().to_string(),
-            rquickjs::Type::Null => "null".to_string(),
-            rquickjs::Type::Undefined => "undefined".to_string(),
-            _ => format!(
-                "Execution resulted in a non-primitive type: {:?}",
-                result.type_of()
-            ),
+    let result = context.with(|ctx| -> Result<ScriptOutput, AppError> {
+        let fire_timer = tracing::info_span!("instantiate").in_scope(|| -> QuickJsResult<_> {
+            // Register JavaScript functions with stdout/stderr capture
+            let output_buffers = js_ffis::OutputBuffers {
+                stdout: stdout_buffer.clone(),
+                stderr: stderr_buffer.clone(),
+                logs: logs.clone(),
+                budget: output_budget.clone(),
+                console_calls: console_calls.clone(),
+            };
+            // Corrected: Use the alias js_ffis
+            js_ffis::register_to_globals_with_capture(&ctx, output_buffers, fetch_ctx.clone())?;
+            ctx.globals()
+                .set("HOYA_CODE_SHA256", script.sha256.as_str())?;
+            let clock = Arc::new(GuestClock::new(deterministic.is_some()));
+            js_ffis::install_timezone(&ctx, timezone, clock.clone())?;
+            js_ffis::install_env(&ctx, &env)?;
+            js_ffis::install_input(&ctx, input.as_deref())?;
+            js_ffis::install_clock(&ctx, clock)?;
+            let random = Arc::new(Mutex::new(GuestRandom::new(deterministic)));
+            js_ffis::install_crypto(&ctx, random.clone())?;
+            if let Some(mode) = deterministic {
+                js_ffis::install_deterministic_random(&ctx, mode, random)?;
+            }
+            if let Some(namespace) = storage {
+                js_ffis::install_store(&ctx, namespace)?;
+            }
+            js_ffis::install_kv(&ctx, kv)?;
+            js_ffis::install_timers(&ctx, timers.clone())
+        });
+        record_phase(&phases, |phases| {
+            phases.instantiate_ms = Some(instantiate_started.elapsed().as_millis() as u64)
+        });
+        let fire_timer = fire_timer?;
+        let event_loop = EventLoop {
+            timers: timers.clone(),
+            fire_timer,
+            deadline,
+            cancel: loop_cancel.clone(),
+            timeout: limits.timeout,
         };
 
-        Ok(output)
-    })?;
+        // The heap limit applies from here on, so a small one still leaves room for the globals
+        runtime.set_memory_limit(usize::try_from(limits.memory_bytes).unwrap_or(usize::MAX));
 
-    // Calculate execution time
-    let execution_time = start_time.elapsed().as_millis() as u64;
+        // Execute the JS code under its name, so stack traces point at the source.
+        // It is compiled first, so syntax errors are told apart from errors it throws.
+        // Modules are compiled as their imports resolve, so their run includes compiling.
+        let run_started = Cell::new(None);
+        let result = run_span.in_scope(|| {
+            let value = if script.module {
+                run_started.set(Some(Instant::now()));
+                modules::run_module(
+                    &ctx,
+                    js_code.as_bytes(),
+                    &script.name,
+                    &import_log,
+                    &event_loop,
+                )?
+            } else {
+                let compile_started = Instant::now();
+                let compiled = compile_named(&ctx, &js_code, &script_name);
+                record_phase(&phases, |phases| {
+                    phases.compile_ms = Some(compile_started.elapsed().as_millis() as u64)
+                });
+                let compiled = compiled.map_err(|e| syntax_error(&ctx, e, &script.name))?;
+                run_started.set(Some(Instant::now()));
+                run_compiled(&ctx, compiled).map_err(|e| exception_error(&ctx, e, &script.name))?
+            };
+            let value = settle(&ctx, value, &script.name, &event_loop)?;
+            // Timers still pending run before the output is taken
+            event_loop.drive(&ctx, None, &script.name)?;
+            if let Some((message, details)) = rejections.first() {
+                return Err(AppError::JsException {
+                    message: format!("Unhandled promise rejection: {}", message),
+                    details,
+                    source: script.name.clone(),
+                });
+            }
+            Ok(value)
+        });
+        if let Some(run_started) = run_started.get() {
+            record_phase(&phases, |phases| {
+                phases.run_ms = Some(run_started.elapsed().as_millis() as u64)
+            });
+        }
 
-    // Generate ISO timestamp
-    let now = SystemTime::now();
-    let timestamp = match now.duration_since(UNIX_EPOCH) {
-        Ok(duration) => {
-            let datetime = chrono::DateTime::<chrono::Utc>::from_timestamp(
-                duration.as_secs() as i64,
-                duration.subsec_nanos(),
-            )
-            .unwrap_or_else(|| chrono::Utc::now());
-            datetime.to_rfc3339()
-        }
-        Err(_) => chrono::Utc::now().to_rfc3339(),
-    };
+        convert_result(&ctx, result?, &script.name)
+    });
+    if output_budget.should_terminate() {
+        return Err(failed(AppError::OutputBudgetExceeded {
+            limit_bytes: output_budget.limit(),
+        }));
+    }
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(failed(AppError::Cancelled));
+    }
+    if timed_out.load(Ordering::Relaxed) {
+        return Err(failed(AppError::Timeout {
+            timeout_ms: limits.timeout.as_millis() as u64,
+        }));
+    }
+    let (result, output_json) = result.map_err(|e| failed(memory_error(e, limits.memory_bytes)))?;
+    let memory_used_bytes = runtime.memory_usage().memory_used_size.max(0) as u64;
+
+    let execution_time = start_time.elapsed().as_millis() as u64;
+    let mut metadata = ExecutionMetadata::new("javascript", resource_size, execution_time);
+    metadata.set_phases(phases.get());
+    metadata.memory_used_bytes = Some(memory_used_bytes);
+    metadata.console_calls_dropped = Some(console_calls.dropped());
 
     // Get the captured stdout and stderr
-    let stdout = stdout_buffer.lock().map(|s| s.clone()).unwrap_or_default();
-    let stderr = stderr_buffer.lock().map(|s| s.clone()).unwrap_or_default();
+    let stdout = output::lock(&stdout_buffer).clone();
+    let stderr = output::lock(&stderr_buffer).clone();
+    let logs = output::lock(&logs).clone();
 
     // Return the execution result with metadata
-    Ok(Json(ExecuteResponse {
-        status: "success".to_string(),
-        output: Some(result),
-        stdout: Some(stdout),
-        stderr: Some(stderr),
-        error: None,
-        metadata: ExecutionMetadata {
-            execution_time,
-            code_type: "javascript".to_string(),
-            timestamp,
-            resource_size,
-        },
-    }))
+    Ok(ExecutionResult {
+        output: result,
+        output_json,
+        stdout,
+        stderr,
+        logs,
+        metadata,
+    })
+}
+
+/// Update the phases timed so far
+fn record_phase(phases: &Cell<EnginePhases>, record: impl FnOnce(&mut EnginePhases)) {
+    let mut recorded = phases.get();
+    record(&mut recorded);
+    phases.set(recorded);
+}
+
+/// Message of the `InternalError` QuickJS throws when the heap limit is reached
+const OUT_OF_MEMORY: &str = "out of memory";
+
+/// Report a script that failed because it ran out of heap as `MemoryLimitExceeded`
+///
+/// QuickJS throws an ordinary `InternalError` when an allocation would go past
+/// the runtime's memory limit; it is recognized by its message, also when it
+/// rejected the script's promise.
+///
+/// # Arguments
+///
+/// * `error` - The error the script failed with
+/// * `memory_bytes` - The heap limit the script ran with
+fn memory_error(error: AppError, memory_bytes: u64) -> AppError {
+    let AppError::JsException { message, .. } = &error else {
+        return error;
+    };
+    let thrown = message
+        .strip_prefix("Promise rejected: ")
+        .or_else(|| message.strip_prefix("Unhandled promise rejection: "))
+        .unwrap_or(message);
+    if thrown != OUT_OF_MEMORY {
+        return error;
+    }
+    AppError::MemoryLimitExceeded {
+        limit_bytes: memory_bytes,
+        requested_bytes: None,
+    }
+}
+
+/// Compile a classic script under a file name without running it
+///
+/// `Ctx::eval` names every script `eval_script`; the name given here shows up
+/// in the `stack` of errors thrown by the script instead.
+fn compile_named<'js>(ctx: &Ctx<'js>, source: &CStr, name: &CStr) -> QuickJsResult<Value<'js>> {
+    // SAFETY: `source` and `name` are NUL-terminated as JS_Eval requires, and the returned
+    // value is handed to `Value::from_raw`, which takes ownership and frees it on drop.
+    let compiled = unsafe {
+        let raw = qjs::JS_Eval(
+            ctx.as_raw().as_ptr(),
+            source.as_ptr(),
+            source.to_bytes().len() as _,
+            name.as_ptr(),
+            (qjs::JS_EVAL_TYPE_GLOBAL | qjs::JS_EVAL_FLAG_COMPILE_ONLY) as i32,
+        );
+        Value::from_raw(ctx.clone(), raw)
+    };
+    if compiled.is_exception() {
+        return Err(rquickjs::Error::Exception);
+    }
+    Ok(compiled)
+}
+
+/// Run a script compiled by `compile_named` and return its completion value
+fn run_compiled<'js>(ctx: &Ctx<'js>, compiled: Value<'js>) -> QuickJsResult<Value<'js>> {
+    // SAFETY: JS_EvalFunction takes ownership of the compiled function, which
+    // `into_raw` gives up, and the returned value is handed to `Value::from_raw`.
+    let value = unsafe {
+        let raw = qjs::JS_EvalFunction(ctx.as_raw().as_ptr(), compiled.into_raw());
+        Value::from_raw(ctx.clone(), raw)
+    };
+    if value.is_exception() {
+        return Err(rquickjs::Error::Exception);
+    }
+    Ok(value)
+}
+
+/// Longest the event loop sleeps before checking whether the execution was cancelled
+const TIMER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What the event loop of an execution needs besides its context
+struct EventLoop<'js> {
+    /// Timers scheduled by the script
+    timers: js_ffis::Timers,
+    /// Calls a timer's callback, as returned by `install_timers`
+    fire_timer: rquickjs::Function<'js>,
+    /// When the execution times out
+    deadline: Instant,
+    /// Cancellation of the execution
+    cancel: CancelToken,
+    /// The execution's timeout, for the error reporting it
+    timeout: Duration,
+}
+
+impl<'js> EventLoop<'js> {
+    /// Run pending jobs and fire timers as they fall due, until `until` is
+    /// settled, or without one until neither jobs nor timers are left
+    ///
+    /// Jobs and timer callbacks run under the script's interrupt handler;
+    /// between them the loop checks the deadline and cancellation itself, so
+    /// intervals and timers due after the deadline cannot keep it going.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AppError>` - `Ok` once done; `Timeout` or `Cancelled` if
+    ///   the execution ended first, or a `JsException` if a timer callback threw
+    fn drive(
+        &self,
+        ctx: &Ctx<'js>,
+        until: Option<&rquickjs::Promise<'js>>,
+        source: &str,
+    ) -> Result<(), AppError> {
+        loop {
+            while ctx.execute_pending_job() {}
+            if until.is_some_and(|promise| !matches!(promise.state(), PromiseState::Pending)) {
+                return Ok(());
+            }
+            let Some(due) = self.timers.next_due() else {
+                return Ok(());
+            };
+            if self.cancel.is_cancelled() {
+                return Err(AppError::Cancelled);
+            }
+            let now = Instant::now();
+            if now >= self.deadline || due >= self.deadline {
+                return Err(AppError::Timeout {
+                    timeout_ms: self.timeout.as_millis() as u64,
+                });
+            }
+            if due > now {
+                std::thread::sleep((due - now).min(TIMER_POLL_INTERVAL));
+                continue;
+            }
+            if let Some((id, last)) = self.timers.take_due(now) {
+                self.fire_timer
+                    .call::<_, ()>((id, last))
+                    .map_err(|e| exception_error(ctx, e, source))?;
+            }
+        }
+    }
+}
+
+/// Promises rejected while no handler was attached to them, with the messages
+/// and details of their reasons, in the order they were rejected
+#[derive(Clone, Default)]
+struct Rejections(Arc<Mutex<Vec<(usize, String, JsErrorDetails)>>>);
+
+impl Rejections {
+    /// Record what the runtime's rejection tracker reports about `promise`
+    ///
+    /// A rejection is recorded when `promise` is rejected without a handler, and
+    /// forgotten when a handler is attached to it later on.
+    fn track(&self, promise: &Value<'_>, reason: &Value<'_>, is_handled: bool, source: &str) {
+        // SAFETY: a promise is an object, whose JSValue holds a pointer; it is
+        // only compared, never dereferenced
+        let address = unsafe { qjs::JS_VALUE_GET_PTR(promise.as_raw()) } as usize;
+        if is_handled {
+            if let Ok(mut rejections) = self.0.lock() {
+                rejections.retain(|(rejected, ..)| *rejected != address);
+            }
+            return;
+        }
+        // Described before locking: describing the reason may run JavaScript
+        let (message, details) = thrown_error(reason, "", source);
+        if let Ok(mut rejections) = self.0.lock() {
+            rejections.push((address, message, details));
+        }
+    }
+
+    /// Message and details of the first rejection still without a handler
+    fn first(&self) -> Option<(String, JsErrorDetails)> {
+        let rejections = self.0.lock().ok()?;
+        let (_, message, details) = rejections.first()?;
+        Some((message.clone(), details.clone()))
+    }
+}
+
+/// Run the event loop until a promise settles and replace it by the value it resolved to
+///
+/// Values other than promises are returned once the loop has nothing left to run.
+///
+/// # Arguments
+///
+/// * `ctx` - Context the script ran in
+/// * `value` - Completion value of the script
+/// * `source` - File name the script ran under
+/// * `event_loop` - The execution's timers and deadline
+///
+/// # Returns
+///
+/// * `Result<Value, AppError>` - The value, or a `JsException` if the promise
+///   was rejected or can no longer settle
+fn settle<'js>(
+    ctx: &Ctx<'js>,
+    value: Value<'js>,
+    source: &str,
+    event_loop: &EventLoop<'js>,
+) -> Result<Value<'js>, AppError> {
+    let Some(promise) = value.as_promise() else {
+        event_loop.drive(ctx, None, source)?;
+        return Ok(value);
+    };
+    event_loop.drive(ctx, Some(promise), source)?;
+    // Neither the job queue nor a timer is left to settle it; there is no pending I/O
+    if matches!(promise.state(), PromiseState::Pending) {
+        return Err(AppError::JsException {
+            message: "The script's promise never settled".to_string(),
+            details: JsErrorDetails::default(),
+            source: source.to_string(),
+        });
+    }
+    // A settled promise resolves to any value, so failing here means it was rejected
+    promise.finish::<Value>().map_err(|e| match e {
+        rquickjs::Error::Exception => {
+            let (message, details) = thrown_error(&ctx.catch(), "", source);
+            AppError::JsException {
+                message: format!("Promise rejected: {}", message),
+                details,
+                source: source.to_string(),
+            }
+        }
+        other => exception_error(ctx, other, source),
+    })
+}
+
+/// Convert a script's completion value into the `output` string and its JSON form
+///
+/// Primitives keep their string form. Other values are serialized with
+/// `JSON.stringify` and `JSON_REPLACER`, and `output` is the JSON text, or the
+/// string itself when the value serializes to a string (e.g. a `Date` or a `BigInt`).
+///
+/// # Arguments
+///
+/// * `ctx` - Context the script ran in
+/// * `result` - The settled completion value
+/// * `source` - File name the script ran under
+///
+/// # Returns
+///
+/// * `Result<ScriptOutput, AppError>` - The output and its JSON form (`None` for
+///   `undefined`), or a `JsException` if the value cannot be serialized
+fn convert_result<'js>(
+    ctx: &Ctx<'js>,
+    result: Value<'js>,
+    source: &str,
+) -> Result<ScriptOutput, AppError> {
+    let converted = match result.type_of() {
+        rquickjs::Type::String => {
+            let text = result.as_string().unwrap().to_string()?;
+            (text.clone(), Some(serde_json::Value::String(text)))
+        }
+        rquickjs::Type::Int => {
+            let number = result.as_int().unwrap();
+            (number.to_string(), Some(number.into()))
+        }
+        rquickjs::Type::Bool => {
+            let flag = result.as_bool().unwrap();
+            (flag.to_string(), Some(flag.into()))
+        }
+        rquickjs::Type::Float => {
+            let number = result.as_float().unwrap();
+            // NaN and the infinities have no JSON form
+            let json =
+                serde_json::Number::from_f64(number).map_or(serde_json::Value::Null, Into::into);
+            (number.to_string(), Some(json))
+        }
+        rquickjs::Type::Null => ("null".to_string(), Some(serde_json::Value::Null)),
+        rquickjs::Type::Undefined => ("undefined".to_string(), None),
+        _ => {
+            let text = stringify(ctx, result).map_err(|e| match e {
+                rquickjs::Error::Exception => {
+                    let (message, details) = thrown_error(&ctx.catch(), "Uncaught ", source);
+                    AppError::JsException {
+                        message: format!(
+                            "The script's result cannot be converted to JSON: {}",
+                            message
+                        ),
+                        details,
+                        source: source.to_string(),
+                    }
+                }
+                other => exception_error(ctx, other, source),
+            })?;
+            let Some(text) = text else {
+                return Ok(("undefined".to_string(), None));
+            };
+            let json: serde_json::Value = serde_json::from_str(&text).map_err(|e| {
+                AppError::Internal(format!("JSON.stringify produced invalid JSON: {}", e))
+            })?;
+            match json {
+                serde_json::Value::String(string) => {
+                    (string.clone(), Some(serde_json::Value::String(string)))
+                }
+                json => (text, Some(json)),
+            }
+        }
+    };
+    Ok(converted)
+}
+
+/// Serialize a value with `JSON.stringify` and `JSON_REPLACER`
+///
+/// Circular references make `JSON.stringify` throw a `TypeError`, and nesting
+/// deeper than the QuickJS stack allows throws a `RangeError`.
+fn stringify<'js>(ctx: &Ctx<'js>, value: Value<'js>) -> QuickJsResult<Option<String>> {
+    let replacer: Value = ctx.eval(JSON_REPLACER)?;
+    ctx.json_stringify_replacer(value, replacer)?
+        .map(|text| text.to_string())
+        .transpose()
+}
+
+/// Convert an error escaping a script into an `AppError`, keeping the thrown
+/// value's message, name, stack and location, or the value itself when it is not an error
+///
+/// # Arguments
+///
+/// * `ctx` - Context the script ran in, holding the pending exception
+/// * `error` - The error the evaluation failed with
+/// * `source` - File name the script ran under
+fn exception_error(ctx: &Ctx<'_>, error: rquickjs::Error, source: &str) -> AppError {
+    let (message, details) = match error {
+        rquickjs::Error::Exception => thrown_error(&ctx.catch(), "Uncaught ", source),
+        other => (other.to_string(), JsErrorDetails::default()),
+    };
+    AppError::JsException {
+        message,
+        details,
+        source: source.to_string(),
+    }
+}
+
+/// Convert an error compiling a script into an `AppError::JsSyntaxError`
+///
+/// # Arguments
+///
+/// * `ctx` - Context the script was compiled in, holding the pending exception
+/// * `error` - The error the compilation failed with
+/// * `source` - File name the script was compiled under
+fn syntax_error(ctx: &Ctx<'_>, error: rquickjs::Error, source: &str) -> AppError {
+    let (message, stack) = caught_message(ctx, error);
+    AppError::JsSyntaxError {
+        message,
+        stack,
+        source: source.to_string(),
+    }
+}
+
+/// Message and stack of the exception an evaluation failed with
+fn caught_message(ctx: &Ctx<'_>, error: rquickjs::Error) -> (String, Option<String>) {
+    match error {
+        rquickjs::Error::Exception => thrown_message(&ctx.catch(), "Uncaught "),
+        other => (other.to_string(), None),
+    }
+}
+
+/// Message and stack of a thrown value; values that are not errors are described
+/// by their string form after `prefix`
+fn thrown_message(thrown: &Value<'_>, prefix: &str) -> (String, Option<String>) {
+    match thrown.as_exception() {
+        Some(exception) => (
+            exception
+                .message()
+                .unwrap_or_else(|| "Uncaught exception".to_string()),
+            exception.stack(),
+        ),
+        None => (
+            thrown
+                .get::<Coerced<String>>()
+                .map(|thrown| format!("{}{}", prefix, thrown.0))
+                .unwrap_or_else(|_| "Uncaught exception".to_string()),
+            None,
+        ),
+    }
+}
+
+/// Message and details of a thrown value
+///
+/// Errors give their `name`, `stack` and the `lineNumber` and `columnNumber`
+/// they carry, or else the location of the first frame of their stack in
+/// `source`. Values that are not errors are described by their string form
+/// after `prefix`, and serialized into the details as JSON when they can be.
+fn thrown_error(thrown: &Value<'_>, prefix: &str, source: &str) -> (String, JsErrorDetails) {
+    let (message, stack) = thrown_message(thrown, prefix);
+    let Some(exception) = thrown.as_exception() else {
+        let ctx = thrown.ctx();
+        let json = match stringify(ctx, thrown.clone()) {
+            Ok(text) => text.and_then(|text| serde_json::from_str(&text).ok()),
+            Err(_) => {
+                // A getter or `toJSON` threw; the value is described by its message alone
+                ctx.catch();
+                None
+            }
+        };
+        let details = JsErrorDetails {
+            thrown: json,
+            ..JsErrorDetails::default()
+        };
+        return (message, details);
+    };
+    let number = |key: &str| exception.get::<_, Option<u32>>(key).ok().flatten();
+    let (line, column) = match number("lineNumber") {
+        Some(line) => (Some(line), number("columnNumber")),
+        None => stack
+            .as_deref()
+            .and_then(|stack| stack_location(stack, source))
+            .map_or((None, None), |(line, column)| (Some(line), column)),
+    };
+    let details = JsErrorDetails {
+        name: exception.get::<_, Option<String>>("name").ok().flatten(),
+        stack,
+        line,
+        column,
+        thrown: None,
+    };
+    (message, details)
+}
+
+/// Line and, when given, column of the first frame of `stack` in `source`
+///
+/// QuickJS writes frames as `at name (source:line:column)`, or without the
+/// name and parentheses for top-level code.
+fn stack_location(stack: &str, source: &str) -> Option<(u32, Option<u32>)> {
+    stack.lines().find_map(|frame| {
+        let frame = frame.trim().strip_prefix("at ")?;
+        let location = match frame.rfind(" (") {
+            Some(open) => frame[open + 2..].strip_suffix(')')?,
+            None => frame,
+        };
+        let mut numbers = location.strip_prefix(source)?.strip_prefix(':')?.split(':');
+        let line = numbers.next()?.parse().ok()?;
+        Some((line, numbers.next().and_then(|column| column.parse().ok())))
+    })
+}
+
+/// Check that source code parses as a JavaScript script without running it
+///
+/// # Arguments
+///
+/// * `source` - JavaScript source code to check
+///
+/// # Returns
+///
+/// * `Result<(), String>` - `Ok` if the source compiles, otherwise the syntax error message
+pub fn check_syntax(source: &str) -> Result<(), String> {
+    check_compiles(source, qjs::JS_EVAL_TYPE_GLOBAL)
+}
+
+/// Check that source code parses as an ES module without running it
+///
+/// Imports are not resolved, so a module importing code that does not exist passes.
+///
+/// # Arguments
+///
+/// * `source` - JavaScript source code to check
+///
+/// # Returns
+///
+/// * `Result<(), String>` - `Ok` if the source compiles, otherwise the syntax error message
+pub fn check_module_syntax(source: &str) -> Result<(), String> {
+    check_compiles(source, qjs::JS_EVAL_TYPE_MODULE)
+}
+
+/// Whether source code reads as an ES module rather than a classic script:
+/// a line opens with an `import` or `export` declaration, and the code
+/// compiles as a module but not as a script
+///
+/// ```
+/// use hoya::js_engine::looks_like_module;
+///
+/// assert!(looks_like_module("export default 42;"));
+/// assert!(!looks_like_module("import(\"data:text/javascript,export default 1\");"));
+/// assert!(!looks_like_module("const exported = 1;"));
+/// ```
+pub fn looks_like_module(source: &str) -> bool {
+    let declares = source.lines().map(str::trim_start).any(|line| {
+        ["import", "export"].iter().any(|keyword| {
+            line.strip_prefix(keyword)
+                .is_some_and(|rest| rest.starts_with([' ', '\t', '{', '*', '"', '\'']))
+        })
+    });
+    declares && check_syntax(source).is_err() && check_module_syntax(source).is_ok()
+}
+
+/// Compile source code as a script or module (`eval_type`) without running it
+fn check_compiles(source: &str, eval_type: u32) -> Result<(), String> {
+    let source = CString::new(source).map_err(|_| "source contains a NUL byte".to_string())?;
+    let runtime = Runtime::new().map_err(|e| e.to_string())?;
+    let context = Context::full(&runtime).map_err(|e| e.to_string())?;
+
+    context.with(|ctx| {
+        // rquickjs has no compile-only mode for classic scripts, so call QuickJS directly.
+        // Module imports are only resolved once a module is evaluated.
+        // SAFETY: `source` is NUL-terminated as JS_Eval requires, and the returned value is
+        // handed to `Value::from_raw`, which takes ownership and frees it on drop.
+        let compiled = unsafe {
+            let raw = qjs::JS_Eval(
+                ctx.as_raw().as_ptr(),
+                source.as_ptr(),
+                source.as_bytes().len() as _,
+                c"<probe>".as_ptr(),
+                (eval_type | qjs::JS_EVAL_FLAG_COMPILE_ONLY) as i32,
+            );
+            Value::from_raw(ctx.clone(), raw)
+        };
+
+        if compiled.is_exception() {
+            let exception = ctx.catch();
+            let message = exception
+                .as_exception()
+                .and_then(|e| e.message())
+                .unwrap_or_else(|| "syntax error".to_string());
+            return Err(message);
+        }
+        Ok(())
+    })
 }
@@ -0,0 +1,293 @@
+//! ES modules.
+//!
+//! Modules are evaluated with QuickJS' module API instead of as classic
+//! scripts. Imports are resolved by `ImportResolver`: `data:` URLs stand for
+//! themselves, absolute `http` and `https` URLs too, and relative specifiers
+//! (`./`, `../`, `/`) are joined onto the URL of the importing module. Every
+//! other specifier, such as a bare package name or a relative import from
+//! code without a URL, fails with `MODULE_RESOLUTION_DISABLED`.
+//!
+//! `ModuleLoader` decodes `data:` URLs in place and fetches the others with
+//! the execution's fetch context, so imports go through the same client,
+//! allowlist, outbound policy, call limit, byte quota and per-call limits as
+//! the `fetch` global. Fetched sources are kept for the rest of the
+//! execution, and QuickJS links every resolved URL once, which is what makes
+//! cyclic imports work as the specification says. An import that cannot be
+//! fetched throws an error naming the URL and the chain of modules that
+//! imported it.
+
+use super::{exception_error, settle, syntax_error, EventLoop};
+use crate::error::AppError;
+use crate::net::{self, FetchContext};
+use crate::types::{BodyEncoding, WasmFetchOptions};
+use rquickjs::loader::{Loader, Resolver};
+use rquickjs::module::Declared;
+use rquickjs::{Ctx, Function, Module, Result as QuickJsResult, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Modules an importer chain lists at most, innermost first
+const MAX_CHAIN_LENGTH: usize = 16;
+
+/// What the resolver saw, shared between the resolver, the loader and the engine
+#[derive(Clone, Default)]
+pub(super) struct ImportLog(Arc<Mutex<ImportRecords>>);
+
+/// Contents of an `ImportLog`
+#[derive(Default)]
+struct ImportRecords {
+    /// The first specifier the resolver refused
+    refused: Option<String>,
+    /// The module that first imported each resolved module
+    importers: HashMap<String, String>,
+}
+
+impl ImportLog {
+    /// Remember a refused `specifier`, unless an earlier refusal is remembered already
+    fn refuse(&self, specifier: &str) {
+        if let Ok(mut records) = self.0.lock() {
+            records.refused.get_or_insert_with(|| specifier.to_string());
+        }
+    }
+
+    /// The remembered refused specifier, forgetting it
+    fn take_refused(&self) -> Option<String> {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|mut records| records.refused.take())
+    }
+
+    /// Remember that `importer` imports `resolved`, unless another module did first
+    fn imported(&self, resolved: &str, importer: &str) {
+        if let Ok(mut records) = self.0.lock() {
+            records
+                .importers
+                .entry(resolved.to_string())
+                .or_insert_with(|| importer.to_string());
+        }
+    }
+
+    /// The modules through which `name` was imported, its importer first
+    fn chain(&self, name: &str) -> Vec<String> {
+        let Ok(records) = self.0.lock() else {
+            return Vec::new();
+        };
+        let mut chain: Vec<String> = Vec::new();
+        let mut current = name;
+        while let Some(importer) = records.importers.get(current) {
+            // A cycle leads back to a module already listed
+            if importer == name || chain.contains(importer) || chain.len() == MAX_CHAIN_LENGTH {
+                break;
+            }
+            chain.push(importer.clone());
+            current = importer;
+        }
+        chain
+    }
+}
+
+/// Resolves `data:` and `http(s)` URLs, and relative specifiers against the
+/// importing module's URL; refuses every other specifier
+pub(super) struct ImportResolver {
+    /// Where imports and refused specifiers are recorded
+    pub log: ImportLog,
+}
+
+impl Resolver for ImportResolver {
+    fn resolve<'js>(&mut self, _ctx: &Ctx<'js>, base: &str, name: &str) -> QuickJsResult<String> {
+        let resolved = if is_data_url(name) {
+            Some(name.to_string())
+        } else {
+            resolve_url(base, name)
+        };
+        let Some(resolved) = resolved else {
+            self.log.refuse(name);
+            return Err(rquickjs::Error::new_resolving_message(
+                base,
+                name,
+                "MODULE_RESOLUTION_DISABLED: only data: and http(s) URLs, and URLs relative \
+                 to a module downloaded from one, can be imported",
+            ));
+        };
+        self.log.imported(&resolved, base);
+        Ok(resolved)
+    }
+}
+
+/// Declares imported modules, decoding `data:` URLs and fetching the others
+pub(super) struct ModuleLoader {
+    /// The execution's fetch context, shared with the `fetch` globals
+    pub fetch_ctx: Arc<FetchContext>,
+    /// Importers recorded by the resolver, for error messages
+    pub log: ImportLog,
+    /// Sources loaded so far in this execution, by URL
+    pub sources: HashMap<String, Vec<u8>>,
+}
+
+impl Loader for ModuleLoader {
+    fn load<'js>(&mut self, ctx: &Ctx<'js>, name: &str) -> QuickJsResult<Module<'js, Declared>> {
+        let source = match self.sources.get(name) {
+            Some(source) => source.clone(),
+            None => {
+                let source = self.fetch(name).map_err(|reason| {
+                    let chain = self.log.chain(name);
+                    let message = match chain.is_empty() {
+                        true => reason,
+                        false => format!("{} (imported by {})", reason, chain.join(" <- ")),
+                    };
+                    rquickjs::Error::new_loading_message(name, message)
+                })?;
+                self.sources.insert(name.to_string(), source.clone());
+                source
+            }
+        };
+        Module::declare(ctx.clone(), name, source)
+    }
+}
+
+impl ModuleLoader {
+    /// Source of the module at `url`, or why it could not be loaded
+    fn fetch(&self, url: &str) -> Result<Vec<u8>, String> {
+        if is_data_url(url) {
+            return data_url_source(url).ok_or_else(|| "not a valid data: URL".to_string());
+        }
+        let options = WasmFetchOptions {
+            url: url.to_string(),
+            method: "GET".to_string(),
+            headers: HashMap::new(),
+            body: None,
+            body_encoding: BodyEncoding::Utf8,
+            cookies: None,
+            timeout_ms: None,
+            max_redirects: None,
+            max_response_bytes: None,
+        };
+        let response = net::fetch_blocking(&self.fetch_ctx, options).map_err(|e| e.to_string())?;
+        if let Some(error) = response.error {
+            return Err(format!("{}: {}", error.code, error.message));
+        }
+        if !(200..300).contains(&response.status) {
+            return Err(format!("HTTP {}", response.status));
+        }
+        if response.body_encoding != BodyEncoding::Utf8 {
+            return Err("the module is not valid UTF-8".to_string());
+        }
+        Ok(response.body.into_bytes())
+    }
+}
+
+/// Evaluate an ES module and return the value its output is taken from
+///
+/// That is the module's default export, or else what its `main` export
+/// returns when called, or `undefined` when it exports neither. Evaluation,
+/// top-level `await` included, is driven to completion before the exports
+/// are read.
+///
+/// # Arguments
+///
+/// * `ctx` - Context to evaluate the module in, with `ImportResolver` installed
+/// * `source` - The module's source
+/// * `name` - File name of the module in stack traces and error details, and
+///   the base URL of its relative imports
+/// * `log` - The resolver's record of imports
+/// * `event_loop` - The execution's timers, which top-level `await` may wait for
+///
+/// # Returns
+///
+/// * `Result<Value, AppError>` - The output value, `ModuleResolutionDisabled`
+///   if a static import was refused, or the syntax error or exception the
+///   module failed with
+pub(super) fn run_module<'js>(
+    ctx: &Ctx<'js>,
+    source: &[u8],
+    name: &str,
+    log: &ImportLog,
+    event_loop: &EventLoop<'js>,
+) -> Result<Value<'js>, AppError> {
+    let unresolved = |error: AppError| match log.take_refused() {
+        Some(specifier) => AppError::ModuleResolutionDisabled {
+            specifier,
+            source: name.to_string(),
+        },
+        None => error,
+    };
+    let declared = Module::declare(ctx.clone(), name, source)
+        .map_err(|e| unresolved(syntax_error(ctx, e, name)))?;
+    let (module, evaluation) = declared
+        .eval()
+        .map_err(|e| unresolved(exception_error(ctx, e, name)))?;
+    settle(ctx, evaluation.into_value(), name, event_loop)?;
+
+    let namespace = module
+        .namespace()
+        .map_err(|e| exception_error(ctx, e, name))?;
+    let default: Value = namespace
+        .get("default")
+        .map_err(|e| exception_error(ctx, e, name))?;
+    if !default.is_undefined() {
+        return Ok(default);
+    }
+    let main: Option<Function> = namespace
+        .get("main")
+        .map_err(|e| exception_error(ctx, e, name))?;
+    match main {
+        Some(main) => main.call(()).map_err(|e| exception_error(ctx, e, name)),
+        None => Ok(Value::new_undefined(ctx.clone())),
+    }
+}
+
+/// Whether `specifier` is a `data:` URL
+fn is_data_url(specifier: &str) -> bool {
+    specifier
+        .get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"))
+}
+
+/// URL of the module `specifier` imported by the module at `base`
+///
+/// Absolute `http` and `https` URLs stand for themselves; `./`, `../` and `/`
+/// specifiers are joined onto `base` when it is such a URL itself.
+fn resolve_url(base: &str, specifier: &str) -> Option<String> {
+    let is_web = |url: &url::Url| matches!(url.scheme(), "http" | "https");
+    if let Ok(url) = url::Url::parse(specifier) {
+        return is_web(&url).then(|| url.to_string());
+    }
+    if !["./", "../", "/"]
+        .iter()
+        .any(|prefix| specifier.starts_with(prefix))
+    {
+        return None;
+    }
+    let base = url::Url::parse(base).ok().filter(is_web)?;
+    base.join(specifier).ok().map(|url| url.to_string())
+}
+
+/// Source held by a `data:` URL: its data percent-decoded, then base64-decoded
+/// when the URL is marked `;base64`
+fn data_url_source(url: &str) -> Option<Vec<u8>> {
+    let (header, data) = url.get(5..)?.split_once(',')?;
+    let data = percent_decode(data)?;
+    if header.to_ascii_lowercase().ends_with(";base64") {
+        crate::base64::decode(std::str::from_utf8(&data).ok()?)
+    } else {
+        Some(data)
+    }
+}
+
+/// Decode `%XX` escapes; `None` for a malformed escape
+fn percent_decode(text: &str) -> Option<Vec<u8>> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            decoded.push(u8::from_str_radix(text.get(i + 1..i + 3)?, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(decoded)
+}
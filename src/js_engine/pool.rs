@@ -0,0 +1,217 @@
+//! Pool of QuickJS runtimes.
+//!
+//! Building a `Runtime` costs milliseconds per execution, so the server keeps
+//! `js.runtime_pool_size` of them ready. Each lives on a thread of its own for
+//! its whole life, because QuickJS measures its stack limit from the thread
+//! that created it. An execution checks out an idle thread, waiting for at
+//! most `js.runtime_checkout_timeout_ms` while all of them are busy, hands it
+//! the job and blocks until the job is done, like guest fetch calls do with
+//! the fetch runtime.
+//!
+//! Executions never share globals: each one creates a context of its own, a
+//! pristine realm, on the pooled runtime and drops it when it finishes. The
+//! interrupt handler, rejection tracker, module loader and heap limit an
+//! execution installs on the runtime are reset once it returns. A runtime
+//! whose execution was interrupted, ran out of heap or panicked may hold
+//! half-finished state, so its thread drops it and builds a fresh one before
+//! taking the next job.
+
+use crate::error::{AppError, ExecutionResult};
+use rquickjs::loader::{BuiltinLoader, BuiltinResolver};
+use rquickjs::Runtime;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Stack of each runtime thread, as large as that of the blocking-pool threads
+/// scripts run on without a pool; `js.max_stack_bytes` is bounded by it
+const THREAD_STACK_BYTES: usize = 2 << 20;
+
+/// An execution run on a pooled runtime
+type Job = Box<dyn FnOnce(&Runtime) -> Result<ExecutionResult, AppError> + Send>;
+
+/// A job, and where its thread sends the result
+type Request = (Job, mpsc::SyncSender<Result<ExecutionResult, AppError>>);
+
+/// Runtimes on threads of their own, checked out by one execution at a time
+pub struct RuntimePool {
+    /// Threads waiting for a job, by the sender of their job channel
+    idle: Mutex<Vec<mpsc::Sender<Request>>>,
+    /// Notified whenever a thread becomes idle again
+    returned: Condvar,
+    /// Threads started by the pool
+    size: usize,
+    /// Longest an execution waits for an idle thread
+    checkout_timeout: Duration,
+}
+
+impl RuntimePool {
+    /// Start `size` threads, each building its runtime right away
+    ///
+    /// The threads end once the pool is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Runtimes to keep ready
+    /// * `checkout_timeout` - Longest an execution waits for one of them
+    ///
+    /// # Returns
+    ///
+    /// * `std::io::Result<RuntimePool>` - The pool, or why a thread could not be started
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hoya::js_engine::{execute_js, JsExecution, RuntimePool};
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    ///
+    /// let pool = Arc::new(RuntimePool::start(1, Duration::from_secs(5)).unwrap());
+    /// let run = |code: &[u8]| {
+    ///     let mut execution = JsExecution::from(code);
+    ///     execution.runtimes = Some(pool.clone());
+    ///     execute_js(execution)
+    /// };
+    /// assert_eq!(run(b"globalThis.leaked = 'secret'; 1").unwrap().output, "1");
+    /// // The same runtime, in a new realm
+    /// assert_eq!(run(b"typeof leaked").unwrap().output, "undefined");
+    /// ```
+    pub fn start(size: usize, checkout_timeout: Duration) -> std::io::Result<Self> {
+        let mut idle = Vec::with_capacity(size);
+        for index in 0..size {
+            let (sender, requests) = mpsc::channel::<Request>();
+            std::thread::Builder::new()
+                .name(format!("hoya-js-{}", index))
+                .stack_size(THREAD_STACK_BYTES)
+                .spawn(move || serve(requests))?;
+            idle.push(sender);
+        }
+        Ok(RuntimePool {
+            idle: Mutex::new(idle),
+            returned: Condvar::new(),
+            size,
+            checkout_timeout,
+        })
+    }
+
+    /// Run `job` on an idle runtime and block until it is done
+    ///
+    /// # Arguments
+    ///
+    /// * `job` - The execution, given the runtime to create its context on
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ExecutionResult, AppError>` - What the job returned,
+    ///   `RuntimePoolExhausted` if no runtime became idle in time, or an
+    ///   `Internal` error if the job panicked
+    pub fn run<F>(&self, job: F) -> Result<ExecutionResult, AppError>
+    where
+        F: FnOnce(&Runtime) -> Result<ExecutionResult, AppError> + Send + 'static,
+    {
+        let thread = self.checkout()?;
+        let (sender, receiver) = mpsc::sync_channel(1);
+        if thread.send((Box::new(job), sender)).is_err() {
+            // Threads only end with the pool; this one is not handed out again
+            return Err(AppError::Internal(
+                "A JavaScript runtime thread has stopped".to_string(),
+            ));
+        }
+        // The thread drops the sender without a result when the job panics
+        let result = receiver.recv().unwrap_or_else(|_| {
+            Err(AppError::Internal(
+                "The JavaScript runtime failed before the script finished".to_string(),
+            ))
+        });
+        self.checkin(thread);
+        result
+    }
+
+    /// Take an idle thread, waiting for one to be returned when all are busy
+    fn checkout(&self) -> Result<mpsc::Sender<Request>, AppError> {
+        let started = Instant::now();
+        let deadline = started + self.checkout_timeout;
+        let mut idle = self
+            .idle
+            .lock()
+            .map_err(|_| AppError::Internal("The runtime pool lock is poisoned".to_string()))?;
+        loop {
+            if let Some(thread) = idle.pop() {
+                return Ok(thread);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(AppError::RuntimePoolExhausted {
+                    pool_size: self.size,
+                    waited_ms: started.elapsed().as_millis() as u64,
+                });
+            }
+            idle = self
+                .returned
+                .wait_timeout(idle, deadline - now)
+                .map_err(|_| AppError::Internal("The runtime pool lock is poisoned".to_string()))?
+                .0;
+        }
+    }
+
+    /// Make a thread available to the next execution
+    fn checkin(&self, thread: mpsc::Sender<Request>) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.push(thread);
+            self.returned.notify_one();
+        }
+    }
+}
+
+/// Run jobs on a runtime of the thread's own until the pool is dropped
+fn serve(requests: mpsc::Receiver<Request>) {
+    let mut runtime = Runtime::new().ok();
+    while let Ok((job, reply)) = requests.recv() {
+        let current = match runtime.take().map_or_else(Runtime::new, Ok) {
+            Ok(current) => current,
+            Err(e) => {
+                let _ = reply.send(Err(e.into()));
+                continue;
+            }
+        };
+        let Ok(result) = catch_unwind(AssertUnwindSafe(|| job(&current))) else {
+            tracing::error!("A JavaScript execution panicked; its runtime is discarded");
+            continue;
+        };
+        if reusable(&result) {
+            reset(&current);
+            runtime = Some(current);
+        }
+        // Fails only if the caller is gone
+        let _ = reply.send(result);
+    }
+}
+
+/// Whether a runtime can run another execution after one that ended with `result`
+///
+/// Executions interrupted for their timeout, cancellation or output budget,
+/// and those that ran out of heap, may have left the runtime mid-operation.
+fn reusable(result: &Result<ExecutionResult, AppError>) -> bool {
+    let Err(error) = result else {
+        return true;
+    };
+    !matches!(
+        error.cause(),
+        AppError::Timeout { .. }
+            | AppError::Cancelled
+            | AppError::OutputBudgetExceeded { .. }
+            | AppError::MemoryLimitExceeded { .. }
+            | AppError::QuickJs(_)
+            | AppError::Internal(_)
+    )
+}
+
+/// Remove what an execution installed on the runtime, and free what it left behind
+fn reset(runtime: &Runtime) {
+    runtime.set_interrupt_handler(None);
+    runtime.set_host_promise_rejection_tracker(None);
+    runtime.set_loader(BuiltinResolver::default(), BuiltinLoader::default());
+    runtime.set_memory_limit(usize::MAX);
+    runtime.run_gc();
+}
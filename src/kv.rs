@@ -0,0 +1,341 @@
+//! # Host key-value store
+//!
+//! An in-memory key-value store executions share through named namespaces.
+//! A request picks its namespace with `kv_namespace`; executions naming the
+//! same namespace see each other's entries for as long as the server runs.
+//! Without one, the execution gets an isolated namespace of its own that is
+//! dropped when it finishes.
+//!
+//! Guests reach their namespace through the `kv_get`/`kv_set`/`kv_set_ttl`/
+//! `kv_delete` WebAssembly imports and `hoya.kv` in JavaScript. Unlike
+//! persistent storage it needs no permission and nothing is written to disk.
+//! Each operation is atomic; read-modify-write sequences spanning several
+//! operations are not.
+//!
+//! Namespaces are limited to `kv.max_keys` entries and `kv.max_bytes` bytes
+//! of keys and values, and the server to `kv.max_namespaces` shared
+//! namespaces; a write that would exceed any of them is refused and changes
+//! nothing. Entries may be written with a time to live, after which they are
+//! gone and no longer count against the quotas. A namespace whose last entry
+//! is deleted or expires stops counting against `kv.max_namespaces`.
+
+use crate::config::KvConfig;
+use crate::storage::{Usage, MAX_KEY_BYTES};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Longest accepted namespace name, in bytes
+pub const MAX_NAMESPACE_BYTES: usize = 128;
+
+/// Why a key-value operation was refused
+#[derive(Debug)]
+pub enum KvError {
+    /// The key is empty or longer than `MAX_KEY_BYTES`
+    InvalidKey,
+    /// The write would take the namespace past one of its quotas
+    QuotaExceeded {
+        /// Entries the namespace would hold
+        keys: u64,
+        /// Bytes the namespace would hold
+        bytes: u64,
+    },
+    /// The write would create a shared namespace past `kv.max_namespaces`
+    TooManyNamespaces {
+        /// Shared namespaces allowed
+        max: usize,
+    },
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::InvalidKey => {
+                write!(f, "Keys must be between 1 and {} bytes long", MAX_KEY_BYTES)
+            }
+            KvError::QuotaExceeded { keys, bytes } => write!(
+                f,
+                "The write would leave the namespace with {} entries and {} bytes",
+                keys, bytes
+            ),
+            KvError::TooManyNamespaces { max } => {
+                write!(f, "The server already holds {} key-value namespaces", max)
+            }
+        }
+    }
+}
+
+/// A stored value
+struct Entry {
+    /// The value as written
+    value: Vec<u8>,
+    /// When the entry expires, if it was written with a time to live
+    expires: Option<Instant>,
+}
+
+/// Entries of one namespace
+#[derive(Default)]
+struct Entries {
+    /// Entries by key, including expired ones not dropped yet
+    values: HashMap<Vec<u8>, Entry>,
+    /// Bytes of keys and values held
+    bytes: u64,
+}
+
+impl Entries {
+    /// Drop the entries whose time to live has passed
+    fn expire(&mut self, now: Instant) {
+        let bytes = &mut self.bytes;
+        self.values.retain(|key, entry| {
+            let live = !entry.expires.is_some_and(|expires| expires <= now);
+            if !live {
+                *bytes -= (key.len() + entry.value.len()) as u64;
+            }
+            live
+        });
+    }
+
+    /// Value of the live entry under `key`
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.expire(Instant::now());
+        self.values.get(key).map(|entry| entry.value.clone())
+    }
+
+    /// Write an entry unless the namespace would exceed `quota`
+    fn set(
+        &mut self,
+        quota: Usage,
+        key: &[u8],
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> Result<(), KvError> {
+        let now = Instant::now();
+        self.expire(now);
+        let replaced = self
+            .values
+            .get(key)
+            .map(|entry| (key.len() + entry.value.len()) as u64);
+        let after = Usage {
+            keys: self.values.len() as u64 + u64::from(replaced.is_none()),
+            bytes: self.bytes - replaced.unwrap_or(0) + (key.len() + value.len()) as u64,
+        };
+        if after.keys > quota.keys || after.bytes > quota.bytes {
+            return Err(KvError::QuotaExceeded {
+                keys: after.keys,
+                bytes: after.bytes,
+            });
+        }
+        self.values.insert(
+            key.to_vec(),
+            Entry {
+                value: value.to_vec(),
+                // A time to live too long to represent never runs out
+                expires: ttl.and_then(|ttl| now.checked_add(ttl)),
+            },
+        );
+        self.bytes = after.bytes;
+        Ok(())
+    }
+
+    /// Remove the live entry under `key`, returning whether there was one
+    fn delete(&mut self, key: &[u8]) -> bool {
+        self.expire(Instant::now());
+        match self.values.remove(key) {
+            Some(entry) => {
+                self.bytes -= (key.len() + entry.value.len()) as u64;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Shared namespaces, held for as long as the server runs
+pub struct KvStore {
+    /// Non-empty shared namespaces, by name
+    namespaces: Mutex<HashMap<String, Entries>>,
+    /// Shared namespaces allowed at once
+    max_namespaces: usize,
+    /// Entries and bytes allowed per namespace
+    quota: Usage,
+}
+
+/// Handle on the namespace of one execution
+#[derive(Clone)]
+pub struct KvNamespace {
+    /// Entries and bytes allowed in the namespace
+    quota: Usage,
+    /// Where the entries live
+    scope: Scope,
+}
+
+/// Where a namespace's entries live
+#[derive(Clone)]
+enum Scope {
+    /// In the store, under a name other executions can use too
+    Shared {
+        /// The store holding the namespace
+        store: Arc<KvStore>,
+        /// Name the request chose
+        name: String,
+    },
+    /// With the handle only, dropped with the execution's last clone of it
+    Isolated(Arc<Mutex<Entries>>),
+}
+
+/// Lock `mutex`, recovering the guard if a holder panicked
+///
+/// Every operation leaves the entries consistent before it can panic.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Reject keys no namespace can hold
+fn check_key(key: &[u8]) -> Result<(), KvError> {
+    if key.is_empty() || key.len() > MAX_KEY_BYTES {
+        return Err(KvError::InvalidKey);
+    }
+    Ok(())
+}
+
+/// Check a requested namespace name
+///
+/// # Returns
+///
+/// * `Result<(), String>` - Why the name cannot be used, if it cannot:
+///   names are 1 to `MAX_NAMESPACE_BYTES` ASCII letters, digits, `.`, `_`,
+///   `-` and `:`
+pub fn validate_namespace(name: &str) -> Result<(), String> {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | ':');
+    if name.is_empty() || name.len() > MAX_NAMESPACE_BYTES || !name.chars().all(valid_char) {
+        return Err(format!(
+            "kv_namespace must be 1 to {} ASCII letters, digits, '.', '_', '-' or ':'",
+            MAX_NAMESPACE_BYTES
+        ));
+    }
+    Ok(())
+}
+
+impl KvStore {
+    /// An empty store with the quotas of the `[kv]` section
+    pub fn new(config: &KvConfig) -> Self {
+        KvStore {
+            namespaces: Mutex::new(HashMap::new()),
+            max_namespaces: config.max_namespaces,
+            quota: Usage {
+                keys: config.max_keys,
+                bytes: config.max_bytes,
+            },
+        }
+    }
+
+    /// Handle on the shared namespace `name`, which `validate_namespace` accepted
+    pub fn namespace(self: &Arc<Self>, name: &str) -> KvNamespace {
+        KvNamespace {
+            quota: self.quota,
+            scope: Scope::Shared {
+                store: self.clone(),
+                name: name.to_string(),
+            },
+        }
+    }
+
+    /// A new namespace no other execution can reach, with the store's quotas
+    ///
+    /// ```
+    /// use hoya::config::KvConfig;
+    /// use hoya::kv::KvStore;
+    /// use std::sync::Arc;
+    ///
+    /// let store = Arc::new(KvStore::new(&KvConfig::default()));
+    /// let shared = store.namespace("counters");
+    /// shared.set(b"visits", b"1", None).unwrap();
+    /// assert_eq!(store.namespace("counters").get(b"visits").unwrap(), Some(b"1".to_vec()));
+    /// assert_eq!(store.isolated().get(b"visits").unwrap(), None);
+    /// ```
+    pub fn isolated(&self) -> KvNamespace {
+        KvNamespace {
+            quota: self.quota,
+            scope: Scope::Isolated(Arc::new(Mutex::new(Entries::default()))),
+        }
+    }
+
+    /// Run `f` on the entries of the shared namespace `name`, dropping it once empty
+    fn with_shared<T>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut Entries) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let mut namespaces = lock(&self.namespaces);
+        if !namespaces.contains_key(name) && namespaces.len() >= self.max_namespaces {
+            // Namespaces whose entries all expired make room
+            let now = Instant::now();
+            namespaces.retain(|_, entries| {
+                entries.expire(now);
+                !entries.values.is_empty()
+            });
+            if namespaces.len() >= self.max_namespaces {
+                let mut entries = Entries::default();
+                let result = f(&mut entries)?;
+                // Reads and deletes of a missing namespace leave it missing
+                if entries.values.is_empty() {
+                    return Ok(result);
+                }
+                return Err(KvError::TooManyNamespaces {
+                    max: self.max_namespaces,
+                });
+            }
+        }
+        let entries = namespaces.entry(name.to_string()).or_default();
+        let result = f(entries);
+        if entries.values.is_empty() {
+            namespaces.remove(name);
+        }
+        result
+    }
+}
+
+impl KvNamespace {
+    /// Run `f` on the namespace's entries
+    fn with_entries<T>(
+        &self,
+        f: impl FnOnce(&mut Entries) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        match &self.scope {
+            Scope::Shared { store, name } => store.with_shared(name, f),
+            Scope::Isolated(entries) => f(&mut lock(entries)),
+        }
+    }
+
+    /// Value stored under `key`, if any and not expired
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        check_key(key)?;
+        self.with_entries(|entries| Ok(entries.get(key)))
+    }
+
+    /// Store `value` under `key`, replacing any previous value
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Key of 1 to `MAX_KEY_BYTES` bytes
+    /// * `value` - The value
+    /// * `ttl` - How long the entry lives; `None` keeps it until it is
+    ///   deleted or the server stops
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), KvError>` - `QuotaExceeded` or `TooManyNamespaces` if
+    ///   the write would exceed a quota, in which case nothing is written
+    pub fn set(&self, key: &[u8], value: &[u8], ttl: Option<Duration>) -> Result<(), KvError> {
+        check_key(key)?;
+        let quota = self.quota;
+        self.with_entries(|entries| entries.set(quota, key, value, ttl))
+    }
+
+    /// Remove `key`, returning whether it was present
+    pub fn delete(&self, key: &[u8]) -> Result<bool, KvError> {
+        check_key(key)?;
+        self.with_entries(|entries| Ok(entries.delete(key)))
+    }
+}
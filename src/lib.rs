@@ -0,0 +1,80 @@
+//! # Hoya
+//!
+//! Hoya is a service that executes JavaScript and WebAssembly code from remote URLs.
+//! It provides a simple HTTP API for executing code and returning results.
+//!
+//! ## Features
+//!
+//! - Execute JavaScript code using QuickJS engine
+//! - Execute WebAssembly modules with Wasmtime
+//! - Fetch and execute code from remote URLs
+//! - Inject utility functions into JavaScript and WASM environments
+//!
+//! ## API
+//!
+//! The service exposes a POST endpoint at `/execute` that accepts a JSON payload
+//! with a URL pointing to JavaScript (.js) or WebAssembly (.wasm) code.
+//!
+//! ## Embedding
+//!
+//! The `hoya` binary is a thin wrapper around this crate: it loads a
+//! [`config::Config`], builds a [`state::AppState`] and serves
+//! [`server::router`]. The engines can also be used without HTTP:
+//! [`js_engine::execute_js`] and the async [`wasm_engine::execute_wasm`]
+//! return an [`error::ExecutionResult`], or the [`error::AppError`] the
+//! execution failed with, and the host functions they install are exported as
+//! [`js_engine::register_to_globals_with_capture`] and
+//! [`wasm_engine::register_linker_functions`]. Guest fetch calls run on a
+//! [`fetch_runtime::FetchRuntime`] of their own, so scripts may be run from
+//! any thread, including from inside a current-thread Tokio runtime, and
+//! modules may be awaited on any runtime or driven by any executor.
+//!
+//! ```
+//! let result = hoya::js_engine::execute_js(b"[1, 2, 3].map((n) => n * 2)".into()).unwrap();
+//! assert_eq!(result.output_json, Some(serde_json::json!([2, 4, 6])));
+//! ```
+
+pub mod admission;
+pub mod audit;
+pub mod auth;
+pub mod base64;
+pub mod cancel;
+pub mod circuit;
+pub mod clock;
+pub mod compression;
+pub mod config;
+pub mod detect;
+pub mod download;
+pub mod download_cache;
+pub mod error;
+pub mod executor;
+pub mod fetch_runtime;
+pub mod guest_env;
+pub mod history;
+pub mod jobs;
+pub mod js_engine;
+pub mod kv;
+pub mod limits;
+pub mod live;
+pub mod manifest;
+pub mod metrics;
+pub mod negotiate;
+pub mod net;
+pub mod outbound;
+pub mod output;
+pub mod random;
+pub mod record;
+pub mod registry;
+pub mod reporter;
+pub mod rfc3339;
+pub mod selftest;
+pub mod server;
+pub mod state;
+pub mod storage;
+pub mod stream;
+pub mod telemetry;
+pub mod types;
+pub mod warmup;
+pub mod wasm_engine;
+pub mod wat;
+pub mod wire;
@@ -0,0 +1,362 @@
+//! # Execution limits
+//!
+//! Every resource limit of an execution is resolved here, in one place, from
+//! the request's `limits` object and the server configuration, which supplies
+//! the default and the hard cap of each limit:
+//!
+//! | Limit              | Default                          | Cap                       |
+//! |--------------------|----------------------------------|---------------------------|
+//! | `timeout_ms`       | `execution.default_timeout_ms`   | `execution.max_timeout_ms`|
+//! | `memory_bytes`     | `wasm.default_memory_bytes`      | `wasm.max_memory_bytes`   |
+//! |   (JavaScript)     | `js.default_memory_bytes`        | `js.max_memory_bytes`     |
+//! | `max_output_bytes` | `execution.max_output_bytes`     | the same                  |
+//! | `max_stdout_bytes` | `execution.max_stdout_bytes`     | the same                  |
+//! | `max_stderr_bytes` | `execution.max_stderr_bytes`     | the same                  |
+//! | `max_log_bytes`    | `execution.max_log_bytes`        | the same                  |
+//! | `max_fetch_calls`  | `execution.max_fetch_calls`      | the same                  |
+//! | `max_fetch_bytes`  | `execution.max_fetch_bytes`      | the same                  |
+//! | `max_log_entries`  | `execution.max_log_entries`      | the same                  |
+//! | `max_fuel`         | `wasm.default_fuel`              | `wasm.max_fuel`           |
+//!
+//! Requests can only stay within the caps: values of 0 or above the cap fail
+//! the request with `INVALID_OPTIONS`, which reports every such field with
+//! the requested value and the cap, not just the first.
+//! `memory_bytes` is resolved again for JavaScript once the code type is
+//! known, against the `js` settings.
+//! The top-level `timeout_ms`, `max_memory_bytes` and `max_output_bytes`
+//! request fields are deprecated aliases of the first three limits; when both
+//! are set, `limits` wins.
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::output::{Channel, OutputBudget};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// Longest a single guest sleep (`sleep_ms` in WebAssembly, `sleep` in JavaScript) lasts, in milliseconds
+pub const MAX_SLEEP_MS_PER_CALL: u32 = 10_000;
+
+/// Limits asked for by a request; unset fields use the server defaults
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RequestedLimits {
+    /// Execution timeout in milliseconds
+    pub timeout_ms: Option<u64>,
+    /// Largest size WebAssembly memories may grow to, or heap a script may allocate, in bytes
+    pub memory_bytes: Option<u64>,
+    /// Bytes of output captured across all channels
+    pub max_output_bytes: Option<u64>,
+    /// Bytes of stdout captured
+    pub max_stdout_bytes: Option<u64>,
+    /// Bytes of stderr captured
+    pub max_stderr_bytes: Option<u64>,
+    /// Bytes of `app_log` messages captured
+    pub max_log_bytes: Option<u64>,
+    /// Guest fetch calls sent; later calls fail with `FETCH_LIMIT_EXCEEDED`
+    pub max_fetch_calls: Option<u64>,
+    /// Response body bytes received by guest fetch calls; later calls fail with `FETCH_QUOTA_EXCEEDED`
+    pub max_fetch_bytes: Option<u64>,
+    /// Messages captured across stdout, stderr and `app_log`
+    pub max_log_entries: Option<u64>,
+    /// Fuel WebAssembly modules may consume before they fail with `RESOURCE_EXHAUSTED`
+    pub max_fuel: Option<u64>,
+}
+
+/// A requested value the server does not allow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionViolation {
+    /// The request field, e.g. `limits.timeout_ms`
+    pub field: String,
+    /// The requested value
+    pub requested: u64,
+    /// The server's cap for the field
+    pub maximum: u64,
+}
+
+impl fmt::Display for OptionViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.requested {
+            0 => write!(f, "{} must be at least 1", self.field),
+            _ => write!(f, "{} exceeds maximum {}", self.field, self.maximum),
+        }
+    }
+}
+
+/// Resolved limits of one execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Wall-clock limit of the execution
+    pub timeout: Duration,
+    /// Largest size each WebAssembly memory may grow to, or heap a script may allocate;
+    /// going beyond it fails with `MEMORY_LIMIT_EXCEEDED`
+    pub memory_bytes: u64,
+    /// Bytes of output captured across all channels
+    pub max_output_bytes: usize,
+    /// Bytes of stdout captured, within `max_output_bytes`
+    pub max_stdout_bytes: usize,
+    /// Bytes of stderr captured, within `max_output_bytes`
+    pub max_stderr_bytes: usize,
+    /// Bytes of `app_log` messages captured, within `max_output_bytes`
+    pub max_log_bytes: usize,
+    /// Guest fetch calls sent
+    pub max_fetch_calls: u64,
+    /// Response body bytes received by guest fetch calls
+    pub max_fetch_bytes: u64,
+    /// Messages captured across stdout, stderr and `app_log`
+    pub max_log_entries: u64,
+    /// Fuel given to each WebAssembly store; JavaScript ignores it
+    pub max_fuel: u64,
+}
+
+impl RequestedLimits {
+    /// Fill the fields unset here from another set of requested limits
+    pub fn or(&self, other: &RequestedLimits) -> RequestedLimits {
+        RequestedLimits {
+            timeout_ms: self.timeout_ms.or(other.timeout_ms),
+            memory_bytes: self.memory_bytes.or(other.memory_bytes),
+            max_output_bytes: self.max_output_bytes.or(other.max_output_bytes),
+            max_stdout_bytes: self.max_stdout_bytes.or(other.max_stdout_bytes),
+            max_stderr_bytes: self.max_stderr_bytes.or(other.max_stderr_bytes),
+            max_log_bytes: self.max_log_bytes.or(other.max_log_bytes),
+            max_fetch_calls: self.max_fetch_calls.or(other.max_fetch_calls),
+            max_fetch_bytes: self.max_fetch_bytes.or(other.max_fetch_bytes),
+            max_log_entries: self.max_log_entries.or(other.max_log_entries),
+            max_fuel: self.max_fuel.or(other.max_fuel),
+        }
+    }
+}
+
+impl Limits {
+    /// The server defaults, for executions that ask for nothing
+    pub fn defaults(config: &Config) -> Limits {
+        let none = RequestedLimits::default();
+        // Defaults never exceed their caps; `Config::validate` ensures it
+        Limits::resolve(config, &none, &none).unwrap_or(Limits {
+            timeout: Duration::from_millis(config.execution.default_timeout_ms),
+            memory_bytes: config.wasm.default_memory_bytes,
+            max_output_bytes: config.execution.max_output_bytes,
+            max_stdout_bytes: config.execution.max_stdout_bytes,
+            max_stderr_bytes: config.execution.max_stderr_bytes,
+            max_log_bytes: config.execution.max_log_bytes,
+            max_fetch_calls: config.execution.max_fetch_calls,
+            max_fetch_bytes: config.execution.max_fetch_bytes,
+            max_log_entries: config.execution.max_log_entries,
+            max_fuel: config.wasm.default_fuel,
+        })
+    }
+
+    /// Resolve and validate the limits of one execution
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Server configuration supplying defaults and caps
+    /// * `requested` - The request's `limits` object
+    /// * `aliases` - Values of the deprecated top-level fields, used where `requested` is unset
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Limits, AppError>` - The limits, or `InvalidOptions` listing
+    ///   every value that is 0 or above its cap
+    pub fn resolve(
+        config: &Config,
+        requested: &RequestedLimits,
+        aliases: &RequestedLimits,
+    ) -> Result<Limits, AppError> {
+        let execution = &config.execution;
+        // (field in `limits`, deprecated top-level field, requested, alias, default, cap)
+        let rows = [
+            (
+                "timeout_ms",
+                Some("timeout_ms"),
+                requested.timeout_ms,
+                aliases.timeout_ms,
+                execution.default_timeout_ms,
+                execution.max_timeout_ms,
+            ),
+            (
+                "memory_bytes",
+                Some("max_memory_bytes"),
+                requested.memory_bytes,
+                aliases.memory_bytes,
+                config.wasm.default_memory_bytes,
+                config.wasm.max_memory_bytes,
+            ),
+            (
+                "max_output_bytes",
+                Some("max_output_bytes"),
+                requested.max_output_bytes,
+                aliases.max_output_bytes,
+                execution.max_output_bytes as u64,
+                execution.max_output_bytes as u64,
+            ),
+            (
+                "max_stdout_bytes",
+                None,
+                requested.max_stdout_bytes,
+                None,
+                execution.max_stdout_bytes as u64,
+                execution.max_stdout_bytes as u64,
+            ),
+            (
+                "max_stderr_bytes",
+                None,
+                requested.max_stderr_bytes,
+                None,
+                execution.max_stderr_bytes as u64,
+                execution.max_stderr_bytes as u64,
+            ),
+            (
+                "max_log_bytes",
+                None,
+                requested.max_log_bytes,
+                None,
+                execution.max_log_bytes as u64,
+                execution.max_log_bytes as u64,
+            ),
+            (
+                "max_fetch_calls",
+                None,
+                requested.max_fetch_calls,
+                None,
+                execution.max_fetch_calls,
+                execution.max_fetch_calls,
+            ),
+            (
+                "max_fetch_bytes",
+                None,
+                requested.max_fetch_bytes,
+                None,
+                execution.max_fetch_bytes,
+                execution.max_fetch_bytes,
+            ),
+            (
+                "max_log_entries",
+                None,
+                requested.max_log_entries,
+                None,
+                execution.max_log_entries,
+                execution.max_log_entries,
+            ),
+            (
+                "max_fuel",
+                None,
+                requested.max_fuel,
+                None,
+                config.wasm.default_fuel,
+                config.wasm.max_fuel,
+            ),
+        ];
+
+        let mut values = [0u64; 10];
+        let mut violations = Vec::new();
+        for (value, (name, alias_name, requested, alias, default, maximum)) in
+            values.iter_mut().zip(rows)
+        {
+            match resolve_one(name, alias_name, requested, alias, default, maximum) {
+                Ok(resolved) => *value = resolved,
+                Err(violation) => violations.push(violation),
+            }
+        }
+        if !violations.is_empty() {
+            return Err(AppError::InvalidOptions(violations));
+        }
+
+        let [timeout_ms, memory_bytes, max_output_bytes, max_stdout_bytes, max_stderr_bytes, max_log_bytes, max_fetch_calls, max_fetch_bytes, max_log_entries, max_fuel] =
+            values;
+        Ok(Limits {
+            timeout: Duration::from_millis(timeout_ms),
+            memory_bytes,
+            max_output_bytes: max_output_bytes as usize,
+            max_stdout_bytes: max_stdout_bytes as usize,
+            max_stderr_bytes: max_stderr_bytes as usize,
+            max_log_bytes: max_log_bytes as usize,
+            max_fetch_calls,
+            max_fetch_bytes,
+            max_log_entries,
+            max_fuel,
+        })
+    }
+
+    /// Resolve `memory_bytes` for a script, against the JavaScript heap default and cap
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Server configuration supplying `js.default_memory_bytes` and `js.max_memory_bytes`
+    /// * `requested` - The request's `limits` object
+    /// * `aliases` - Values of the deprecated top-level fields, used where `requested` is unset
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, AppError>` - The heap limit in bytes, or `InvalidOptions`
+    pub fn js_memory_bytes(
+        config: &Config,
+        requested: &RequestedLimits,
+        aliases: &RequestedLimits,
+    ) -> Result<u64, AppError> {
+        resolve_one(
+            "memory_bytes",
+            Some("max_memory_bytes"),
+            requested.memory_bytes,
+            aliases.memory_bytes,
+            config.js.default_memory_bytes,
+            config.js.max_memory_bytes,
+        )
+        .map_err(|violation| AppError::InvalidOptions(vec![violation]))
+    }
+
+    /// Create the output budget enforcing the output, per-channel and log limits
+    ///
+    /// # Arguments
+    ///
+    /// * `strict` - Whether exhausting the budget terminates the execution
+    /// * `echo_to_host` - Whether captured lines are also printed by the host (`capture.echo_to_host`)
+    pub fn output_budget(&self, strict: bool, echo_to_host: bool) -> OutputBudget {
+        OutputBudget::new(
+            self.max_output_bytes,
+            self.max_log_entries,
+            strict,
+            echo_to_host,
+        )
+        .with_channel_limit(Channel::Stdout, self.max_stdout_bytes)
+        .with_channel_limit(Channel::Stderr, self.max_stderr_bytes)
+        .with_channel_limit(Channel::Log, self.max_log_bytes)
+    }
+}
+
+/// Resolve one limit from the request, its deprecated alias and the server's default and cap
+///
+/// # Arguments
+///
+/// * `name` - Field of the limit in `limits`
+/// * `alias_name` - Deprecated top-level field for the same limit, if any
+/// * `requested` - Value in `limits`, which wins over the alias
+/// * `alias` - Value of the deprecated field
+/// * `default` - Value used when neither is set
+/// * `maximum` - Largest value a request may ask for
+///
+/// # Returns
+///
+/// * `Result<u64, OptionViolation>` - The limit, or the violation if the requested value is 0 or above `maximum`
+fn resolve_one(
+    name: &str,
+    alias_name: Option<&str>,
+    requested: Option<u64>,
+    alias: Option<u64>,
+    default: u64,
+    maximum: u64,
+) -> Result<u64, OptionViolation> {
+    let (field, requested) = match (requested, alias_name.zip(alias)) {
+        (Some(requested), _) => (format!("limits.{}", name), requested),
+        (None, Some((alias_name, alias))) => (alias_name.to_string(), alias),
+        (None, None) => return Ok(default),
+    };
+    if requested == 0 || requested > maximum {
+        return Err(OptionViolation {
+            field,
+            requested,
+            maximum,
+        });
+    }
+    Ok(requested)
+}
@@ -0,0 +1,66 @@
+//! # Live output over Server-Sent Events
+//!
+//! `POST /execute/stream` answers with an event stream instead of a single
+//! response: every line the guest writes is sent as a `stdout`, `stderr` or
+//! `log` event as soon as the output budget admits it, and a final `result`
+//! event carries the execute response. Only admitted lines are sent, so the
+//! stream never carries more output than a buffered response.
+//!
+//! Lines a module writes through WASI stdout/stderr are collected from its
+//! pipes when it finishes and arrive just before the `result` event. A client
+//! that disconnects before the `result` event cancels the execution; the
+//! disconnect is noticed when the next event or keep-alive comment is written.
+
+use crate::output::Channel;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::stream::Stream;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Time between keep-alive comments while the guest is quiet
+///
+/// Writing them is also how a client that went away is noticed.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Something sent to a live output stream
+#[derive(Debug)]
+pub enum LiveEvent {
+    /// A captured line and the channel it was written to
+    Output(Channel, String),
+    /// The execute response, serialized in the requested version; ends the stream
+    Result(String),
+}
+
+/// Sending half of a live output stream
+pub type LiveSender = UnboundedSender<LiveEvent>;
+
+/// Turn the events of one execution into a Server-Sent Events response
+///
+/// The stream ends after the `result` event, or when every sender is gone.
+///
+/// # Arguments
+///
+/// * `events` - Receiving half of the execution's live output stream
+///
+/// # Returns
+///
+/// * `Sse<impl Stream>` - The response, with keep-alive comments while the guest is quiet
+pub fn sse(
+    events: UnboundedReceiver<LiveEvent>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = futures_util::stream::unfold(Some(events), |events| async move {
+        let mut events = events?;
+        let event = match events.recv().await? {
+            LiveEvent::Output(channel, line) => {
+                return Some((
+                    Ok(Event::default().event(channel.name()).data(line)),
+                    Some(events),
+                ))
+            }
+            LiveEvent::Result(json) => Event::default().event("result").data(json),
+        };
+        Some((Ok(event), None))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL))
+}
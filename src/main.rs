@@ -1,131 +1,91 @@
-//! # Hoya
+//! # Hoya server
 //!
-//! Hoya is a service that executes JavaScript and WebAssembly code from remote URLs.
-//! It provides a simple HTTP API for executing code and returning results.
-//!
-//! ## Features
-//!
-//! - Execute JavaScript code using QuickJS engine
-//! - Execute WebAssembly modules with Wasmtime
-//! - Fetch and execute code from remote URLs
-//! - Inject utility functions into JavaScript and WASM environments
-//!
-//! ## API
-//!
-//! The service exposes a POST endpoint at `/execute` that accepts a JSON payload
-//! with a URL pointing to JavaScript (.js) or WebAssembly (.wasm) code.
-
-use axum::{routing::post, Json, Router};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::net::SocketAddr;
-
-mod error;
-mod js_engine;
-mod wasm_engine;
-
-use error::{AppError, ExecuteResponse};
-
-/// Data structures for Wasm fetch communication (JSON)
-/// These are also defined in wasm_ffis.rs. Consider moving to a shared location.
-#[derive(Serialize, Deserialize, Debug)]
-struct WasmFetchOptions {
-    url: String,
-    method: String,
-    headers: HashMap<String, String>,
-    body: Option<String>,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct WasmFetchError {
-    code: String,
-    message: String,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct WasmFetchResponse {
-    status: u16,
-    headers: HashMap<String, String>,
-    body: String,
-    error: Option<WasmFetchError>,
-}
-
-/// Type of code to be executed
-enum CodeType {
-    /// JavaScript code (.js files)
-    JavaScript,
-    /// WebAssembly code (.wasm files)
-    WebAssembly,
-}
+//! Loads the configuration, warms the configured code and serves the
+//! [`hoya::server::router`] until the process is asked to stop. Everything
+//! else lives in the `hoya` library crate.
 
-/// Request payload for the execute endpoint
-#[derive(Deserialize)]
-struct ExecuteRequest {
-    /// URL pointing to JavaScript or WebAssembly code to execute
-    url: String,
-}
+use clap::Parser;
+use hoya::config::{Args, Config, WarmupMode};
+use hoya::server;
+use hoya::state::AppState;
+use hoya::warmup;
+use std::sync::Arc;
 
-/// Handler for the /execute endpoint
-///
-/// This function handles POST requests to the /execute endpoint. It downloads
-/// and executes code from the provided URL, and returns the execution result.
-///
-/// # Arguments
-///
-/// * `payload` - JSON payload containing a URL to code to execute
-///
-/// # Returns
-///
-/// * `Result<Json<ExecuteResponse>, AppError>` - Execution result or error
-async fn execute_handler(
-    Json(payload): Json<ExecuteRequest>,
-) -> Result<Json<ExecuteResponse>, AppError> {
-    println!("Received URL: {}", payload.url);
+#[tokio::main]
+async fn main() {
+    // Prints the usage and exits on --help or invalid arguments
+    let args = Args::parse();
+
+    // Fail fast on an invalid configuration
+    let config = Config::load(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let state = AppState::new(config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    // Warm the configured URLs, before accepting connections in block mode
+    let state = Arc::new(state);
+    match state.config.warmup.mode {
+        WarmupMode::Block => warmup::startup(state.clone()).await,
+        WarmupMode::Background => {
+            tokio::spawn(warmup::startup(state.clone()));
+        }
+    }
+    warmup::spawn_refresh(state.clone());
 
-    // Determine code type from URL
-    let code_type = if payload.url.ends_with(".js") {
-        CodeType::JavaScript
-    } else if payload.url.ends_with(".wasm") {
-        CodeType::WebAssembly
-    } else {
-        return Err(AppError::Internal(
-            "Unsupported file extension. Only .js and .wasm are supported.".to_string(),
-        ));
-    };
+    // Serve every endpoint from the shared state
+    let app = server::router(state.clone());
 
-    // Download code from URL
-    let response = reqwest::get(&payload.url)
+    // Bind to the configured address, 127.0.0.1:3000 by default
+    let addr = state.config.server.addr();
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to listen on {}: {}", addr, e);
+            std::process::exit(1);
+        });
+    tracing::info!("Listening on {}", addr);
+    axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
         .await
-        .map_err(AppError::Reqwest)?;
+        .unwrap();
 
-    if !response.status().is_success() {
-        return Err(AppError::Internal(format!(
-            "Failed to download code: HTTP status {}",
-            response.status()
-        )));
+    // Flush records of the executions that finished while shutting down
+    if let Some(audit) = &state.audit {
+        audit.shutdown();
     }
-    let downloaded_code = response.bytes().await.map_err(AppError::Reqwest)?;
-
-    // TODO: JavaScript execution and WebAssembly execution
-
-    match code_type {
-        CodeType::JavaScript => js_engine::execute_js(downloaded_code),
-        CodeType::WebAssembly => wasm_engine::execute_wasm(downloaded_code),
+    if let Some(reporting) = &state.reporting {
+        reporting.shutdown();
+    }
+    if let Some(tracing) = &state.tracing {
+        tracing.shutdown();
     }
 }
 
-#[tokio::main]
-async fn main() {
-    // Create a router with a single POST route for the execute endpoint
-    let app = Router::new().route("/execute", post(execute_handler));
+/// Resolve when the process is asked to stop (Ctrl+C or SIGTERM)
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
 
-    // Bind to localhost:3000
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    println!("Listening on {}", addr);
-    axum::serve(
-        tokio::net::TcpListener::bind(addr).await.unwrap(),
-        app.into_make_service(),
-    )
-    .await
-    .unwrap();
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("Shutting down");
 }
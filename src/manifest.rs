@@ -0,0 +1,255 @@
+//! # Module manifests
+//!
+//! A WebAssembly module can declare what it needs in a custom section named
+//! `hoya.manifest`, holding a JSON object with any of these fields:
+//!
+//! ```json
+//! {
+//!   "timeout_ms": 10000,
+//!   "max_memory_bytes": 268435456,
+//!   "permissions": ["fetch"],
+//!   "fetch_allowlist": ["api.example.com"]
+//! }
+//! ```
+//!
+//! Declared values replace the server defaults of the execution, clamped by
+//! server policy (`execution.max_timeout_ms`, `wasm.max_memory_bytes`); the
+//! request can only lower them further. A manifest listing `permissions`
+//! without `"fetch"` disables guest fetch, one without `"storage"` keeps the
+//! module away from persistent storage even when the request grants it, and
+//! a `fetch_allowlist` limits fetch calls to the listed hosts. The resulting limits are reported in
+//! `metadata.manifest`.
+
+use crate::config::Config;
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+
+/// Name of the custom section holding the manifest
+pub const SECTION_NAME: &str = "hoya.manifest";
+
+/// Host capabilities a module can ask for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Outbound HTTP through `fetch`
+    Fetch,
+    /// The module's persistent key-value namespace; also needs the request to ask for it
+    Storage,
+}
+
+/// Requirements declared by a module
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Timeout the module needs, in milliseconds
+    pub timeout_ms: Option<u64>,
+    /// Largest size the module's memories may grow to, in bytes
+    pub max_memory_bytes: Option<u64>,
+    /// Capabilities the module uses; unset grants all of them
+    pub permissions: Option<Vec<Permission>>,
+    /// Hosts the module fetches from; unset allows any host
+    pub fetch_allowlist: Option<Vec<String>>,
+}
+
+/// Limits of an execution after combining server policy, the manifest and the request
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AppliedManifest {
+    /// Execution timeout in milliseconds
+    pub timeout_ms: u64,
+    /// Largest size the module's memories may grow to, in bytes
+    pub max_memory_bytes: u64,
+    /// Capabilities granted to the module
+    pub permissions: Vec<Permission>,
+    /// Hosts fetch calls may go to; unset allows any host
+    pub fetch_allowlist: Option<Vec<String>>,
+}
+
+/// Read the manifest of a WebAssembly module, if it has one
+///
+/// # Arguments
+///
+/// * `wasm` - The module's binary
+///
+/// # Returns
+///
+/// * `Result<Option<Manifest>, AppError>` - The manifest, `None` without a
+///   `hoya.manifest` section, or `InvalidManifest` when the section is
+///   duplicated, not JSON or does not match the schema
+pub fn read(wasm: &[u8]) -> Result<Option<Manifest>, AppError> {
+    let mut found = None;
+    for (name, payload) in custom_sections(wasm) {
+        if name != SECTION_NAME.as_bytes() {
+            continue;
+        }
+        if found.is_some() {
+            return Err(AppError::InvalidManifest(format!(
+                "The module has more than one {} section",
+                SECTION_NAME
+            )));
+        }
+        let manifest: Manifest = serde_json::from_slice(payload).map_err(|e| {
+            AppError::InvalidManifest(format!("Invalid {} section: {}", SECTION_NAME, e))
+        })?;
+        manifest.validate()?;
+        found = Some(manifest);
+    }
+    Ok(found)
+}
+
+/// Id and payload of each section, stopping at the first malformed section
+pub(crate) fn sections(wasm: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut sections = Vec::new();
+    // Skip the magic number and version
+    let mut rest = wasm.get(8..).unwrap_or_default();
+    while let Some((&id, after_id)) = rest.split_first() {
+        let Some((size, after_size)) = read_leb128(after_id) else {
+            break;
+        };
+        let Some(body) = after_size.get(..size) else {
+            break;
+        };
+        sections.push((id, body));
+        rest = &after_size[size..];
+    }
+    sections
+}
+
+/// Name and payload of each custom section, stopping at the first malformed section
+fn custom_sections(wasm: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut custom = Vec::new();
+    for (_, body) in sections(wasm).into_iter().filter(|(id, _)| *id == 0) {
+        let Some((name_len, after_len)) = read_leb128(body) else {
+            break;
+        };
+        let Some(name) = after_len.get(..name_len) else {
+            break;
+        };
+        custom.push((name, &after_len[name_len..]));
+    }
+    custom
+}
+
+/// Decode an unsigned LEB128 number, returning it and the bytes after it
+pub(crate) fn read_leb128(bytes: &[u8]) -> Option<(usize, &[u8])> {
+    let mut value: usize = 0;
+    for (i, &byte) in bytes.iter().enumerate().take(5) {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, &bytes[i + 1..]));
+        }
+    }
+    None
+}
+
+/// Check a list of hosts and normalize them to lowercase
+///
+/// # Arguments
+///
+/// * `field` - Name of the list, used in error messages
+/// * `hosts` - Bare host names, without scheme, port or path
+///
+/// # Returns
+///
+/// * `Result<Vec<String>, String>` - The normalized hosts, or a message naming the bad entry
+pub fn normalize_hosts(field: &str, hosts: &[String]) -> Result<Vec<String>, String> {
+    hosts
+        .iter()
+        .map(|host| match url::Host::parse(host) {
+            Ok(_) if !host.is_empty() && !host.contains([':', '/']) => {
+                Ok(host.to_ascii_lowercase())
+            }
+            _ => Err(format!("{} entry {:?} is not a host name", field, host)),
+        })
+        .collect()
+}
+
+impl Manifest {
+    /// Reject values no execution could satisfy
+    fn validate(&self) -> Result<(), AppError> {
+        if self.timeout_ms == Some(0) || self.max_memory_bytes == Some(0) {
+            return Err(AppError::InvalidManifest(
+                "timeout_ms and max_memory_bytes must be greater than 0".to_string(),
+            ));
+        }
+        if let Some(hosts) = &self.fetch_allowlist {
+            normalize_hosts("fetch_allowlist", hosts).map_err(AppError::InvalidManifest)?;
+        }
+        Ok(())
+    }
+
+    /// Combine the manifest with server policy and the request's own limits
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Server configuration supplying defaults and maximums
+    /// * `timeout_ms` - Timeout asked for by the request, already within server policy
+    /// * `max_memory_bytes` - Memory limit asked for by the request, already within server policy
+    /// * `fetch_allowlist` - Normalized hosts the request allows, if it restricts fetch
+    /// * `storage` - Whether the request grants the `storage` permission
+    ///
+    /// # Returns
+    ///
+    /// * `AppliedManifest` - The limits of the execution
+    pub fn apply(
+        &self,
+        config: &Config,
+        timeout_ms: Option<u64>,
+        max_memory_bytes: Option<u64>,
+        fetch_allowlist: Option<&[String]>,
+        storage: bool,
+    ) -> AppliedManifest {
+        let declared_timeout = self
+            .timeout_ms
+            .unwrap_or(config.execution.default_timeout_ms)
+            .min(config.execution.max_timeout_ms);
+        let declared_memory = self
+            .max_memory_bytes
+            .unwrap_or(config.wasm.default_memory_bytes)
+            .min(config.wasm.max_memory_bytes);
+
+        let declares = |permission| {
+            self.permissions
+                .as_ref()
+                .map_or(true, |permissions| permissions.contains(&permission))
+        };
+        let fetch_granted = declares(Permission::Fetch);
+        let declared_hosts = if fetch_granted {
+            self.fetch_allowlist.as_deref().map(|hosts| {
+                hosts
+                    .iter()
+                    .map(|host| host.to_ascii_lowercase())
+                    .collect::<Vec<_>>()
+            })
+        } else {
+            Some(Vec::new())
+        };
+        let fetch_allowlist = match (declared_hosts, fetch_allowlist) {
+            (Some(declared), Some(requested)) => Some(
+                declared
+                    .into_iter()
+                    .filter(|host| requested.contains(host))
+                    .collect(),
+            ),
+            (declared, requested) => declared.or_else(|| requested.map(<[String]>::to_vec)),
+        };
+
+        let mut permissions = Vec::new();
+        if fetch_allowlist
+            .as_ref()
+            .map_or(true, |hosts| !hosts.is_empty())
+        {
+            permissions.push(Permission::Fetch);
+        }
+        if storage && declares(Permission::Storage) {
+            permissions.push(Permission::Storage);
+        }
+
+        AppliedManifest {
+            timeout_ms: timeout_ms.map_or(declared_timeout, |ms| ms.min(declared_timeout)),
+            max_memory_bytes: max_memory_bytes
+                .map_or(declared_memory, |bytes| bytes.min(declared_memory)),
+            permissions,
+            fetch_allowlist,
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! # Prometheus metrics
+//!
+//! A registry of service metrics held in shared state and rendered in the
+//! Prometheus text format by the `/metrics` endpoint.
+//!
+//! Executions are counted by code type (`javascript`, `webassembly`, or
+//! `unknown` when they failed before the code was identified) and response
+//! status. Durations are histograms in seconds: code downloads, the compile
+//! and instantiate phases of WebAssembly modules (compile includes module
+//! cache lookups), and engine run time by code type. Requests waiting for
+//! an execution slot are a gauge, and those refused with `SERVER_BUSY` a
+//! counter.
+//!
+//! Guest fetch series are labeled by destination host. To keep label
+//! cardinality bounded (guests choose the URLs), only hosts listed in the
+//! `metrics.fetch_hosts` config get their own label value; every other host
+//! is recorded as `"other"`.
+
+use crate::net::FetchRecord;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Label value used for hosts outside the configured label allowlist
+const OTHER_HOST: &str = "other";
+
+/// Service metrics
+pub struct Metrics {
+    /// Registry all metrics are registered with
+    registry: Registry,
+    /// Hosts that are labeled individually in guest fetch series
+    labeled_hosts: HashSet<String>,
+    /// Duration of guest fetch calls by destination host
+    guest_fetch_duration: HistogramVec,
+    /// Guest fetch calls by destination host and response status class
+    guest_fetch_requests: IntCounterVec,
+    /// Guest fetch calls that received no HTTP response or could not read its body, by destination host
+    guest_fetch_errors: IntCounterVec,
+    /// Response body bytes received by guest fetch calls, by destination host
+    guest_fetch_bytes: IntCounterVec,
+    /// Finished executions by code type and response status
+    executions: IntCounterVec,
+    /// Executions started and not finished yet
+    executions_in_flight: IntGauge,
+    /// Requests waiting for an execution slot
+    executions_queued: IntGauge,
+    /// Requests refused because no execution slot became free in time
+    executions_rejected_busy: IntCounter,
+    /// Engine run time of executions by code type
+    execution_duration: HistogramVec,
+    /// Duration of code downloads, failed ones included
+    download_duration: Histogram,
+    /// Duration of WebAssembly compile and instantiate phases
+    wasm_phase_duration: HistogramVec,
+}
+
+/// An execution counted in the in-flight or queued gauge until it is dropped
+pub struct InFlight(IntGauge);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+impl Metrics {
+    /// Create and register all metrics
+    ///
+    /// # Arguments
+    ///
+    /// * `labeled_hosts` - Hosts that get their own label value in guest fetch series
+    pub fn new(labeled_hosts: &[String]) -> prometheus::Result<Self> {
+        let registry = Registry::new();
+
+        let guest_fetch_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "hoya_guest_fetch_duration_seconds",
+                "Duration of guest fetch calls",
+            ),
+            &["host"],
+        )?;
+        let guest_fetch_requests = IntCounterVec::new(
+            Opts::new("hoya_guest_fetch_requests_total", "Guest fetch calls"),
+            &["host", "status_class"],
+        )?;
+        let guest_fetch_errors = IntCounterVec::new(
+            Opts::new(
+                "hoya_guest_fetch_errors_total",
+                "Guest fetch calls that received no HTTP response or could not read its body",
+            ),
+            &["host"],
+        )?;
+        let guest_fetch_bytes = IntCounterVec::new(
+            Opts::new(
+                "hoya_guest_fetch_response_bytes_total",
+                "Response body bytes received by guest fetch calls",
+            ),
+            &["host"],
+        )?;
+
+        let executions = IntCounterVec::new(
+            Opts::new("hoya_executions_total", "Finished executions"),
+            &["code_type", "status"],
+        )?;
+        let executions_in_flight = IntGauge::new(
+            "hoya_executions_in_flight",
+            "Executions currently running, downloads included",
+        )?;
+        let executions_queued = IntGauge::new(
+            "hoya_executions_queued",
+            "Requests waiting for an execution slot",
+        )?;
+        let executions_rejected_busy = IntCounter::new(
+            "hoya_executions_rejected_busy_total",
+            "Requests refused with SERVER_BUSY after waiting for an execution slot",
+        )?;
+        let execution_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "hoya_execution_duration_seconds",
+                "Engine run time of executions",
+            ),
+            &["code_type"],
+        )?;
+        let download_duration = Histogram::with_opts(HistogramOpts::new(
+            "hoya_download_duration_seconds",
+            "Duration of code downloads",
+        ))?;
+        let wasm_phase_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "hoya_wasm_phase_duration_seconds",
+                "Duration of WebAssembly compile and instantiate phases",
+            ),
+            &["phase"],
+        )?;
+
+        registry.register(Box::new(guest_fetch_duration.clone()))?;
+        registry.register(Box::new(guest_fetch_requests.clone()))?;
+        registry.register(Box::new(guest_fetch_errors.clone()))?;
+        registry.register(Box::new(guest_fetch_bytes.clone()))?;
+        registry.register(Box::new(executions.clone()))?;
+        registry.register(Box::new(executions_in_flight.clone()))?;
+        registry.register(Box::new(executions_queued.clone()))?;
+        registry.register(Box::new(executions_rejected_busy.clone()))?;
+        registry.register(Box::new(execution_duration.clone()))?;
+        registry.register(Box::new(download_duration.clone()))?;
+        registry.register(Box::new(wasm_phase_duration.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            labeled_hosts: labeled_hosts
+                .iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+            guest_fetch_duration,
+            guest_fetch_requests,
+            guest_fetch_errors,
+            guest_fetch_bytes,
+            executions,
+            executions_in_flight,
+            executions_queued,
+            executions_rejected_busy,
+            execution_duration,
+            download_duration,
+            wasm_phase_duration,
+        })
+    }
+
+    /// Map a destination host to its label value
+    fn host_label<'a>(&self, host: &'a str) -> &'a str {
+        if self.labeled_hosts.contains(host) {
+            host
+        } else {
+            OTHER_HOST
+        }
+    }
+
+    /// Record a completed guest fetch call
+    pub fn observe_guest_fetch(&self, record: &FetchRecord) {
+        let host = self.host_label(&record.host);
+        self.guest_fetch_duration
+            .with_label_values(&[host])
+            .observe(record.duration.as_secs_f64());
+        self.guest_fetch_requests
+            .with_label_values(&[host, record.status_class])
+            .inc();
+        if record.failed {
+            self.guest_fetch_errors.with_label_values(&[host]).inc();
+        }
+        self.guest_fetch_bytes
+            .with_label_values(&[host])
+            .inc_by(record.bytes);
+    }
+
+    /// Count an execution as in flight until the returned guard is dropped
+    pub fn start_execution(&self) -> InFlight {
+        self.executions_in_flight.inc();
+        InFlight(self.executions_in_flight.clone())
+    }
+
+    /// Count a request as waiting for an execution slot until the returned guard is dropped
+    pub fn queue_execution(&self) -> InFlight {
+        self.executions_queued.inc();
+        InFlight(self.executions_queued.clone())
+    }
+
+    /// Count a request refused because no execution slot became free in time
+    pub fn reject_busy(&self) {
+        self.executions_rejected_busy.inc();
+    }
+
+    /// Record a finished execution
+    ///
+    /// # Arguments
+    ///
+    /// * `code_type` - `metadata.code_type` of the response
+    /// * `status` - `status` of the response ("success" or "error")
+    /// * `execution_time` - Engine run time; not observed for code of an unknown type
+    pub fn observe_execution(&self, code_type: &str, status: &str, execution_time: Duration) {
+        self.executions
+            .with_label_values(&[code_type, status])
+            .inc();
+        if code_type != "unknown" {
+            self.execution_duration
+                .with_label_values(&[code_type])
+                .observe(execution_time.as_secs_f64());
+        }
+    }
+
+    /// Record the duration of a code download
+    pub fn observe_download(&self, duration: Duration) {
+        self.download_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Record the duration of a WebAssembly phase ("compile" or "instantiate")
+    pub fn observe_wasm_phase(&self, phase: &str, duration: Duration) {
+        self.wasm_phase_duration
+            .with_label_values(&[phase])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // Encoding into a Vec cannot fail for well-formed metric families
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
@@ -0,0 +1,125 @@
+//! # Content negotiation
+//!
+//! The one place that decides how an execute response is encoded, from the
+//! request's `Accept` header. JSON (in the requested response version) is
+//! the default, including for `*/*` and requests without an `Accept` header.
+//! JSON bodies are buffered, or streamed when the request asks for it.
+//!
+//! `text/plain` is meant for shell pipelines: the body is just the captured
+//! stdout, or the `output` field when stdout is empty, or the error message
+//! when there is neither. Success and failure are told apart by the HTTP
+//! status, and failures carry their error code in `X-Hoya-Error-Code`.
+
+use crate::error::ExecuteResponse;
+use crate::stream;
+use crate::wire::ResponseVersion;
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+
+/// Response header carrying the error code of a failed execution in text mode
+pub const ERROR_CODE_HEADER: &str = "x-hoya-error-code";
+
+/// Encoding of an execute response body
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// The full response as JSON
+    #[default]
+    Json,
+    /// Only the guest's output, as plain text
+    Text,
+}
+
+impl ResponseFormat {
+    /// Pick the format the request's `Accept` header prefers
+    ///
+    /// Media ranges are weighed by their `q` parameter; on a tie the earlier
+    /// one wins. Unsupported media types are ignored, and JSON is used when
+    /// nothing supported is acceptable.
+    pub fn from_accept(headers: &HeaderMap) -> ResponseFormat {
+        let Some(accept) = headers.get(ACCEPT).and_then(|value| value.to_str().ok()) else {
+            return ResponseFormat::Json;
+        };
+
+        let mut best: Option<(ResponseFormat, f32)> = None;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_type = params
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let format = match media_type.as_str() {
+                "application/json" | "application/*" | "*/*" => ResponseFormat::Json,
+                "text/plain" | "text/*" => ResponseFormat::Text,
+                _ => continue,
+            };
+            if quality > 0.0 && best.map_or(true, |(_, best_quality)| quality > best_quality) {
+                best = Some((format, quality));
+            }
+        }
+        best.map_or(ResponseFormat::Json, |(format, _)| format)
+    }
+
+    /// Encode an execute response
+    ///
+    /// # Arguments
+    ///
+    /// * `status_code` - HTTP status of the response
+    /// * `version` - Response version used for JSON bodies
+    /// * `streamed` - Stream JSON bodies in chunks instead of buffering them
+    /// * `response` - The execution result or error
+    ///
+    /// # Returns
+    ///
+    /// * `Response` - The encoded HTTP response
+    pub fn render(
+        self,
+        status_code: StatusCode,
+        version: ResponseVersion,
+        streamed: bool,
+        response: ExecuteResponse,
+    ) -> Response {
+        match self {
+            ResponseFormat::Json if streamed => (
+                status_code,
+                [(CONTENT_TYPE, HeaderValue::from_static("application/json"))],
+                stream::json_body(version, response),
+            )
+                .into_response(),
+            ResponseFormat::Json => (status_code, Json(version.wire(response))).into_response(),
+            ResponseFormat::Text => render_text(status_code, response),
+        }
+    }
+}
+
+/// Encode an execute response as plain text
+fn render_text(status_code: StatusCode, response: ExecuteResponse) -> Response {
+    let error_code = response.error.as_ref().map(|error| error.code.clone());
+    let body = match response.stdout.filter(|stdout| !stdout.is_empty()) {
+        Some(stdout) => stdout,
+        None => response
+            .output
+            .or_else(|| response.error.map(|error| error.message))
+            .unwrap_or_default(),
+    };
+
+    let mut text = (
+        status_code,
+        [(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        )],
+        body,
+    )
+        .into_response();
+    if let Some(value) = error_code.and_then(|code| HeaderValue::from_str(&code).ok()) {
+        text.headers_mut().insert(ERROR_CODE_HEADER, value);
+    }
+    text
+}
@@ -0,0 +1,675 @@
+//! Outbound HTTP for guest code.
+//!
+//! The fetch core shared by the WebAssembly `fetch` host function and the
+//! JavaScript `fetch` and `hoyaFetch` globals. The wasm import and `hoyaFetch`
+//! speak the same JSON contract (`WasmFetchOptions` in, `WasmFetchResponse`
+//! out, see `crate::types`) so guests compiled to either runtime can target
+//! one fetch protocol; the JavaScript `fetch` passes the same fields as plain
+//! objects.
+//!
+//! `fetch` and `fetch_json` are async and run on whatever runtime awaits them.
+//! Guest calls never await them on the caller's runtime, though: the
+//! WebAssembly imports hand them to the context's `FetchRuntime` with
+//! `FetchRuntime::run`, and JavaScript host functions, which are synchronous,
+//! use the `fetch_blocking` and `fetch_json_blocking` variants, which wait
+//! for the request there.
+
+use crate::base64;
+use crate::cancel::CancelToken;
+use crate::download::{is_secret_header, same_origin};
+use crate::fetch_runtime::FetchRuntime;
+use crate::metrics::Metrics;
+use crate::outbound::{self, CallLimits, OutboundPolicy};
+use crate::types::{BodyEncoding, WasmFetchOptions, WasmFetchResponse};
+use anyhow::{anyhow, Result as AnyhowResult};
+use reqwest::cookie::{CookieStore, Jar};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+/// Per-execution state backing guest HTTP requests
+pub struct FetchContext {
+    /// HTTP client for making network requests
+    pub client: reqwest::Client,
+    /// Runtime the requests run on, whichever thread the guest calls from
+    pub runtime: Arc<FetchRuntime>,
+    /// Server-wide policy every request and redirect hop is checked against
+    pub policy: Arc<OutboundPolicy>,
+    /// Cookie jar scoped to this execution, present when cookies are enabled
+    pub cookie_jar: Option<Arc<Jar>>,
+    /// Service metrics that every fetch call is recorded in
+    pub metrics: Arc<Metrics>,
+    /// Per-host summary of this execution's fetch calls, reported in metadata
+    pub stats: Arc<Mutex<NetworkStats>>,
+    /// Parent of the `guest_fetch` spans; the engines point it at their `run` span
+    pub span: tracing::Span,
+    /// Lowercase hosts fetch calls may go to; `None` allows any host
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Fetch calls sent before further calls fail with `FETCH_LIMIT_EXCEEDED`
+    pub max_calls: u64,
+    /// Fetch calls sent or refused for the limit so far
+    calls: AtomicU64,
+    /// Response body bytes received before further calls fail with `FETCH_QUOTA_EXCEEDED`
+    pub max_bytes: u64,
+    /// Response body bytes received so far
+    bytes: AtomicU64,
+    /// Set when the execution is cancelled; later calls are refused
+    pub cancel: CancelToken,
+}
+
+/// A clone shares the cookie jar, the summary and the cancel token with the
+/// original, and starts counting calls and bytes from the original's counts
+impl Clone for FetchContext {
+    fn clone(&self) -> Self {
+        FetchContext {
+            client: self.client.clone(),
+            runtime: self.runtime.clone(),
+            policy: self.policy.clone(),
+            cookie_jar: self.cookie_jar.clone(),
+            metrics: self.metrics.clone(),
+            stats: self.stats.clone(),
+            span: self.span.clone(),
+            allowed_hosts: self.allowed_hosts.clone(),
+            max_calls: self.max_calls,
+            calls: AtomicU64::new(self.calls.load(Ordering::Relaxed)),
+            max_bytes: self.max_bytes,
+            bytes: AtomicU64::new(self.bytes.load(Ordering::Relaxed)),
+            cancel: self.cancel.clone(),
+        }
+    }
+}
+
+impl FetchContext {
+    /// Create a fetch context, with a fresh cookie jar if `cookies` is set
+    ///
+    /// Fetch spans are parented to the span current at creation until an
+    /// engine sets `span`.
+    pub fn new(
+        client: reqwest::Client,
+        runtime: Arc<FetchRuntime>,
+        policy: Arc<OutboundPolicy>,
+        cookies: bool,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        FetchContext {
+            client,
+            runtime,
+            policy,
+            cookie_jar: cookies.then(|| Arc::new(Jar::default())),
+            metrics,
+            stats: Arc::new(Mutex::new(NetworkStats::default())),
+            span: tracing::Span::current(),
+            allowed_hosts: None,
+            max_calls: u64::MAX,
+            calls: AtomicU64::new(0),
+            max_bytes: u64::MAX,
+            bytes: AtomicU64::new(0),
+            cancel: CancelToken::default(),
+        }
+    }
+
+    /// The envelope refusing a request to `url`, if it may not be sent
+    ///
+    /// Hosts outside the execution's allowlist get `FETCH_DENIED`, targets
+    /// the outbound policy refuses `FETCH_BLOCKED`.
+    fn refusal(&self, url: &reqwest::Url) -> Option<WasmFetchResponse> {
+        let host = url
+            .host_str()
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_else(|| "invalid".to_string());
+        if let Some(allowed) = &self.allowed_hosts {
+            if !allowed.contains(&host) {
+                return Some(WasmFetchResponse::from_error(
+                    "FETCH_DENIED",
+                    format!("Fetching from {} is not allowed for this execution", host),
+                ));
+            }
+        }
+        self.policy.check(url).err().map(|reason| {
+            WasmFetchResponse::from_error(
+                "FETCH_BLOCKED",
+                format!("Fetching {} is blocked: {}", url, reason),
+            )
+        })
+    }
+
+    /// Record a completed fetch call in the service metrics and the execution summary
+    fn record(&self, record: FetchRecord) {
+        self.metrics.observe_guest_fetch(&record);
+        if let Ok(mut stats) = self.stats.lock() {
+            stats.add(&record);
+        }
+    }
+}
+
+/// Outcome of a single guest fetch call
+pub struct FetchRecord {
+    /// Destination host
+    pub host: String,
+    /// Time from sending the request to reading the whole body (or failing)
+    pub duration: Duration,
+    /// Response status class ("2xx" ... "5xx"), or "error" if no response was received
+    pub status_class: &'static str,
+    /// Whether the call failed without an HTTP response, or its body could not be read
+    pub failed: bool,
+    /// Response body size in bytes
+    pub bytes: u64,
+}
+
+/// Per-execution summary of guest fetch calls
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct NetworkStats {
+    /// Total number of fetch calls
+    pub fetch_calls: u64,
+    /// Response body bytes received from all hosts
+    pub bytes: u64,
+    /// Breakdown by destination host
+    pub hosts: HashMap<String, HostNetworkStats>,
+}
+
+/// Fetch call summary for one destination host
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct HostNetworkStats {
+    /// Number of fetch calls
+    pub calls: u64,
+    /// Calls that received no HTTP response or could not read its body
+    pub errors: u64,
+    /// Summed duration of all calls in milliseconds
+    pub duration_ms: u64,
+    /// Response body bytes received
+    pub bytes: u64,
+    /// Number of calls per response status class
+    pub status_classes: HashMap<String, u64>,
+}
+
+impl NetworkStats {
+    /// Add a completed fetch call to the summary
+    fn add(&mut self, record: &FetchRecord) {
+        self.fetch_calls += 1;
+        self.bytes += record.bytes;
+        let host = self.hosts.entry(record.host.clone()).or_default();
+        host.calls += 1;
+        host.errors += u64::from(record.failed);
+        host.duration_ms += record.duration.as_millis() as u64;
+        host.bytes += record.bytes;
+        *host
+            .status_classes
+            .entry(record.status_class.to_string())
+            .or_default() += 1;
+    }
+}
+
+/// Status class label ("2xx", "4xx", ...) for an HTTP status code
+fn status_class(status: u16) -> &'static str {
+    match status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+/// Parse a `Cookie` header value (`a=1; b=2`) into a name/value map
+fn parse_cookie_header(value: &reqwest::header::HeaderValue) -> HashMap<String, String> {
+    value
+        .to_str()
+        .unwrap_or("")
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Headers of a request to `url`, with the execution's cookies for it added
+///
+/// Cookies the guest set itself are kept, followed by the jar's.
+fn with_jar_cookies(
+    headers: &reqwest::header::HeaderMap,
+    jar: Option<&Arc<Jar>>,
+    url: &reqwest::Url,
+) -> reqwest::header::HeaderMap {
+    let mut headers = headers.clone();
+    if let Some(jar_cookies) = jar.and_then(|jar| jar.cookies(url)) {
+        let cookie_header = match headers.get(reqwest::header::COOKIE) {
+            Some(existing) => reqwest::header::HeaderValue::from_str(&format!(
+                "{}; {}",
+                existing.to_str().unwrap_or(""),
+                jar_cookies.to_str().unwrap_or("")
+            ))
+            .unwrap_or(jar_cookies),
+            None => jar_cookies,
+        };
+        headers.insert(reqwest::header::COOKIE, cookie_header);
+    }
+    headers
+}
+
+/// Perform a guest HTTP request
+///
+/// Malformed options (bad method, header names or values) are returned as
+/// errors, since they indicate a bug in the guest. Failures to reach the
+/// server are reported inside the response's error envelope instead so the
+/// guest can handle them.
+///
+/// When the context has a cookie jar, its cookies for the URL are sent with
+/// the request and any `Set-Cookie` headers in the response are stored back.
+/// Calls to hosts outside the context's allowlist are answered with a
+/// `FETCH_DENIED` envelope, calls the outbound policy refuses with a
+/// `FETCH_BLOCKED` envelope, calls beyond its call limit with a
+/// `FETCH_LIMIT_EXCEEDED` envelope, calls after its byte quota is used up
+/// with a `FETCH_QUOTA_EXCEEDED` envelope, and calls made after the execution
+/// was cancelled with an `EXECUTION_CANCELLED` envelope, without being sent.
+/// A response whose body uses up the quota is dropped and answered with the
+/// `FETCH_QUOTA_EXCEEDED` envelope as well. Bodies are decoded as UTF-8.
+///
+/// Redirects are followed here and every hop is checked like the first
+/// request: a hop to a refused host ends the call with the same envelope.
+/// Credential headers are dropped once a hop leaves the original origin.
+///
+/// The call's timeout, redirect limit and response size limit are the
+/// guest's options clamped to the outbound policy's maxima. A call that runs
+/// past its timeout is answered with a `FETCH_TIMEOUT` envelope, one
+/// redirected more often than its limit with `TOO_MANY_REDIRECTS`, and one
+/// whose body is larger than its limit with `RESPONSE_TOO_LARGE`.
+///
+/// The request runs on the runtime awaiting the call, which must have its IO
+/// and time drivers enabled.
+///
+/// # Arguments
+///
+/// * `fetch_ctx` - Per-execution fetch state (client, cookie jar)
+/// * `options` - Request description sent by the guest
+///
+/// # Returns
+///
+/// * `AnyhowResult<WasmFetchResponse>` - Response (possibly with an error envelope) or an error
+pub async fn fetch(
+    fetch_ctx: &FetchContext,
+    options: WasmFetchOptions,
+) -> AnyhowResult<WasmFetchResponse> {
+    let mut http_method = reqwest::Method::from_bytes(options.method.as_bytes())
+        .map_err(|_| anyhow!("fetch: invalid HTTP method string: {}", options.method))?;
+
+    let mut http_headers = reqwest::header::HeaderMap::new();
+    for (key, value) in options.headers {
+        let header_name = reqwest::header::HeaderName::from_bytes(key.as_bytes())
+            .map_err(|_| anyhow!("fetch: invalid header name {}", key))?;
+        let header_value = reqwest::header::HeaderValue::from_str(&value)
+            .map_err(|_| anyhow!("fetch: invalid header value for {}", key))?;
+        http_headers.insert(header_name, header_value);
+    }
+
+    let body_encoding = options.body_encoding;
+    let mut body = match (options.body, body_encoding) {
+        (Some(body), BodyEncoding::Base64) => {
+            Some(base64::decode(&body).ok_or_else(|| anyhow!("fetch: body is not valid base64"))?)
+        }
+        (body, _) => body.map(String::into_bytes),
+    };
+
+    if fetch_ctx.cancel.is_cancelled() {
+        return Ok(WasmFetchResponse::from_error(
+            "EXECUTION_CANCELLED",
+            "The execution was cancelled".to_string(),
+        ));
+    }
+    let origin = match reqwest::Url::parse(&options.url) {
+        Ok(url) => url,
+        Err(e) => {
+            return Ok(WasmFetchResponse::from_error(
+                "FETCH_FAILED",
+                format!("Invalid URL {:?}: {}", options.url, e),
+            ))
+        }
+    };
+    let host = origin
+        .host_str()
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_else(|| "invalid".to_string());
+    if let Some(refusal) = fetch_ctx.refusal(&origin) {
+        return Ok(refusal);
+    }
+    if fetch_ctx.bytes.load(Ordering::Relaxed) >= fetch_ctx.max_bytes {
+        return Ok(quota_exceeded(fetch_ctx));
+    }
+    if fetch_ctx.calls.fetch_add(1, Ordering::Relaxed) >= fetch_ctx.max_calls {
+        return Ok(WasmFetchResponse::from_error(
+            "FETCH_LIMIT_EXCEEDED",
+            format!(
+                "This execution may make at most {} fetch calls",
+                fetch_ctx.max_calls
+            ),
+        ));
+    }
+    let span = tracing::info_span!(
+        parent: &fetch_ctx.span,
+        "guest_fetch",
+        server.address = %host,
+        http.request.method = %http_method,
+        http.response.status_code = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    );
+    let start_time = Instant::now();
+    let limits = fetch_ctx.policy.call_limits(
+        options.timeout_ms,
+        options.max_redirects,
+        options.max_response_bytes,
+    );
+    let deadline = start_time + limits.timeout;
+
+    let jar = fetch_ctx.cookie_jar.as_ref();
+    let mut current = origin.clone();
+    let mut redirects = 0;
+    let response = loop {
+        let mut request_builder = fetch_ctx
+            .client
+            .request(http_method.clone(), current.clone())
+            .headers(with_jar_cookies(&http_headers, jar, &current))
+            .timeout(deadline.saturating_duration_since(Instant::now()));
+        if let Some(body) = &body {
+            request_builder = request_builder.body(body.clone());
+        }
+
+        let sent = request_builder.send().instrument(span.clone()).await;
+        let response = match sent {
+            Ok(response) => response,
+            Err(e) => {
+                // The client's resolver found only non-public addresses, so nothing was sent
+                if let Some(refused) = outbound::refused_address(&e) {
+                    return Ok(WasmFetchResponse::from_error(
+                        "FETCH_BLOCKED",
+                        format!("Fetching {} is blocked: {}", current, refused),
+                    ));
+                }
+                span.record("otel.status_code", "ERROR");
+                fetch_ctx.record(FetchRecord {
+                    host,
+                    duration: start_time.elapsed(),
+                    status_class: "error",
+                    failed: true,
+                    bytes: 0,
+                });
+                if e.is_timeout() {
+                    return Ok(timed_out(&limits));
+                }
+                return Ok(WasmFetchResponse::from_error(
+                    "FETCH_FAILED",
+                    format!("HTTP request execution failed: {}", e),
+                ));
+            }
+        };
+        if let Some(jar) = jar {
+            jar.set_cookies(
+                &mut response
+                    .headers()
+                    .get_all(reqwest::header::SET_COOKIE)
+                    .iter(),
+                &current,
+            );
+        }
+
+        let status = response.status();
+        let next = match status.as_u16() {
+            301 | 302 | 303 | 307 | 308 => response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|location| current.join(location).ok()),
+            _ => None,
+        };
+        let Some(next) = next else {
+            break response;
+        };
+
+        // The redirect was received, so the call counts as answered
+        let refusal = if redirects == limits.max_redirects {
+            Some(WasmFetchResponse::from_error(
+                "TOO_MANY_REDIRECTS",
+                format!("More than {} redirects", limits.max_redirects),
+            ))
+        } else {
+            fetch_ctx.refusal(&next)
+        };
+        if let Some(refusal) = refusal {
+            span.record("http.response.status_code", status.as_u16());
+            fetch_ctx.record(FetchRecord {
+                host,
+                duration: start_time.elapsed(),
+                status_class: status_class(status.as_u16()),
+                failed: false,
+                bytes: 0,
+            });
+            return Ok(refusal);
+        }
+        redirects += 1;
+
+        // As browsers do, 303 and a POST's 301 or 302 continue as a GET without body
+        if status == reqwest::StatusCode::SEE_OTHER
+            || (matches!(status.as_u16(), 301 | 302) && http_method == reqwest::Method::POST)
+        {
+            http_method = reqwest::Method::GET;
+            body = None;
+        }
+        if !same_origin(&origin, &next) {
+            let secret_names: Vec<reqwest::header::HeaderName> = http_headers
+                .keys()
+                .filter(|name| is_secret_header(name.as_str()))
+                .cloned()
+                .collect();
+            for name in secret_names {
+                http_headers.remove(name);
+            }
+        }
+        current = next;
+    };
+
+    let status_code = response.status().as_u16();
+    span.record("http.response.status_code", status_code);
+    let mut response_headers_map = HashMap::new();
+    for (name, value) in response.headers().iter() {
+        response_headers_map.insert(name.to_string(), value.to_str().unwrap_or("").to_string());
+    }
+
+    let cookies = match (jar, options.cookies) {
+        (Some(jar), Some(true)) => Some(
+            jar.cookies(&origin)
+                .map(|value| parse_cookie_header(&value))
+                .unwrap_or_default(),
+        ),
+        _ => None,
+    };
+
+    let read = read_body(fetch_ctx, response, &limits)
+        .instrument(span.clone())
+        .await;
+    // A body that breaks off is still recorded, as a failed call
+    if read.is_err() {
+        span.record("otel.status_code", "ERROR");
+    }
+    fetch_ctx.record(FetchRecord {
+        host,
+        duration: start_time.elapsed(),
+        status_class: status_class(status_code),
+        failed: read.is_err(),
+        bytes: read
+            .as_ref()
+            .map_or(0, |(body_bytes, _)| body_bytes.len() as u64),
+    });
+    let (body_bytes, cut) = read?;
+    match cut {
+        None => {}
+        Some(BodyCut::Quota) => return Ok(quota_exceeded(fetch_ctx)),
+        Some(BodyCut::TooLarge) => {
+            return Ok(WasmFetchResponse::from_error(
+                "RESPONSE_TOO_LARGE",
+                format!(
+                    "The response body is larger than {} bytes",
+                    limits.max_response_bytes
+                ),
+            ))
+        }
+        Some(BodyCut::TimedOut) => return Ok(timed_out(&limits)),
+    }
+    // Bodies that are not text, or that the guest wants as base64, are base64-encoded
+    let (body, body_encoding) = match body_encoding {
+        BodyEncoding::Utf8 => match String::from_utf8(body_bytes) {
+            Ok(text) => (text, BodyEncoding::Utf8),
+            Err(e) => (base64::encode(e.as_bytes()), BodyEncoding::Base64),
+        },
+        BodyEncoding::Base64 => (base64::encode(&body_bytes), BodyEncoding::Base64),
+    };
+
+    Ok(WasmFetchResponse {
+        status: status_code,
+        headers: response_headers_map,
+        body,
+        body_encoding,
+        error: None,
+        cookies,
+    })
+}
+
+/// Why a response body was not read to the end
+enum BodyCut {
+    /// The execution used up its byte quota
+    Quota,
+    /// The body is larger than the call's `max_response_bytes`
+    TooLarge,
+    /// The call ran past its timeout
+    TimedOut,
+}
+
+/// Read a response body, counting it against the execution's byte quota
+///
+/// A body whose `Content-Length` is beyond the call's `max_response_bytes` is
+/// not read at all. Otherwise reading stops at the chunk that takes the body
+/// past `max_response_bytes`, which is dropped uncounted, or at the chunk
+/// that takes the execution past `max_bytes`, which is counted but dropped.
+///
+/// # Returns
+///
+/// * `AnyhowResult<(Vec<u8>, Option<BodyCut>)>` - The bytes read, and why
+///   reading stopped early if it did
+async fn read_body(
+    fetch_ctx: &FetchContext,
+    mut response: reqwest::Response,
+    limits: &CallLimits,
+) -> AnyhowResult<(Vec<u8>, Option<BodyCut>)> {
+    let mut body = Vec::new();
+    if response
+        .content_length()
+        .is_some_and(|len| len > limits.max_response_bytes)
+    {
+        return Ok((body, Some(BodyCut::TooLarge)));
+    }
+    loop {
+        let chunk = match response.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return Ok((body, None)),
+            Err(e) if e.is_timeout() => return Ok((body, Some(BodyCut::TimedOut))),
+            Err(e) => return Err(anyhow!("fetch: failed to read response body: {}", e)),
+        };
+        let len = chunk.len() as u64;
+        if body.len() as u64 + len > limits.max_response_bytes {
+            return Ok((body, Some(BodyCut::TooLarge)));
+        }
+        if fetch_ctx.bytes.fetch_add(len, Ordering::Relaxed) + len > fetch_ctx.max_bytes {
+            return Ok((body, Some(BodyCut::Quota)));
+        }
+        body.extend_from_slice(&chunk);
+    }
+}
+
+/// The envelope of a call that ran past its timeout
+fn timed_out(limits: &CallLimits) -> WasmFetchResponse {
+    WasmFetchResponse::from_error(
+        "FETCH_TIMEOUT",
+        format!(
+            "The fetch call took longer than {} ms",
+            limits.timeout.as_millis()
+        ),
+    )
+}
+
+/// The envelope of a call refused because the execution used up its byte quota
+fn quota_exceeded(fetch_ctx: &FetchContext) -> WasmFetchResponse {
+    WasmFetchResponse::from_error(
+        "FETCH_QUOTA_EXCEEDED",
+        format!(
+            "This execution may receive at most {} response bytes",
+            fetch_ctx.max_bytes
+        ),
+    )
+}
+
+/// Perform a guest HTTP request described by a JSON `WasmFetchOptions` document
+///
+/// # Arguments
+///
+/// * `fetch_ctx` - Per-execution fetch state (client, cookie jar)
+/// * `options_json` - Serialized `WasmFetchOptions`
+///
+/// # Returns
+///
+/// * `AnyhowResult<Vec<u8>>` - Serialized `WasmFetchResponse` or an error
+pub async fn fetch_json(fetch_ctx: &FetchContext, options_json: &[u8]) -> AnyhowResult<Vec<u8>> {
+    let options: WasmFetchOptions = serde_json::from_slice(options_json)
+        .map_err(|e| anyhow!("fetch: failed to deserialize options JSON: {}", e))?;
+
+    let response = fetch(fetch_ctx, options).await?;
+
+    serde_json::to_vec(&response)
+        .map_err(|e| anyhow!("fetch: failed to serialize response to JSON: {}", e))
+}
+
+/// Perform a guest HTTP request from synchronous code, such as a JavaScript
+/// host function, and block until it completes
+///
+/// The request runs on the context's fetch runtime, so this may be called
+/// from any thread but that runtime's own, including from inside another
+/// Tokio runtime.
+///
+/// # Arguments
+///
+/// * `fetch_ctx` - Per-execution fetch state, shared with the fetch runtime for the call
+/// * `options` - Request description sent by the guest
+///
+/// # Returns
+///
+/// * `AnyhowResult<WasmFetchResponse>` - Response (possibly with an error envelope) or an error
+pub fn fetch_blocking(
+    fetch_ctx: &Arc<FetchContext>,
+    options: WasmFetchOptions,
+) -> AnyhowResult<WasmFetchResponse> {
+    let shared = fetch_ctx.clone();
+    fetch_ctx
+        .runtime
+        .block_on(async move { fetch(&shared, options).await })?
+}
+
+/// `fetch_json` from synchronous code, blocking until the request completes
+///
+/// # Arguments
+///
+/// * `fetch_ctx` - Per-execution fetch state, shared with the fetch runtime for the call
+/// * `options_json` - Serialized `WasmFetchOptions`
+///
+/// # Returns
+///
+/// * `AnyhowResult<Vec<u8>>` - Serialized `WasmFetchResponse` or an error
+pub fn fetch_json_blocking(
+    fetch_ctx: &Arc<FetchContext>,
+    options_json: &[u8],
+) -> AnyhowResult<Vec<u8>> {
+    let shared = fetch_ctx.clone();
+    let options_json = options_json.to_vec();
+    fetch_ctx
+        .runtime
+        .block_on(async move { fetch_json(&shared, &options_json).await })?
+}
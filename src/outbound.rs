@@ -0,0 +1,316 @@
+//! Outbound policy for guest fetch calls.
+//!
+//! Guest code is untrusted, and the host sends its requests from inside the
+//! server's network. Before every request, and again before every redirect
+//! hop, the target is checked against the `[fetch]` policy: hosts matching
+//! `deny_domains` are refused; hosts matching `allow_domains` are let
+//! through; any other host is refused if it is, or only resolves to, a
+//! loopback, link-local, private (RFC 1918), unique local (IPv6 ULA) or
+//! unspecified address, unless `allow_private_addresses` is set. Refused
+//! requests are never sent and answer the guest with a `FETCH_BLOCKED`
+//! envelope.
+//!
+//! Hosts given as addresses are checked before the request. Names are
+//! checked by `PublicResolver`, the DNS resolver of the fetch client, which
+//! drops non-public addresses from every lookup the client connects with, so
+//! a name cannot resolve to a public address for a check and a private one
+//! for the connection. Names that do not resolve are refused too. Code
+//! downloads go through the same checks, under the `[download]` section's own
+//! policy (see `download::CodeUrlPolicy`).
+//!
+//! The policy also holds the hard maxima of a single call: guests may ask for
+//! a shorter timeout, fewer redirects or a smaller response, never for more
+//! than `max_timeout_ms`, `max_redirects` and `max_response_bytes`.
+
+use crate::config::FetchConfig;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// Limits of a single fetch call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallLimits {
+    /// Time from sending the first request to reading the whole body
+    pub timeout: Duration,
+    /// Redirects followed before the call fails with `TOO_MANY_REDIRECTS`
+    pub max_redirects: usize,
+    /// Body bytes received before the call fails with `RESPONSE_TOO_LARGE`
+    pub max_response_bytes: u64,
+}
+
+impl CallLimits {
+    /// The hard maxima of the `[fetch]` section
+    fn maxima(config: &FetchConfig) -> Self {
+        CallLimits {
+            timeout: Duration::from_millis(config.max_timeout_ms),
+            max_redirects: config.max_redirects,
+            max_response_bytes: config.max_response_bytes,
+        }
+    }
+}
+
+/// Where guest fetch calls may go
+#[derive(Debug, Clone)]
+pub struct OutboundPolicy {
+    /// Send requests to private addresses without checking
+    allow_private_addresses: bool,
+    /// Lowercase domains, with their subdomains, exempt from the address check
+    allow_domains: Vec<String>,
+    /// Lowercase domains, with their subdomains, never fetched
+    deny_domains: Vec<String>,
+    /// Most a single call may ask for
+    maxima: CallLimits,
+}
+
+impl OutboundPolicy {
+    /// Build the policy from the `[fetch]` section
+    pub fn from_config(config: &FetchConfig) -> Self {
+        let lowercase = |domains: &[String]| {
+            domains
+                .iter()
+                .map(|domain| domain.trim_end_matches('.').to_ascii_lowercase())
+                .collect()
+        };
+        OutboundPolicy {
+            allow_private_addresses: config.allow_private_addresses,
+            allow_domains: lowercase(&config.allow_domains),
+            deny_domains: lowercase(&config.deny_domains),
+            maxima: CallLimits::maxima(config),
+        }
+    }
+
+    /// A policy that lets every request through
+    ///
+    /// Only for guests embedded in the server, such as the self-test's, which
+    /// fetch this server over loopback.
+    pub fn unrestricted() -> Self {
+        OutboundPolicy {
+            allow_private_addresses: true,
+            allow_domains: Vec::new(),
+            deny_domains: Vec::new(),
+            maxima: CallLimits::maxima(&FetchConfig::default()),
+        }
+    }
+
+    /// Limits of a call, as the guest asked for them within the server's maxima
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout_ms` - The guest's timeout in milliseconds, if it set one
+    /// * `max_redirects` - The guest's redirect limit, if it set one
+    /// * `max_response_bytes` - The guest's response size limit, if it set one
+    ///
+    /// # Returns
+    ///
+    /// * `CallLimits` - Each limit clamped to its maximum, or the maximum when unset
+    pub fn call_limits(
+        &self,
+        timeout_ms: Option<u64>,
+        max_redirects: Option<usize>,
+        max_response_bytes: Option<u64>,
+    ) -> CallLimits {
+        CallLimits {
+            timeout: timeout_ms
+                .map(Duration::from_millis)
+                .map_or(self.maxima.timeout, |timeout| {
+                    timeout.min(self.maxima.timeout)
+                }),
+            max_redirects: max_redirects.map_or(self.maxima.max_redirects, |max| {
+                max.min(self.maxima.max_redirects)
+            }),
+            max_response_bytes: max_response_bytes.map_or(self.maxima.max_response_bytes, |max| {
+                max.min(self.maxima.max_response_bytes)
+            }),
+        }
+    }
+
+    /// The resolver the fetch client connects with, dropping the addresses this policy refuses
+    pub fn resolver(&self) -> PublicResolver {
+        PublicResolver {
+            allow_private_addresses: self.allow_private_addresses,
+            allow_domains: self.allow_domains.clone(),
+        }
+    }
+
+    /// Check whether a request to `url` may be sent
+    ///
+    /// Only hosts given as addresses are checked against the address ranges
+    /// here; names are checked as the client resolves them (see `PublicResolver`).
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The request's URL, or the location a redirect points to
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), String>` - Why the request is refused, if it is
+    pub fn check(&self, url: &Url) -> Result<(), String> {
+        let Some(host) = normalized_host(url) else {
+            return Err(format!("{} has no host", url));
+        };
+        if matches_any(&host, &self.deny_domains) {
+            return Err(format!("{} is denied by the server", host));
+        }
+        if self.allow_private_addresses || matches_any(&host, &self.allow_domains) {
+            return Ok(());
+        }
+        match non_public_literal(url) {
+            Some(address) => Err(format!("{} is the non-public address {}", host, address)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// DNS resolver for clients whose requests must not reach non-public addresses
+///
+/// Every lookup keeps only the public addresses a name resolves to, and
+/// fails with `NonPublicAddress` when there are none, or with an error
+/// naming the host when the name does not resolve. Names matching
+/// `allow_domains` keep all their addresses.
+#[derive(Debug, Clone)]
+pub struct PublicResolver {
+    /// Keep every address
+    allow_private_addresses: bool,
+    /// Lowercase domains, with their subdomains, whose addresses are all kept
+    allow_domains: Vec<String>,
+}
+
+impl PublicResolver {
+    /// A resolver for the code download client, which has no domains exempt from the check
+    ///
+    /// # Arguments
+    ///
+    /// * `allow_private_addresses` - Keep every address, as `download.allow_private_addresses` says
+    pub fn for_downloads(allow_private_addresses: bool) -> Self {
+        PublicResolver {
+            allow_private_addresses,
+            allow_domains: Vec::new(),
+        }
+    }
+
+    /// Resolve `host`, keeping the addresses this resolver lets through
+    async fn lookup(self, host: String) -> Result<Addrs, Box<dyn std::error::Error + Send + Sync>> {
+        // The client replaces the port with the URL's
+        let resolved: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+            .await
+            .map_err(|e| format!("{} does not resolve: {}", host, e))?
+            .collect();
+        let Some(first) = resolved.first() else {
+            return Err(format!("{} does not resolve to any address", host).into());
+        };
+        if self.allow_private_addresses || matches_any(&host, &self.allow_domains) {
+            return Ok(Box::new(resolved.into_iter()));
+        }
+        let address = first.ip();
+        let public: Vec<SocketAddr> = resolved
+            .into_iter()
+            .filter(|resolved| !is_private(resolved.ip()))
+            .collect();
+        if public.is_empty() {
+            return Err(NonPublicAddress { host, address }.into());
+        }
+        Ok(Box::new(public.into_iter()))
+    }
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().trim_end_matches('.').to_ascii_lowercase();
+        Box::pin(self.clone().lookup(host))
+    }
+}
+
+/// A name resolved only to addresses a `PublicResolver` drops
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonPublicAddress {
+    /// The name, in lowercase
+    pub host: String,
+    /// The first address it resolved to
+    pub address: IpAddr,
+}
+
+impl fmt::Display for NonPublicAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} resolves to the non-public address {}",
+            self.host, self.address
+        )
+    }
+}
+
+impl std::error::Error for NonPublicAddress {}
+
+/// The `NonPublicAddress` a request failed on, found among the causes of its error
+pub fn refused_address<'a>(
+    error: &'a (dyn std::error::Error + 'static),
+) -> Option<&'a NonPublicAddress> {
+    let mut cause = Some(error);
+    while let Some(error) = cause {
+        if let Some(refused) = error.downcast_ref::<NonPublicAddress>() {
+            return Some(refused);
+        }
+        cause = error.source();
+    }
+    None
+}
+
+/// Host of `url` in lowercase, without IPv6 brackets or a trailing dot
+pub fn normalized_host(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(
+        host.trim_start_matches('[')
+            .trim_end_matches(']')
+            .trim_end_matches('.')
+            .to_ascii_lowercase(),
+    )
+}
+
+/// The host of `url`, when it is given as a loopback, link-local, private,
+/// unique local or unspecified address
+///
+/// Names are left to `PublicResolver`.
+pub fn non_public_literal(url: &Url) -> Option<IpAddr> {
+    let address = normalized_host(url)?.parse::<IpAddr>().ok()?;
+    is_private(address).then_some(address)
+}
+
+/// Whether `host` is one of `domains` or a subdomain of one
+fn matches_any(host: &str, domains: &[String]) -> bool {
+    domains.iter().any(|domain| {
+        host == domain
+            || host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+/// Whether an address is loopback, link-local, private, unique local or unspecified
+fn is_private(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(v4) => is_private_v4(v4),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_private_v4(v4),
+            None => is_private_v6(v6),
+        },
+    }
+}
+
+/// IPv4 loopback (127/8), link-local (169.254/16), RFC 1918 and unspecified addresses
+fn is_private_v4(address: Ipv4Addr) -> bool {
+    address.is_loopback()
+        || address.is_link_local()
+        || address.is_private()
+        || address.is_unspecified()
+}
+
+/// IPv6 loopback, link-local (fe80::/10), unique local (fc00::/7) and unspecified addresses
+fn is_private_v6(address: Ipv6Addr) -> bool {
+    let first = address.segments()[0];
+    address.is_loopback()
+        || address.is_unspecified()
+        || (first & 0xffc0) == 0xfe80
+        || (first & 0xfe00) == 0xfc00
+}
@@ -0,0 +1,444 @@
+//! # Output budget
+//!
+//! One byte budget per execution shared by every output channel a guest can
+//! write to, together with a cap on the number of captured messages. Each
+//! message is either admitted whole or dropped whole, so the captured output
+//! of an execution never exceeds either limit. Once a message has been
+//! dropped the budget is exhausted and every later message on every channel
+//! is dropped too, keeping the captured output a prefix of what the guest
+//! produced.
+//!
+//! Each channel may also have a byte cap of its own. A message that does not
+//! fit under its channel's cap closes that channel only: later messages on
+//! it are dropped, those on other channels are still captured while the
+//! shared budget lasts. Bytes produced on every channel, captured or not,
+//! are counted either way, and a channel that dropped anything is reported
+//! as truncated.
+//!
+//! The budget also carries whether captured lines are echoed to the host's
+//! log as `tracing` events, and the live output stream they are published to when
+//! the execution is streamed, so every capture path makes the same choice.
+//!
+//! Capture buffers and log lists are locked through `lock`, which recovers
+//! a lock whose holder panicked: a buffer is only ever appended to, so what
+//! it held before is intact, and an execution failing in a host function
+//! still reports everything captured up to that call.
+
+use crate::live::{LiveEvent, LiveSender};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Output channels counted against the budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Captured standard output
+    Stdout,
+    /// Captured standard error
+    Stderr,
+    /// `app_log` messages
+    Log,
+}
+
+impl Channel {
+    /// All channels, in the order they are reported
+    const ALL: [Channel; 3] = [Channel::Stdout, Channel::Stderr, Channel::Log];
+
+    /// Name of the channel in the response and in live output events
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Stdout => "stdout",
+            Channel::Stderr => "stderr",
+            Channel::Log => "log",
+        }
+    }
+}
+
+/// Severity of a console call or `app_log` message
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /// `console.debug`
+    Debug,
+    /// `console.info`
+    Info,
+    /// `console.log`
+    Log,
+    /// `console.warn`
+    Warn,
+    /// `console.error`
+    Error,
+    /// `console.trace`, whose message ends with the stack
+    Trace,
+}
+
+impl LogLevel {
+    /// Parse a level by its name, as the console functions pass it
+    pub fn parse(name: &str) -> Option<LogLevel> {
+        match name {
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "log" => Some(LogLevel::Log),
+            "warn" => Some(LogLevel::Warn),
+            "error" => Some(LogLevel::Error),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+
+    /// Normalize the level an `app_log` call was made with
+    ///
+    /// Names are matched case-insensitively and `warning` is read as `warn`;
+    /// anything but `debug`, `info`, `warn` and `error` is logged as `info`.
+    pub fn from_app_log(name: &str) -> LogLevel {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "debug" => LogLevel::Debug,
+            "warn" | "warning" => LogLevel::Warn,
+            "error" => LogLevel::Error,
+            _ => LogLevel::Info,
+        }
+    }
+
+    /// Name of the level in `app_log` lines, such as `[WASM LOG - WARN]`
+    pub fn label(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Log => "LOG",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+            LogLevel::Trace => "TRACE",
+        }
+    }
+}
+
+/// What produced a log entry
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogSource {
+    /// One of the JavaScript `console` functions
+    Console,
+    /// The `app_log` host function of either engine
+    AppLog,
+}
+
+/// A captured console call or `app_log` message, reported in the response's `logs`
+#[derive(Serialize, Debug, Clone)]
+pub struct LogEntry {
+    /// Severity of the call
+    pub level: LogLevel,
+    /// What made the call
+    pub source: LogSource,
+    /// The formatted console message as written to stdout or stderr, or the
+    /// `app_log` message without its `[.. LOG - LEVEL]` prefix
+    pub message: String,
+    /// Unix time of the call in milliseconds
+    pub timestamp_ms: u64,
+}
+
+impl LogEntry {
+    /// Create an entry timestamped now
+    pub fn now(level: LogLevel, source: LogSource, message: &str) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        LogEntry {
+            level,
+            source,
+            message: message.to_string(),
+            timestamp_ms,
+        }
+    }
+
+    /// Print an `app_log` entry through `tracing`, at its own level
+    ///
+    /// The events have the `GUEST_LOG_TARGET` target and are printed only if
+    /// they pass the server's `server.log_level` filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `engine` - Engine the guest ran in, `javascript` or `webassembly`
+    pub fn echo(&self, engine: &'static str) {
+        let message = &self.message;
+        match self.level {
+            LogLevel::Debug => tracing::debug!(target: GUEST_LOG_TARGET, engine, "{}", message),
+            LogLevel::Warn => tracing::warn!(target: GUEST_LOG_TARGET, engine, "{}", message),
+            LogLevel::Error => tracing::error!(target: GUEST_LOG_TARGET, engine, "{}", message),
+            LogLevel::Trace => tracing::trace!(target: GUEST_LOG_TARGET, engine, "{}", message),
+            LogLevel::Info | LogLevel::Log => {
+                tracing::info!(target: GUEST_LOG_TARGET, engine, "{}", message)
+            }
+        }
+    }
+}
+
+/// `tracing` target of the events guest output is echoed with
+pub const GUEST_LOG_TARGET: &str = "hoya::guest";
+
+/// Print a captured stdout or stderr line through `tracing`
+///
+/// Standard output is echoed at the info level and standard error at the
+/// warn level, with the `GUEST_LOG_TARGET` target.
+///
+/// # Arguments
+///
+/// * `engine` - Engine the guest ran in, `javascript` or `webassembly`
+/// * `channel` - Channel the line was captured on
+/// * `line` - The captured line
+pub fn echo_line(engine: &'static str, channel: Channel, line: &str) {
+    let channel_name = channel.name();
+    match channel {
+        Channel::Stderr => {
+            tracing::warn!(target: GUEST_LOG_TARGET, engine, channel = channel_name, "{}", line)
+        }
+        Channel::Stdout | Channel::Log => {
+            tracing::info!(target: GUEST_LOG_TARGET, engine, channel = channel_name, "{}", line)
+        }
+    }
+}
+
+/// Lock a capture buffer or log list, recovering the guard if a holder panicked
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Messages and bytes dropped on one channel
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DroppedOutput {
+    /// Number of dropped messages
+    pub messages: u64,
+    /// Number of dropped bytes
+    pub bytes: u64,
+}
+
+/// Summary of an execution's output budget, reported in the response metadata
+#[derive(Serialize, Debug, Clone)]
+pub struct OutputUsage {
+    /// The budget in bytes
+    pub limit_bytes: usize,
+    /// Bytes the guest wrote by channel, captured or dropped (only channels it wrote to)
+    pub produced_bytes: BTreeMap<&'static str, u64>,
+    /// Bytes captured across all channels
+    pub used_bytes: usize,
+    /// Messages captured before further messages are dropped
+    pub limit_entries: u64,
+    /// Messages captured across all channels
+    pub used_entries: u64,
+    /// Dropped output by channel (only channels that dropped something)
+    pub dropped: BTreeMap<&'static str, DroppedOutput>,
+}
+
+/// Byte budget shared by all output channels of one execution
+#[derive(Debug)]
+pub struct OutputBudget {
+    /// Maximum number of bytes captured across all channels
+    limit: usize,
+    /// Maximum number of messages captured across all channels
+    entry_limit: u64,
+    /// Terminate the execution instead of dropping output once exhausted
+    strict: bool,
+    /// Also echo captured lines to the host's log
+    echo_to_host: bool,
+    /// Live output stream captured lines are published to
+    live: Option<LiveSender>,
+    /// Bytes admitted so far
+    used: AtomicUsize,
+    /// Messages admitted so far
+    entries: AtomicU64,
+    /// Set when the first message is dropped
+    exhausted: AtomicBool,
+    /// Byte cap of each channel, indexed by `Channel as usize`
+    channel_limits: [usize; 3],
+    /// Bytes admitted per channel, indexed by `Channel as usize`
+    channel_used: [AtomicUsize; 3],
+    /// Set per channel when a message went over the channel's cap
+    channel_closed: [AtomicBool; 3],
+    /// Bytes produced per channel, admitted or dropped, indexed by `Channel as usize`
+    produced_bytes: [AtomicU64; 3],
+    /// Dropped messages per channel, indexed by `Channel as usize`
+    dropped_messages: [AtomicU64; 3],
+    /// Dropped bytes per channel, indexed by `Channel as usize`
+    dropped_bytes: [AtomicU64; 3],
+}
+
+impl OutputBudget {
+    /// Create a budget
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - Maximum number of bytes captured across all channels
+    /// * `entry_limit` - Maximum number of messages captured across all channels
+    /// * `strict` - Whether exhausting the budget terminates the execution
+    /// * `echo_to_host` - Whether captured lines are also printed by the host
+    pub fn new(limit: usize, entry_limit: u64, strict: bool, echo_to_host: bool) -> Self {
+        OutputBudget {
+            limit,
+            entry_limit,
+            strict,
+            echo_to_host,
+            live: None,
+            used: AtomicUsize::new(0),
+            entries: AtomicU64::new(0),
+            exhausted: AtomicBool::new(false),
+            channel_limits: [usize::MAX; 3],
+            channel_used: Default::default(),
+            channel_closed: Default::default(),
+            produced_bytes: Default::default(),
+            dropped_messages: Default::default(),
+            dropped_bytes: Default::default(),
+        }
+    }
+
+    /// Cap the bytes captured on one channel, on top of the shared budget
+    ///
+    /// ```
+    /// use hoya::output::{Channel, OutputBudget};
+    ///
+    /// let budget = OutputBudget::new(1024, 100, false, false).with_channel_limit(Channel::Stdout, 8);
+    /// assert!(budget.admit(Channel::Stdout, 6));
+    /// assert!(!budget.admit(Channel::Stdout, 6));
+    /// assert!(!budget.admit(Channel::Stdout, 1));
+    /// assert!(budget.admit(Channel::Stderr, 6));
+    /// assert!(budget.is_truncated(Channel::Stdout) && !budget.is_truncated(Channel::Stderr));
+    /// assert_eq!(budget.usage().produced_bytes["stdout"], 13);
+    /// ```
+    pub fn with_channel_limit(mut self, channel: Channel, limit: usize) -> Self {
+        self.channel_limits[channel as usize] = limit;
+        self
+    }
+
+    /// Publish captured lines to a live output stream
+    pub fn with_live(mut self, live: Option<LiveSender>) -> Self {
+        self.live = live;
+        self
+    }
+
+    /// Send a captured line to the live output stream, if there is one
+    ///
+    /// A stream whose client has gone away is ignored; the execution is
+    /// cancelled separately.
+    pub fn publish(&self, channel: Channel, line: &str) {
+        if let Some(live) = &self.live {
+            let _ = live.send(LiveEvent::Output(channel, line.to_string()));
+        }
+    }
+
+    /// Reserve room for a message of `len` bytes on `channel`
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - `true` if the message may be captured, `false` if it must be dropped
+    pub fn admit(&self, channel: Channel, len: usize) -> bool {
+        let index = channel as usize;
+        self.produced_bytes[index].fetch_add(len as u64, Ordering::Relaxed);
+        if !self.channel_closed[index].load(Ordering::Acquire) {
+            let reserved = self.channel_used[index].fetch_update(
+                Ordering::AcqRel,
+                Ordering::Acquire,
+                |used| {
+                    used.checked_add(len)
+                        .filter(|&total| total <= self.channel_limits[index])
+                },
+            );
+            match reserved {
+                Ok(_) if self.admit_shared(len) => return true,
+                Ok(_) => {
+                    self.channel_used[index].fetch_sub(len, Ordering::AcqRel);
+                }
+                Err(_) => self.channel_closed[index].store(true, Ordering::Release),
+            }
+        }
+
+        self.dropped_messages[index].fetch_add(1, Ordering::Relaxed);
+        self.dropped_bytes[index].fetch_add(len as u64, Ordering::Relaxed);
+        false
+    }
+
+    /// Reserve room for a message of `len` bytes in the budget shared by all channels
+    fn admit_shared(&self, len: usize) -> bool {
+        if !self.exhausted.load(Ordering::Acquire) {
+            let entry = self
+                .entries
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |entries| {
+                    (entries < self.entry_limit).then_some(entries + 1)
+                });
+            if entry.is_ok() {
+                let reserved =
+                    self.used
+                        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                            used.checked_add(len).filter(|&total| total <= self.limit)
+                        });
+                if reserved.is_ok() {
+                    return true;
+                }
+                self.entries.fetch_sub(1, Ordering::AcqRel);
+            }
+            self.exhausted.store(true, Ordering::Release);
+        }
+        false
+    }
+
+    /// Whether output of `channel` was dropped, by its own cap or the shared budget
+    pub fn is_truncated(&self, channel: Channel) -> bool {
+        self.dropped_messages[channel as usize].load(Ordering::Relaxed) > 0
+    }
+
+    /// Whether output has been dropped
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Acquire)
+    }
+
+    /// Whether the execution must be terminated because the budget is exhausted
+    pub fn should_terminate(&self) -> bool {
+        self.strict && self.is_exhausted()
+    }
+
+    /// Whether exhausting the budget terminates the execution
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Whether captured lines are also echoed to the host's log
+    pub fn echo_to_host(&self) -> bool {
+        self.echo_to_host
+    }
+
+    /// The budget in bytes
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Summarize usage for the response metadata
+    pub fn usage(&self) -> OutputUsage {
+        let dropped = Channel::ALL
+            .iter()
+            .filter_map(|&channel| {
+                let index = channel as usize;
+                let messages = self.dropped_messages[index].load(Ordering::Relaxed);
+                (messages > 0).then(|| {
+                    let bytes = self.dropped_bytes[index].load(Ordering::Relaxed);
+                    (channel.name(), DroppedOutput { messages, bytes })
+                })
+            })
+            .collect();
+        let produced_bytes = Channel::ALL
+            .iter()
+            .filter_map(|&channel| {
+                let bytes = self.produced_bytes[channel as usize].load(Ordering::Relaxed);
+                (bytes > 0).then_some((channel.name(), bytes))
+            })
+            .collect();
+
+        OutputUsage {
+            limit_bytes: self.limit,
+            produced_bytes,
+            used_bytes: self.used.load(Ordering::Acquire),
+            limit_entries: self.entry_limit,
+            used_entries: self.entries.load(Ordering::Acquire),
+            dropped,
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! # Guest randomness
+//!
+//! Guests draw random bytes through `GuestRandom`: `crypto.getRandomValues`
+//! and `crypto.randomUUID` in JavaScript, the `random_bytes` import in
+//! WebAssembly. The bytes come from the host's generator, within a per-call
+//! and a per-execution budget.
+//!
+//! When a request sets `deterministic: true`, every source of randomness
+//! offered to the guest, `Math.random` included, draws from one ChaCha20
+//! keystream derived from the request's seed instead, so two runs with the
+//! same seed see the same values.
+
+/// ChaCha20 "expand 32-byte k" constants
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+/// Settings of a deterministic execution
+#[derive(Debug, Clone, Copy)]
+pub struct DeterministicMode {
+    /// Seed of the execution's random stream
+    pub seed: u64,
+    /// Make the seeded `Math.random` non-writable and non-configurable
+    pub lock_math_random: bool,
+}
+
+/// Most bytes a single `getRandomValues` or `random_bytes` call may ask for
+pub const MAX_RANDOM_BYTES_PER_CALL: usize = 65_536;
+
+/// Most random bytes an execution may draw in all
+pub const MAX_RANDOM_BYTES_PER_EXECUTION: u64 = 1 << 20;
+
+/// Why a guest's request for random bytes was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomError {
+    /// The call asked for more than `MAX_RANDOM_BYTES_PER_CALL` bytes
+    TooLarge,
+    /// The execution drew `MAX_RANDOM_BYTES_PER_EXECUTION` bytes already
+    BudgetExhausted,
+    /// The host's generator failed
+    Unavailable,
+}
+
+impl std::fmt::Display for RandomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RandomError::TooLarge => write!(
+                f,
+                "At most {} random bytes can be requested at once",
+                MAX_RANDOM_BYTES_PER_CALL
+            ),
+            RandomError::BudgetExhausted => write!(
+                f,
+                "The execution drew its {} random bytes already",
+                MAX_RANDOM_BYTES_PER_EXECUTION
+            ),
+            RandomError::Unavailable => write!(f, "The host's random generator failed"),
+        }
+    }
+}
+
+/// Source of an execution's random bytes
+///
+/// Draws from the host's generator, or from the seeded keystream in
+/// deterministic mode, so seeded sources repeat and unseeded ones do not:
+///
+/// ```
+/// use hoya::random::{DeterministicMode, GuestRandom};
+///
+/// let mode = DeterministicMode { seed: 42, lock_math_random: true };
+/// let draw = |mode| {
+///     let mut bytes = [0u8; 16];
+///     GuestRandom::new(mode).fill(&mut bytes).unwrap();
+///     bytes
+/// };
+/// assert_eq!(draw(Some(mode)), draw(Some(mode)));
+/// assert_ne!(draw(None), draw(None));
+/// ```
+pub struct GuestRandom {
+    /// The seeded keystream, in deterministic mode
+    seeded: Option<ChaChaRng>,
+    /// Bytes the execution may still draw
+    remaining: u64,
+}
+
+impl GuestRandom {
+    /// A source for an execution, seeded when `deterministic` is set
+    pub fn new(deterministic: Option<DeterministicMode>) -> Self {
+        GuestRandom {
+            seeded: deterministic.map(|mode| ChaChaRng::from_seed(mode.seed)),
+            remaining: MAX_RANDOM_BYTES_PER_EXECUTION,
+        }
+    }
+
+    /// Fill `dest` with random bytes, counting them against the execution's budget
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), RandomError>` - `Ok` once `dest` is filled, or why nothing was drawn
+    pub fn fill(&mut self, dest: &mut [u8]) -> Result<(), RandomError> {
+        if dest.len() > MAX_RANDOM_BYTES_PER_CALL {
+            return Err(RandomError::TooLarge);
+        }
+        if dest.len() as u64 > self.remaining {
+            return Err(RandomError::BudgetExhausted);
+        }
+        match &mut self.seeded {
+            Some(rng) => rng.fill_bytes(dest),
+            None => getrandom::fill(dest).map_err(|_| RandomError::Unavailable)?,
+        }
+        self.remaining -= dest.len() as u64;
+        Ok(())
+    }
+
+    /// Next float of the seeded keystream, for the seeded `Math.random`
+    ///
+    /// Floats do not count against the byte budget.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<f64>` - A float in `[0, 1)`, or `None` outside deterministic mode
+    pub fn next_f64(&mut self) -> Option<f64> {
+        self.seeded.as_mut().map(ChaChaRng::next_f64)
+    }
+}
+
+/// ChaCha20 keystream generator seeded from a `u64`
+///
+/// The 256-bit key is expanded from the seed with SplitMix64; the nonce is
+/// zero and the 64-bit block counter starts at zero.
+pub struct ChaChaRng {
+    /// Key words
+    key: [u32; 8],
+    /// Index of the next keystream block
+    counter: u64,
+    /// Current keystream block
+    block: [u8; 64],
+    /// Bytes of `block` already handed out
+    used: usize,
+}
+
+impl ChaChaRng {
+    /// Create a generator from a seed
+    pub fn from_seed(seed: u64) -> Self {
+        let mut state = seed;
+        let mut key = [0u32; 8];
+        for pair in key.chunks_mut(2) {
+            let word = splitmix64(&mut state);
+            pair[0] = word as u32;
+            pair[1] = (word >> 32) as u32;
+        }
+        ChaChaRng {
+            key,
+            counter: 0,
+            block: [0; 64],
+            used: 64,
+        }
+    }
+
+    /// Fill `dest` with the next bytes of the keystream
+    pub fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let mut filled = 0;
+        while filled < dest.len() {
+            if self.used == self.block.len() {
+                self.refill();
+            }
+            let take = (self.block.len() - self.used).min(dest.len() - filled);
+            dest[filled..filled + take].copy_from_slice(&self.block[self.used..self.used + take]);
+            self.used += take;
+            filled += take;
+        }
+    }
+
+    /// Next 64 bits of the keystream
+    pub fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Uniform float in `[0, 1)` built from the top 53 bits of a `u64`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    /// Compute the next keystream block
+    fn refill(&mut self) {
+        let mut input = [0u32; 16];
+        input[..4].copy_from_slice(&CHACHA_CONSTANTS);
+        input[4..12].copy_from_slice(&self.key);
+        input[12] = self.counter as u32;
+        input[13] = (self.counter >> 32) as u32;
+
+        for (chunk, word) in self.block.chunks_mut(4).zip(chacha20_block(&input)) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        self.counter = self.counter.wrapping_add(1);
+        self.used = 0;
+    }
+}
+
+/// SplitMix64 step, used to expand the seed into key material
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The ChaCha20 block function (RFC 8439, section 2.3)
+///
+/// Seeded streams are a public contract, so the function is pinned to the
+/// RFC's test vector (section 2.3.2):
+///
+/// ```
+/// use hoya::random::chacha20_block;
+///
+/// // Constants, the key 00 01 .. 1f, block count 1 and nonce 00:00:00:09:00:00:00:4a:00:00:00:00
+/// let input = [
+///     0x61707865, 0x3320646e, 0x79622d32, 0x6b206574,
+///     0x03020100, 0x07060504, 0x0b0a0908, 0x0f0e0d0c,
+///     0x13121110, 0x17161514, 0x1b1a1918, 0x1f1e1d1c,
+///     0x00000001, 0x09000000, 0x4a000000, 0x00000000,
+/// ];
+/// assert_eq!(chacha20_block(&input), [
+///     0xe4e7f110, 0x15593bd1, 0x1fdd0f50, 0xc47120a3,
+///     0xc7f4d1c7, 0x0368c033, 0x9aaa2204, 0x4e6cd4c3,
+///     0x466482d2, 0x09aa9f07, 0x05d7c214, 0xa2028bd9,
+///     0xd19c12b5, 0xb94e16de, 0xe883d0cb, 0x4e3c50a2,
+/// ]);
+/// ```
+pub fn chacha20_block(input: &[u32; 16]) -> [u32; 16] {
+    let mut x = *input;
+    for _ in 0..10 {
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+    for (word, original) in x.iter_mut().zip(input) {
+        *word = word.wrapping_add(*original);
+    }
+    x
+}
+
+/// The ChaCha quarter round on four words of the state
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
@@ -0,0 +1,103 @@
+//! # Execution records
+//!
+//! The summary of a finished execution shared by the audit log and the
+//! reporters. Records describe what ran and how it ended but never contain
+//! code, request headers or captured output.
+
+use crate::error::ExecuteResponse;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Summary of a finished execution, written to the audit log and handed to reporters
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExecutionRecord {
+    /// RFC 3339 time at which the execution finished
+    pub timestamp: String,
+    /// Identifier generated for the request
+    pub request_id: String,
+    /// Label of the caller's API token (unset until callers authenticate)
+    pub caller: Option<String>,
+    /// URL the code was requested from, without credentials, query or fragment
+    pub source_url: String,
+    /// Hex SHA-256 digest of the executed code, if it was downloaded
+    pub code_sha256: Option<String>,
+    /// "javascript", "webassembly", or "unknown" when no engine ran
+    pub code_type: String,
+    /// "success" or "error"
+    pub status: String,
+    /// HTTP status code of the response
+    pub http_status: u16,
+    /// Error code of a failed execution
+    pub error_code: Option<String>,
+    /// Wall-clock time spent handling the request, in milliseconds
+    pub request_duration_ms: u64,
+    /// Time spent executing the code, in milliseconds
+    pub execution_time_ms: u64,
+    /// Hosts contacted by guest fetch calls
+    pub fetch_hosts: Vec<String>,
+    /// Size of the execution result in bytes
+    pub output_bytes: usize,
+    /// Size of the captured stdout in bytes
+    pub stdout_bytes: usize,
+    /// Size of the captured stderr in bytes
+    pub stderr_bytes: usize,
+}
+
+impl ExecutionRecord {
+    /// Summarize a finished request
+    ///
+    /// # Arguments
+    ///
+    /// * `request_id` - Identifier generated for the request
+    /// * `source_url` - URL the code was requested from
+    /// * `http_status` - HTTP status code of the response
+    /// * `response` - The response body sent to the caller
+    /// * `request_duration` - Wall-clock time spent handling the request
+    pub fn new(
+        request_id: &str,
+        source_url: &str,
+        http_status: u16,
+        response: &ExecuteResponse,
+        request_duration: Duration,
+    ) -> Self {
+        let mut fetch_hosts: Vec<String> = response
+            .metadata
+            .network
+            .as_ref()
+            .map(|network| network.hosts.keys().cloned().collect())
+            .unwrap_or_default();
+        fetch_hosts.sort();
+
+        ExecutionRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            request_id: request_id.to_string(),
+            caller: None,
+            source_url: redact_url(source_url),
+            code_sha256: response.metadata.code_sha256.clone(),
+            code_type: response.metadata.code_type.clone(),
+            status: response.status.clone(),
+            http_status,
+            error_code: response.error.as_ref().map(|error| error.code.clone()),
+            request_duration_ms: request_duration.as_millis() as u64,
+            execution_time_ms: response.metadata.execution_time,
+            fetch_hosts,
+            output_bytes: response.output.as_ref().map_or(0, String::len),
+            stdout_bytes: response.stdout.as_ref().map_or(0, String::len),
+            stderr_bytes: response.stderr.as_ref().map_or(0, String::len),
+        }
+    }
+}
+
+/// Strip the parts of a URL that may carry secrets (userinfo, query, fragment)
+pub fn redact_url(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(mut url) => {
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            url.set_query(None);
+            url.set_fragment(None);
+            url.to_string()
+        }
+        Err(_) => "<invalid url>".to_string(),
+    }
+}
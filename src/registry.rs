@@ -0,0 +1,361 @@
+//! # Module registry
+//!
+//! `POST /modules` obtains code once, from a URL or inline, checks it the way
+//! an execution would and keeps it under a name; `POST /execute` with
+//! `module` instead of `url` then runs it without downloading, detecting or
+//! compiling anything. WebAssembly modules are kept compiled and linked, and
+//! pinned in the shared module cache so its evictions never drop them.
+//! JavaScript has no compiled form to keep, so scripts are kept as their
+//! syntax-checked source.
+//!
+//! Registering a name that is taken replaces its module. Concurrent
+//! registrations of one name each prepare their own code without holding the
+//! registry, and the last one to finish wins; each is told the digest it
+//! registered and the one it replaced. At most `modules.max_modules` modules
+//! and `modules.max_bytes` of code are held; registrations past either fail
+//! with `MODULE_REGISTRY_FULL` (507).
+
+use crate::base64;
+use crate::compression;
+use crate::config::ModulesConfig;
+use crate::detect::{self, CodeType};
+use crate::download;
+use crate::error::AppError;
+use crate::js_engine;
+use crate::manifest::{self, Manifest};
+use crate::record;
+use crate::state::AppState;
+use crate::types::BodyEncoding;
+use crate::wasm_engine::{self, SharedEngine, WasmCtx};
+use crate::wat::{self, SourceFormat};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use wasmtime::InstancePre;
+
+/// Longest module name, in bytes
+pub const MAX_NAME_BYTES: usize = 128;
+
+/// Request body of `POST /modules`
+#[derive(Deserialize, Debug)]
+pub struct RegisterRequest {
+    /// Name the module is registered, and run, under
+    pub name: String,
+    /// URL to download the code from
+    pub url: Option<String>,
+    /// The code itself, instead of a URL
+    pub code: Option<String>,
+    /// How `code` is written: as text, or base64-encoded for binaries
+    #[serde(default)]
+    pub code_encoding: BodyEncoding,
+    /// Type of the code, taking precedence over the URL, `Content-Type` and payload
+    pub code_type: Option<CodeType>,
+    /// Hex SHA-256 digest the code must have once obtained (and decompressed)
+    pub sha256: Option<String>,
+    /// Extra headers for the code download request, named in `download.forward_headers`
+    pub download_headers: Option<HashMap<String, String>>,
+}
+
+/// Code kept under a name, ready to run
+pub struct RegisteredModule {
+    /// Name the module is registered under
+    pub name: String,
+    /// Type of the code
+    pub code_type: CodeType,
+    /// The code as the engine runs it: assembled for the text format
+    pub code: bytes::Bytes,
+    /// Hex SHA-256 digest of the code as obtained (and decompressed)
+    pub code_sha256: String,
+    /// Format the WebAssembly module was written in, when it was not the binary format
+    pub source_format: Option<SourceFormat>,
+    /// The WebAssembly module's manifest as declared, if it has one
+    pub manifest: Option<Manifest>,
+    /// URL the code was downloaded from; ES modules resolve relative imports against it
+    pub url: Option<String>,
+    /// RFC 3339 time of the registration
+    pub registered_at: String,
+    /// The compiled module, pinned in the shared cache while it is registered
+    compiled: Option<InstancePre<WasmCtx>>,
+}
+
+/// A registered module, as listed by `GET /modules`
+#[derive(Serialize, Debug)]
+pub struct ModuleSummary {
+    /// Name the module is registered under
+    pub name: String,
+    /// "javascript" or "webassembly"
+    pub code_type: &'static str,
+    /// "wat" for modules written in the WebAssembly text format
+    pub source_format: Option<SourceFormat>,
+    /// Hex SHA-256 digest of the code
+    pub code_sha256: String,
+    /// Size of the code, as counted against `modules.max_bytes`
+    pub size_bytes: usize,
+    /// Size of the compiled module's code (WebAssembly only)
+    pub compiled_bytes: Option<usize>,
+    /// URL the code was downloaded from, without credentials, query or fragment
+    pub url: Option<String>,
+    /// RFC 3339 time of the registration
+    pub registered_at: String,
+}
+
+impl RegisteredModule {
+    /// Describe the module for listings
+    pub fn summary(&self) -> ModuleSummary {
+        ModuleSummary {
+            name: self.name.clone(),
+            code_type: match self.code_type {
+                CodeType::JavaScript | CodeType::Module => "javascript",
+                CodeType::WebAssembly | CodeType::Wat => "webassembly",
+            },
+            source_format: self.source_format,
+            code_sha256: self.code_sha256.clone(),
+            size_bytes: self.code.len(),
+            compiled_bytes: self.compiled.as_ref().map(|compiled| {
+                let range = compiled.module().image_range();
+                (range.end as usize).saturating_sub(range.start as usize)
+            }),
+            url: self.url.as_deref().map(record::redact_url),
+            registered_at: self.registered_at.clone(),
+        }
+    }
+}
+
+/// Named modules, bounded in count and bytes
+pub struct ModuleRegistry {
+    /// Modules held at once; 0 disables registration
+    max_modules: usize,
+    /// Bytes of code held at once
+    max_bytes: u64,
+    /// Engine whose module cache pins the compiled modules
+    engine: Arc<SharedEngine>,
+    /// Modules by name
+    modules: Mutex<BTreeMap<String, Arc<RegisteredModule>>>,
+}
+
+/// Check a module name: 1 to `MAX_NAME_BYTES` ASCII letters, digits, '.', '_' or '-'
+///
+/// ```
+/// use hoya::registry::validate_name;
+///
+/// assert!(validate_name("resize-v2.1").is_ok());
+/// assert!(validate_name("").is_err());
+/// assert!(validate_name("a/b").is_err());
+/// ```
+pub fn validate_name(name: &str) -> Result<(), AppError> {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-');
+    if name.is_empty() || name.len() > MAX_NAME_BYTES || !name.chars().all(valid_char) {
+        return Err(AppError::InvalidRequest(format!(
+            "module names are 1 to {} ASCII letters, digits, '.', '_' or '-'",
+            MAX_NAME_BYTES
+        )));
+    }
+    Ok(())
+}
+
+impl ModuleRegistry {
+    /// Create an empty registry with the limits of the `[modules]` section
+    pub fn new(config: &ModulesConfig, engine: Arc<SharedEngine>) -> Self {
+        ModuleRegistry {
+            max_modules: config.max_modules,
+            max_bytes: config.max_bytes,
+            engine,
+            modules: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The module registered under `name`
+    pub fn get(&self, name: &str) -> Option<Arc<RegisteredModule>> {
+        self.modules.lock().ok()?.get(name).cloned()
+    }
+
+    /// Every registered module, by name
+    pub fn list(&self) -> Vec<ModuleSummary> {
+        match self.modules.lock() {
+            Ok(modules) => modules.values().map(|module| module.summary()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Register a prepared module, replacing the one under its name
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Arc<RegisteredModule>>, AppError>` - The replaced
+    ///   module, if any, or `ModuleRegistryFull` when the module does not fit
+    ///   beside the others
+    pub fn insert(
+        &self,
+        module: RegisteredModule,
+    ) -> Result<Option<Arc<RegisteredModule>>, AppError> {
+        let mut modules = self
+            .modules
+            .lock()
+            .map_err(|_| AppError::Internal("The module registry lock is poisoned".to_string()))?;
+        let others = modules.values().filter(|other| other.name != module.name);
+        let (count, bytes) = others.fold((0, 0u64), |(count, bytes), other| {
+            (count + 1, bytes + other.code.len() as u64)
+        });
+        if count >= self.max_modules {
+            return Err(AppError::ModuleRegistryFull {
+                limit: "max_modules",
+                maximum: self.max_modules as u64,
+            });
+        }
+        if bytes + module.code.len() as u64 > self.max_bytes {
+            return Err(AppError::ModuleRegistryFull {
+                limit: "max_bytes",
+                maximum: self.max_bytes,
+            });
+        }
+        if let Some(compiled) = &module.compiled {
+            self.engine.modules.pin(&module.code_sha256, compiled);
+        }
+        let replaced = modules.insert(module.name.clone(), Arc::new(module));
+        if let Some(replaced) = &replaced {
+            self.release(replaced);
+        }
+        Ok(replaced)
+    }
+
+    /// Remove the module registered under `name`
+    pub fn remove(&self, name: &str) -> Option<Arc<RegisteredModule>> {
+        let removed = self.modules.lock().ok()?.remove(name)?;
+        self.release(&removed);
+        Some(removed)
+    }
+
+    /// Unpin a module that left the registry
+    fn release(&self, module: &RegisteredModule) {
+        if module.compiled.is_some() {
+            self.engine.modules.unpin(&module.code_sha256);
+        }
+    }
+}
+
+/// Obtain, check and compile the code of a registration, without registering it
+///
+/// # Arguments
+///
+/// * `state` - Shared service state holding the download client, caches and engine
+/// * `request` - The registration
+///
+/// # Returns
+///
+/// * `Result<RegisteredModule, AppError>` - The module, ready for
+///   `ModuleRegistry::insert`, or the error obtaining, detecting or compiling it failed with
+pub async fn prepare(
+    state: &AppState,
+    request: RegisterRequest,
+) -> Result<RegisteredModule, AppError> {
+    validate_name(&request.name)?;
+    let expected_sha256 = request
+        .sha256
+        .as_deref()
+        .map(download::parse_sha256)
+        .transpose()?;
+
+    let (code, content_type) = match (&request.url, &request.code) {
+        (Some(url), None) => {
+            let code_url = reqwest::Url::parse(url)
+                .map_err(|e| AppError::InvalidRequest(format!("Invalid code URL: {}", e)))?;
+            download::CodeUrlPolicy::from_config(&state.config.download).check_url(&code_url)?;
+            let headers =
+                download::build_headers(&state.config.download, request.download_headers.as_ref())?;
+            let downloaded = download::download(
+                &state.download_client,
+                url,
+                headers,
+                &state.config.download,
+                &state.download_cache,
+            )
+            .await?;
+            let code = match detect::from_extension(url) {
+                Some((_, true)) => {
+                    compression::gunzip(&downloaded.code, compression::MAX_DECOMPRESSED_SIZE)?
+                }
+                _ => downloaded.code,
+            };
+            (code, downloaded.content_type)
+        }
+        (None, Some(code)) => {
+            let code = match request.code_encoding {
+                BodyEncoding::Utf8 => code.clone().into_bytes(),
+                BodyEncoding::Base64 => base64::decode(code).ok_or_else(|| {
+                    AppError::InvalidRequest("code is not valid base64".to_string())
+                })?,
+            };
+            let limit_bytes = state.config.download.max_bytes;
+            if code.len() as u64 > limit_bytes {
+                return Err(AppError::CodeTooLarge {
+                    limit_bytes,
+                    observed_bytes: code.len() as u64,
+                });
+            }
+            (bytes::Bytes::from(code), None)
+        }
+        _ => {
+            return Err(AppError::InvalidRequest(
+                "Give exactly one of url and code".to_string(),
+            ))
+        }
+    };
+
+    let code_sha256 = format!("{:x}", Sha256::digest(&code));
+    if let Some(expected) = expected_sha256 {
+        if expected != code_sha256 {
+            return Err(AppError::ChecksumMismatch {
+                expected,
+                actual: code_sha256,
+            });
+        }
+    }
+    let url = request.url.as_deref().unwrap_or("");
+    let code_type = detect::decide(request.code_type, url, content_type.as_deref(), &code)?;
+    let (code, source_format) = match code_type {
+        CodeType::Wat => (wat::assemble(&code)?, Some(SourceFormat::Wat)),
+        _ => (code, None),
+    };
+
+    // Compilation is CPU-bound; keep it off this worker's other tasks
+    let (manifest, compiled) = tokio::task::block_in_place(|| -> Result<_, AppError> {
+        match code_type {
+            CodeType::JavaScript | CodeType::Module => {
+                let source = std::str::from_utf8(&code).map_err(|e| {
+                    AppError::InvalidRequest(format!("{} is not UTF-8: {}", request.name, e))
+                })?;
+                let checked = match code_type {
+                    CodeType::Module => js_engine::check_module_syntax(source),
+                    _ => js_engine::check_syntax(source),
+                };
+                checked.map_err(|message| AppError::JsSyntaxError {
+                    message,
+                    stack: None,
+                    source: request.name.clone(),
+                })?;
+                Ok((None, None))
+            }
+            CodeType::WebAssembly | CodeType::Wat => {
+                let manifest = manifest::read(&code)?;
+                let (compiled, _) = state
+                    .wasm
+                    .instance_pre(&code_sha256, &code, None, false)
+                    .map_err(|e| wasm_engine::compile_error(e, None))?;
+                Ok((manifest, Some(compiled)))
+            }
+        }
+    })?;
+
+    Ok(RegisteredModule {
+        name: request.name,
+        code_type,
+        code,
+        code_sha256,
+        source_format,
+        manifest,
+        url: request.url,
+        registered_at: chrono::Utc::now().to_rfc3339(),
+        compiled,
+    })
+}
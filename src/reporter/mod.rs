@@ -0,0 +1,175 @@
+//! # Execution reporters
+//!
+//! Reporters are notified of every finished execution and, in addition, of
+//! every failed one, so failures can be pushed to incident tooling. The
+//! built-in reporters (`webhook` and `log`) are selected with `[[reporters]]`
+//! entries in the config file; other implementations of `Reporter` can be
+//! added to a `ReporterRegistry` before it is started.
+//!
+//! Events are handed to a dedicated dispatcher thread over a channel, so a
+//! slow or failing reporter never delays the response to the caller.
+//! Reporters are called one after another on that thread, in the order they
+//! were registered.
+
+mod webhook;
+
+pub use webhook::WebhookReporter;
+
+use crate::config::ReporterConfig;
+use crate::error::AppErrorContext;
+use crate::record::ExecutionRecord;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+/// Receiver of execution events
+pub trait Reporter: Send + Sync {
+    /// Called for every finished execution, successful or not
+    fn on_execution_complete(&self, record: &ExecutionRecord);
+
+    /// Called for every failed execution, after `on_execution_complete`
+    fn on_error(&self, err: &AppErrorContext);
+}
+
+/// Logs one `info` event per completion and one `warn` event per error, each
+/// carrying the `execution_id`, through the server's `tracing` subscriber
+pub struct LogReporter;
+
+impl Reporter for LogReporter {
+    fn on_execution_complete(&self, record: &ExecutionRecord) {
+        tracing::info!(
+            execution_id = %record.request_id,
+            status = %record.status,
+            http_status = record.http_status,
+            duration_ms = record.request_duration_ms,
+            "Execution finished"
+        );
+    }
+
+    fn on_error(&self, err: &AppErrorContext) {
+        tracing::warn!(
+            execution_id = %err.execution_id,
+            source_url = %err.source_url,
+            error_code = %err.error.code,
+            "Execution failed: {}",
+            err.error.message
+        );
+    }
+}
+
+/// Reporters to be notified, in registration order
+#[derive(Default)]
+pub struct ReporterRegistry {
+    reporters: Vec<Box<dyn Reporter>>,
+}
+
+impl ReporterRegistry {
+    /// Create the built-in reporters listed in the configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `configs` - The `[[reporters]]` entries of the config file
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ReporterRegistry, String>` - The registry or a description of what failed
+    pub fn from_config(configs: &[ReporterConfig]) -> Result<Self, String> {
+        let mut registry = ReporterRegistry::default();
+        for config in configs {
+            match config {
+                ReporterConfig::Webhook(webhook) => {
+                    registry.register(Box::new(WebhookReporter::new(webhook)?))
+                }
+                ReporterConfig::Log => registry.register(Box::new(LogReporter)),
+            }
+        }
+        Ok(registry)
+    }
+
+    /// Add a reporter; it is notified after the ones registered before it
+    pub fn register(&mut self, reporter: Box<dyn Reporter>) {
+        self.reporters.push(reporter);
+    }
+
+    /// Whether no reporter is registered
+    pub fn is_empty(&self) -> bool {
+        self.reporters.is_empty()
+    }
+
+    /// Start the dispatcher thread that notifies the registered reporters
+    ///
+    /// # Returns
+    ///
+    /// * `std::io::Result<Reporting>` - The handle, or the error spawning the thread
+    pub fn start(self) -> std::io::Result<Reporting> {
+        let (sender, receiver) = mpsc::channel();
+        let handle = std::thread::Builder::new()
+            .name("hoya-reporter".to_string())
+            .spawn(move || self.run(receiver))?;
+
+        Ok(Reporting {
+            sender: Mutex::new(Some(sender)),
+            dispatcher: Mutex::new(Some(handle)),
+        })
+    }
+
+    /// Notify the reporters of events until the sending side is dropped
+    fn run(self, receiver: Receiver<Event>) {
+        while let Ok(event) = receiver.recv() {
+            for reporter in &self.reporters {
+                reporter.on_execution_complete(&event.record);
+                if let Some(error) = &event.error {
+                    reporter.on_error(error);
+                }
+            }
+        }
+    }
+}
+
+/// A finished execution queued for the reporters
+struct Event {
+    /// Summary of the execution
+    record: ExecutionRecord,
+    /// The failure, if the execution failed
+    error: Option<AppErrorContext>,
+}
+
+/// Handle to the reporter dispatcher thread
+pub struct Reporting {
+    /// Queue of events for the dispatcher; taken on shutdown to stop the dispatcher
+    sender: Mutex<Option<Sender<Event>>>,
+    /// The dispatcher thread, joined on shutdown
+    dispatcher: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Reporting {
+    /// Queue a finished execution for the reporters
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - Summary of the execution
+    /// * `error` - The failure, if the execution failed
+    pub fn report(&self, record: ExecutionRecord, error: Option<AppErrorContext>) {
+        if let Ok(sender) = self.sender.lock() {
+            if let Some(sender) = sender.as_ref() {
+                // Fails only after shutdown, when the reporters are gone
+                let _ = sender.send(Event { record, error });
+            }
+        }
+    }
+
+    /// Notify the reporters of all queued events and stop the dispatcher thread
+    pub fn shutdown(&self) {
+        if let Ok(mut sender) = self.sender.lock() {
+            sender.take();
+        }
+        let handle = self
+            .dispatcher
+            .lock()
+            .ok()
+            .and_then(|mut dispatcher| dispatcher.take());
+        if let Some(handle) = handle {
+            let _ = handle.join();
+        }
+    }
+}
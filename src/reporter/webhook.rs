@@ -0,0 +1,138 @@
+//! Reporter posting each event as JSON to a configured URL.
+
+use super::Reporter;
+use crate::config::WebhookReporterConfig;
+use crate::error::AppErrorContext;
+use crate::record::ExecutionRecord;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+/// Delay before the first retry; doubled for every further attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Body of a webhook request, discriminated by `event`
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum WebhookPayload<'a> {
+    /// An execution finished, successfully or not
+    ExecutionComplete { record: &'a ExecutionRecord },
+    /// An execution failed
+    Error { error: &'a AppErrorContext },
+}
+
+/// Posts events to a webhook, retrying network errors, 429 and 5xx responses
+pub struct WebhookReporter {
+    /// HTTP client with the configured request timeout
+    client: reqwest::Client,
+    /// Runtime driving the requests; reporters are called outside of it
+    runtime: Handle,
+    /// URL the events are posted to
+    url: String,
+    /// Extra headers sent with every request
+    headers: HeaderMap,
+    /// Attempts per event before it is dropped
+    max_attempts: u32,
+    /// Only post failed executions
+    errors_only: bool,
+}
+
+impl WebhookReporter {
+    /// Create a reporter; must be called from within the tokio runtime
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - URL, headers and delivery settings
+    ///
+    /// # Returns
+    ///
+    /// * `Result<WebhookReporter, String>` - The reporter or a description of what failed
+    pub fn new(config: &WebhookReporterConfig) -> Result<Self, String> {
+        let runtime = Handle::try_current()
+            .map_err(|e| format!("Webhook reporter needs a tokio runtime: {}", e))?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in &config.headers {
+            let header_name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid webhook header name {}", name))?;
+            // The value may be a credential; never echo it
+            let header_value = HeaderValue::from_str(value)
+                .map_err(|_| format!("Invalid webhook header value for {}", name))?;
+            headers.insert(header_name, header_value);
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .map_err(|e| format!("Failed to build webhook client: {}", e))?;
+
+        Ok(WebhookReporter {
+            client,
+            runtime,
+            url: config.url.clone(),
+            headers,
+            max_attempts: config.max_attempts,
+            errors_only: config.errors_only,
+        })
+    }
+
+    /// Deliver one event, blocking the dispatcher thread until it succeeds or is given up
+    fn post(&self, payload: &WebhookPayload) {
+        let body = match serde_json::to_vec(payload) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("Failed to serialize webhook event: {}", e);
+                return;
+            }
+        };
+
+        for attempt in 1..=self.max_attempts {
+            let request = self
+                .client
+                .post(&self.url)
+                .headers(self.headers.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            let failure = match self.runtime.block_on(request.send()) {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response)
+                    if response.status().is_server_error()
+                        || response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    format!("HTTP {}", response.status())
+                }
+                Ok(response) => {
+                    tracing::warn!(
+                        "Webhook {} rejected an event with HTTP {}",
+                        self.url,
+                        response.status()
+                    );
+                    return;
+                }
+                Err(e) => e.to_string(),
+            };
+
+            if attempt == self.max_attempts {
+                tracing::error!(
+                    "Failed to deliver an event to webhook {} after {} attempts: {}",
+                    self.url,
+                    attempt,
+                    failure
+                );
+            } else {
+                std::thread::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt - 1));
+            }
+        }
+    }
+}
+
+impl Reporter for WebhookReporter {
+    fn on_execution_complete(&self, record: &ExecutionRecord) {
+        if !self.errors_only {
+            self.post(&WebhookPayload::ExecutionComplete { record });
+        }
+    }
+
+    fn on_error(&self, err: &AppErrorContext) {
+        self.post(&WebhookPayload::Error { error: err });
+    }
+}
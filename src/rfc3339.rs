@@ -0,0 +1,96 @@
+//! RFC 3339 timestamp helpers for guests.
+//!
+//! `no_std` WebAssembly modules would otherwise hand-roll calendar math to
+//! print a date. Both runtimes format through `format` so guests get the same
+//! UTC string regardless of engine or host time zone. Formatting and parsing
+//! follow chrono: leap seconds (`23:59:60`) parse to the preceding second, and
+//! years past 9999 are formatted with a leading `+`.
+//!
+//! Local times use the request's IANA `timezone` (UTC by default), never the
+//! host's, with offsets from the tz database in chrono-tz.
+
+use chrono::{DateTime, Offset, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Format unix seconds as a UTC RFC 3339 string, e.g. `2024-01-01T00:00:00Z`
+///
+/// # Arguments
+///
+/// * `unix_secs` - Seconds since the Unix epoch
+///
+/// # Returns
+///
+/// * `Option<String>` - The timestamp, or `None` if it is outside chrono's range
+pub fn format(unix_secs: i64) -> Option<String> {
+    DateTime::<Utc>::from_timestamp(unix_secs, 0)
+        .map(|datetime| datetime.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// Parse an RFC 3339 timestamp with any UTC offset into unix seconds
+///
+/// # Arguments
+///
+/// * `timestamp` - Timestamp such as `2024-01-01T05:30:00+05:30`
+///
+/// # Returns
+///
+/// * `Option<i64>` - Seconds since the Unix epoch, or `None` for invalid input
+pub fn parse(timestamp: &str) -> Option<i64> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|datetime| datetime.timestamp())
+}
+
+/// Resolve the IANA time zone named by a request
+///
+/// # Arguments
+///
+/// * `name` - Zone name such as `Europe/Berlin`, or `None` for UTC
+///
+/// # Returns
+///
+/// * `Result<Tz, String>` - The zone, or a message naming the unknown value
+pub fn timezone(name: Option<&str>) -> Result<Tz, String> {
+    match name {
+        Some(name) => name
+            .parse::<Tz>()
+            .map_err(|_| format!("Unknown timezone {:?}; expected an IANA name", name)),
+        None => Ok(Tz::UTC),
+    }
+}
+
+/// Format unix seconds as an RFC 3339 string in a time zone, e.g. `2024-03-31T03:00:00+02:00`
+///
+/// # Arguments
+///
+/// * `unix_secs` - Seconds since the Unix epoch
+/// * `tz` - Zone whose offset the string carries; a zero offset is written as `Z`
+///
+/// # Returns
+///
+/// * `Option<String>` - The timestamp, or `None` if it is outside chrono's range
+pub fn format_local(unix_secs: i64, tz: Tz) -> Option<String> {
+    DateTime::<Utc>::from_timestamp(unix_secs, 0).map(|datetime| {
+        datetime
+            .with_timezone(&tz)
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    })
+}
+
+/// Offset of a time zone from UTC at an instant, in seconds east of UTC
+///
+/// # Arguments
+///
+/// * `unix_millis` - Milliseconds since the Unix epoch
+/// * `tz` - The zone
+///
+/// # Returns
+///
+/// * `Option<i32>` - The offset, or `None` if the instant is outside chrono's range
+pub fn utc_offset_seconds(unix_millis: i64, tz: Tz) -> Option<i32> {
+    DateTime::<Utc>::from_timestamp_millis(unix_millis).map(|datetime| {
+        tz.offset_from_utc_datetime(&datetime.naive_utc())
+            .fix()
+            .local_minus_utc()
+    })
+}
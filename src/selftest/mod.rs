@@ -0,0 +1,293 @@
+//! # Self-test
+//!
+//! Runs a tiny embedded WebAssembly module and JavaScript snippet through the
+//! same executors as `/execute` and checks that every
+//! host capability they exercise produced the expected output. The guests
+//! print fixed marker lines; each capability is checked against its own line
+//! so a failure names exactly what broke.
+
+use crate::executor::{ExecOptions, Execution, Executor, JsExecutor, WasmExecutor};
+use crate::limits::Limits;
+use crate::net::FetchContext;
+use crate::outbound::OutboundPolicy;
+use crate::state::AppState;
+use chrono_tz::Tz;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Self-test WebAssembly module, assembled from `selftest.wat`
+const SELFTEST_WASM: &[u8] = include_bytes!("selftest.wasm");
+
+/// Self-test JavaScript snippet
+const SELFTEST_JS: &str = include_str!("selftest.js");
+
+/// Largest accepted difference between guest and host clocks, in seconds
+const MAX_CLOCK_SKEW_SECS: u64 = 60;
+
+/// Result of one capability check
+#[derive(Serialize, Debug)]
+pub struct CapabilityCheck {
+    /// Name of the host capability, e.g. "app_log"
+    pub capability: &'static str,
+    /// Whether the capability behaved as expected
+    pub passed: bool,
+    /// What went wrong, for failed checks
+    pub message: Option<String>,
+}
+
+/// Self-test results of one engine
+#[derive(Serialize, Debug)]
+pub struct EngineReport {
+    /// "webassembly" or "javascript"
+    pub engine: &'static str,
+    /// Whether every check of this engine passed
+    pub passed: bool,
+    /// Wall-clock time of the engine run, in milliseconds
+    pub duration_ms: u64,
+    /// One entry per exercised capability
+    pub checks: Vec<CapabilityCheck>,
+}
+
+/// Full self-test report
+#[derive(Serialize, Debug)]
+pub struct SelftestReport {
+    /// Whether every check passed
+    pub passed: bool,
+    /// Failed checks as "engine.capability"
+    pub failed: Vec<String>,
+    /// Wall-clock time of the whole self-test, in milliseconds
+    pub duration_ms: u64,
+    /// Per-engine results
+    pub engines: Vec<EngineReport>,
+}
+
+/// Run the self-test against both engines
+///
+/// # Arguments
+///
+/// * `state` - Shared service state; the guests use its engines, clients and limits
+/// * `fetch_url` - URL the JavaScript guest fetches to check `hoyaFetch`, if any
+///
+/// # Returns
+///
+/// * `SelftestReport` - Pass/fail result per engine and capability
+pub async fn run(state: &AppState, fetch_url: Option<&str>) -> SelftestReport {
+    let start_time = Instant::now();
+
+    let wasm = run_engine(state, "webassembly", "WASM", false, |fetch_ctx| {
+        let code = bytes::Bytes::from_static(SELFTEST_WASM);
+        let limits = Limits::defaults(&state.config);
+        WasmExecutor::new(state.wasm.clone())
+            .execute(code.clone(), options(state, &code, fetch_ctx, limits))
+    })
+    .await;
+
+    let js = run_engine(
+        state,
+        "javascript",
+        "JS",
+        fetch_url.is_some(),
+        |fetch_ctx| {
+            let source = match fetch_url {
+                Some(url) => format!("var SELFTEST_FETCH_URL = {:?};\n{}", url, SELFTEST_JS),
+                None => SELFTEST_JS.to_string(),
+            };
+            let code = bytes::Bytes::from(source);
+            let mut limits = Limits::defaults(&state.config);
+            limits.memory_bytes = state.config.js.default_memory_bytes;
+            let executor = JsExecutor {
+                runtimes: state.js_runtimes.clone(),
+                ..JsExecutor::new(&state.config.js)
+            };
+            executor.execute(code.clone(), options(state, &code, fetch_ctx, limits))
+        },
+    )
+    .await;
+
+    let engines = vec![wasm, js];
+    let failed: Vec<String> = engines
+        .iter()
+        .flat_map(|engine| {
+            engine
+                .checks
+                .iter()
+                .filter(|check| !check.passed)
+                .map(move |check| format!("{}.{}", engine.engine, check.capability))
+        })
+        .collect();
+
+    SelftestReport {
+        passed: failed.is_empty(),
+        failed,
+        duration_ms: start_time.elapsed().as_millis() as u64,
+        engines,
+    }
+}
+
+/// Options of a self-test execution: no URL, no storage, an isolated key-value
+/// namespace, UTC and a fresh output budget
+fn options(state: &AppState, code: &[u8], fetch_ctx: FetchContext, limits: Limits) -> ExecOptions {
+    ExecOptions {
+        sha256: format!("{:x}", Sha256::digest(code)),
+        url: None,
+        fetch_ctx,
+        output_budget: Arc::new(limits.output_budget(false, state.config.capture.echo_to_host)),
+        deterministic: None,
+        timezone: Tz::UTC,
+        limits,
+        storage: None,
+        kv: state.kv.isolated(),
+        env: Arc::default(),
+        input: None,
+        compat: None,
+        stub_missing_imports: false,
+        lossy_app_log: false,
+    }
+}
+
+/// Run one guest and check its captured stdout
+///
+/// # Arguments
+///
+/// * `state` - Shared service state
+/// * `engine` - Engine name used in the report
+/// * `log_tag` - Engine tag in `app_log` lines ("WASM" or "JS")
+/// * `check_fetch` - Whether the guest was asked to fetch over loopback
+/// * `execute` - Starts the guest with a fresh fetch context
+async fn run_engine(
+    state: &AppState,
+    engine: &'static str,
+    log_tag: &str,
+    check_fetch: bool,
+    execute: impl FnOnce(FetchContext) -> Execution,
+) -> EngineReport {
+    let start_time = Instant::now();
+    // The embedded guests only fetch this server, which the outbound policy would refuse
+    let fetch_ctx = FetchContext::new(
+        state.fetch_client.clone(),
+        state.fetch_runtime.clone(),
+        Arc::new(OutboundPolicy::unrestricted()),
+        false,
+        state.metrics.clone(),
+    );
+    let result = execute(fetch_ctx).await;
+    let duration_ms = start_time.elapsed().as_millis() as u64;
+
+    let stdout = match result {
+        Ok(result) => result.stdout,
+        Err(e) => {
+            // Nothing below can be judged without a completed run
+            let mut checks = vec![CapabilityCheck::fail(
+                "execute",
+                format!("{} execution failed: {}", engine, e),
+            )];
+            checks.extend(
+                capabilities(check_fetch)
+                    .into_iter()
+                    .map(|capability| CapabilityCheck::fail(capability, "not run".to_string())),
+            );
+            return EngineReport {
+                engine,
+                passed: false,
+                duration_ms,
+                checks,
+            };
+        }
+    };
+    let lines: Vec<&str> = stdout.lines().collect();
+
+    let mut checks = vec![CapabilityCheck::pass("execute")];
+    checks.push(expect_line(
+        "app_log",
+        &lines,
+        &format!("[{} LOG - INFO]: selftest log", log_tag),
+    ));
+    checks.push(expect_line("stdout", &lines, "selftest stdout"));
+    checks.push(check_unixtime(&lines));
+    if check_fetch {
+        checks.push(expect_line("fetch", &lines, "selftest fetch 200"));
+    }
+
+    EngineReport {
+        engine,
+        passed: checks.iter().all(|check| check.passed),
+        duration_ms,
+        checks,
+    }
+}
+
+/// Capabilities checked after the guest ran
+fn capabilities(check_fetch: bool) -> Vec<&'static str> {
+    let mut capabilities = vec!["app_log", "stdout", "get_unixtime"];
+    if check_fetch {
+        capabilities.push("fetch");
+    }
+    capabilities
+}
+
+/// Check that the guest printed `expected` as a line of its own
+fn expect_line(capability: &'static str, lines: &[&str], expected: &str) -> CapabilityCheck {
+    if lines.contains(&expected) {
+        CapabilityCheck::pass(capability)
+    } else {
+        CapabilityCheck::fail(
+            capability,
+            format!("expected the line {:?} in captured stdout", expected),
+        )
+    }
+}
+
+/// Check that the guest saw a unix time close to the host's
+fn check_unixtime(lines: &[&str]) -> CapabilityCheck {
+    let reported = lines
+        .iter()
+        .find_map(|line| line.strip_prefix("selftest unixtime "));
+    let Some(reported) = reported else {
+        return CapabilityCheck::fail(
+            "get_unixtime",
+            "the guest printed no \"selftest unixtime\" line".to_string(),
+        );
+    };
+    let Ok(guest_secs) = reported.parse::<u64>() else {
+        return CapabilityCheck::fail(
+            "get_unixtime",
+            format!("the guest reported a malformed time {:?}", reported),
+        );
+    };
+    let host_secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+    if guest_secs.abs_diff(host_secs) > MAX_CLOCK_SKEW_SECS {
+        return CapabilityCheck::fail(
+            "get_unixtime",
+            format!(
+                "the guest reported {} but the host clock reads {}",
+                guest_secs, host_secs
+            ),
+        );
+    }
+    CapabilityCheck::pass("get_unixtime")
+}
+
+impl CapabilityCheck {
+    /// A passed check
+    fn pass(capability: &'static str) -> Self {
+        CapabilityCheck {
+            capability,
+            passed: true,
+            message: None,
+        }
+    }
+
+    /// A failed check with the reason
+    fn fail(capability: &'static str, message: String) -> Self {
+        CapabilityCheck {
+            capability,
+            passed: false,
+            message: Some(message),
+        }
+    }
+}
@@ -0,0 +1,12 @@
+// Self-test guest for the JavaScript engine. When the host prepends a
+// SELFTEST_FETCH_URL binding, it also fetches that URL over loopback.
+app_log("info", "selftest log");
+console.log("selftest stdout");
+console.log("selftest unixtime " + Math.floor(get_unixtime()));
+if (typeof SELFTEST_FETCH_URL !== "undefined") {
+  const response = JSON.parse(
+    hoyaFetch(JSON.stringify({ url: SELFTEST_FETCH_URL, method: "GET", headers: {} }))
+  );
+  console.log("selftest fetch " + (response.error ? response.error.code : response.status));
+}
+"selftest complete";
@@ -0,0 +1,39 @@
+;; Self-test guest for the WebAssembly engine, assembled into selftest.wasm
+;; with `wat2wasm selftest.wat` (or any equivalent WAT assembler).
+;;
+;; Writes one app_log line, one plain stdout line and the host's unix time
+;; as "selftest unixtime <seconds>".
+(module
+  (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+  (import "env" "capture_stdout" (func $capture_stdout (param i32 i32)))
+  (import "env" "get_unixtime" (func $get_unixtime (result i64)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "info")
+  (data (i32.const 16) "selftest log")
+  (data (i32.const 32) "selftest stdout")
+  (data (i32.const 64) "selftest unixtime ")
+  (func (export "_start")
+    (local $time i64)
+    (local $pos i32)
+    (call $app_log (i32.const 0) (i32.const 4) (i32.const 16) (i32.const 12))
+    (call $capture_stdout (i32.const 32) (i32.const 15))
+    ;; Format the time right to left, ending at offset 128
+    (local.set $time (call $get_unixtime))
+    (local.set $pos (i32.const 128))
+    (loop $digits
+      (local.set $pos (i32.sub (local.get $pos) (i32.const 1)))
+      (i32.store8
+        (local.get $pos)
+        (i32.add
+          (i32.const 48)
+          (i32.wrap_i64 (i64.rem_u (local.get $time) (i64.const 10)))))
+      (local.set $time (i64.div_u (local.get $time) (i64.const 10)))
+      (br_if $digits (i64.ne (local.get $time) (i64.const 0))))
+    ;; Move the digits right behind the prefix (offset 82) and emit the line
+    (memory.copy
+      (i32.const 82)
+      (local.get $pos)
+      (i32.sub (i32.const 128) (local.get $pos)))
+    (call $capture_stdout
+      (i32.const 64)
+      (i32.sub (i32.const 146) (local.get $pos)))))
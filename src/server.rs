@@ -0,0 +1,1555 @@
+//! # HTTP server
+//!
+//! Request handlers and the router serving them. `router` is all a binary
+//! needs besides the configuration and the shared state; the handlers
+//! download the code, pick the engine and turn its `ExecutionResult` into
+//! the execute response of the requested version and encoding.
+
+use crate::admission::AdmissionStatus;
+use crate::auth::{ApiKey, KeySlot};
+use crate::cancel::{self, CancelOutcome, CancelToken, RunningSummary};
+use crate::circuit;
+use crate::compression;
+use crate::detect::{self, CodeType};
+use crate::download;
+use crate::download_cache::DownloadCacheStatus;
+use crate::error::{AppError, AppErrorContext, ExecuteResponse, ExecutionResult};
+use crate::executor::{self, engine_failure, ExecOptions};
+use crate::guest_env::GuestEnv;
+use crate::history::{HistoryRecord, Outcome, ReplayDiff};
+use crate::jobs::JobRecord;
+use crate::kv;
+use crate::limits::{Limits, RequestedLimits};
+use crate::live::{self, LiveEvent, LiveSender};
+use crate::manifest::{self, AppliedManifest, Manifest, Permission};
+use crate::negotiate::ResponseFormat;
+use crate::net::FetchContext;
+use crate::random::DeterministicMode;
+use crate::record::{self, ExecutionRecord};
+use crate::registry::{self, ModuleSummary, RegisterRequest, RegisteredModule};
+use crate::rfc3339;
+use crate::selftest::{self, SelftestReport};
+use crate::state::AppState;
+use crate::storage::{self, EntrySummary, Usage};
+use crate::telemetry;
+use crate::warmup::{self, WarmupOutcome};
+use crate::wasm_engine;
+use crate::wat;
+use crate::wire::{self, ResponseVersion};
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::OwnedSemaphorePermit;
+use tracing::Instrument;
+
+/// Build the router serving every endpoint from the shared state
+///
+/// # Arguments
+///
+/// * `state` - Shared application state, built from a validated configuration
+///
+/// # Returns
+///
+/// * `Router` - The routes, ready for `axum::serve`
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/execute", post(execute_handler))
+        .route("/execute/stream", post(execute_stream_handler))
+        .route("/jobs", post(submit_job_handler))
+        .route("/jobs/:id", get(job_handler))
+        .route("/executions/running", get(running_handler))
+        .route("/executions/:id", get(execution_handler))
+        .route("/executions/:id/replay", post(replay_handler))
+        .route("/executions/:id/cancel", post(cancel_handler))
+        .route("/selftest", post(selftest_handler))
+        .route("/warmup", post(warmup_handler))
+        .route(
+            "/modules",
+            post(register_module_handler).get(list_modules_handler),
+        )
+        .route("/modules/:name", delete(delete_module_handler))
+        .route("/inspect", post(inspect_handler))
+        .route("/health", get(health_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/stats", get(stats_handler))
+        .route("/status", get(status_handler))
+        .route("/admin/circuits/reset", post(circuit_reset_handler))
+        .route(
+            "/admin/storage/:namespace",
+            get(storage_namespace_handler).delete(storage_clear_handler),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_api_key,
+        ))
+        .with_state(state)
+}
+
+/// Code type detection strategy for URLs without a recognized extension
+///
+/// Payloads are always probed when nothing else names their type; the field
+/// is still accepted so existing requests keep working.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum DetectMode {
+    /// Probe the downloaded payload: wasm magic bytes first, then a JS syntax check
+    Auto,
+}
+
+/// Request payload for the execute endpoint
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExecuteRequest {
+    /// URL pointing to JavaScript or WebAssembly code to execute; unset when `module` is given
+    #[serde(default)]
+    pub url: String,
+    /// Name of a module registered through `POST /modules`, run without downloading it
+    pub module: Option<String>,
+    /// Type of the code, taking precedence over the URL, `Content-Type` and payload
+    pub code_type: Option<CodeType>,
+    /// Former opt-in to content probing, which now always applies
+    pub detect: Option<DetectMode>,
+    /// Keep a cookie jar for guest fetch calls, scoped to this execution
+    #[serde(default)]
+    pub cookies: bool,
+    /// Hex SHA-256 digest the code must have once downloaded (and decompressed)
+    pub sha256: Option<String>,
+    /// Check `sha256` against the artifact as downloaded, before a `.wasm.gz`
+    /// is decompressed
+    #[serde(default)]
+    pub sha256_compressed: bool,
+    /// Extra headers for the code download request (e.g. registry credentials),
+    /// named in `download.forward_headers`
+    pub download_headers: Option<HashMap<String, String>>,
+    /// Resource limits of the execution, at most the server's caps
+    #[serde(default)]
+    pub limits: RequestedLimits,
+    /// Deprecated alias of `limits.timeout_ms`
+    pub timeout_ms: Option<u64>,
+    /// Deprecated alias of `limits.memory_bytes`
+    pub max_memory_bytes: Option<u64>,
+    /// Hosts guest fetch calls may go to; unset allows any host
+    pub fetch_allowlist: Option<Vec<String>>,
+    /// Capabilities granted on top of the defaults; `storage` opens the module's namespace
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    /// Shared key-value namespace of the guest's `kv_*` imports and `hoya.kv`;
+    /// unset gives the execution a namespace of its own
+    pub kv_namespace: Option<String>,
+    /// Deprecated alias of `limits.max_output_bytes`
+    pub max_output_bytes: Option<u64>,
+    /// Fail with `OUTPUT_BUDGET_EXCEEDED` instead of dropping output once the budget is spent
+    pub strict_output: Option<bool>,
+    /// Draw all guest randomness from a stream seeded with `seed`
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Seed for deterministic mode (defaults to 0)
+    pub seed: Option<u64>,
+    /// IANA time zone of the guest's local-time functions (defaults to UTC)
+    pub timezone: Option<String>,
+    /// Variables exposed to the guest as `env`, within the server's `execution.max_env_*` limits
+    pub env: Option<HashMap<String, String>>,
+    /// Data handed to the guest: the `input` global in JavaScript, `input_read` in WebAssembly
+    pub input: Option<serde_json::Value>,
+    /// Shim the host imports of the toolchain that built a WebAssembly module;
+    /// ignored for scripts
+    pub compat: Option<wasm_engine::Compat>,
+    /// Define missing function imports of a WebAssembly module as placeholders
+    /// failing with `STUBBED_IMPORT_CALLED` when called, rather than refusing it
+    #[serde(default)]
+    pub stub_missing_imports: bool,
+    /// Replace invalid UTF-8 in a WebAssembly module's `app_log` text instead
+    /// of failing the execution; ignored for scripts
+    #[serde(default)]
+    pub lossy_app_log: bool,
+    /// Response body version; overrides the `X-Hoya-Response-Version` header
+    pub response_version: Option<u32>,
+    /// Stream the JSON response in chunks, with the output fields last
+    #[serde(default)]
+    pub stream: bool,
+}
+
+impl ExecuteRequest {
+    /// Where the request's code comes from: its URL, or `module:<name>` for a registered module
+    pub fn source(&self) -> String {
+        match &self.module {
+            Some(name) => format!("module:{}", name),
+            None => self.url.clone(),
+        }
+    }
+}
+
+/// Handler for the /execute endpoint
+///
+/// This function handles POST requests to the /execute endpoint. It downloads
+/// and executes code from the provided URL, and returns the execution result.
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `headers` - Request headers; a `traceparent` joins the execution to the
+///   caller's trace, `X-Hoya-Response-Version` selects the response version
+///   and `Accept` the encoding
+/// * `payload` - JSON payload containing a URL to code to execute
+///
+/// # Returns
+///
+/// * `Response` - Execution result, an error response carrying the code
+///   digest when the code had already been obtained, or `SERVER_BUSY` with a
+///   `Retry-After` header when no execution slot became free in time
+async fn execute_handler(
+    State(state): State<Arc<AppState>>,
+    caller: Option<Extension<Arc<ApiKey>>>,
+    headers: HeaderMap,
+    Json(payload): Json<ExecuteRequest>,
+) -> Response {
+    let format = ResponseFormat::from_accept(&headers);
+    let version = match ResponseVersion::resolve(
+        headers.get(wire::RESPONSE_VERSION_HEADER),
+        payload.response_version,
+    ) {
+        Ok(version) => version,
+        Err(e) => {
+            let (status_code, response) = e.into_execute_response(|_| {});
+            return format.render(status_code, ResponseVersion::V1, false, response);
+        }
+    };
+    let caller = caller.map(|Extension(key)| key);
+    let _slots = match admit(&state, caller.as_ref()).await {
+        Ok(slots) => slots,
+        Err(e) => {
+            let retry_after_secs = retry_after_secs(&state, &e);
+            let (status_code, response) = e.into_execute_response(|_| {});
+            return with_retry_after_header(
+                retry_after_secs,
+                format.render(status_code, version, false, response),
+            );
+        }
+    };
+    let streamed = payload.stream;
+    let span = telemetry::execution_span(&headers);
+    let api_key = caller.as_ref().map(|key| key.name.as_str());
+    let (status_code, response) = run_execution(&state, payload, None, span, None, api_key).await;
+    let execution_id = response.metadata.execution_id.clone();
+    let rendered = with_trace_id_header(
+        response.metadata.trace_id.clone(),
+        format.render(status_code, version, streamed, response),
+    );
+    with_execution_id_header(execution_id, rendered)
+}
+
+/// Handler for the /execute/stream endpoint
+///
+/// Takes the same body as `/execute` and answers with Server-Sent Events:
+/// the guest's lines as `stdout`, `stderr` and `log` events while it runs,
+/// then a `result` event holding the execute response in the requested
+/// version. The request's `stream` flag and the `Accept` header do not
+/// apply. Disconnecting before the `result` event cancels the execution.
+async fn execute_stream_handler(
+    State(state): State<Arc<AppState>>,
+    caller: Option<Extension<Arc<ApiKey>>>,
+    headers: HeaderMap,
+    Json(payload): Json<ExecuteRequest>,
+) -> Response {
+    let version = match ResponseVersion::resolve(
+        headers.get(wire::RESPONSE_VERSION_HEADER),
+        payload.response_version,
+    ) {
+        Ok(version) => version,
+        Err(e) => return e.into_response(),
+    };
+    let caller = caller.map(|Extension(key)| key);
+    let slots = match admit(&state, caller.as_ref()).await {
+        Ok(slots) => slots,
+        Err(e) => return with_retry_after_header(retry_after_secs(&state, &e), e.into_response()),
+    };
+    let span = telemetry::execution_span(&headers);
+    let (sender, events) = tokio::sync::mpsc::unbounded_channel();
+
+    // The execution outlives this handler, which returns as soon as the stream starts
+    tokio::spawn(async move {
+        let _slots = slots;
+        let api_key = caller.as_ref().map(|key| key.name.as_str());
+        let live = Some(sender.clone());
+        let (_, response) = run_execution(&state, payload, None, span, live, api_key).await;
+        let json = serde_json::to_string(&version.wire(response)).unwrap_or_default();
+        let _ = sender.send(LiveEvent::Result(json));
+    });
+
+    live::sse(events).into_response()
+}
+
+/// Slots an execution holds until it finishes: its API key's and the server's
+struct ExecutionSlots {
+    /// Counts the execution against the key's `max_concurrent`, if it has a key
+    _key: Option<KeySlot>,
+    /// One of the `execution.max_concurrent` slots
+    _server: OwnedSemaphorePermit,
+}
+
+/// Take the slots an execution needs, the API key's first so it is refused without waiting
+///
+/// # Returns
+///
+/// * `Result<ExecutionSlots, AppError>` - The slots, `QuotaExceeded` when the
+///   caller's key runs its `max_concurrent` executions already, or `ServerBusy`
+async fn admit(state: &AppState, caller: Option<&Arc<ApiKey>>) -> Result<ExecutionSlots, AppError> {
+    let key = caller.map(|key| key.start_execution()).transpose()?;
+    let server = state.admission.admit(&state.metrics).await?;
+    Ok(ExecutionSlots {
+        _key: key,
+        _server: server,
+    })
+}
+
+/// Seconds a refused client should wait before retrying, when the refusal tells
+fn retry_after_secs(state: &AppState, error: &AppError) -> Option<u64> {
+    match error {
+        AppError::ServerBusy { .. } => Some(state.admission.retry_after_secs()),
+        AppError::QuotaExceeded {
+            retry_after_secs, ..
+        } => *retry_after_secs,
+        _ => None,
+    }
+}
+
+/// Add the `Retry-After` header to a refused response
+fn with_retry_after_header(retry_after_secs: Option<u64>, mut response: Response) -> Response {
+    if let Some(secs) = retry_after_secs {
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    }
+    response
+}
+
+/// Middleware refusing requests without a configured API key
+///
+/// Requests for `auth.public_paths`, and all requests when no key is
+/// configured, pass through. Others must carry a key within its
+/// `requests_per_minute`; the key is handed to the handlers as an
+/// `Extension<Arc<ApiKey>>`.
+async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !state.auth.required(request.uri().path()) {
+        return next.run(request).await;
+    }
+    match state.auth.authenticate(request.headers()) {
+        Ok(key) => {
+            request.extensions_mut().insert(key);
+            next.run(request).await
+        }
+        Err(e @ AppError::Unauthorized(_)) => {
+            let mut response = e.into_response();
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            response
+        }
+        Err(e) => with_retry_after_header(retry_after_secs(&state, &e), e.into_response()),
+    }
+}
+
+/// Add the `X-Hoya-Execution-Id` header to a response
+fn with_execution_id_header(execution_id: Option<String>, mut response: Response) -> Response {
+    if let Some(value) = execution_id.and_then(|id| HeaderValue::from_str(&id).ok()) {
+        response
+            .headers_mut()
+            .insert(cancel::EXECUTION_ID_HEADER, value);
+    }
+    response
+}
+
+/// Add the `X-Hoya-Trace-Id` header to a response when the execution was traced
+fn with_trace_id_header(trace_id: Option<String>, mut response: Response) -> Response {
+    if let Some(value) = trace_id.and_then(|id| HeaderValue::from_str(&id).ok()) {
+        response
+            .headers_mut()
+            .insert(telemetry::TRACE_ID_HEADER, value);
+    }
+    response
+}
+
+/// Response body of the replay endpoint
+#[derive(Serialize)]
+struct ReplayResponse {
+    /// Execution that was replayed
+    replay_of: String,
+    /// Comparison of the replay with the original outcome
+    diff: ReplayDiff,
+    /// The replay's own execution result
+    result: ExecuteResponse,
+}
+
+/// Handler for the /executions/{id} endpoint
+///
+/// Returns the history record of a recent execution.
+async fn execution_handler(
+    State(state): State<Arc<AppState>>,
+    Path(execution_id): Path<String>,
+) -> Result<Json<HistoryRecord>, AppError> {
+    find_execution(&state, &execution_id).map(Json)
+}
+
+/// Handler for the /executions/{id}/replay endpoint
+///
+/// Runs a recorded execution again with its original request and compares
+/// the outcome with the recorded one. The code must still be downloadable
+/// and hash to the recorded digest, otherwise the replay fails with
+/// `REPLAY_SOURCE_UNAVAILABLE`.
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `execution_id` - Identifier of the execution to replay
+/// * `headers` - Request headers; a `traceparent` joins the replay to the caller's trace
+///
+/// # Returns
+///
+/// * `Response` - The replay result with its diff, or an error response
+async fn replay_handler(
+    State(state): State<Arc<AppState>>,
+    Path(execution_id): Path<String>,
+    caller: Option<Extension<Arc<ApiKey>>>,
+    headers: HeaderMap,
+) -> Response {
+    let original = match find_execution(&state, &execution_id) {
+        Ok(original) => original,
+        Err(e) => return e.into_response(),
+    };
+    let caller = caller.map(|Extension(key)| key);
+    let _slots = match admit(&state, caller.as_ref()).await {
+        Ok(slots) => slots,
+        Err(e) => return with_retry_after_header(retry_after_secs(&state, &e), e.into_response()),
+    };
+
+    let span = telemetry::execution_span(&headers);
+    let (status_code, response) = run_execution(
+        &state,
+        original.request.clone(),
+        Some(&original),
+        span,
+        None,
+        caller.as_ref().map(|key| key.name.as_str()),
+    )
+    .await;
+    let trace_id = response.metadata.trace_id.clone();
+    if response.error.as_ref().map(|error| error.code.as_str()) == Some("REPLAY_SOURCE_UNAVAILABLE")
+    {
+        return with_trace_id_header(trace_id, (status_code, Json(response)).into_response());
+    }
+
+    let diff = ReplayDiff::between(
+        &original.outcome,
+        &Outcome::from_response(status_code.as_u16(), &response),
+    );
+    let body = ReplayResponse {
+        replay_of: execution_id,
+        diff,
+        result: response,
+    };
+    with_trace_id_header(trace_id, (status_code, Json(body)).into_response())
+}
+
+/// Response body of the running executions endpoint
+#[derive(Serialize)]
+struct RunningResponse {
+    /// Executions in progress, longest running first
+    executions: Vec<RunningSummary>,
+}
+
+/// Handler for the /executions/running endpoint
+///
+/// Lists the executions in progress, whose ids can be cancelled.
+async fn running_handler(State(state): State<Arc<AppState>>) -> Json<RunningResponse> {
+    Json(RunningResponse {
+        executions: state.executions.running(),
+    })
+}
+
+/// Handler for the /executions/{id}/cancel endpoint
+///
+/// Asks a running execution to stop. The engine notices within an epoch
+/// tick (WebAssembly) or a few thousand instructions (JavaScript), once any
+/// host call in progress returns; the execution's own response then fails
+/// with `EXECUTION_CANCELLED`.
+///
+/// # Returns
+///
+/// * `Result<(StatusCode, Json<serde_json::Value>), AppError>` - 202 once the
+///   execution was asked to stop, `NotFound` for an unknown id and
+///   `ExecutionFinished` for an execution that already ended
+async fn cancel_handler(
+    State(state): State<Arc<AppState>>,
+    Path(execution_id): Path<String>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    match state.executions.cancel(&execution_id) {
+        CancelOutcome::Cancelled => Ok((
+            StatusCode::ACCEPTED,
+            Json(serde_json::json!({ "execution_id": execution_id, "cancelled": true })),
+        )),
+        CancelOutcome::Finished => Err(AppError::ExecutionFinished(format!(
+            "Execution {} has already finished",
+            execution_id
+        ))),
+        CancelOutcome::Unknown => Err(AppError::NotFound(format!(
+            "Unknown execution: {}",
+            execution_id
+        ))),
+    }
+}
+
+/// Look up a recent execution in the history
+fn find_execution(state: &AppState, execution_id: &str) -> Result<HistoryRecord, AppError> {
+    state
+        .history
+        .as_ref()
+        .and_then(|history| history.get(execution_id))
+        .ok_or_else(|| AppError::NotFound(format!("Unknown execution: {}", execution_id)))
+}
+
+/// Handler for the /jobs endpoint
+///
+/// Accepts the same body as `/execute`, queues the execution on a background
+/// task and answers at once with the job's id. The job's result is read with
+/// `GET /jobs/{id}`, in the response version the request selected; `stream`
+/// and the `Accept` header do not apply. Once one of the `jobs.max_concurrent`
+/// slots is free, the job is admitted like an `/execute` request; a refusal
+/// completes it with the error response.
+///
+/// # Returns
+///
+/// * `Result<(StatusCode, Json<serde_json::Value>), AppError>` - 202 with the
+///   queued job, or `JobQueueFull` when `jobs.max_queued` jobs are pending
+async fn submit_job_handler(
+    State(state): State<Arc<AppState>>,
+    caller: Option<Extension<Arc<ApiKey>>>,
+    headers: HeaderMap,
+    Json(payload): Json<ExecuteRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let version = ResponseVersion::resolve(
+        headers.get(wire::RESPONSE_VERSION_HEADER),
+        payload.response_version,
+    )?;
+    let job_id = state.jobs.submit()?;
+    let span = telemetry::execution_span(&headers);
+
+    let task_state = state.clone();
+    let task_job_id = job_id.clone();
+    let caller = caller.map(|Extension(key)| key);
+    tokio::spawn(async move {
+        let _slot = task_state.jobs.wait_for_slot().await;
+        task_state.jobs.start(&task_job_id);
+        // A job runs under the same key and server slots as an /execute request
+        let (status_code, response) = match admit(&task_state, caller.as_ref()).await {
+            Ok(_slots) => {
+                let api_key = caller.as_ref().map(|key| key.name.as_str());
+                run_execution(&task_state, payload, None, span, None, api_key).await
+            }
+            Err(e) => e.into_execute_response(|_| {}),
+        };
+        let result = serde_json::to_value(version.wire(response)).unwrap_or_default();
+        task_state
+            .jobs
+            .complete(&task_job_id, status_code.as_u16(), result);
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "job_id": job_id, "status": "queued" })),
+    ))
+}
+
+/// Handler for the /jobs/{id} endpoint
+///
+/// Reports a job's state, with the execute response once it has completed.
+async fn job_handler(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Result<Json<JobRecord>, AppError> {
+    state
+        .jobs
+        .get(&job_id)
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("Unknown job: {}", job_id)))
+}
+
+/// Run an execution and record it in the audit log and history
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `payload` - The execute request
+/// * `replay_of` - The recorded execution this run replays, if any
+/// * `span` - Root span of the execution, from `telemetry::execution_span`
+/// * `live` - Stream captured lines are published to; the execution is
+///   cancelled once its receiver is gone
+/// * `api_key` - Name of the API key the request was made with, if any
+///
+/// # Returns
+///
+/// * `(StatusCode, ExecuteResponse)` - HTTP status and body of the response
+async fn run_execution(
+    state: &AppState,
+    payload: ExecuteRequest,
+    replay_of: Option<&HistoryRecord>,
+    span: tracing::Span,
+    live: Option<LiveSender>,
+    api_key: Option<&str>,
+) -> (StatusCode, ExecuteResponse) {
+    let started = std::time::Instant::now();
+    let execution_id = uuid::Uuid::new_v4().to_string();
+    let request = payload.clone();
+    span.record("execution_id", execution_id.as_str());
+    if let Some(api_key) = api_key {
+        span.record("api_key", api_key);
+    }
+    let registration = state
+        .executions
+        .register(&execution_id, record::redact_url(&request.source()));
+    let in_flight = state.metrics.start_execution();
+    if let Some(live) = &live {
+        // A streaming client that goes away cancels its execution
+        let (live, token) = (live.clone(), registration.token.clone());
+        tokio::spawn(async move {
+            live.closed().await;
+            token.cancel();
+        });
+    }
+
+    let pinned_sha256 = replay_of.and_then(|original| original.outcome.code_sha256.as_deref());
+    let mut code_sha256 = None;
+    let cancel = registration.token.clone();
+    let (status_code, mut response) = match execute(
+        state,
+        payload,
+        pinned_sha256,
+        &mut code_sha256,
+        cancel,
+        live,
+    )
+    .instrument(span.clone())
+    .await
+    {
+        Ok(result) => (StatusCode::OK, result.into()),
+        Err(e) => e.into_execute_response(|metadata| metadata.code_sha256 = code_sha256),
+    };
+    drop(registration);
+    drop(in_flight);
+    // The execution duration histogram keeps measuring the engine, not the download
+    let engine_time = response
+        .metadata
+        .execution_time
+        .saturating_sub(response.metadata.download_ms.unwrap_or(0));
+    state.metrics.observe_execution(
+        &response.metadata.code_type,
+        &response.status,
+        std::time::Duration::from_millis(engine_time),
+    );
+    response.metadata.execution_id = Some(execution_id.clone());
+    response.metadata.api_key = api_key.map(str::to_string);
+    response.metadata.trace_id = telemetry::trace_id(&span);
+    span.record("http.response.status_code", status_code.as_u16());
+    if response.error.is_some() {
+        span.record("otel.status_code", "ERROR");
+    }
+
+    let record = ExecutionRecord::new(
+        &execution_id,
+        &request.source(),
+        status_code.as_u16(),
+        &response,
+        started.elapsed(),
+    );
+    if let Some(reporting) = &state.reporting {
+        reporting.report(
+            record.clone(),
+            AppErrorContext::from_response(&record, &response),
+        );
+    }
+    if let Some(audit) = &state.audit {
+        audit.record(record);
+    }
+    if let Some(history) = &state.history {
+        history.insert(HistoryRecord::new(
+            execution_id,
+            request,
+            Outcome::from_response(status_code.as_u16(), &response),
+            replay_of.map(|original| original.execution_id.clone()),
+        ));
+    }
+
+    (status_code, response)
+}
+
+/// Serialize a request's `input` into the JSON handed to the guest
+///
+/// # Arguments
+///
+/// * `input` - The request's `input`
+/// * `max_bytes` - `execution.max_input_bytes`
+///
+/// # Returns
+///
+/// * `Result<bytes::Bytes, AppError>` - The JSON document, or `InvalidRequest` when it is too large
+fn serialize_input(input: &serde_json::Value, max_bytes: usize) -> Result<bytes::Bytes, AppError> {
+    let json = serde_json::to_vec(input)
+        .map_err(|e| AppError::Internal(format!("Failed to serialize input: {}", e)))?;
+    if json.len() > max_bytes {
+        return Err(AppError::InvalidRequest(format!(
+            "input is {} bytes of JSON, more than {}",
+            json.len(),
+            max_bytes
+        )));
+    }
+    Ok(bytes::Bytes::from(json))
+}
+
+/// Download and execute the code named by an execute request
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `payload` - The execute request
+/// * `pinned_sha256` - Digest the code must have (when replaying); a failed
+///   download or a different digest is `ReplaySourceUnavailable`
+/// * `code_sha256` - Set to the digest of the code as soon as it is known
+/// * `cancel` - Token that stops the engine once set
+/// * `live` - Stream captured lines are published to, if the execution is streamed
+///
+/// # Returns
+///
+/// * `Result<ExecutionResult, AppError>` - Execution result or error
+async fn execute(
+    state: &AppState,
+    payload: ExecuteRequest,
+    pinned_sha256: Option<&str>,
+    code_sha256: &mut Option<String>,
+    cancel: CancelToken,
+    live: Option<LiveSender>,
+) -> Result<ExecutionResult, AppError> {
+    tracing::info!(url = %record::redact_url(&payload.source()), "Received execute request");
+
+    let aliases = RequestedLimits {
+        timeout_ms: payload.timeout_ms,
+        memory_bytes: payload.max_memory_bytes,
+        max_output_bytes: payload.max_output_bytes,
+        ..RequestedLimits::default()
+    };
+    let mut limits = Limits::resolve(&state.config, &payload.limits, &aliases)?;
+    let requested_limits = payload.limits.or(&aliases);
+    let fetch_allowlist = payload
+        .fetch_allowlist
+        .as_deref()
+        .map(|hosts| manifest::normalize_hosts("fetch_allowlist", hosts))
+        .transpose()
+        .map_err(AppError::InvalidRequest)?;
+    let storage_requested = payload.permissions.contains(&Permission::Storage);
+    if storage_requested && state.storage.is_none() {
+        return Err(AppError::InvalidRequest(
+            "The storage permission is not available: storage is not enabled on this server"
+                .to_string(),
+        ));
+    }
+    if let Some(name) = &payload.kv_namespace {
+        kv::validate_namespace(name).map_err(AppError::InvalidRequest)?;
+    }
+    let expected_sha256 = payload
+        .sha256
+        .as_deref()
+        .map(download::parse_sha256)
+        .transpose()?;
+    if payload.sha256_compressed && expected_sha256.is_none() {
+        return Err(AppError::InvalidRequest(
+            "sha256_compressed requires sha256".to_string(),
+        ));
+    }
+    let strict_output = payload
+        .strict_output
+        .unwrap_or(state.config.execution.strict_output);
+    let output_budget = Arc::new(
+        limits
+            .output_budget(strict_output, state.config.capture.echo_to_host)
+            .with_live(live),
+    );
+    if payload.seed.is_some() && !payload.deterministic {
+        return Err(AppError::InvalidRequest(
+            "seed requires deterministic: true".to_string(),
+        ));
+    }
+    let timezone =
+        rfc3339::timezone(payload.timezone.as_deref()).map_err(AppError::InvalidRequest)?;
+    let env = Arc::new(GuestEnv::new(
+        payload.env.as_ref(),
+        &state.config.execution,
+    )?);
+    let input = payload
+        .input
+        .as_ref()
+        .map(|input| serialize_input(input, state.config.execution.max_input_bytes))
+        .transpose()?;
+    let deterministic = payload.deterministic.then(|| DeterministicMode {
+        seed: payload.seed.unwrap_or(0),
+        lock_math_random: state.config.js.lock_math_random,
+    });
+
+    // A registered module runs as it was prepared; any other code is downloaded first
+    let registered = match (&payload.module, payload.url.is_empty()) {
+        (None, false) => None,
+        (Some(_), true) if payload.sha256_compressed => {
+            return Err(AppError::InvalidRequest(
+                "sha256_compressed applies to downloaded code, not registered modules".to_string(),
+            ))
+        }
+        (Some(name), true) => Some(state.modules.get(name).ok_or_else(|| match pinned_sha256 {
+            Some(_) => AppError::ReplaySourceUnavailable(format!(
+                "Module {} is no longer registered",
+                name
+            )),
+            None => AppError::NotFound(format!("Unknown module: {}", name)),
+        })?),
+        _ => {
+            return Err(AppError::InvalidRequest(
+                "Give exactly one of url and module".to_string(),
+            ))
+        }
+    };
+
+    let download_headers = match &registered {
+        Some(_) => HeaderMap::new(),
+        None => {
+            // Download code from URL, with server default headers overridden by request headers
+            let download_headers =
+                download::build_headers(&state.config.download, payload.download_headers.as_ref())?;
+            if !download_headers.is_empty() {
+                tracing::debug!(
+                    headers = %download::describe_headers(&download_headers, &state.config.download),
+                    "Download headers"
+                );
+            }
+
+            // Refuse URLs the download policy rules out before anything is requested or counted
+            let code_url = reqwest::Url::parse(&payload.url)
+                .map_err(|e| AppError::InvalidRequest(format!("Invalid code URL: {}", e)))?;
+            download::CodeUrlPolicy::from_config(&state.config.download).check_url(&code_url)?;
+            download_headers
+        }
+    };
+
+    // Fail fast on sources that keep failing; the permit reports how this attempt ends
+    let permit = match (&registered, &state.circuits) {
+        (None, Some(circuits)) => Some(circuits.admit(&payload.url)?),
+        _ => None,
+    };
+    let result = async {
+        let load_started = std::time::Instant::now();
+        let expected_sha256 = expected_sha256.as_deref();
+        let loaded = match &registered {
+            Some(module) => {
+                *code_sha256 = Some(module.code_sha256.clone());
+                check_digest(
+                    &payload,
+                    &module.code_sha256,
+                    pinned_sha256,
+                    expected_sha256,
+                )?;
+                LoadedCode::registered(module)
+            }
+            None => {
+                // With sha256_compressed the artifact was checked before it was decompressed
+                let (expected_artifact_sha256, expected_sha256) = if payload.sha256_compressed {
+                    (expected_sha256, None)
+                } else {
+                    (None, expected_sha256)
+                };
+                let (code, content_type, download) = download_code(
+                    state,
+                    &payload,
+                    download_headers,
+                    pinned_sha256,
+                    expected_artifact_sha256,
+                )
+                .await?;
+                let digest = format!("{:x}", Sha256::digest(&code));
+                *code_sha256 = Some(digest.clone());
+                check_digest(&payload, &digest, pinned_sha256, expected_sha256)?;
+                LoadedCode::detect(&payload, code, content_type.as_deref(), digest, download)?
+            }
+        };
+        let digest = loaded.digest.clone();
+        let code_type = loaded.code_type;
+
+        // A module's manifest replaces the server defaults, within server policy and the request
+        let applied_manifest = match code_type {
+            CodeType::WebAssembly | CodeType::Wat => loaded.manifest.map(|manifest| {
+                manifest.apply(
+                    &state.config,
+                    requested_limits.timeout_ms,
+                    requested_limits.memory_bytes,
+                    fetch_allowlist.as_deref(),
+                    storage_requested,
+                )
+            }),
+            // Scripts get the JavaScript heap default and cap instead
+            CodeType::JavaScript | CodeType::Module => {
+                limits.memory_bytes =
+                    Limits::js_memory_bytes(&state.config, &payload.limits, &aliases)?;
+                None
+            }
+        };
+        if let Some(applied) = &applied_manifest {
+            limits.timeout = std::time::Duration::from_millis(applied.timeout_ms);
+            limits.memory_bytes = applied.max_memory_bytes;
+        }
+
+        let mut fetch_ctx = FetchContext::new(
+            state.fetch_client.clone(),
+            state.fetch_runtime.clone(),
+            state.outbound.clone(),
+            payload.cookies,
+            state.metrics.clone(),
+        );
+        fetch_ctx.allowed_hosts = match &applied_manifest {
+            Some(applied) => applied.fetch_allowlist.clone(),
+            None => fetch_allowlist,
+        };
+        fetch_ctx.max_calls = limits.max_fetch_calls;
+        fetch_ctx.max_bytes = limits.max_fetch_bytes;
+        fetch_ctx.cancel = cancel;
+        let network_stats = fetch_ctx.stats.clone();
+        let storage_granted = match &applied_manifest {
+            Some(applied) => applied.permissions.contains(&Permission::Storage),
+            None => storage_requested,
+        };
+        let storage = state
+            .storage
+            .as_ref()
+            .filter(|_| storage_granted)
+            .map(|storage| storage.namespace(&digest));
+        let kv = match &payload.kv_namespace {
+            Some(name) => state.kv.namespace(name),
+            None => state.kv.isolated(),
+        };
+
+        // The execution is a task of its own, so a panicking engine fails this request only
+        let executor = executor::for_code_type(
+            code_type,
+            &state.config,
+            &state.wasm,
+            state.js_runtimes.as_ref(),
+        );
+        let seed = deterministic.map(|mode| mode.seed);
+        let options = ExecOptions {
+            sha256: digest.clone(),
+            url: loaded.url,
+            fetch_ctx,
+            output_budget: output_budget.clone(),
+            deterministic,
+            timezone,
+            limits,
+            storage,
+            kv,
+            env,
+            input,
+            compat: payload.compat,
+            stub_missing_imports: payload.stub_missing_imports,
+            lossy_app_log: payload.lossy_app_log,
+        };
+        let effective_options = options.effective(code_type);
+        let execution = executor.execute(loaded.code, options).in_current_span();
+        let mut result = tokio::spawn(execution).await.map_err(engine_failure)?;
+        // Executions that failed after they started report the same metadata as successful ones
+        let metadata = match &mut result {
+            Ok(response) => Some(&mut response.metadata),
+            Err(error) => error.partial_metadata(),
+        };
+        if let Some(metadata) = metadata {
+            // The engine timed its own phases; the total covers the download too
+            metadata.execution_time = load_started.elapsed().as_millis() as u64;
+            if let Some(download) = loaded.download {
+                metadata.download_ms = Some(download.time.as_millis() as u64);
+                metadata.compressed_size = download.compressed_size;
+                metadata.download = Some(download.report);
+                metadata.download_cache = Some(download.cache);
+            }
+            metadata.registered_module = registered.as_ref().map(|module| module.name.clone());
+            metadata.code_sha256 = Some(digest);
+            metadata.network = network_stats.lock().ok().map(|stats| stats.clone());
+            metadata.output = Some(output_budget.usage());
+            metadata.manifest = applied_manifest;
+            metadata.source_format = loaded.source_format;
+            metadata.seed = seed;
+            metadata.effective_options = Some(effective_options);
+        }
+
+        result
+    }
+    .await;
+    if let Some(permit) = permit {
+        permit.finish(result.as_ref().err());
+    }
+    result
+}
+
+/// Code ready to hand to an engine
+struct LoadedCode {
+    /// The code, decompressed, and assembled when written in the text format
+    code: bytes::Bytes,
+    /// Type of the code
+    code_type: CodeType,
+    /// Format the WebAssembly module was written in, when it was not the binary format
+    source_format: Option<wat::SourceFormat>,
+    /// Hex SHA-256 digest of the code as obtained (and decompressed)
+    digest: String,
+    /// The WebAssembly module's manifest as declared, if it has one
+    manifest: Option<Manifest>,
+    /// URL the code was downloaded from
+    url: Option<String>,
+    /// How the code was downloaded; `None` for registered modules
+    download: Option<DownloadInfo>,
+}
+
+/// How downloaded code was obtained, as reported in the metadata
+struct DownloadInfo {
+    /// Time spent downloading
+    time: std::time::Duration,
+    /// Size of the downloaded artifact, if it was gzipped
+    compressed_size: Option<usize>,
+    /// Attempts made to download the code
+    report: download::DownloadReport,
+    /// Whether the code came from the download cache
+    cache: DownloadCacheStatus,
+}
+
+impl LoadedCode {
+    /// The code of a registered module, as it was prepared
+    fn registered(module: &RegisteredModule) -> Self {
+        LoadedCode {
+            code: module.code.clone(),
+            code_type: module.code_type,
+            source_format: module.source_format,
+            digest: module.code_sha256.clone(),
+            manifest: module.manifest.clone(),
+            url: module.url.clone(),
+            download: None,
+        }
+    }
+
+    /// Decide the type of downloaded code, assemble it and read its manifest
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The execute request, naming the code's `url` and `code_type`
+    /// * `code` - The downloaded code, decompressed
+    /// * `content_type` - `Content-Type` the origin sent with the code
+    /// * `digest` - Hex SHA-256 digest of `code`
+    /// * `download` - How the code was downloaded
+    fn detect(
+        payload: &ExecuteRequest,
+        code: bytes::Bytes,
+        content_type: Option<&str>,
+        digest: String,
+        download: DownloadInfo,
+    ) -> Result<Self, AppError> {
+        let code_type = detect::decide(payload.code_type, &payload.url, content_type, &code)?;
+        let (code, source_format) = match code_type {
+            CodeType::Wat => (wat::assemble(&code)?, Some(wat::SourceFormat::Wat)),
+            _ => (code, None),
+        };
+        let manifest = match code_type {
+            CodeType::WebAssembly | CodeType::Wat => manifest::read(&code)?,
+            CodeType::JavaScript | CodeType::Module => None,
+        };
+        Ok(LoadedCode {
+            code,
+            code_type,
+            source_format,
+            digest,
+            manifest,
+            url: Some(payload.url.clone()),
+            download: Some(download),
+        })
+    }
+}
+
+/// Refuse code whose digest is not the one a replay or the request pinned
+///
+/// # Arguments
+///
+/// * `payload` - The execute request
+/// * `digest` - Hex SHA-256 digest of the code
+/// * `pinned_sha256` - Digest of the original execution, when replaying
+/// * `expected_sha256` - The request's `sha256`
+fn check_digest(
+    payload: &ExecuteRequest,
+    digest: &str,
+    pinned_sha256: Option<&str>,
+    expected_sha256: Option<&str>,
+) -> Result<(), AppError> {
+    if let Some(pinned) = pinned_sha256 {
+        if pinned != digest {
+            return Err(AppError::ReplaySourceUnavailable(format!(
+                "The code at {} changed since the original execution (sha256 {} instead of {})",
+                payload.source(),
+                digest,
+                pinned
+            )));
+        }
+    }
+    if let Some(expected) = expected_sha256 {
+        if expected != digest {
+            return Err(AppError::ChecksumMismatch {
+                expected: expected.to_string(),
+                actual: digest.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Download the code of an execute request
+///
+/// # Arguments
+///
+/// * `state` - Shared application state
+/// * `payload` - The execute request, naming the code's `url`
+/// * `headers` - Headers of the download request
+/// * `pinned_sha256` - Digest the code must have (when replaying); a failed
+///   download is `ReplaySourceUnavailable`
+/// * `expected_artifact_sha256` - Digest the artifact must have as downloaded,
+///   for requests with `sha256_compressed`
+///
+/// # Returns
+///
+/// * `Result<(bytes::Bytes, Option<String>, DownloadInfo), AppError>` - The
+///   code, decompressed, with the `Content-Type` the origin sent and how it
+///   was downloaded, or the error downloading or decompressing it failed with
+async fn download_code(
+    state: &AppState,
+    payload: &ExecuteRequest,
+    headers: HeaderMap,
+    pinned_sha256: Option<&str>,
+    expected_artifact_sha256: Option<&str>,
+) -> Result<(bytes::Bytes, Option<String>, DownloadInfo), AppError> {
+    let download_span = tracing::info_span!("download", url = %record::redact_url(&payload.url));
+    let download_started = std::time::Instant::now();
+    let downloaded = download::download(
+        &state.download_client,
+        &payload.url,
+        headers,
+        &state.config.download,
+        &state.download_cache,
+    )
+    .instrument(download_span)
+    .await;
+    let download_time = download_started.elapsed();
+    state.metrics.observe_download(download_time);
+    let download::Downloaded {
+        code: downloaded_code,
+        content_type,
+        report,
+        cache,
+    } = downloaded.map_err(|e| match pinned_sha256 {
+        Some(_) => AppError::ReplaySourceUnavailable(format!(
+            "The original code can no longer be downloaded: {}",
+            e
+        )),
+        None => e,
+    })?;
+
+    // A corrupt or tampered artifact is refused before it is inflated
+    if let Some(expected) = expected_artifact_sha256 {
+        let digest = format!("{:x}", Sha256::digest(&downloaded_code));
+        if expected != digest {
+            return Err(AppError::ChecksumMismatch {
+                expected: expected.to_string(),
+                actual: digest,
+            });
+        }
+    }
+
+    // Inflate gzipped artifacts before handing them to the engine; whether the
+    // artifact is gzipped comes from the URL
+    let gzipped = detect::from_extension(&payload.url).is_some_and(|(_, gzipped)| gzipped);
+    let (code, compressed_size) = if gzipped {
+        let compressed_size = downloaded_code.len();
+        let code = compression::gunzip(&downloaded_code, compression::MAX_DECOMPRESSED_SIZE)?;
+        (code, Some(compressed_size))
+    } else {
+        (downloaded_code, None)
+    };
+    let download = DownloadInfo {
+        time: download_time,
+        compressed_size,
+        report,
+        cache,
+    };
+    Ok((code, content_type, download))
+}
+
+/// Request body of the inspect endpoint
+#[derive(Deserialize, Debug)]
+struct InspectRequest {
+    /// URL of the code to inspect
+    url: String,
+    /// Extra headers for the code download request (e.g. registry credentials)
+    download_headers: Option<HashMap<String, String>>,
+}
+
+/// Response body of the inspect endpoint
+#[derive(Serialize, Debug)]
+struct InspectResponse {
+    /// Type of the code ("javascript" or "webassembly")
+    code_type: &'static str,
+    /// Size of the code in bytes (after decompression)
+    resource_size: usize,
+    /// Hex SHA-256 digest of the code (after decompression)
+    code_sha256: String,
+    /// The module's manifest as declared
+    manifest: Option<Manifest>,
+    /// Limits an execution without request overrides would get from the manifest
+    applied: Option<AppliedManifest>,
+}
+
+/// Handler for the /inspect endpoint
+///
+/// Downloads code without running it and reports its type, digest and
+/// manifest, so a module's manifest can be checked before it is deployed.
+/// Without a recognized extension the payload is probed.
+///
+/// # Returns
+///
+/// * `Result<Json<InspectResponse>, AppError>` - The description, or the error
+///   the download, detection or manifest failed with
+async fn inspect_handler(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<InspectRequest>,
+) -> Result<Json<InspectResponse>, AppError> {
+    let download_headers =
+        download::build_headers(&state.config.download, payload.download_headers.as_ref())?;
+    let downloaded = download::download(
+        &state.download_client,
+        &payload.url,
+        download_headers,
+        &state.config.download,
+        &state.download_cache,
+    )
+    .await?;
+    let code = match detect::from_extension(&payload.url) {
+        Some((_, true)) => {
+            compression::gunzip(&downloaded.code, compression::MAX_DECOMPRESSED_SIZE)?
+        }
+        _ => downloaded.code,
+    };
+    let code_type = detect::decide(
+        None,
+        &payload.url,
+        downloaded.content_type.as_deref(),
+        &code,
+    )?;
+
+    let (code_type, manifest) = match code_type {
+        CodeType::JavaScript | CodeType::Module => ("javascript", None),
+        CodeType::WebAssembly => ("webassembly", manifest::read(&code)?),
+        CodeType::Wat => ("webassembly", manifest::read(&wat::assemble(&code)?)?),
+    };
+    let applied = manifest
+        .as_ref()
+        .map(|manifest| manifest.apply(&state.config, None, None, None, false));
+    Ok(Json(InspectResponse {
+        code_type,
+        resource_size: code.len(),
+        code_sha256: format!("{:x}", Sha256::digest(&code)),
+        manifest,
+        applied,
+    }))
+}
+
+/// Options of a self-test run
+#[derive(Deserialize, Debug, Default)]
+struct SelftestRequest {
+    /// Also check guest fetch by requesting this server's /metrics over loopback
+    #[serde(default)]
+    loopback_fetch: bool,
+}
+
+/// Handler for the /selftest endpoint
+///
+/// Runs the embedded self-test guests through both engines. The body is
+/// optional; without one the loopback fetch check is skipped.
+///
+/// # Returns
+///
+/// * `(StatusCode, Json<SelftestReport>)` - The report, with 200 when every
+///   check passed and 503 otherwise
+async fn selftest_handler(
+    State(state): State<Arc<AppState>>,
+    payload: Option<Json<SelftestRequest>>,
+) -> (StatusCode, Json<SelftestReport>) {
+    let options = payload.map(|Json(options)| options).unwrap_or_default();
+    let fetch_url = options
+        .loopback_fetch
+        .then(|| format!("{}/metrics", state.config.server.local_url()));
+    let report = selftest::run(&state, fetch_url.as_deref()).await;
+    let status = if report.passed {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+/// Request body of the warmup endpoint
+#[derive(Deserialize, Debug)]
+struct WarmupRequest {
+    /// URLs to download, verify and compile
+    urls: Vec<String>,
+}
+
+/// Response body of the warmup endpoint
+#[derive(Serialize, Debug)]
+struct WarmupResponse {
+    /// One outcome per requested URL, in request order
+    results: Vec<WarmupOutcome>,
+}
+
+/// Handler for the /warmup endpoint
+///
+/// Warms each URL once, without retries; the caller decides whether to try again.
+async fn warmup_handler(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<WarmupRequest>,
+) -> Result<Json<WarmupResponse>, AppError> {
+    if payload.urls.is_empty() {
+        return Err(AppError::InvalidRequest(
+            "urls must not be empty".to_string(),
+        ));
+    }
+    let results = warmup::warm_all(&state, &payload.urls, 1).await;
+    Ok(Json(WarmupResponse { results }))
+}
+
+/// Response body of module registration
+#[derive(Serialize, Debug)]
+struct RegisterResponse {
+    /// The module as registered
+    #[serde(flatten)]
+    module: ModuleSummary,
+    /// Digest of the module the registration replaced, if the name was taken
+    replaced_sha256: Option<String>,
+}
+
+/// Handler for POST on the /modules endpoint
+///
+/// Obtains, checks and compiles the code, then registers it under its name.
+///
+/// # Returns
+///
+/// * `Result<(StatusCode, Json<RegisterResponse>), AppError>` - 201 with the
+///   module for a new name, 200 when it replaced a module, or the error
+///   preparing or registering it failed with
+async fn register_module_handler(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<RegisterRequest>,
+) -> Result<(StatusCode, Json<RegisterResponse>), AppError> {
+    let module = registry::prepare(&state, payload).await?;
+    let summary = module.summary();
+    let replaced = state.modules.insert(module)?;
+    tracing::info!(
+        module = %summary.name,
+        sha256 = %summary.code_sha256,
+        replaced = replaced.is_some(),
+        "Registered module"
+    );
+    let status = match replaced {
+        Some(_) => StatusCode::OK,
+        None => StatusCode::CREATED,
+    };
+    Ok((
+        status,
+        Json(RegisterResponse {
+            module: summary,
+            replaced_sha256: replaced.map(|replaced| replaced.code_sha256.clone()),
+        }),
+    ))
+}
+
+/// Handler for GET on the /modules endpoint
+async fn list_modules_handler(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "modules": state.modules.list() }))
+}
+
+/// Handler for DELETE on the /modules/{name} endpoint
+///
+/// Executions already running the module finish with it.
+async fn delete_module_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    state
+        .modules
+        .remove(&name)
+        .ok_or_else(|| AppError::NotFound(format!("Unknown module: {}", name)))?;
+    Ok(Json(serde_json::json!({ "name": name, "deleted": true })))
+}
+
+/// Handler for the /health endpoint
+///
+/// # Returns
+///
+/// * `(StatusCode, Json<serde_json::Value>)` - 200 once the startup warmup
+///   finished, 503 while it is still running
+async fn health_handler(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if state.ready.load(std::sync::atomic::Ordering::Acquire) {
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({ "status": "ready" })),
+        )
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "warming" })),
+        )
+    }
+}
+
+/// Response body of the stats endpoint
+#[derive(Serialize, Debug)]
+struct StatsResponse {
+    /// Sources with recent failures, open circuits first
+    circuits: Vec<circuit::CircuitStatus>,
+    /// Usage of the compiled module cache
+    module_cache: wasm_engine::CacheStats,
+}
+
+/// Handler for the /stats endpoint
+///
+/// Lists the circuits of sources with recent failures and the usage of the
+/// compiled module cache.
+async fn stats_handler(State(state): State<Arc<AppState>>) -> Json<StatsResponse> {
+    let circuits = state
+        .circuits
+        .as_ref()
+        .map(|circuits| circuits.snapshot())
+        .unwrap_or_default();
+    Json(StatsResponse {
+        circuits,
+        module_cache: state.wasm.modules.stats(),
+    })
+}
+
+/// Handler for the /status endpoint
+///
+/// Reports how many executions hold a slot and how many requests wait for one.
+async fn status_handler(State(state): State<Arc<AppState>>) -> Json<AdmissionStatus> {
+    Json(state.admission.status())
+}
+
+/// Request body of the circuit reset endpoint
+#[derive(Deserialize, Debug)]
+struct CircuitResetRequest {
+    /// URL of the source whose circuit is closed
+    url: String,
+}
+
+/// Handler for the /admin/circuits/reset endpoint
+///
+/// Closes a source's circuit and forgets its failures, e.g. after the
+/// source was fixed, so the next request runs without waiting for a probe.
+async fn circuit_reset_handler(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CircuitResetRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let reset = state
+        .circuits
+        .as_ref()
+        .is_some_and(|circuits| circuits.reset(&payload.url));
+    if !reset {
+        return Err(AppError::NotFound(format!(
+            "No circuit for {}",
+            record::redact_url(&payload.url)
+        )));
+    }
+    Ok(Json(
+        serde_json::json!({ "url": record::redact_url(&payload.url), "reset": true }),
+    ))
+}
+
+/// Response body of the storage admin endpoint
+#[derive(Serialize, Debug)]
+struct StorageNamespaceResponse {
+    /// Name of the namespace (the module's code digest)
+    namespace: String,
+    /// Keys and bytes the namespace holds
+    usage: Usage,
+    /// Keys and bytes each namespace may hold
+    quota: Usage,
+    /// The namespace's keys in byte order
+    entries: Vec<EntrySummary>,
+}
+
+/// The storage of the server, or `NOT_FOUND` when it is not enabled
+fn enabled_storage(state: &AppState) -> Result<&storage::Storage, AppError> {
+    state
+        .storage
+        .as_deref()
+        .ok_or_else(|| AppError::NotFound("Storage is not enabled on this server".to_string()))
+}
+
+/// Handler for GET on the /admin/storage/{namespace} endpoint
+///
+/// Lists the keys of a module's namespace with the size of their values,
+/// without the values themselves, and how much of the quota it uses.
+async fn storage_namespace_handler(
+    State(state): State<Arc<AppState>>,
+    Path(namespace): Path<String>,
+) -> Result<Json<StorageNamespaceResponse>, AppError> {
+    let storage = enabled_storage(&state)?;
+    let usage = storage
+        .usage(&namespace)
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let entries = storage
+        .entries(&namespace)
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(Json(StorageNamespaceResponse {
+        namespace,
+        usage,
+        quota: storage.quota(),
+        entries,
+    }))
+}
+
+/// Handler for DELETE on the /admin/storage/{namespace} endpoint
+///
+/// Removes every entry of a module's namespace.
+async fn storage_clear_handler(
+    State(state): State<Arc<AppState>>,
+    Path(namespace): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let cleared = enabled_storage(&state)?
+        .clear(&namespace)
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(Json(
+        serde_json::json!({ "namespace": namespace, "cleared": cleared }),
+    ))
+}
+
+/// Handler for the /metrics endpoint
+///
+/// Renders the service metrics in the Prometheus text exposition format.
+async fn metrics_handler(State(state): State<Arc<AppState>>) -> String {
+    state.metrics.render()
+}
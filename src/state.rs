@@ -0,0 +1,187 @@
+//! Shared application state handed to every request handler.
+
+use crate::admission::Admission;
+use crate::audit::AuditLog;
+use crate::auth::Auth;
+use crate::cancel::Executions;
+use crate::circuit::CircuitBreaker;
+use crate::config::Config;
+use crate::download_cache::DownloadCache;
+use crate::fetch_runtime::FetchRuntime;
+use crate::history::History;
+use crate::jobs::Jobs;
+use crate::js_engine::RuntimePool;
+use crate::kv::KvStore;
+use crate::metrics::Metrics;
+use crate::outbound::{OutboundPolicy, PublicResolver};
+use crate::registry::ModuleRegistry;
+use crate::reporter::{ReporterRegistry, Reporting};
+use crate::storage::Storage;
+use crate::telemetry::{self, Tracing};
+use crate::wasm_engine::SharedEngine;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Resources shared across all executions
+pub struct AppState {
+    /// Server configuration
+    pub config: Config,
+    /// HTTP client for code downloads
+    ///
+    /// Redirects are disabled so `download::download` can follow them itself
+    /// and drop credentials when a hop leaves the original origin.
+    pub download_client: reqwest::Client,
+    /// Code downloaded before, reused while fresh or revalidated
+    pub download_cache: DownloadCache,
+    /// HTTP client backing guest fetch calls
+    ///
+    /// One client per server keeps its connection pool (keep-alive, HTTP/2
+    /// multiplexing) alive across executions. It holds no per-execution state:
+    /// cookie jars live in each execution's `FetchContext` and headers are set
+    /// per request, so nothing leaks between executions through the pool.
+    pub fetch_client: reqwest::Client,
+    /// Runtime guest fetch calls run on
+    ///
+    /// Its own thread, so JavaScript fetch host functions never block the
+    /// runtime that serves requests or whichever runtime an embedder runs
+    /// executions on, and WebAssembly fetch imports and their timers need no
+    /// runtime of the caller's.
+    pub fetch_runtime: Arc<FetchRuntime>,
+    /// Where guest fetch calls may go, checked before every request and redirect hop
+    pub outbound: Arc<OutboundPolicy>,
+    /// Service metrics exposed at `/metrics`
+    pub metrics: Arc<Metrics>,
+    /// WebAssembly engine shared by all executions, with its epoch ticker
+    ///
+    /// Shared with the tasks that run executions.
+    pub wasm: Arc<SharedEngine>,
+    /// Modules registered by name with `POST /modules`
+    pub modules: ModuleRegistry,
+    /// QuickJS runtimes kept ready for scripts, unless `js.runtime_pool_size` is 0
+    pub js_runtimes: Option<Arc<RuntimePool>>,
+    /// Execution audit log, when `audit.path` is configured
+    pub audit: Option<AuditLog>,
+    /// Recent executions, unless `history.capacity` is 0
+    pub history: Option<History>,
+    /// Running executions and their cancel tokens
+    pub executions: Executions,
+    /// Slots limiting how many executions run at once
+    pub admission: Admission,
+    /// API keys requests must carry, with their quotas
+    pub auth: Auth,
+    /// Asynchronous jobs submitted with `POST /jobs`
+    pub jobs: Jobs,
+    /// Persistent key-value store, when `storage.path` is configured
+    pub storage: Option<Arc<Storage>>,
+    /// In-memory key-value namespaces shared between executions
+    pub kv: Arc<KvStore>,
+    /// Per-source circuits, unless `circuit_breaker.failure_threshold` is 0
+    pub circuits: Option<CircuitBreaker>,
+    /// OTLP trace export, when `tracing.endpoint` is configured
+    pub tracing: Option<Tracing>,
+    /// Dispatcher of execution events, when `reporters` are configured
+    pub reporting: Option<Reporting>,
+    /// Whether the startup warmup has finished, as reported by `/health`
+    pub ready: AtomicBool,
+}
+
+impl AppState {
+    /// Build the shared state from a validated configuration
+    ///
+    /// # Returns
+    ///
+    /// * `Result<AppState, String>` - The state or a description of what failed
+    pub fn new(config: Config) -> Result<AppState, String> {
+        // Without the system proxy, so every name goes through the resolver's address check
+        let download_client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .no_proxy()
+            .dns_resolver(Arc::new(PublicResolver::for_downloads(
+                config.download.allow_private_addresses,
+            )))
+            .build()
+            .map_err(|e| format!("Failed to build download client: {}", e))?;
+        let fetch_client = config
+            .fetch
+            .build_client()
+            .map_err(|e| format!("Failed to build fetch client: {}", e))?;
+        let fetch_runtime =
+            FetchRuntime::start().map_err(|e| format!("Failed to start fetch runtime: {}", e))?;
+        let outbound = OutboundPolicy::from_config(&config.fetch);
+        let metrics = Metrics::new(&config.metrics.fetch_hosts)
+            .map_err(|e| format!("Failed to register metrics: {}", e))?;
+        let wasm = Arc::new(
+            SharedEngine::new(&config.wasm)
+                .map_err(|e| format!("Failed to create wasm engine: {}", e))?,
+        );
+        let modules = ModuleRegistry::new(&config.modules, wasm.clone());
+        let js_runtimes = match config.js.runtime_pool_size {
+            0 => None,
+            size => Some(Arc::new(
+                RuntimePool::start(
+                    size,
+                    Duration::from_millis(config.js.runtime_checkout_timeout_ms),
+                )
+                .map_err(|e| format!("Failed to start JavaScript runtime pool: {}", e))?,
+            )),
+        };
+        let audit = match &config.audit.path {
+            Some(path) => Some(
+                AuditLog::start(&config.audit, path)
+                    .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?,
+            ),
+            None => None,
+        };
+        let jobs = Jobs::new(&config.jobs);
+        let admission = Admission::new(&config.execution);
+        let auth = Auth::new(&config.auth);
+        let history = (config.history.capacity > 0).then(|| History::new(config.history.capacity));
+        let storage = match &config.storage.path {
+            Some(path) => Some(Arc::new(Storage::open(&config.storage, path).map_err(
+                |e| format!("Failed to open storage {}: {}", path.display(), e),
+            )?)),
+            None => None,
+        };
+        let download_cache = DownloadCache::new(config.download.cache_max_bytes);
+        let kv = Arc::new(KvStore::new(&config.kv));
+        let circuits = (config.circuit_breaker.failure_threshold > 0)
+            .then(|| CircuitBreaker::new(&config.circuit_breaker));
+        let tracing = telemetry::init(&config.server, &config.tracing)?;
+        let reporters = ReporterRegistry::from_config(&config.reporters)
+            .map_err(|e| format!("Failed to create reporters: {}", e))?;
+        let reporting = if reporters.is_empty() {
+            None
+        } else {
+            Some(
+                reporters
+                    .start()
+                    .map_err(|e| format!("Failed to start reporters: {}", e))?,
+            )
+        };
+        Ok(AppState {
+            config,
+            download_client,
+            download_cache,
+            fetch_client,
+            fetch_runtime: Arc::new(fetch_runtime),
+            outbound: Arc::new(outbound),
+            metrics: Arc::new(metrics),
+            wasm,
+            modules,
+            js_runtimes,
+            audit,
+            history,
+            executions: Executions::default(),
+            admission,
+            auth,
+            jobs,
+            storage,
+            kv,
+            circuits,
+            tracing,
+            reporting,
+            ready: AtomicBool::new(false),
+        })
+    }
+}
@@ -0,0 +1,280 @@
+//! # Persistent storage
+//!
+//! An opt-in key-value store that outlives executions, so modules can keep
+//! counters or sync cursors between runs. Every module gets its own
+//! namespace, named after the hex SHA-256 digest of its code; a new version
+//! of a module therefore starts with an empty namespace.
+//!
+//! Guests reach their namespace through the `pkv_get`/`pkv_set`/`pkv_delete`
+//! WebAssembly imports and the `hoyaStore` JavaScript global, and only when
+//! the request lists the `"storage"` permission. Each operation is atomic;
+//! all operations are serialized through one connection, so concurrent
+//! executions of the same module never see a partially applied write.
+//! Read-modify-write sequences spanning several operations are not atomic.
+//!
+//! Namespaces are limited to `storage.max_keys` keys and `storage.max_bytes`
+//! bytes of keys and values; a write that would exceed either is refused
+//! and leaves the namespace unchanged. Entries live in a SQLite database at
+//! `storage.path`.
+
+use crate::config::StorageConfig;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Longest key accepted, in bytes
+pub const MAX_KEY_BYTES: usize = 1024;
+
+/// Why a storage operation failed
+#[derive(Debug)]
+pub enum StorageError {
+    /// The key is empty or longer than `MAX_KEY_BYTES`
+    InvalidKey,
+    /// The write would take the namespace past one of its quotas
+    QuotaExceeded {
+        /// Keys the namespace would hold
+        keys: u64,
+        /// Bytes the namespace would hold
+        bytes: u64,
+    },
+    /// The database failed
+    Backend(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::InvalidKey => {
+                write!(f, "Keys must be between 1 and {} bytes long", MAX_KEY_BYTES)
+            }
+            StorageError::QuotaExceeded { keys, bytes } => write!(
+                f,
+                "The write would leave the namespace with {} keys and {} bytes",
+                keys, bytes
+            ),
+            StorageError::Backend(message) => write!(f, "Storage failed: {}", message),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(error: rusqlite::Error) -> Self {
+        StorageError::Backend(error.to_string())
+    }
+}
+
+/// Size of a namespace
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Number of keys
+    pub keys: u64,
+    /// Bytes of keys and values
+    pub bytes: u64,
+}
+
+/// One entry of a namespace, as listed by the admin endpoint
+#[derive(Serialize, Debug, Clone)]
+pub struct EntrySummary {
+    /// The key, with invalid UTF-8 replaced
+    pub key: String,
+    /// Size of the value in bytes
+    pub value_bytes: u64,
+}
+
+/// The database holding every namespace, shared by all executions
+pub struct Storage {
+    /// Connection through which every operation is serialized
+    connection: Mutex<Connection>,
+    /// Keys allowed per namespace
+    max_keys: u64,
+    /// Bytes of keys and values allowed per namespace
+    max_bytes: u64,
+}
+
+/// Handle on one module's namespace, handed to an execution
+#[derive(Clone)]
+pub struct Namespace {
+    /// The shared database
+    storage: Arc<Storage>,
+    /// Name of the namespace (the module's code digest)
+    name: String,
+}
+
+impl Storage {
+    /// Open or create the database
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Quotas of each namespace
+    /// * `path` - File holding the database
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, StorageError>` - The database, or why it could not be opened
+    pub fn open(config: &StorageConfig, path: &Path) -> Result<Storage, StorageError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS entries (
+                 namespace TEXT NOT NULL,
+                 key BLOB NOT NULL,
+                 value BLOB NOT NULL,
+                 PRIMARY KEY (namespace, key)
+             ) WITHOUT ROWID;",
+        )?;
+        Ok(Storage {
+            connection: Mutex::new(connection),
+            max_keys: config.max_keys,
+            max_bytes: config.max_bytes,
+        })
+    }
+
+    /// Handle on the namespace of the module with the given digest
+    pub fn namespace(self: &Arc<Self>, code_sha256: &str) -> Namespace {
+        Namespace {
+            storage: self.clone(),
+            name: code_sha256.to_string(),
+        }
+    }
+
+    /// Run `f` with the connection, serialized with every other operation
+    fn with_connection<T>(
+        &self,
+        f: impl FnOnce(&Connection) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| StorageError::Backend("connection lock poisoned".to_string()))?;
+        f(&connection)
+    }
+
+    /// Keys and bytes held by a namespace
+    pub fn usage(&self, namespace: &str) -> Result<Usage, StorageError> {
+        self.with_connection(|connection| usage(connection, namespace))
+    }
+
+    /// Keys of a namespace in byte order, with the size of their values
+    pub fn entries(&self, namespace: &str) -> Result<Vec<EntrySummary>, StorageError> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT key, LENGTH(value) FROM entries WHERE namespace = ?1 ORDER BY key",
+            )?;
+            let rows = statement.query_map(params![namespace], |row| {
+                Ok(EntrySummary {
+                    key: String::from_utf8_lossy(&row.get::<_, Vec<u8>>(0)?).into_owned(),
+                    value_bytes: row.get(1)?,
+                })
+            })?;
+            Ok(rows.collect::<Result<Vec<_>, _>>()?)
+        })
+    }
+
+    /// Remove every entry of a namespace, returning how many there were
+    pub fn clear(&self, namespace: &str) -> Result<u64, StorageError> {
+        self.with_connection(|connection| {
+            let removed = connection.execute(
+                "DELETE FROM entries WHERE namespace = ?1",
+                params![namespace],
+            )?;
+            Ok(removed as u64)
+        })
+    }
+
+    /// Quotas of every namespace
+    pub fn quota(&self) -> Usage {
+        Usage {
+            keys: self.max_keys,
+            bytes: self.max_bytes,
+        }
+    }
+}
+
+/// Keys and bytes held by a namespace
+fn usage(connection: &Connection, namespace: &str) -> Result<Usage, StorageError> {
+    Ok(connection.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(LENGTH(key) + LENGTH(value)), 0)
+         FROM entries WHERE namespace = ?1",
+        params![namespace],
+        |row| {
+            Ok(Usage {
+                keys: row.get(0)?,
+                bytes: row.get(1)?,
+            })
+        },
+    )?)
+}
+
+/// Reject keys no namespace can hold
+fn check_key(key: &[u8]) -> Result<(), StorageError> {
+    if key.is_empty() || key.len() > MAX_KEY_BYTES {
+        return Err(StorageError::InvalidKey);
+    }
+    Ok(())
+}
+
+impl Namespace {
+    /// Value stored under `key`, if any
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        check_key(key)?;
+        self.storage.with_connection(|connection| {
+            Ok(connection
+                .query_row(
+                    "SELECT value FROM entries WHERE namespace = ?1 AND key = ?2",
+                    params![self.name, key],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        })
+    }
+
+    /// Store `value` under `key`, replacing any previous value
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), StorageError>` - `QuotaExceeded` if the namespace would
+    ///   exceed its key or byte quota, in which case nothing is written
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        check_key(key)?;
+        let storage = &self.storage;
+        storage.with_connection(|connection| {
+            let current = usage(connection, &self.name)?;
+            let replaced: Option<u64> = connection
+                .query_row(
+                    "SELECT LENGTH(key) + LENGTH(value) FROM entries WHERE namespace = ?1 AND key = ?2",
+                    params![self.name, key],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let after = Usage {
+                keys: current.keys + u64::from(replaced.is_none()),
+                bytes: current.bytes - replaced.unwrap_or(0) + (key.len() + value.len()) as u64,
+            };
+            if after.keys > storage.max_keys || after.bytes > storage.max_bytes {
+                return Err(StorageError::QuotaExceeded {
+                    keys: after.keys,
+                    bytes: after.bytes,
+                });
+            }
+            connection.execute(
+                "INSERT OR REPLACE INTO entries (namespace, key, value) VALUES (?1, ?2, ?3)",
+                params![self.name, key, value],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Remove `key`, returning whether it was present
+    pub fn delete(&self, key: &[u8]) -> Result<bool, StorageError> {
+        check_key(key)?;
+        self.storage.with_connection(|connection| {
+            let removed = connection.execute(
+                "DELETE FROM entries WHERE namespace = ?1 AND key = ?2",
+                params![self.name, key],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+}
@@ -0,0 +1,146 @@
+//! # Streamed JSON responses
+//!
+//! A buffered execute response is serialized into one string before the
+//! first byte is sent, which doubles peak memory for large outputs. With the
+//! request's `stream` flag the body is written as a sequence of chunks
+//! instead: status, error and metadata come first, followed by the output
+//! fields, which are escaped and sent straight from their buffers in
+//! `CHUNK_BYTES` pieces. The assembled body is the same JSON document, only
+//! with the output fields last. Structured `logs` are serialized whole, with
+//! the output fields they belong to.
+//!
+//! Output budgets are applied while the guest runs, so a streamed response
+//! never carries more output than a buffered one.
+
+use crate::error::ExecuteResponse;
+use crate::wire::ResponseVersion;
+use axum::body::Body;
+use bytes::Bytes;
+use std::convert::Infallible;
+
+/// Bytes of output escaped and sent per chunk
+pub const CHUNK_BYTES: usize = 64 * 1024;
+
+/// A part of the streamed body
+enum Piece {
+    /// JSON emitted as is
+    Raw(String),
+    /// A string value, escaped chunk by chunk; `None` is emitted as `null`
+    Text(Option<String>),
+}
+
+impl Piece {
+    /// Split the piece into body chunks, escaping text only when it is polled
+    fn into_chunks(self) -> Box<dyn Iterator<Item = Bytes> + Send> {
+        match self {
+            Piece::Raw(json) => Box::new(std::iter::once(Bytes::from(json))),
+            Piece::Text(None) => Box::new(std::iter::once(Bytes::from_static(b"null"))),
+            Piece::Text(Some(text)) => Box::new(
+                std::iter::once(Bytes::from_static(b"\""))
+                    .chain(TextChunks { text, offset: 0 })
+                    .chain(std::iter::once(Bytes::from_static(b"\""))),
+            ),
+        }
+    }
+}
+
+/// Escaped pieces of a string, split on character boundaries
+struct TextChunks {
+    /// The string being streamed
+    text: String,
+    /// Start of the next chunk
+    offset: usize,
+}
+
+impl Iterator for TextChunks {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        if self.offset >= self.text.len() {
+            return None;
+        }
+        let mut end = (self.offset + CHUNK_BYTES).min(self.text.len());
+        while !self.text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let quoted = serde_json::to_string(&self.text[self.offset..end]).unwrap_or_default();
+        self.offset = end;
+        // Drop the quotes around the escaped chunk
+        Some(Bytes::from(quoted[1..quoted.len() - 1].to_string()))
+    }
+}
+
+/// Write an execute response as a chunked JSON body with the output fields last
+///
+/// # Arguments
+///
+/// * `version` - Response version of the body
+/// * `response` - The execution result or error
+///
+/// # Returns
+///
+/// * `Body` - The streamed body
+pub fn json_body(version: ResponseVersion, mut response: ExecuteResponse) -> Body {
+    let output = response.output.take();
+    let stdout = response.stdout.take();
+    let stderr = response.stderr.take();
+
+    // Serialize everything but the output fields, then reopen the object
+    let mut head = match serde_json::to_value(version.wire(response)) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    };
+    // Version 2 keeps the fields of `outputs` besides the streams, such as the truncation flags
+    let (logs, outputs_rest) = match version {
+        ResponseVersion::V1 => (head.remove("logs"), String::new()),
+        ResponseVersion::V2 => {
+            let mut outputs = match head.remove("outputs") {
+                Some(serde_json::Value::Object(outputs)) => outputs,
+                _ => serde_json::Map::new(),
+            };
+            let logs = outputs.remove("logs");
+            let rest = outputs
+                .into_iter()
+                .filter(|(field, _)| field != "stdout" && field != "stderr")
+                .map(|(field, value)| format!(",{}:{}", serde_json::Value::String(field), value))
+                .collect();
+            (logs, rest)
+        }
+    };
+    let logs = logs.unwrap_or_default();
+    for field in ["output", "stdout", "stderr"] {
+        head.remove(field);
+    }
+    let mut head = serde_json::Value::Object(head).to_string();
+    head.pop();
+    if head.len() > 1 {
+        head.push(',');
+    }
+
+    let pieces = match version {
+        ResponseVersion::V1 => vec![
+            Piece::Raw(head + "\"output\":"),
+            Piece::Text(output),
+            Piece::Raw(",\"stdout\":".to_string()),
+            Piece::Text(stdout),
+            Piece::Raw(",\"stderr\":".to_string()),
+            Piece::Text(stderr),
+            Piece::Raw(format!(",\"logs\":{}}}", logs)),
+        ],
+        ResponseVersion::V2 => vec![
+            Piece::Raw(head + "\"output\":"),
+            Piece::Text(output),
+            Piece::Raw(",\"outputs\":{\"stdout\":".to_string()),
+            Piece::Text(stdout),
+            Piece::Raw(",\"stderr\":".to_string()),
+            Piece::Text(stderr),
+            Piece::Raw(format!(",\"logs\":{}{}}}}}", logs, outputs_rest)),
+        ],
+    };
+
+    let chunks = pieces
+        .into_iter()
+        .flat_map(Piece::into_chunks)
+        .map(Ok::<_, Infallible>);
+    Body::from_stream(futures_util::stream::iter(chunks))
+}
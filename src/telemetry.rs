@@ -0,0 +1,176 @@
+//! # Trace export
+//!
+//! Executions are exported as OpenTelemetry traces over OTLP/HTTP when the
+//! `tracing.endpoint` config is set. Each request gets a root `execute` span
+//! with `download`, `compile` (WebAssembly only), `instantiate` and `run`
+//! children, and every guest fetch call adds a `guest_fetch` span under `run`.
+//!
+//! A W3C `traceparent` header on the incoming request makes the execution part
+//! of the caller's trace. Spans are created with `tracing` and only exported
+//! when an endpoint is set.
+//!
+//! # Logs
+//!
+//! The same subscriber prints the server's log events to stdout, as text or
+//! JSON lines (`server.log_format`), filtered by the `RUST_LOG`-style
+//! directives of `server.log_level`. Events happening during an execution
+//! carry the `execute` span and its `execution_id`. Guest output echoed by
+//! the host has the `hoya::guest` target, so it can be filtered on its own.
+
+use crate::config::{LogFormat, OtlpProtocol, ServerConfig, TracingConfig};
+use axum::http::HeaderMap;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+use opentelemetry_otlp::{Protocol, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Registry;
+
+/// Response header carrying the trace id of the execution
+pub const TRACE_ID_HEADER: &str = "x-hoya-trace-id";
+
+/// Handle to the OTLP export pipeline
+pub struct Tracing {
+    /// Provider owning the batch exporter, flushed on shutdown
+    provider: SdkTracerProvider,
+}
+
+/// Install the global `tracing` subscriber
+///
+/// Events passing `server.log_level` are printed to stdout in
+/// `server.log_format`, prefixed with the spans they happened in; spans are
+/// exported over OTLP when `tracing.endpoint` is set.
+///
+/// # Arguments
+///
+/// * `server` - Log filter and format
+/// * `config` - Export settings
+///
+/// # Returns
+///
+/// * `Result<Option<Tracing>, String>` - The export handle, if an endpoint is
+///   configured, or a description of what failed
+pub fn init(server: &ServerConfig, config: &TracingConfig) -> Result<Option<Tracing>, String> {
+    let filter: Targets = server
+        .log_level
+        .parse()
+        .map_err(|e| format!("Invalid log filter {:?}: {}", server.log_level, e))?;
+    let tracing = match &config.endpoint {
+        Some(endpoint) => Some(
+            Tracing::start(config, endpoint)
+                .map_err(|e| format!("Failed to start trace export to {}: {}", endpoint, e))?,
+        ),
+        None => None,
+    };
+    let export = tracing
+        .as_ref()
+        .map(|tracing| tracing_opentelemetry::layer().with_tracer(tracing.provider.tracer("hoya")));
+    let print: Box<dyn Layer<Registry> + Send + Sync> = match server.log_format {
+        LogFormat::Pretty => Box::new(tracing_subscriber::fmt::layer()),
+        LogFormat::Json => Box::new(tracing_subscriber::fmt::layer().json()),
+    };
+    tracing_subscriber::registry()
+        .with(print.with_filter(filter))
+        .with(export)
+        .try_init()
+        .map_err(|e| format!("Failed to install the log subscriber: {}", e))?;
+    Ok(tracing)
+}
+
+impl Tracing {
+    /// Build the OTLP exporter and its tracer provider
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Export settings
+    /// * `endpoint` - OTLP/HTTP traces URL
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Tracing, String>` - The handle or a description of what failed
+    fn start(config: &TracingConfig, endpoint: &str) -> Result<Self, String> {
+        let protocol = match config.protocol {
+            OtlpProtocol::HttpProtobuf => Protocol::HttpBinary,
+            OtlpProtocol::HttpJson => Protocol::HttpJson,
+        };
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_protocol(protocol)
+            .with_endpoint(endpoint)
+            .with_headers(config.headers.clone())
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        // Honor the caller's decision for propagated traces, sample our own by ratio
+        let sampler =
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sampling_ratio)));
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_sampler(sampler)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(config.service_name.clone())
+                    .build(),
+            )
+            .build();
+        Ok(Tracing { provider })
+    }
+
+    /// Export the spans still queued and stop the exporter
+    pub fn shutdown(&self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::error!("Failed to flush traces: {}", e);
+        }
+    }
+}
+
+/// Reads propagation headers from an incoming request
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Create the root span of an execution, continuing the caller's trace if any
+///
+/// # Arguments
+///
+/// * `headers` - Headers of the incoming request; a valid `traceparent` becomes the parent
+///
+/// # Returns
+///
+/// * `tracing::Span` - The span, with `execution_id` and the status fields left to record
+pub fn execution_span(headers: &HeaderMap) -> tracing::Span {
+    let span = tracing::info_span!(
+        "execute",
+        execution_id = tracing::field::Empty,
+        api_key = tracing::field::Empty,
+        http.response.status_code = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    );
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    if parent.span().span_context().is_valid() {
+        span.set_parent(parent);
+    }
+    span
+}
+
+/// Hex trace id of a span, if tracing is enabled
+pub fn trace_id(span: &tracing::Span) -> Option<String> {
+    let context = span.context();
+    let span_context = context.span().span_context().clone();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
@@ -0,0 +1,192 @@
+//! Guest fetch protocol types.
+//!
+//! The JSON documents exchanged by the WebAssembly `fetch` import and the
+//! JavaScript `hoyaFetch` global: `WasmFetchOptions` in, `WasmFetchResponse`
+//! out, with a `WasmFetchError` envelope when no response was received.
+//! Bodies that are not text travel base64-encoded, flagged by `BodyEncoding`.
+//! The JSON Schemas of both documents are published as `FETCH_OPTIONS_SCHEMA`
+//! and `FETCH_RESPONSE_SCHEMA` for guests to validate against.
+//!
+//! Every type converts to JSON and back without loss:
+//!
+//! ```
+//! use hoya::types::WasmFetchResponse;
+//!
+//! let response = WasmFetchResponse::from_error("FETCH_FAILED", "connection refused".to_string());
+//! let json = serde_json::to_string(&response).unwrap();
+//! assert_eq!(
+//!     json,
+//!     r#"{"status":0,"headers":{},"body":"","body_encoding":"utf8","error":{"code":"FETCH_FAILED","message":"connection refused"}}"#
+//! );
+//! assert_eq!(serde_json::from_str::<WasmFetchResponse>(&json).unwrap(), response);
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// JSON Schema of the options document of a guest fetch call
+///
+/// ```
+/// let schema: serde_json::Value = serde_json::from_str(hoya::types::FETCH_OPTIONS_SCHEMA).unwrap();
+/// assert_eq!(schema["required"], serde_json::json!(["url", "method", "headers"]));
+/// ```
+pub const FETCH_OPTIONS_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "WasmFetchOptions",
+  "type": "object",
+  "required": ["url", "method", "headers"],
+  "properties": {
+    "url": { "type": "string" },
+    "method": { "type": "string" },
+    "headers": { "type": "object", "additionalProperties": { "type": "string" } },
+    "body": { "type": ["string", "null"] },
+    "body_encoding": { "enum": ["utf8", "base64"] },
+    "cookies": { "type": ["boolean", "null"] },
+    "timeout_ms": { "type": ["integer", "null"], "minimum": 0 },
+    "max_redirects": { "type": ["integer", "null"], "minimum": 0 },
+    "max_response_bytes": { "type": ["integer", "null"], "minimum": 0 }
+  }
+}"#;
+
+/// JSON Schema of the response document of a guest fetch call
+///
+/// ```
+/// let schema: serde_json::Value = serde_json::from_str(hoya::types::FETCH_RESPONSE_SCHEMA).unwrap();
+/// assert_eq!(
+///     schema["required"],
+///     serde_json::json!(["status", "headers", "body", "body_encoding", "error"])
+/// );
+/// ```
+pub const FETCH_RESPONSE_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "WasmFetchResponse",
+  "type": "object",
+  "required": ["status", "headers", "body", "body_encoding", "error"],
+  "properties": {
+    "status": { "type": "integer", "minimum": 0, "maximum": 999 },
+    "headers": { "type": "object", "additionalProperties": { "type": "string" } },
+    "body": { "type": "string" },
+    "body_encoding": { "enum": ["utf8", "base64"] },
+    "error": {
+      "oneOf": [
+        { "type": "null" },
+        {
+          "type": "object",
+          "required": ["code", "message"],
+          "properties": {
+            "code": { "type": "string" },
+            "message": { "type": "string" }
+          }
+        }
+      ]
+    },
+    "cookies": { "type": "object", "additionalProperties": { "type": "string" } }
+  }
+}"#;
+
+/// How a fetch body is written into its JSON document
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyEncoding {
+    /// The body is text and written as is
+    #[default]
+    Utf8,
+    /// The body is written base64-encoded
+    Base64,
+}
+
+impl BodyEncoding {
+    /// Name of the encoding in JSON documents, e.g. "base64"
+    pub fn name(self) -> &'static str {
+        match self {
+            BodyEncoding::Utf8 => "utf8",
+            BodyEncoding::Base64 => "base64",
+        }
+    }
+}
+
+/// Options for a guest HTTP request
+///
+/// ```
+/// use hoya::types::WasmFetchOptions;
+///
+/// let json = r#"{"url":"https://example.com/","method":"GET","headers":{"accept":"text/plain"}}"#;
+/// let options: WasmFetchOptions = serde_json::from_str(json).unwrap();
+/// assert_eq!(options.body, None);
+/// let again = serde_json::to_string(&options).unwrap();
+/// assert_eq!(serde_json::from_str::<WasmFetchOptions>(&again).unwrap(), options);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WasmFetchOptions {
+    /// URL to send the request to
+    pub url: String,
+    /// HTTP method (e.g., "GET", "POST")
+    pub method: String,
+    /// HTTP headers
+    pub headers: HashMap<String, String>,
+    /// Optional request body, as text or base64 as `body_encoding` says
+    pub body: Option<String>,
+    /// Encoding of `body`; `base64` also asks for the response body in base64
+    #[serde(default)]
+    pub body_encoding: BodyEncoding,
+    /// Return the execution's cookies for this URL in the response
+    pub cookies: Option<bool>,
+    /// Milliseconds the whole call may take, redirects and body included;
+    /// clamped to the server's `fetch.max_timeout_ms`, which is the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Redirects to follow; clamped to the server's `fetch.max_redirects`, which is the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_redirects: Option<usize>,
+    /// Largest response body to accept in bytes; clamped to the server's
+    /// `fetch.max_response_bytes`, which is the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_response_bytes: Option<u64>,
+}
+
+/// HTTP response data for guest code
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WasmFetchResponse {
+    /// HTTP status code
+    pub status: u16,
+    /// Response headers
+    pub headers: HashMap<String, String>,
+    /// Response body as text, or base64-encoded when it is not valid UTF-8 or the guest asked for it
+    pub body: String,
+    /// Encoding of `body`
+    #[serde(default)]
+    pub body_encoding: BodyEncoding,
+    /// Optional error information
+    pub error: Option<WasmFetchError>,
+    /// Cookies held for the request URL after the response, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cookies: Option<HashMap<String, String>>,
+}
+
+/// Error information for HTTP requests
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WasmFetchError {
+    /// Error code identifier
+    pub code: String,
+    /// Error message
+    pub message: String,
+}
+
+impl WasmFetchResponse {
+    /// Build a response carrying only an error envelope
+    ///
+    /// A status of 0 indicates that no HTTP response was received.
+    pub fn from_error(code: &str, message: String) -> Self {
+        WasmFetchResponse {
+            status: 0,
+            headers: HashMap::new(),
+            body: String::new(),
+            body_encoding: BodyEncoding::Utf8,
+            error: Some(WasmFetchError {
+                code: code.to_string(),
+                message,
+            }),
+            cookies: None,
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! # Warmup
+//!
+//! Downloads, verifies and compiles code ahead of its first execution so the
+//! first request for a hot module does not pay the compile cost. WebAssembly
+//! modules are compiled into the shared module cache; JavaScript has no
+//! compiled form to keep, so it is only downloaded and syntax-checked.
+//!
+//! The `/warmup` endpoint and the startup warm list (`warmup.urls`) share
+//! `warm_all`. At startup, failed downloads are retried with exponential
+//! backoff, and `/health` reports ready once every URL succeeded or ran out
+//! of attempts.
+
+use crate::compression;
+use crate::detect::{self, CodeType};
+use crate::download;
+use crate::error::{AppError, ErrorInfo};
+use crate::js_engine;
+use crate::state::AppState;
+use crate::wasm_engine::{self, CacheStatus};
+use crate::wat;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Delay before the first retry of a failed warmup
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest delay between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Result of warming one URL
+#[derive(Serialize, Debug)]
+pub struct WarmupOutcome {
+    /// URL that was warmed
+    pub url: String,
+    /// "ok" or "error"
+    pub status: &'static str,
+    /// "javascript" or "webassembly", once the code was downloaded
+    pub code_type: Option<&'static str>,
+    /// Hex SHA-256 digest of the code, once it was downloaded
+    pub code_sha256: Option<String>,
+    /// Whether the module was already compiled (WebAssembly only)
+    pub module_cache: Option<CacheStatus>,
+    /// Number of attempts made
+    pub attempts: u32,
+    /// Wall-clock time of all attempts, in milliseconds
+    pub duration_ms: u64,
+    /// Why the last attempt failed
+    pub error: Option<ErrorInfo>,
+}
+
+/// Code that was successfully warmed
+struct WarmedCode {
+    /// "javascript" or "webassembly"
+    code_type: &'static str,
+    /// Hex SHA-256 digest of the code
+    code_sha256: String,
+    /// Whether the module was already compiled (WebAssembly only)
+    module_cache: Option<CacheStatus>,
+}
+
+/// Warm several URLs concurrently
+///
+/// # Arguments
+///
+/// * `state` - Shared service state holding the caches
+/// * `urls` - URLs to warm
+/// * `max_attempts` - Attempts per URL; only download failures are retried
+///
+/// # Returns
+///
+/// * `Vec<WarmupOutcome>` - One outcome per URL, in the order of `urls`
+pub async fn warm_all(
+    state: &Arc<AppState>,
+    urls: &[String],
+    max_attempts: u32,
+) -> Vec<WarmupOutcome> {
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, url) in urls.iter().enumerate() {
+        let state = state.clone();
+        let url = url.clone();
+        tasks.spawn(async move { (index, warm_with_retry(&state, url, max_attempts).await) });
+    }
+
+    let mut outcomes = Vec::with_capacity(urls.len());
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok(outcome) => outcomes.push(outcome),
+            Err(e) => tracing::error!("Warmup task failed: {}", e),
+        }
+    }
+    outcomes.sort_by_key(|(index, _)| *index);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// Warm the configured URLs once and mark the server ready
+///
+/// Failures are logged and never abort startup.
+pub async fn startup(state: Arc<AppState>) {
+    let urls = &state.config.warmup.urls;
+    if !urls.is_empty() {
+        tracing::info!("Warming {} URL(s)", urls.len());
+        let outcomes = warm_all(&state, urls, state.config.warmup.max_attempts).await;
+        let failed = outcomes.iter().filter(|o| o.status != "ok").count();
+        tracing::info!(
+            "Warmup finished: {} ok, {} failed",
+            outcomes.len() - failed,
+            failed
+        );
+    }
+    state.ready.store(true, Ordering::Release);
+}
+
+/// Re-warm the configured URLs every `warmup.refresh_interval_secs`, if set
+pub fn spawn_refresh(state: Arc<AppState>) {
+    let interval_secs = state.config.warmup.refresh_interval_secs;
+    if interval_secs == 0 || state.config.warmup.urls.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        // The first tick completes immediately; startup already warmed once
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let urls = &state.config.warmup.urls;
+            warm_all(&state, urls, state.config.warmup.max_attempts).await;
+        }
+    });
+}
+
+/// Warm one URL, retrying download failures with exponential backoff
+async fn warm_with_retry(state: &AppState, url: String, max_attempts: u32) -> WarmupOutcome {
+    let start_time = Instant::now();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        match warm_url(state, &url).await {
+            Ok(warmed) => {
+                tracing::info!(
+                    "Warmed {} ({}, sha256 {})",
+                    url,
+                    warmed.code_type,
+                    warmed.code_sha256
+                );
+                return WarmupOutcome {
+                    url,
+                    status: "ok",
+                    code_type: Some(warmed.code_type),
+                    code_sha256: Some(warmed.code_sha256),
+                    module_cache: warmed.module_cache,
+                    attempts,
+                    duration_ms: start_time.elapsed().as_millis() as u64,
+                    error: None,
+                };
+            }
+            Err(e) if attempts < max_attempts && is_transient(&e) => {
+                tracing::warn!(
+                    "Warmup of {} failed (attempt {}/{}), retrying in {} ms: {}",
+                    url,
+                    attempts,
+                    max_attempts,
+                    backoff.as_millis(),
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Warmup of {} failed after {} attempt(s): {}",
+                    url,
+                    attempts,
+                    e
+                );
+                let (_, response) = e.into_execute_response(|_| {});
+                return WarmupOutcome {
+                    url,
+                    status: "error",
+                    code_type: None,
+                    code_sha256: None,
+                    module_cache: None,
+                    attempts,
+                    duration_ms: start_time.elapsed().as_millis() as u64,
+                    error: response.error,
+                };
+            }
+        }
+    }
+}
+
+/// Whether a warmup failure may go away by itself (a failed or refused download)
+fn is_transient(error: &AppError) -> bool {
+    matches!(error, AppError::Reqwest(_) | AppError::Download(_))
+}
+
+/// Download, verify and compile the code at `url` the way `/execute` would
+async fn warm_url(state: &AppState, url: &str) -> Result<WarmedCode, AppError> {
+    let headers = download::build_headers(&state.config.download, None)?;
+    let downloaded = download::download(
+        &state.download_client,
+        url,
+        headers,
+        &state.config.download,
+        &state.download_cache,
+    )
+    .await?;
+
+    let code = match detect::from_extension(url) {
+        Some((_, true)) => {
+            compression::gunzip(&downloaded.code, compression::MAX_DECOMPRESSED_SIZE)?
+        }
+        _ => downloaded.code,
+    };
+    let code_sha256 = format!("{:x}", Sha256::digest(&code));
+    let code_type = detect::decide(None, url, downloaded.content_type.as_deref(), &code)?;
+    let code = match code_type {
+        CodeType::Wat => wat::assemble(&code)?,
+        _ => code,
+    };
+
+    // Compilation is CPU-bound; keep it off this worker's other tasks
+    tokio::task::block_in_place(|| match code_type {
+        CodeType::JavaScript | CodeType::Module => {
+            let source = std::str::from_utf8(&code)
+                .map_err(|e| AppError::InvalidRequest(format!("{} is not UTF-8: {}", url, e)))?;
+            let checked = match code_type {
+                CodeType::Module => js_engine::check_module_syntax(source),
+                _ => js_engine::check_syntax(source),
+            };
+            checked.map_err(|message| AppError::JsSyntaxError {
+                message,
+                stack: None,
+                source: url.to_string(),
+            })?;
+            Ok(WarmedCode {
+                code_type: "javascript",
+                code_sha256,
+                module_cache: None,
+            })
+        }
+        CodeType::WebAssembly | CodeType::Wat => {
+            let (_, module_cache) = state
+                .wasm
+                .instance_pre(&code_sha256, &code, None, false)
+                .map_err(|e| wasm_engine::compile_error(e, None))?;
+            Ok(WarmedCode {
+                code_type: "webassembly",
+                code_sha256,
+                module_cache: Some(module_cache),
+            })
+        }
+    })
+}
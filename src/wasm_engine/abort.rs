@@ -0,0 +1,77 @@
+//! The `env.abort` import of AssemblyScript modules.
+//!
+//! AssemblyScript compiles failed assertions, `throw` and runtime errors
+//! into a call to `abort(message, file, line, column)`, where `message` and
+//! `file` point at AssemblyScript strings: UTF-16LE code units, with their
+//! length in bytes in the 32-bit word just before the pointer (the `rtSize`
+//! field of the object header). The call never returns; it ends the
+//! execution with `GUEST_ABORT`, carrying what the guest said about where
+//! and why, and the output captured until then.
+
+use std::fmt;
+
+/// Most bytes of an AssemblyScript string decoded, so an error body stays small
+pub const MAX_ABORT_STRING_BYTES: usize = 8192;
+
+/// A guest called `abort`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestAbort {
+    /// The abort message, if the guest passed one that could be read
+    pub message: Option<String>,
+    /// Source file the abort happened in, if the guest passed one that could be read
+    pub file: Option<String>,
+    /// One-based source line, 0 when unknown
+    pub line: u32,
+    /// One-based source column, 0 when unknown
+    pub column: u32,
+}
+
+impl fmt::Display for GuestAbort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message.as_deref().unwrap_or("abort"))?;
+        if let Some(file) = &self.file {
+            write!(f, " at {}:{}:{}", file, self.line, self.column)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for GuestAbort {}
+
+/// Read the AssemblyScript string at `ptr` in guest memory
+///
+/// ```
+/// use hoya::wasm_engine::read_assemblyscript_string;
+///
+/// // "hi": its byte length, then the UTF-16LE code units the pointer (4) points at
+/// let memory = [4, 0, 0, 0, b'h', 0, b'i', 0];
+/// assert_eq!(read_assemblyscript_string(&memory, 4).as_deref(), Some("hi"));
+/// assert_eq!(read_assemblyscript_string(&memory, 0), None);
+/// assert_eq!(read_assemblyscript_string(&memory, 6), None);
+/// ```
+///
+/// # Arguments
+///
+/// * `memory` - The guest's memory
+/// * `ptr` - Pointer to the string's first code unit; 0 is AssemblyScript's `null`
+///
+/// # Returns
+///
+/// * `Option<String>` - The string, cut at `MAX_ABORT_STRING_BYTES` bytes
+///   and with unpaired surrogates replaced, or `None` for `null` and strings
+///   outside the memory
+pub fn read_assemblyscript_string(memory: &[u8], ptr: u32) -> Option<String> {
+    let ptr = ptr as usize;
+    let header = memory.get(ptr.checked_sub(4)?..ptr)?;
+    let len = u32::from_le_bytes(header.try_into().ok()?) as usize;
+    let bytes = memory.get(ptr..ptr.checked_add(len)?)?;
+    let bytes = &bytes[..len.min(MAX_ABORT_STRING_BYTES) & !1];
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+    Some(
+        char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+    )
+}
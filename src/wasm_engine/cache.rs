@@ -0,0 +1,280 @@
+//! Cache of compiled WebAssembly modules.
+//!
+//! Compiling a module is the most expensive step of a wasm execution, and
+//! compiled modules can be instantiated any number of times. Each module is
+//! cached already linked against the shared linker, as an `InstancePre`, so
+//! an execution only creates its store and instantiates; resolving imports
+//! by name happens once per module, not once per request. Modules are keyed
+//! by the SHA-256 digest of their bytes, so a cache hit never depends on the
+//! URL the code came from, and the least recently used module is evicted
+//! once the cache holds too many modules or too many bytes of compiled code.
+//!
+//! Modules registered with `POST /modules` are pinned: they are found like
+//! cached ones but never evicted, and count against neither limit, until
+//! every registration holding them is replaced or deleted.
+//!
+//! With a disk cache, a module missing from memory is loaded from disk before
+//! it is compiled, and every module compiled is written there, so a restarted
+//! server does not compile its modules again.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use wasmtime::{Engine, InstancePre, Linker, Module};
+
+use super::disk_cache::DiskCache;
+use super::imports::{missing_imports, stub_missing_functions, MissingImports};
+use super::WasmCtx;
+
+/// Whether a module came from the cache
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheStatus {
+    /// The module was already compiled
+    Hit,
+    /// The module was compiled before and loaded from the disk cache
+    Disk,
+    /// The module was compiled for this request
+    Miss,
+}
+
+/// A cached module
+struct Entry {
+    /// Hex SHA-256 digest of the module's bytes
+    key: String,
+    /// The compiled module, linked against the shared linker
+    instance_pre: InstancePre<WasmCtx>,
+    /// Size of the module's compiled code, in bytes
+    size: usize,
+}
+
+/// Cache usage, as reported by `GET /stats`
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CacheStats {
+    /// Modules currently cached
+    pub modules: usize,
+    /// Compiled code of the cached modules, in bytes
+    pub bytes: usize,
+    /// Lookups that found the module compiled
+    pub hits: u64,
+    /// Lookups that loaded the module from the disk cache
+    pub disk_hits: u64,
+    /// Lookups that compiled the module
+    pub misses: u64,
+    /// Modules pinned by registrations, outside the limits
+    pub pinned: usize,
+}
+
+/// A module pinned by registrations
+struct Pin {
+    /// The compiled module, linked against the shared linker
+    instance_pre: InstancePre<WasmCtx>,
+    /// Registrations holding the module
+    holders: usize,
+}
+
+/// Bounded least-recently-used cache of compiled modules
+pub struct ModuleCache {
+    /// Maximum number of modules kept; 0 disables caching
+    capacity: usize,
+    /// Maximum compiled code kept, in bytes; 0 disables caching
+    max_bytes: usize,
+    /// Modules, least recently used first
+    modules: Mutex<VecDeque<Entry>>,
+    /// Modules kept for registrations, by digest
+    pinned: Mutex<HashMap<String, Pin>>,
+    /// Serialized modules kept across restarts, if configured
+    disk: Option<DiskCache>,
+    /// Lookups that found the module compiled
+    hits: AtomicU64,
+    /// Lookups that loaded the module from the disk cache
+    disk_hits: AtomicU64,
+    /// Lookups that compiled the module
+    misses: AtomicU64,
+}
+
+impl ModuleCache {
+    /// Create an empty cache keeping at most `capacity` modules and `max_bytes` of compiled code
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - Modules kept in memory
+    /// * `max_bytes` - Compiled code kept in memory, in bytes
+    /// * `disk` - Disk cache consulted before compiling, and written after
+    pub fn new(capacity: usize, max_bytes: usize, disk: Option<DiskCache>) -> Self {
+        ModuleCache {
+            capacity,
+            max_bytes,
+            modules: Mutex::new(VecDeque::with_capacity(capacity)),
+            pinned: Mutex::new(HashMap::new()),
+            disk,
+            hits: AtomicU64::new(0),
+            disk_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Return the linked module for `code`, loading or compiling, linking and caching it on a miss
+    ///
+    /// # Arguments
+    ///
+    /// * `engine` - Engine the module is compiled for
+    /// * `linker` - Linker providing the module's imports, built for `engine`
+    /// * `code_sha256` - Hex SHA-256 digest of `code`, followed by the suffixes
+    ///   of the request's link options if any: the cache key
+    /// * `code` - WebAssembly binary
+    /// * `stub_missing_imports` - Define missing function imports as placeholders
+    ///   instead of failing
+    ///
+    /// # Returns
+    ///
+    /// * `anyhow::Result<(InstancePre<WasmCtx>, CacheStatus)>` - The linked module and
+    ///   where it came from, or the compilation or link error; `MissingImports`
+    ///   when the linker lacks any of the module's imports
+    pub fn get_or_compile(
+        &self,
+        engine: &Engine,
+        linker: &Linker<WasmCtx>,
+        code_sha256: &str,
+        code: &[u8],
+        stub_missing_imports: bool,
+    ) -> anyhow::Result<(InstancePre<WasmCtx>, CacheStatus)> {
+        if let Some(instance_pre) = self.pinned(code_sha256).or_else(|| self.get(code_sha256)) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok((instance_pre, CacheStatus::Hit));
+        }
+
+        // Load or compile and link without holding the lock; concurrent misses
+        // for the same module may both compile it, and the later insert wins.
+        // A module whose imports do not link is not cached.
+        let loaded = self
+            .disk
+            .as_ref()
+            .and_then(|disk| disk.load(engine, code_sha256));
+        let (module, status) = match loaded {
+            Some(module) => {
+                self.disk_hits.fetch_add(1, Ordering::Relaxed);
+                (module, CacheStatus::Disk)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                let module = Module::from_binary(engine, code)?;
+                if let Some(disk) = &self.disk {
+                    // The disk cache only saves work; failing to write it fails nothing
+                    if let Err(e) = disk.store(code_sha256, &module) {
+                        tracing::warn!(error = %e, "Failed to write the module disk cache");
+                    }
+                }
+                (module, CacheStatus::Miss)
+            }
+        };
+        // Every missing import is reported at once, rather than the first
+        // one instantiate_pre would stop at
+        let instance_pre = if stub_missing_imports {
+            let mut stubbed = linker.clone();
+            let unstubbed = stub_missing_functions(&mut stubbed, &module);
+            if !unstubbed.is_empty() {
+                return Err(MissingImports(unstubbed).into());
+            }
+            stubbed.instantiate_pre(&module)?
+        } else {
+            let missing = missing_imports(linker, &module);
+            if !missing.is_empty() {
+                return Err(MissingImports(missing).into());
+            }
+            linker.instantiate_pre(&module)?
+        };
+        self.insert(code_sha256, &instance_pre);
+        Ok((instance_pre, status))
+    }
+
+    /// Current usage and lookup counters
+    pub fn stats(&self) -> CacheStats {
+        let (modules, bytes) = match self.modules.lock() {
+            Ok(modules) => (modules.len(), modules.iter().map(|entry| entry.size).sum()),
+            Err(_) => (0, 0),
+        };
+        CacheStats {
+            modules,
+            bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            disk_hits: self.disk_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            pinned: self.pinned.lock().map(|pinned| pinned.len()).unwrap_or(0),
+        }
+    }
+
+    /// Keep a module for a registration until `unpin` is called as often as `pin`
+    ///
+    /// # Arguments
+    ///
+    /// * `code_sha256` - Hex SHA-256 digest of the module's bytes
+    /// * `instance_pre` - The compiled module, from `get_or_compile`
+    pub fn pin(&self, code_sha256: &str, instance_pre: &InstancePre<WasmCtx>) {
+        if let Ok(mut pinned) = self.pinned.lock() {
+            pinned
+                .entry(code_sha256.to_string())
+                .or_insert_with(|| Pin {
+                    instance_pre: instance_pre.clone(),
+                    holders: 0,
+                })
+                .holders += 1;
+        }
+    }
+
+    /// Release a registration's hold on a module; the last release leaves it
+    /// to the least-recently-used cache alone
+    pub fn unpin(&self, code_sha256: &str) {
+        if let Ok(mut pinned) = self.pinned.lock() {
+            if let Some(pin) = pinned.get_mut(code_sha256) {
+                pin.holders -= 1;
+                if pin.holders == 0 {
+                    pinned.remove(code_sha256);
+                }
+            }
+        }
+    }
+
+    /// Look up a pinned module
+    fn pinned(&self, code_sha256: &str) -> Option<InstancePre<WasmCtx>> {
+        let pinned = self.pinned.lock().ok()?;
+        pinned.get(code_sha256).map(|pin| pin.instance_pre.clone())
+    }
+
+    /// Look up a module and mark it as most recently used
+    fn get(&self, code_sha256: &str) -> Option<InstancePre<WasmCtx>> {
+        let mut modules = self.modules.lock().ok()?;
+        let index = modules.iter().position(|entry| entry.key == code_sha256)?;
+        let entry = modules.remove(index)?;
+        let instance_pre = entry.instance_pre.clone();
+        modules.push_back(entry);
+        Some(instance_pre)
+    }
+
+    /// Add a module, evicting least recently used ones until it fits
+    ///
+    /// A module larger than the whole byte budget is not cached.
+    fn insert(&self, code_sha256: &str, instance_pre: &InstancePre<WasmCtx>) {
+        let range = instance_pre.module().image_range();
+        let size = (range.end as usize).saturating_sub(range.start as usize);
+        if self.capacity == 0 || size > self.max_bytes {
+            return;
+        }
+        if let Ok(mut modules) = self.modules.lock() {
+            modules.retain(|entry| entry.key != code_sha256);
+            let mut bytes: usize = modules.iter().map(|entry| entry.size).sum();
+            while modules.len() >= self.capacity || bytes + size > self.max_bytes {
+                match modules.pop_front() {
+                    Some(evicted) => bytes -= evicted.size,
+                    None => break,
+                }
+            }
+            modules.push_back(Entry {
+                key: code_sha256.to_string(),
+                instance_pre: instance_pre.clone(),
+                size,
+            });
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! Compiled WebAssembly modules kept on disk across restarts.
+//!
+//! The in-memory module cache starts empty with every process, so without
+//! this every module recompiles after a restart. With `wasm.disk_cache_path`
+//! set, each module compiled is also serialized into that directory, and a
+//! module missing from memory is deserialized from there before it is
+//! compiled. Files are named after the code digest and a fingerprint of the
+//! engine (wasmtime's version and the engine configuration), so a new
+//! wasmtime or engine setting never loads artifacts built by another; those
+//! age out like any other file.
+//!
+//! Each file starts with the SHA-256 of the serialized module. A file that
+//! is truncated, does not match its checksum or that wasmtime refuses to
+//! deserialize is deleted and the module compiled again; a broken cache never
+//! fails a request. Once the directory holds more than
+//! `wasm.disk_cache_max_bytes`, the files used longest ago (by modification
+//! time, refreshed on every load) are deleted first.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use wasmtime::{Engine, Module};
+
+/// Extension of cache files
+const EXTENSION: &str = "cwasm";
+
+/// Bytes of the checksum heading every cache file
+const CHECKSUM_BYTES: usize = 32;
+
+/// Feeds `Hash` implementations into SHA-256, whose output, unlike
+/// `DefaultHasher`'s, is the same for every build
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap_or_default())
+    }
+}
+
+/// Directory of serialized modules, bounded in bytes
+pub struct DiskCache {
+    /// Directory holding the cache files
+    dir: PathBuf,
+    /// Bytes of cache files kept
+    max_bytes: u64,
+    /// Hex digest of the engine's compatibility hash, part of every file name
+    fingerprint: String,
+}
+
+impl DiskCache {
+    /// Open the cache directory, creating it if needed, for modules compiled by `engine`
+    ///
+    /// ```
+    /// use hoya::wasm_engine::DiskCache;
+    /// use wasmtime::{Config, Engine, Module};
+    ///
+    /// let dir = std::env::temp_dir().join(format!("hoya-disk-cache-{}", std::process::id()));
+    /// let engine = Engine::default();
+    /// let module = Module::new(&engine, "(module (func (export \"_start\")))").unwrap();
+    /// DiskCache::open(&dir, 1 << 20, &engine).unwrap().store("ab12", &module).unwrap();
+    ///
+    /// // A new instance, as after a restart, deserializes the stored module
+    /// let reopened = DiskCache::open(&dir, 1 << 20, &engine).unwrap();
+    /// assert!(reopened.load(&engine, "ab12").is_some());
+    /// assert!(reopened.load(&engine, "cd34").is_none());
+    ///
+    /// // An engine configured differently never loads it
+    /// let other = Engine::new(Config::new().consume_fuel(true)).unwrap();
+    /// assert!(DiskCache::open(&dir, 1 << 20, &other).unwrap().load(&other, "ab12").is_none());
+    ///
+    /// // A truncated file is dropped instead of loaded
+    /// for entry in std::fs::read_dir(&dir).unwrap() {
+    ///     let file = std::fs::OpenOptions::new().write(true).open(entry.unwrap().path()).unwrap();
+    ///     file.set_len(40).unwrap();
+    /// }
+    /// assert!(reopened.load(&engine, "ab12").is_none());
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory holding the cache files
+    /// * `max_bytes` - Bytes of cache files kept
+    /// * `engine` - Engine the modules are compiled for
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<DiskCache>` - The cache, or the error creating the directory
+    pub fn open(dir: &Path, max_bytes: u64, engine: &Engine) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut hasher = Sha256Hasher(Sha256::new());
+        engine.precompile_compatibility_hash().hash(&mut hasher);
+        let fingerprint = format!("{:x}", hasher.0.finalize());
+        Ok(DiskCache {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            fingerprint: fingerprint[..16].to_string(),
+        })
+    }
+
+    /// Deserialize the module stored for `code_sha256`
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Module>` - The module, or `None` when no usable file is
+    ///   stored; an unusable one is deleted
+    pub fn load(&self, engine: &Engine, code_sha256: &str) -> Option<Module> {
+        let path = self.path(code_sha256);
+        let file = fs::read(&path).ok()?;
+        let intact = file.len() > CHECKSUM_BYTES
+            && file[..CHECKSUM_BYTES] == Sha256::digest(&file[CHECKSUM_BYTES..])[..];
+        if !intact {
+            tracing::warn!(path = %path.display(), "Dropping a corrupted module cache file");
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        // SAFETY: the bytes are a module this cache serialized for an engine
+        // with the same fingerprint, checked against the checksum written
+        // with them; wasmtime still validates the header and refuses
+        // artifacts of other versions or configurations.
+        match unsafe { Module::deserialize(engine, &file[CHECKSUM_BYTES..]) } {
+            Ok(module) => {
+                // Loads keep the file among the most recently used
+                if let Ok(file) = fs::File::options().write(true).open(&path) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+                Some(module)
+            }
+            Err(e) => {
+                tracing::warn!(
+                    path = %path.display(),
+                    error = %e,
+                    "Dropping an unusable module cache file"
+                );
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    /// Serialize a compiled module for `code_sha256`, then delete the least
+    /// recently used files until the cache fits `max_bytes`
+    ///
+    /// The file is written under a temporary name and renamed into place,
+    /// so a concurrent load never sees it half written. A module larger than
+    /// the whole budget is not stored.
+    ///
+    /// # Returns
+    ///
+    /// * `anyhow::Result<()>` - Nothing, or the error serializing or writing the module
+    pub fn store(&self, code_sha256: &str, module: &Module) -> anyhow::Result<()> {
+        let serialized = module.serialize()?;
+        let size = (CHECKSUM_BYTES + serialized.len()) as u64;
+        if size > self.max_bytes {
+            return Ok(());
+        }
+        let mut file = Vec::with_capacity(size as usize);
+        file.extend_from_slice(&Sha256::digest(&serialized));
+        file.extend_from_slice(&serialized);
+        let path = self.path(code_sha256);
+        let temporary = self
+            .dir
+            .join(format!(".{}.{}.tmp", code_sha256, uuid::Uuid::new_v4()));
+        fs::write(&temporary, &file)?;
+        if let Err(e) = fs::rename(&temporary, &path) {
+            let _ = fs::remove_file(&temporary);
+            return Err(e.into());
+        }
+        self.evict()?;
+        Ok(())
+    }
+
+    /// Path of the file holding the module for `code_sha256`
+    fn path(&self, code_sha256: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{}-{}.{}",
+            code_sha256, self.fingerprint, EXTENSION
+        ))
+    }
+
+    /// Delete the files used longest ago until the cache fits `max_bytes`
+    fn evict(&self) -> io::Result<()> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((modified, metadata.len(), path));
+        }
+        let mut bytes: u64 = files.iter().map(|(_, size, _)| size).sum();
+        files.sort();
+        for (_, size, path) in files {
+            if bytes <= self.max_bytes {
+                break;
+            }
+            // Another process sharing the directory may have deleted it already
+            let _ = fs::remove_file(&path);
+            bytes -= size;
+        }
+        Ok(())
+    }
+}
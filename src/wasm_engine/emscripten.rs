@@ -0,0 +1,213 @@
+//! Import shims for modules built with Emscripten (`emcc`).
+//!
+//! Even a standalone `emcc` build imports a few functions from `env` that
+//! Emscripten's JavaScript glue would otherwise provide: heap growth
+//! notifications, `abort` and the `__assert_fail` of failed C assertions.
+//! Requests setting `compat: "emscripten"` link against a second linker
+//! holding everything the shared one does, WASI included, plus minimal
+//! versions of those. The shims shadow the AssemblyScript `env.abort`, whose
+//! signature differs, so the two cannot share one linker. Anything else the
+//! module imports from Emscripten's glue is still a missing import.
+
+use anyhow::{anyhow, Result as AnyhowResult};
+use serde::{Deserialize, Serialize};
+use wasmtime::{Caller, Linker};
+
+use super::abort::{GuestAbort, MAX_ABORT_STRING_BYTES};
+use super::ffis::exported_memory;
+use super::memory::MemoryLimitExceeded;
+use super::WasmCtx;
+
+/// Names of the `env` functions `register_emscripten_shims` defines
+pub const EMSCRIPTEN_SHIMS: &[&str] = &[
+    "emscripten_notify_memory_growth",
+    "emscripten_resize_heap",
+    "emscripten_memcpy_big",
+    "_emscripten_memcpy_js",
+    "emscripten_date_now",
+    "__assert_fail",
+    "abort",
+];
+
+/// Size of a WebAssembly page in bytes
+const PAGE_BYTES: u64 = 65536;
+
+/// Toolchain whose host imports a request asks to be shimmed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compat {
+    /// The `env` imports of standalone Emscripten builds
+    Emscripten,
+}
+
+impl Compat {
+    /// Suffix telling this compat's modules apart from plain ones in the module cache
+    pub fn cache_suffix(self) -> &'static str {
+        match self {
+            Compat::Emscripten => "+emscripten",
+        }
+    }
+}
+
+/// Read the NUL-terminated C string at `ptr` in guest memory
+///
+/// ```
+/// use hoya::wasm_engine::read_c_string;
+///
+/// let memory = b"\0x > 0\0main.c";
+/// assert_eq!(read_c_string(memory, 1).as_deref(), Some("x > 0"));
+/// assert_eq!(read_c_string(memory, 0), None);
+/// // Unterminated strings end with the memory
+/// assert_eq!(read_c_string(memory, 7).as_deref(), Some("main.c"));
+/// ```
+///
+/// # Arguments
+///
+/// * `memory` - The guest's memory
+/// * `ptr` - Pointer to the string's first byte; 0 is C's `NULL`
+///
+/// # Returns
+///
+/// * `Option<String>` - The string, cut at `MAX_ABORT_STRING_BYTES` bytes and
+///   with invalid UTF-8 replaced, or `None` for `NULL` and pointers outside the memory
+pub fn read_c_string(memory: &[u8], ptr: u32) -> Option<String> {
+    if ptr == 0 {
+        return None;
+    }
+    let bytes = memory.get(ptr as usize..)?;
+    let bytes = &bytes[..bytes.len().min(MAX_ABORT_STRING_BYTES)];
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
+
+/// Define the Emscripten shims in `linker`, over any function of the same name
+///
+/// # Arguments
+///
+/// * `linker` - A copy of the shared linker
+///
+/// # Returns
+///
+/// * `anyhow::Result<()>` - Nothing, or the error defining a shim
+pub fn register_emscripten_shims(linker: &mut Linker<WasmCtx>) -> AnyhowResult<()> {
+    linker.allow_shadowing(true);
+
+    // The host looks the memory up on every call, so growth needs no bookkeeping
+    linker.func_wrap(
+        "env",
+        "emscripten_notify_memory_growth",
+        |_caller: Caller<'_, WasmCtx>, _memory_index: u32| {},
+    )?;
+
+    // Called by malloc when the heap is full; 1 on success, 0 when the memory cannot grow
+    linker.func_wrap(
+        "env",
+        "emscripten_resize_heap",
+        |mut caller: Caller<'_, WasmCtx>, requested_bytes: u32| -> AnyhowResult<i32> {
+            let memory = exported_memory(&mut caller, "emscripten_resize_heap")?;
+            let current = memory.data_size(&caller) as u64;
+            let requested = requested_bytes as u64;
+            if requested <= current {
+                return Ok(1);
+            }
+            let pages = (requested - current).div_ceil(PAGE_BYTES);
+            match memory.grow(&mut caller, pages) {
+                Ok(_) => Ok(1),
+                // Past the execution's memory limit the module fails as if it ran `memory.grow`
+                Err(e) if e.is::<MemoryLimitExceeded>() => Err(e),
+                Err(_) => Ok(0),
+            }
+        },
+    )?;
+
+    // Older releases import emscripten_memcpy_big, newer ones _emscripten_memcpy_js
+    for name in ["emscripten_memcpy_big", "_emscripten_memcpy_js"] {
+        linker.func_wrap(
+            "env",
+            name,
+            move |mut caller: Caller<'_, WasmCtx>,
+                  dest: u32,
+                  src: u32,
+                  len: u32|
+                  -> AnyhowResult<()> {
+                let memory = exported_memory(&mut caller, name)?;
+                let data = memory.data_mut(&mut caller);
+                let src = src as usize..src as usize + len as usize;
+                if src.end > data.len() || dest as usize + len as usize > data.len() {
+                    return Err(anyhow!("{}: pointer/length out of bounds", name));
+                }
+                data.copy_within(src, dest as usize);
+                Ok(())
+            },
+        )?;
+    }
+
+    linker.func_wrap(
+        "env",
+        "emscripten_date_now",
+        |caller: Caller<'_, WasmCtx>| -> AnyhowResult<f64> {
+            caller
+                .data()
+                .clock
+                .unix_ms()
+                .map(|ms| ms as f64)
+                .ok_or_else(|| anyhow!("emscripten_date_now: Failed to get system time"))
+        },
+    )?;
+
+    // A failed C assert(): ends the run with GUEST_ABORT carrying the assertion's text
+    linker.func_wrap(
+        "env",
+        "__assert_fail",
+        |mut caller: Caller<'_, WasmCtx>,
+         condition_ptr: u32,
+         file_ptr: u32,
+         line: u32,
+         function_ptr: u32|
+         -> AnyhowResult<()> {
+            let (condition, file, function) = match exported_memory(&mut caller, "__assert_fail") {
+                Ok(memory) => {
+                    let data = memory.data(&caller);
+                    (
+                        read_c_string(data, condition_ptr),
+                        read_c_string(data, file_ptr),
+                        read_c_string(data, function_ptr),
+                    )
+                }
+                Err(_) => (None, None, None),
+            };
+            let mut message = format!(
+                "Assertion failed: {}",
+                condition.as_deref().unwrap_or("unknown condition")
+            );
+            if let Some(function) = function {
+                message.push_str(&format!(" in {}", function));
+            }
+            Err(GuestAbort {
+                message: Some(message),
+                file,
+                line,
+                column: 0,
+            }
+            .into())
+        },
+    )?;
+
+    // C's abort() takes no arguments, unlike AssemblyScript's
+    linker.func_wrap(
+        "env",
+        "abort",
+        |_caller: Caller<'_, WasmCtx>| -> AnyhowResult<()> {
+            Err(GuestAbort {
+                message: Some("abort() called".to_string()),
+                file: None,
+                line: 0,
+                column: 0,
+            }
+            .into())
+        },
+    )?;
+
+    linker.allow_shadowing(false);
+    Ok(())
+}
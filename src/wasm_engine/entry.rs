@@ -0,0 +1,153 @@
+//! The functions a module's execution starts from.
+//!
+//! A command module exports `_start`, taking and returning nothing. Modules
+//! following the reactor convention export `_initialize`, which is called
+//! first, before any other entry. Without `_start`, a module exporting
+//! `__main_void() -> i32` or `main`, as `main() -> i32` or
+//! `main(argc: i32, argv: i32) -> i32` (called with no arguments, 0 and 0),
+//! runs that instead, and the value it returns is its exit code. An export
+//! by one of these names that is not a function of the expected type fails
+//! the execution instead of being skipped, so a module never reports
+//! success without having run.
+
+use wasmtime::{Func, Instance, Store, TypedFunc};
+
+use super::imports::{describe_extern, describe_func};
+use super::WasmCtx;
+use crate::error::AppError;
+
+/// The entry function a module runs, by precedence
+pub enum Entry {
+    /// `_start() -> ()`
+    Start(TypedFunc<(), ()>),
+    /// `__main_void() -> i32`
+    MainVoid(TypedFunc<(), i32>),
+    /// `main() -> i32`
+    Main(TypedFunc<(), i32>),
+    /// `main(argc: i32, argv: i32) -> i32`
+    MainArgs(TypedFunc<(i32, i32), i32>),
+}
+
+impl Entry {
+    /// Name of the exported function
+    pub fn name(&self) -> &'static str {
+        match self {
+            Entry::Start(_) => "_start",
+            Entry::MainVoid(_) => "__main_void",
+            Entry::Main(_) | Entry::MainArgs(_) => "main",
+        }
+    }
+}
+
+/// The functions an execution calls, in order
+pub struct Entrypoints {
+    /// `_initialize() -> ()` of a reactor, called before the entry
+    pub initialize: Option<TypedFunc<(), ()>>,
+    /// The entry function, if the module exports one
+    pub entry: Option<Entry>,
+}
+
+impl Entrypoints {
+    /// Find the entry functions an instance exports
+    ///
+    /// # Arguments
+    ///
+    /// * `instance` - The instantiated module
+    /// * `store` - The store the instance lives in
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Entrypoints, AppError>` - The functions to call, or
+    ///   `InvalidEntrypoint` when an export by one of their names has another type
+    pub fn of(instance: &Instance, store: &mut Store<WasmCtx>) -> Result<Self, AppError> {
+        let initialize = match export(instance, store, "_initialize")? {
+            Some(func) => Some(typed(func, store, "_initialize", "func()")?),
+            None => None,
+        };
+        let entry = if let Some(func) = export(instance, store, "_start")? {
+            Some(Entry::Start(typed(func, store, "_start", "func()")?))
+        } else if let Some(func) = export(instance, store, "__main_void")? {
+            Some(Entry::MainVoid(typed(
+                func,
+                store,
+                "__main_void",
+                "func() -> i32",
+            )?))
+        } else if let Some(func) = export(instance, store, "main")? {
+            if let Ok(main) = func.typed::<(), i32>(&*store) {
+                Some(Entry::Main(main))
+            } else {
+                Some(Entry::MainArgs(typed(
+                    func,
+                    store,
+                    "main",
+                    "func() -> i32 or func(i32, i32) -> i32",
+                )?))
+            }
+        } else {
+            None
+        };
+        Ok(Entrypoints { initialize, entry })
+    }
+
+    /// Call `_initialize`, then the entry
+    ///
+    /// # Returns
+    ///
+    /// * `anyhow::Result<Option<i32>>` - The exit code `__main_void` or `main`
+    ///   returned, `None` for `_start` or without an entry, or the error the
+    ///   guest failed with
+    pub async fn run(&self, store: &mut Store<WasmCtx>) -> anyhow::Result<Option<i32>> {
+        if let Some(initialize) = &self.initialize {
+            initialize.call_async(&mut *store, ()).await?;
+        }
+        match &self.entry {
+            None => Ok(None),
+            Some(Entry::Start(start)) => start.call_async(store, ()).await.map(|()| None),
+            Some(Entry::MainVoid(main) | Entry::Main(main)) => {
+                main.call_async(store, ()).await.map(Some)
+            }
+            Some(Entry::MainArgs(main)) => main.call_async(store, (0, 0)).await.map(Some),
+        }
+    }
+}
+
+/// The export named `name` as a function, `None` when there is no such export
+fn export(
+    instance: &Instance,
+    store: &mut Store<WasmCtx>,
+    name: &str,
+) -> Result<Option<Func>, AppError> {
+    match instance.get_export(&mut *store, name) {
+        None => Ok(None),
+        Some(export) => {
+            let ty = export.ty(&*store);
+            match export.into_func() {
+                Some(func) => Ok(Some(func)),
+                None => Err(AppError::InvalidEntrypoint {
+                    export: name.to_string(),
+                    signature: describe_extern(&ty).to_string(),
+                    expected: "a function".to_string(),
+                }),
+            }
+        }
+    }
+}
+
+/// `func` with the parameters and results of `expected`, or `InvalidEntrypoint` naming its own
+fn typed<Params, Results>(
+    func: Func,
+    store: &Store<WasmCtx>,
+    name: &str,
+    expected: &str,
+) -> Result<TypedFunc<Params, Results>, AppError>
+where
+    Params: wasmtime::WasmParams,
+    Results: wasmtime::WasmResults,
+{
+    func.typed(store).map_err(|_| AppError::InvalidEntrypoint {
+        export: name.to_string(),
+        signature: describe_func(&func.ty(store)),
+        expected: expected.to_string(),
+    })
+}
@@ -0,0 +1,188 @@
+//! Epoch-based interruption for WebAssembly execution.
+//!
+//! A single background thread increments the shared Engine's epoch at a
+//! fixed tick. Every store's epoch deadline is the next tick, where its
+//! epoch callback decides whether the execution is interrupted, because it
+//! was cancelled or ran past its deadline, or yields to the runtime and
+//! keeps running for another tick. The tick length is therefore the
+//! granularity of every wasm timeout and cancellation, and the longest a
+//! guest computing without calling the host holds a runtime worker.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+use wasmtime::{
+    Config, Engine, InstancePre, Linker, StoreContextMut, UpdateDeadline, WasmBacktraceDetails,
+};
+
+use super::cache::{CacheStatus, ModuleCache};
+use super::disk_cache::DiskCache;
+use super::emscripten::{register_emscripten_shims, Compat};
+use super::ffis::register_linker_functions;
+use super::WasmCtx;
+use crate::config::WasmConfig;
+
+/// Shortest accepted epoch tick in milliseconds
+pub const MIN_EPOCH_TICK_MS: u64 = 1;
+/// Longest accepted epoch tick in milliseconds
+pub const MAX_EPOCH_TICK_MS: u64 = 1000;
+
+/// Why a running wasm execution was interrupted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    /// The execution ran past its deadline
+    Timeout,
+    /// The guest exhausted a strict output budget
+    OutputBudgetExceeded,
+    /// The execution was cancelled
+    Cancelled,
+}
+
+impl fmt::Display for Interrupt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interrupt::Timeout => write!(f, "execution timed out"),
+            Interrupt::OutputBudgetExceeded => write!(f, "output budget exceeded"),
+            Interrupt::Cancelled => write!(f, "execution cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Interrupt {}
+
+/// A wasmtime Engine shared by all executions, driven by one epoch ticker thread
+pub struct SharedEngine {
+    /// Engine with epoch interruption and fuel consumption enabled
+    pub engine: Engine,
+    /// The `env` host functions and WASI preview1, defined once for every module
+    pub linker: Linker<WasmCtx>,
+    /// `linker` with the Emscripten shims, for requests setting `compat: "emscripten"`
+    pub emscripten_linker: Linker<WasmCtx>,
+    /// Modules compiled for `engine` and linked, keyed by code digest, with a
+    /// suffix for each link option other than plain `linker`
+    pub modules: ModuleCache,
+    /// Tables a module may have
+    pub max_tables: usize,
+    /// Module instances a store may hold
+    pub max_instances: usize,
+}
+
+impl SharedEngine {
+    /// Create the shared Engine and start its epoch ticker thread
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The `[wasm]` section: epoch tick, module caches and store limits
+    ///
+    /// # Returns
+    ///
+    /// * `anyhow::Result<SharedEngine>` - The shared engine or an error
+    pub fn new(config: &WasmConfig) -> anyhow::Result<Self> {
+        let epoch_tick = Duration::from_millis(config.epoch_tick_ms);
+        let mut engine_config = Config::new();
+        engine_config.epoch_interruption(true);
+        // Every store is given `limits.max_fuel`, which caps work independently of time
+        engine_config.consume_fuel(true);
+        // Guests run as futures, so a guest waiting on a fetch call holds no thread
+        engine_config.async_support(true);
+        // Trap backtraces name source files and lines of modules built with debug info
+        engine_config.wasm_backtrace_details(WasmBacktraceDetails::Enable);
+        let engine = Engine::new(&engine_config)?;
+
+        // Modules that import no WASI function are unaffected by it being defined
+        let mut linker = Linker::new(&engine);
+        register_linker_functions(&mut linker)?;
+        wasmtime_wasi::p1::add_to_linker_async(&mut linker, |ctx: &mut WasmCtx| &mut ctx.wasi)?;
+        let mut emscripten_linker = linker.clone();
+        register_emscripten_shims(&mut emscripten_linker)?;
+
+        let ticker_engine = engine.clone();
+        std::thread::Builder::new()
+            .name("hoya-epoch-ticker".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(epoch_tick);
+                ticker_engine.increment_epoch();
+            })?;
+
+        let disk = match &config.disk_cache_path {
+            Some(path) => Some(
+                DiskCache::open(path, config.disk_cache_max_bytes, &engine).map_err(|e| {
+                    anyhow::anyhow!(
+                        "Failed to open the module disk cache {}: {}",
+                        path.display(),
+                        e
+                    )
+                })?,
+            ),
+            None => None,
+        };
+
+        Ok(SharedEngine {
+            engine,
+            linker,
+            emscripten_linker,
+            modules: ModuleCache::new(
+                config.module_cache_capacity,
+                config.module_cache_max_bytes,
+                disk,
+            ),
+            max_tables: config.max_tables,
+            max_instances: config.max_instances,
+        })
+    }
+
+    /// Compiled and linked module for `code`, from the cache when possible
+    ///
+    /// A store only needs `instantiate` on the result; the module's imports
+    /// were resolved against the linker of `compat` when it was first cached.
+    /// Modules linked with stubs are cached apart from the same bytes linked
+    /// without, as are those of each compat.
+    ///
+    /// # Arguments
+    ///
+    /// * `code_sha256` - Hex SHA-256 digest of `code`
+    /// * `code` - WebAssembly binary
+    /// * `compat` - Toolchain whose imports are shimmed, if any
+    /// * `stub_missing_imports` - Define missing function imports as placeholders
+    ///
+    /// # Returns
+    ///
+    /// * `anyhow::Result<(InstancePre<WasmCtx>, CacheStatus)>` - The linked module and
+    ///   where it came from, or the compilation or link error
+    pub fn instance_pre(
+        &self,
+        code_sha256: &str,
+        code: &[u8],
+        compat: Option<Compat>,
+        stub_missing_imports: bool,
+    ) -> anyhow::Result<(InstancePre<WasmCtx>, CacheStatus)> {
+        let mut key = code_sha256.to_string();
+        let linker = match compat {
+            None => &self.linker,
+            Some(compat) => {
+                key.push_str(compat.cache_suffix());
+                &self.emscripten_linker
+            }
+        };
+        if stub_missing_imports {
+            key.push_str("+stubs");
+        }
+        self.modules
+            .get_or_compile(&self.engine, linker, &key, code, stub_missing_imports)
+    }
+}
+
+/// Epoch deadline callback installed on every store, called once per tick
+///
+/// Ticks only approximate wall-clock time, so the callback checks the real
+/// deadline, after the execution's cancel token, and keeps the guest running
+/// one more tick at a time until either stops it. Between ticks the guest
+/// yields, letting the runtime poll other executions on the same worker.
+pub fn on_epoch_deadline(ctx: StoreContextMut<'_, WasmCtx>) -> anyhow::Result<UpdateDeadline> {
+    if ctx.data().fetch.cancel.is_cancelled() {
+        Err(Interrupt::Cancelled.into())
+    } else if Instant::now() < ctx.data().deadline {
+        Ok(UpdateDeadline::Yield(1))
+    } else {
+        Err(Interrupt::Timeout.into())
+    }
+}
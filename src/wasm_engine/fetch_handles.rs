@@ -0,0 +1,84 @@
+//! Guest fetch responses held for reading in pieces.
+//!
+//! The single-shot `fetch` needs a buffer large enough for the whole
+//! response; when it is too small the response is lost and a retry sends the
+//! request again. `fetch_begin` instead keeps the serialized response under a
+//! handle, so the guest can ask for its size with `fetch_len` and copy it out
+//! with `fetch_read` in buffers of any size, then release it with
+//! `fetch_close`. A store holds at most `MAX_OPEN_RESPONSES` responses at
+//! once. Handles are never reused, and all of them go away with the store at
+//! the end of the execution.
+
+use std::collections::HashMap;
+
+/// Responses a store may hold open at once
+const MAX_OPEN_RESPONSES: usize = 16;
+
+/// A response being read
+struct OpenResponse {
+    /// The serialized `WasmFetchResponse`
+    bytes: Vec<u8>,
+    /// Bytes already copied out by `fetch_read`
+    read: usize,
+}
+
+/// Open responses of one execution, by handle
+#[derive(Default)]
+pub struct FetchHandles {
+    /// Responses not closed yet
+    open: HashMap<i32, OpenResponse>,
+    /// The handle given out last; handles start at 1
+    last_handle: i32,
+}
+
+impl FetchHandles {
+    /// Whether `MAX_OPEN_RESPONSES` responses are open already
+    pub fn is_full(&self) -> bool {
+        self.open.len() >= MAX_OPEN_RESPONSES
+    }
+
+    /// Hold a response for reading
+    ///
+    /// # Returns
+    ///
+    /// * `Option<i32>` - The response's handle, or `None` if no more responses may be held
+    pub fn insert(&mut self, bytes: Vec<u8>) -> Option<i32> {
+        if self.is_full() {
+            return None;
+        }
+        let handle = self.last_handle.checked_add(1)?;
+        self.last_handle = handle;
+        self.open.insert(handle, OpenResponse { bytes, read: 0 });
+        Some(handle)
+    }
+
+    /// Size of an open response in bytes
+    pub fn response_len(&self, handle: i32) -> Option<usize> {
+        self.open.get(&handle).map(|response| response.bytes.len())
+    }
+
+    /// Take the next unread bytes of an open response, at most `max_len` of them
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Vec<u8>>` - The bytes, empty once the response is read; `None` if the handle is not open
+    pub fn read(&mut self, handle: i32, max_len: usize) -> Option<Vec<u8>> {
+        let response = self.open.get_mut(&handle)?;
+        let end = response
+            .bytes
+            .len()
+            .min(response.read.saturating_add(max_len));
+        let piece = response.bytes[response.read..end].to_vec();
+        response.read = end;
+        Some(piece)
+    }
+
+    /// Release an open response
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the handle was open
+    pub fn close(&mut self, handle: i32) -> bool {
+        self.open.remove(&handle).is_some()
+    }
+}
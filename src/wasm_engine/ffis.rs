@@ -1,307 +1,998 @@
 //! This module provides Foreign Function Interface (FFI) functions for WebAssembly modules.
 //! It registers functions that can be called from WebAssembly code, such as logging,
-//! time utilities, and HTTP fetch functionality.
+//! returning a result, time utilities, HTTP fetch, persistent storage, the
+//! host key-value store, random bytes and the request's environment variables and input,
+//! and the `abort` import of AssemblyScript modules.
 
 use anyhow::{anyhow, Result as AnyhowResult};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
-use wasmtime::{Caller, Linker};
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use wasmtime::{Caller, Extern, Linker, Memory};
 
+use super::abort::{read_assemblyscript_string, GuestAbort};
+use super::epoch::Interrupt;
 use super::WasmCtx;
+use crate::compression::{self, InflateError};
+use crate::kv::KvError;
+use crate::limits::MAX_SLEEP_MS_PER_CALL;
+use crate::net;
+use crate::output::{self, Channel, LogEntry, LogLevel, LogSource};
+use crate::random::{RandomError, MAX_RANDOM_BYTES_PER_CALL};
+use crate::rfc3339;
+use crate::storage::StorageError;
 
-/// Data structures for Wasm fetch communication (JSON)
-/// 
-/// These are duplicates from main.rs. Consider moving them to a shared module
-/// or passing them as part of WasmCtx if they are only used by these FFI functions.
-#[derive(Serialize, Deserialize, Debug)]
-struct WasmFetchOptions {
-    /// URL to send the request to
-    url: String,
-    /// HTTP method (e.g., "GET", "POST")
-    method: String,
-    /// HTTP headers
-    headers: HashMap<String, String>,
-    /// Optional request body as string, could be base64 for binary data
-    body: Option<String>,
+/// Returned by `gzip_decompress` when the input is not a valid gzip stream
+const GZIP_ERR_CORRUPT: i32 = i32::MIN;
+
+/// Returned by `gzip_decompress` when the output would exceed `MAX_GUEST_DECOMPRESSED_SIZE`
+const GZIP_ERR_TOO_LARGE: i32 = i32::MIN + 1;
+
+/// Returned by `format_rfc3339` when the time is outside the representable range
+const TIME_ERR_OUT_OF_RANGE: i32 = i32::MIN;
+
+/// Returned by `parse_rfc3339` when the input is not a valid RFC 3339 timestamp
+const TIME_ERR_INVALID: i64 = i64::MIN;
+
+/// Returned by `fetch_read`, `fetch_len` and `fetch_close` when the handle is not open
+const FETCH_ERR_INVALID_HANDLE: i32 = i32::MIN;
+
+/// Returned by `fetch_begin` when `MAX_OPEN_RESPONSES` responses are open already
+const FETCH_ERR_TOO_MANY_OPEN: i32 = i32::MIN + 1;
+
+/// Returned by `pkv_get` when the key is not stored
+const PKV_ERR_NOT_FOUND: i32 = i32::MIN;
+
+/// Returned by the `pkv_*` functions when the execution was not granted `storage`
+const PKV_ERR_DENIED: i32 = i32::MIN + 1;
+
+/// Returned by the `pkv_*` functions when the key is empty or too long
+const PKV_ERR_INVALID_KEY: i32 = i32::MIN + 2;
+
+/// Returned by `pkv_set` when the write would exceed the namespace's quota
+const PKV_ERR_QUOTA: i32 = i32::MIN + 3;
+
+/// Returned by `kv_get` when the key is not stored, or its entry expired
+const KV_ERR_NOT_FOUND: i32 = i32::MIN;
+
+/// Returned by the `kv_*` functions when the key is empty or too long
+const KV_ERR_INVALID_KEY: i32 = i32::MIN + 1;
+
+/// Returned by `kv_set` and `kv_set_ttl` when the write would exceed a quota
+const KV_ERR_QUOTA: i32 = i32::MIN + 2;
+
+/// Returned by `sleep_ms` when the execution's deadline came before the sleep's end
+const SLEEP_CUT_BY_DEADLINE: i32 = 1;
+
+/// Returned by `sleep_ms` after sleeping `MAX_SLEEP_MS_PER_CALL` of a longer sleep
+const SLEEP_CAPPED: i32 = 2;
+
+/// How often a sleeping guest checks whether its execution was cancelled
+const SLEEP_CANCEL_POLL: Duration = Duration::from_millis(10);
+
+/// Returned by `random_bytes` when more than `MAX_RANDOM_BYTES_PER_CALL` bytes are asked for
+const RANDOM_ERR_TOO_LARGE: i32 = i32::MIN;
+
+/// Returned by `random_bytes` once the execution drew `MAX_RANDOM_BYTES_PER_EXECUTION` bytes
+const RANDOM_ERR_BUDGET: i32 = i32::MIN + 1;
+
+/// Returned by `env_get` when the variable is not set, and by `env_key_at` past the last variable
+///
+/// A one-byte value does not fit an empty buffer either, so guests asking for
+/// a value pass a buffer of at least one byte to tell the two apart.
+const ENV_ERR_NOT_FOUND: i32 = -1;
+
+/// Translate a refused key-value operation into its `kv_*` error code
+fn kv_status(error: KvError) -> i32 {
+    match error {
+        KvError::InvalidKey => KV_ERR_INVALID_KEY,
+        KvError::QuotaExceeded { .. } | KvError::TooManyNamespaces { .. } => KV_ERR_QUOTA,
+    }
+}
+
+/// Translate a refused storage operation into its `pkv_*` error code, trapping on database failures
+fn storage_status(name: &str, error: StorageError) -> AnyhowResult<i32> {
+    match error {
+        StorageError::InvalidKey => Ok(PKV_ERR_INVALID_KEY),
+        StorageError::QuotaExceeded { .. } => Ok(PKV_ERR_QUOTA),
+        StorageError::Backend(message) => Err(anyhow!("{}: {}", name, message)),
+    }
+}
+
+/// Range of `len` bytes at `ptr` in guest memory
+///
+/// Traps when the range ends past the 32-bit address space, rather than
+/// letting `ptr + len` wrap around to a small, wrong range.
+fn guest_range(name: &str, ptr: u32, len: usize) -> AnyhowResult<Range<usize>> {
+    u32::try_from(len)
+        .ok()
+        .and_then(|len| ptr.checked_add(len))
+        .map(|end| ptr as usize..end as usize)
+        .ok_or_else(|| anyhow!("{}: pointer/length overflow", name))
 }
 
-/// HTTP response data for WebAssembly modules
-#[derive(Serialize, Deserialize, Debug)]
-struct WasmFetchResponse {
-    /// HTTP status code
-    status: u16,
-    /// Response headers
-    headers: HashMap<String, String>,
-    /// Response body as text or base64-encoded binary
-    body: String,
-    /// Optional error information
-    error: Option<WasmFetchError>,
+/// The memory the calling module exports, resolved through the caller
+///
+/// Host functions look the memory up on every call rather than keeping it in
+/// `WasmCtx`, so instances can be created from a pre-linked `InstancePre`
+/// without patching the context after instantiation; only the export's
+/// name, chosen from the module before it is instantiated, is kept there.
+pub(super) fn exported_memory(
+    caller: &mut Caller<'_, WasmCtx>,
+    name: &str,
+) -> AnyhowResult<Memory> {
+    let Some(export) = caller.data().memory.selected.clone() else {
+        return Err(anyhow!("{}: the module exports no usable memory", name));
+    };
+    match caller.get_export(&export) {
+        Some(Extern::Memory(memory)) => Ok(memory),
+        _ => Err(anyhow!("{}: the module does not export '{}'", name, export)),
+    }
+}
+
+/// Read `len` bytes at `ptr` from guest memory
+fn read_guest(
+    caller: &mut Caller<'_, WasmCtx>,
+    name: &str,
+    ptr: u32,
+    len: u32,
+) -> AnyhowResult<Vec<u8>> {
+    let memory = exported_memory(caller, name)?;
+    let range = guest_range(name, ptr, len as usize)?;
+    memory
+        .data(caller)
+        .get(range)
+        .map(|bytes| bytes.to_vec())
+        .ok_or_else(|| anyhow!("{}: source pointer/length out of bounds", name))
 }
 
-/// Error information for HTTP requests
-#[derive(Serialize, Deserialize, Debug)]
-struct WasmFetchError {
-    /// Error code identifier
-    code: String,
-    /// Error message
-    message: String,
+/// Copy `bytes` into the guest buffer at `ptr`, following the negative-length convention
+///
+/// # Returns
+///
+/// * `AnyhowResult<i32>` - Number of bytes written, or the negated required
+///   length if the buffer is smaller than `bytes`
+fn write_guest(
+    caller: &mut Caller<'_, WasmCtx>,
+    name: &str,
+    ptr: u32,
+    max_len: u32,
+    bytes: &[u8],
+) -> AnyhowResult<i32> {
+    if bytes.len() > max_len as usize {
+        // Return negative length if buffer is too small
+        return Ok(-(bytes.len() as i32));
+    }
+    let memory = exported_memory(caller, name)?;
+    let range = guest_range(name, ptr, bytes.len())?;
+    memory
+        .data_mut(caller)
+        .get_mut(range)
+        .ok_or_else(|| anyhow!("{}: destination pointer/length out of bounds", name))?
+        .copy_from_slice(bytes);
+    Ok(bytes.len() as i32)
 }
 
+/// Append a line to a capture buffer, and publish it live, if the output budget admits it
+///
+/// Traps once a strict budget is exhausted, otherwise drops the line silently.
+///
+/// # Returns
+///
+/// * `AnyhowResult<bool>` - Whether the line was captured
+pub(super) fn capture_line(
+    ctx: &WasmCtx,
+    channel: Channel,
+    buffer: &Mutex<String>,
+    message: &str,
+) -> AnyhowResult<bool> {
+    if !ctx.output_budget.admit(channel, message.len() + 1) {
+        if ctx.output_budget.should_terminate() {
+            return Err(Interrupt::OutputBudgetExceeded.into());
+        }
+        return Ok(false);
+    }
+    {
+        let mut buffer = output::lock(buffer);
+        buffer.push_str(message);
+        buffer.push('\n');
+    }
+    ctx.output_budget.publish(channel, message);
+    Ok(true)
+}
+
+/// Decode `app_log` text, with invalid UTF-8 replaced by U+FFFD when `lossy`
+///
+/// Traps on invalid UTF-8 otherwise, naming the flag that avoids it.
+fn app_log_text<'a>(bytes: &'a [u8], lossy: bool, what: &str) -> AnyhowResult<Cow<'a, str>> {
+    if lossy {
+        return Ok(String::from_utf8_lossy(bytes));
+    }
+    std::str::from_utf8(bytes).map(Cow::Borrowed).map_err(|_| {
+        anyhow!(
+            "app_log: {} not valid UTF-8 (lossy_app_log replaces invalid bytes)",
+            what
+        )
+    })
+}
+
+/// Send the request described by the JSON `options` and return the JSON response
+///
+/// The request, and the timer bounding it, run on the context's fetch
+/// runtime, never on the runtime polling the guest, if any. Epoch ticks do
+/// not interrupt a guest suspended in a host call, so a call still waiting
+/// when the execution's deadline passes ends the execution with
+/// `Interrupt::Timeout` itself.
+async fn until_deadline(caller: &Caller<'_, WasmCtx>, options: &[u8]) -> AnyhowResult<Vec<u8>> {
+    let fetch = caller.data().fetch.clone();
+    let runtime = fetch.runtime.clone();
+    let deadline = tokio::time::Instant::from_std(caller.data().deadline);
+    let options = options.to_vec();
+    let request = async move { net::fetch_json(&fetch, &options).await };
+    runtime
+        .run(async move { tokio::time::timeout_at(deadline, request).await })
+        .await?
+        .map_err(|_| Interrupt::Timeout)?
+}
+
+/// The `fetch` import: send the request described by the JSON options at
+/// `options_ptr` and write the JSON response to `resp_buf_ptr`
+///
+/// Returns the response's length, or its negated length if it does not fit
+/// in `resp_buf_max_len` bytes.
+async fn fetch(
+    mut caller: Caller<'_, WasmCtx>,
+    options_ptr: u32,
+    options_len: u32,
+    resp_buf_ptr: u32,
+    resp_buf_max_len: u32,
+) -> AnyhowResult<i32> {
+    let options = read_guest(&mut caller, "fetch", options_ptr, options_len)?;
+
+    // The HTTP request, its error envelope and the JSON contract live in `net`,
+    // shared with the JavaScript `hoyaFetch` global.
+    let response_json_bytes = until_deadline(&caller, &options).await?;
+
+    if response_json_bytes.len() > resp_buf_max_len as usize {
+        // Return negative length if buffer is too small
+        return Ok(-(response_json_bytes.len() as i32));
+    }
+
+    let memory = exported_memory(&mut caller, "fetch")?;
+    let response_range = guest_range("fetch", resp_buf_ptr, response_json_bytes.len())?;
+    let memory_data_mut = memory.data_mut(&mut caller);
+    let response_target_slice = memory_data_mut.get_mut(response_range).ok_or_else(|| {
+        anyhow!("fetch: response buffer pointer/length out of bounds for writing")
+    })?;
+
+    response_target_slice.copy_from_slice(&response_json_bytes);
+    Ok(response_json_bytes.len() as i32)
+}
+
+/// The `fetch_begin` import: send the request described by the JSON options
+/// at `options_ptr` and keep its response under a new handle
+///
+/// Returns the handle, or `FETCH_ERR_TOO_MANY_OPEN` when the guest holds as
+/// many open responses as it may.
+async fn fetch_begin(
+    mut caller: Caller<'_, WasmCtx>,
+    options_ptr: u32,
+    options_len: u32,
+) -> AnyhowResult<i32> {
+    // Refused before the request is sent, so no response is thrown away
+    if caller.data().fetch_handles.is_full() {
+        return Ok(FETCH_ERR_TOO_MANY_OPEN);
+    }
+    let options = read_guest(&mut caller, "fetch_begin", options_ptr, options_len)?;
+    let response = until_deadline(&caller, &options).await?;
+    Ok(caller
+        .data_mut()
+        .fetch_handles
+        .insert(response)
+        .unwrap_or(FETCH_ERR_TOO_MANY_OPEN))
+}
+
+/// The `sleep_ms` import: suspend the guest for `ms` milliseconds, at most
+/// `MAX_SLEEP_MS_PER_CALL` and never past the execution's deadline
+///
+/// Returns 0 after the full sleep, `SLEEP_CUT_BY_DEADLINE` once the deadline
+/// has come, in which case the guest is interrupted with `Interrupt::Timeout`
+/// as soon as it calls a function or loops again, or `SLEEP_CAPPED`. A
+/// cancelled execution stops sleeping at once.
+async fn sleep_ms(caller: Caller<'_, WasmCtx>, ms: u32) -> AnyhowResult<i32> {
+    let wanted = Duration::from_millis(u64::from(ms.min(MAX_SLEEP_MS_PER_CALL)));
+    let wake = Instant::now() + wanted;
+    let deadline = caller.data().deadline;
+    let (until, status) = if deadline <= wake {
+        (deadline, SLEEP_CUT_BY_DEADLINE)
+    } else if ms > MAX_SLEEP_MS_PER_CALL {
+        (wake, SLEEP_CAPPED)
+    } else {
+        (wake, 0)
+    };
+    loop {
+        if caller.data().fetch.cancel.is_cancelled() {
+            return Err(Interrupt::Cancelled.into());
+        }
+        let now = Instant::now();
+        if now >= until {
+            return Ok(status);
+        }
+        let nap = (until - now).min(SLEEP_CANCEL_POLL);
+        // The timer lives on the fetch runtime, so no runtime is needed to poll the guest
+        let runtime = caller.data().fetch.runtime.clone();
+        runtime
+            .run(async move { tokio::time::sleep(nap).await })
+            .await?;
+    }
+}
+
+/// The `kv_set` and `kv_set_ttl` imports: store the guest bytes at `value`
+/// under the key at `key`, both given as pointer and length, for `ttl` or
+/// until deleted
+///
+/// Returns 0, or the `kv_*` error code of a refused write.
+fn kv_set(
+    caller: &mut Caller<'_, WasmCtx>,
+    name: &str,
+    (key_ptr, key_len): (u32, u32),
+    (val_ptr, val_len): (u32, u32),
+    ttl: Option<Duration>,
+) -> AnyhowResult<i32> {
+    let namespace = caller.data().kv.clone();
+    let key = read_guest(caller, name, key_ptr, key_len)?;
+    let value = read_guest(caller, name, val_ptr, val_len)?;
+    match namespace.set(&key, &value, ttl) {
+        Ok(()) => Ok(0),
+        Err(e) => Ok(kv_status(e)),
+    }
+}
+
+/// Names of the `env` functions `register_linker_functions` defines, in
+/// registration order, for error messages naming what the host provides
+pub const HOST_FUNCTIONS: &[&str] = &[
+    "app_log",
+    "capture_stdout",
+    "capture_stderr",
+    "set_output",
+    "get_unixtime",
+    "get_unixtime_ms",
+    "monotonic_now_ns",
+    "format_rfc3339",
+    "get_localtime",
+    "get_utc_offset_seconds",
+    "parse_rfc3339",
+    "get_code_hash",
+    "gzip_compress",
+    "gzip_decompress",
+    "fetch",
+    "fetch_begin",
+    "fetch_len",
+    "fetch_read",
+    "fetch_close",
+    "sleep_ms",
+    "pkv_get",
+    "pkv_set",
+    "pkv_delete",
+    "kv_get",
+    "kv_set",
+    "kv_set_ttl",
+    "kv_delete",
+    "env_get",
+    "env_count",
+    "env_key_at",
+    "input_len",
+    "input_read",
+    "random_bytes",
+    "abort",
+];
+
 /// Register WebAssembly FFI functions with the linker
 ///
 /// This function regiscters all FFI functions that can be called from WebAssembly code,
 /// including logging, time utilities, and HTTP fetch functionality.
+///
+/// `fetch`, `fetch_begin` and `sleep_ms` are async, so the linker's engine
+/// must be built with `Config::async_support` and its instances run with
+/// `call_async`.
 pub fn register_linker_functions(linker: &mut Linker<WasmCtx>) -> AnyhowResult<()> {
     // Register app_log function for WebAssembly logging
     linker.func_wrap(
         "env",
         "app_log",
-        |caller: Caller<'_, WasmCtx>,
+        |mut caller: Caller<'_, WasmCtx>,
          level_ptr: u32,
          level_len: u32,
          msg_ptr: u32,
          msg_len: u32|
          -> AnyhowResult<()> {
-            let memory = caller
-                .data()
-                .memory
-                .ok_or_else(|| anyhow!("app_log: memory not initialized in WasmCtx"))?;
+            let memory = exported_memory(&mut caller, "app_log")?;
+            let level_range = guest_range("app_log", level_ptr, level_len as usize)?;
             let level_bytes = memory
                 .data(&caller)
-                .get(level_ptr as usize..(level_ptr + level_len) as usize)
+                .get(level_range)
                 .ok_or_else(|| anyhow!("app_log: level pointer/length out of bounds"))?;
-            let level_str = std::str::from_utf8(level_bytes)
-                .map_err(|_| anyhow!("app_log: level not valid UTF-8"))?;
+            let lossy = caller.data().lossy_app_log;
+            let level_str = app_log_text(level_bytes, lossy, "level")?;
+            let msg_range = guest_range("app_log", msg_ptr, msg_len as usize)?;
             let msg_bytes = memory
                 .data(&caller)
-                .get(msg_ptr as usize..(msg_ptr + msg_len) as usize)
+                .get(msg_range)
                 .ok_or_else(|| anyhow!("app_log: message pointer/length out of bounds"))?;
-            let msg_str = std::str::from_utf8(msg_bytes)
-                .map_err(|_| anyhow!("app_log: message not valid UTF-8"))?;
-            
-            let log_message = format!("[WASM LOG - {}]: {}", level_str.to_uppercase(), msg_str);
-            println!("{}", log_message);
-            
-            // Capture the output to stdout buffer
-            if let Ok(mut stdout) = caller.data().stdout.lock() {
-                stdout.push_str(&log_message);
-                stdout.push('\n');
+            let msg_str = app_log_text(msg_bytes, lossy, "message")?;
+
+            let entry = LogEntry::now(
+                LogLevel::from_app_log(&level_str),
+                LogSource::AppLog,
+                &msg_str,
+            );
+            let log_message = format!("[WASM LOG - {}]: {}", entry.level.label(), msg_str);
+
+            // Capture the output to stdout buffer and record the entry
+            let ctx = caller.data();
+            if capture_line(ctx, Channel::Log, &ctx.stdout, &log_message)? {
+                if ctx.output_budget.echo_to_host() {
+                    entry.echo("webassembly");
+                }
+                output::lock(&ctx.logs).push(entry);
             }
-            
+
             Ok(())
         },
     )?;
-    
+
     // Register stdout capture function (for println! in Rust)
     linker.func_wrap(
         "env",
         "capture_stdout",
-        |caller: Caller<'_, WasmCtx>,
-         ptr: u32,
-         len: u32|
-         -> AnyhowResult<()> {
-            let memory = caller
-                .data()
-                .memory
-                .ok_or_else(|| anyhow!("capture_stdout: memory not initialized in WasmCtx"))?;
+        |mut caller: Caller<'_, WasmCtx>, ptr: u32, len: u32| -> AnyhowResult<()> {
+            let memory = exported_memory(&mut caller, "capture_stdout")?;
+            let range = guest_range("capture_stdout", ptr, len as usize)?;
             let msg_bytes = memory
                 .data(&caller)
-                .get(ptr as usize..(ptr + len) as usize)
+                .get(range)
                 .ok_or_else(|| anyhow!("capture_stdout: message pointer/length out of bounds"))?;
             let msg_str = std::str::from_utf8(msg_bytes)
                 .map_err(|_| anyhow!("capture_stdout: message not valid UTF-8"))?;
-            
-            println!("{}", msg_str); // Print to host stdout
-            
+
             // Capture to stdout buffer
-            if let Ok(mut stdout) = caller.data().stdout.lock() {
-                stdout.push_str(msg_str);
-                stdout.push('\n');
+            let ctx = caller.data();
+            if capture_line(ctx, Channel::Stdout, &ctx.stdout, msg_str)?
+                && ctx.output_budget.echo_to_host()
+            {
+                output::echo_line("webassembly", Channel::Stdout, msg_str);
             }
-            
+
             Ok(())
         },
     )?;
-    
+
     // Register stderr capture function (for eprintln! in Rust)
     linker.func_wrap(
         "env",
         "capture_stderr",
-        |caller: Caller<'_, WasmCtx>,
-         ptr: u32,
-         len: u32|
-         -> AnyhowResult<()> {
-            let memory = caller
-                .data()
-                .memory
-                .ok_or_else(|| anyhow!("capture_stderr: memory not initialized in WasmCtx"))?;
+        |mut caller: Caller<'_, WasmCtx>, ptr: u32, len: u32| -> AnyhowResult<()> {
+            let memory = exported_memory(&mut caller, "capture_stderr")?;
+            let range = guest_range("capture_stderr", ptr, len as usize)?;
             let msg_bytes = memory
                 .data(&caller)
-                .get(ptr as usize..(ptr + len) as usize)
+                .get(range)
                 .ok_or_else(|| anyhow!("capture_stderr: message pointer/length out of bounds"))?;
             let msg_str = std::str::from_utf8(msg_bytes)
                 .map_err(|_| anyhow!("capture_stderr: message not valid UTF-8"))?;
-            
-            eprintln!("{}", msg_str); // Print to host stderr
-            
+
             // Capture to stderr buffer
-            if let Ok(mut stderr) = caller.data().stderr.lock() {
-                stderr.push_str(msg_str);
-                stderr.push('\n');
+            let ctx = caller.data();
+            if capture_line(ctx, Channel::Stderr, &ctx.stderr, msg_str)?
+                && ctx.output_budget.echo_to_host()
+            {
+                output::echo_line("webassembly", Channel::Stderr, msg_str);
+            }
+
+            Ok(())
+        },
+    )?;
+
+    // Register set_output so modules can return a result instead of printing it
+    linker.func_wrap(
+        "env",
+        "set_output",
+        |mut caller: Caller<'_, WasmCtx>, ptr: u32, len: u32| -> AnyhowResult<()> {
+            let memory = exported_memory(&mut caller, "set_output")?;
+            if guest_range("set_output", ptr, len as usize)?.end > memory.data_size(&caller) {
+                return Err(anyhow!("set_output: result pointer/length out of bounds"));
+            }
+            // The result is charged to stdout's share of the budget, whole or not at all
+            let budget = &caller.data().output_budget;
+            if !budget.admit(Channel::Stdout, len as usize) {
+                if budget.should_terminate() {
+                    return Err(Interrupt::OutputBudgetExceeded.into());
+                }
+                return Ok(());
             }
-            
+
+            // The bytes are read once _start returns
+            caller.data_mut().result = Some((ptr, len));
             Ok(())
         },
     )?;
 
-    // Register get_unixtime function for system time access
+    // Register clock functions, linearized in deterministic mode
     linker.func_wrap(
         "env",
         "get_unixtime",
-        |_caller: Caller<'_, WasmCtx>| -> AnyhowResult<u64> {
-            match SystemTime::now().duration_since(UNIX_EPOCH) {
-                Ok(n) => Ok(n.as_secs()),
-                Err(_) => Err(anyhow!("get_unixtime: Failed to get system time")),
+        |caller: Caller<'_, WasmCtx>| -> AnyhowResult<u64> {
+            match caller.data().clock.unix_ms() {
+                Some(ms) => Ok(ms / 1000),
+                None => Err(anyhow!("get_unixtime: Failed to get system time")),
             }
         },
     )?;
 
-    // Register fetch function for HTTP requests
     linker.func_wrap(
         "env",
-        "fetch",
+        "get_unixtime_ms",
+        |caller: Caller<'_, WasmCtx>| -> AnyhowResult<u64> {
+            caller
+                .data()
+                .clock
+                .unix_ms()
+                .ok_or_else(|| anyhow!("get_unixtime_ms: Failed to get system time"))
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "monotonic_now_ns",
+        |caller: Caller<'_, WasmCtx>| -> u64 { caller.data().clock.monotonic_ns() },
+    )?;
+
+    // Register RFC 3339 functions so modules need not hand-roll calendar math
+    linker.func_wrap(
+        "env",
+        "format_rfc3339",
         |mut caller: Caller<'_, WasmCtx>,
-         options_ptr: u32,
-         options_len: u32,
-         resp_buf_ptr: u32,
-         resp_buf_max_len: u32|
+         unix_secs: u64,
+         out_ptr: u32,
+         out_max_len: u32|
          -> AnyhowResult<i32> {
-            let memory = caller
-                .data()
-                .memory
-                .ok_or_else(|| anyhow!("fetch: memory not initialized in WasmCtx"))?;
+            let formatted = i64::try_from(unix_secs).ok().and_then(rfc3339::format);
+            match formatted {
+                Some(formatted) => write_guest(
+                    &mut caller,
+                    "format_rfc3339",
+                    out_ptr,
+                    out_max_len,
+                    formatted.as_bytes(),
+                ),
+                None => Ok(TIME_ERR_OUT_OF_RANGE),
+            }
+        },
+    )?;
 
-            let options_bytes_vec: Vec<u8> = memory
-                .data(&caller)
-                .get(options_ptr as usize..(options_ptr + options_len) as usize)
-                .ok_or_else(|| anyhow!("fetch: options pointer/length out of bounds"))?
-                .to_vec();
-
-            let fetch_options: WasmFetchOptions = serde_json::from_slice(&options_bytes_vec)
-                .map_err(|e| anyhow!("fetch: failed to deserialize options JSON: {}", e))?;
-
-            let http_method = reqwest::Method::from_bytes(fetch_options.method.as_bytes())
-                .map_err(|_| {
-                    anyhow!(
-                        "fetch: invalid HTTP method string: {}",
-                        fetch_options.method
-                    )
-                })?;
-
-            let mut http_headers = reqwest::header::HeaderMap::new();
-            for (key, value) in fetch_options.headers {
-                let header_name = reqwest::header::HeaderName::from_bytes(key.as_bytes())
-                    .map_err(|_| anyhow!("fetch: invalid header name {}", key))?;
-                let header_value = reqwest::header::HeaderValue::from_str(&value)
-                    .map_err(|_| anyhow!("fetch: invalid header value for {}", key))?;
-                http_headers.insert(header_name, header_value);
+    // Register local time functions, in the zone named by the request's `timezone`, reading the guest clock
+    linker.func_wrap(
+        "env",
+        "get_localtime",
+        |mut caller: Caller<'_, WasmCtx>, out_ptr: u32, out_max_len: u32| -> AnyhowResult<i32> {
+            let ctx = caller.data();
+            let formatted = ctx
+                .clock
+                .unix_ms()
+                .and_then(|ms| rfc3339::format_local((ms / 1000) as i64, ctx.timezone))
+                .ok_or_else(|| anyhow!("get_localtime: current time out of range"))?;
+            write_guest(
+                &mut caller,
+                "get_localtime",
+                out_ptr,
+                out_max_len,
+                formatted.as_bytes(),
+            )
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "get_utc_offset_seconds",
+        |caller: Caller<'_, WasmCtx>| -> AnyhowResult<i32> {
+            let ctx = caller.data();
+            ctx.clock
+                .unix_ms()
+                .and_then(|ms| rfc3339::utc_offset_seconds(ms as i64, ctx.timezone))
+                .ok_or_else(|| anyhow!("get_utc_offset_seconds: current time out of range"))
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "parse_rfc3339",
+        |mut caller: Caller<'_, WasmCtx>, str_ptr: u32, str_len: u32| -> AnyhowResult<i64> {
+            let bytes = read_guest(&mut caller, "parse_rfc3339", str_ptr, str_len)?;
+            Ok(std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(rfc3339::parse)
+                .unwrap_or(TIME_ERR_INVALID))
+        },
+    )?;
+
+    // Register get_code_hash function so modules can verify which bytes are running
+    linker.func_wrap(
+        "env",
+        "get_code_hash",
+        |mut caller: Caller<'_, WasmCtx>, buf_ptr: u32, buf_max_len: u32| -> AnyhowResult<i32> {
+            let memory = exported_memory(&mut caller, "get_code_hash")?;
+            let digest = caller.data().code_sha256.clone().into_bytes();
+
+            if digest.len() > buf_max_len as usize {
+                // Return negative length if buffer is too small
+                return Ok(-(digest.len() as i32));
             }
 
-            // Use the client from WasmCtx instead of creating a new one
-            let client = &caller.data().reqwest_client;
-            let mut request_builder = client
-                .request(http_method, &fetch_options.url)
-                .headers(http_headers);
+            let range = guest_range("get_code_hash", buf_ptr, digest.len())?;
+            let target = memory
+                .data_mut(&mut caller)
+                .get_mut(range)
+                .ok_or_else(|| anyhow!("get_code_hash: buffer pointer/length out of bounds"))?;
+            target.copy_from_slice(&digest);
+            Ok(digest.len() as i32)
+        },
+    )?;
 
-            if let Some(body_str) = fetch_options.body {
-                request_builder = request_builder.body(body_str);
+    // Register gzip functions so modules need not compile in their own deflate implementation
+    linker.func_wrap(
+        "env",
+        "gzip_compress",
+        |mut caller: Caller<'_, WasmCtx>,
+         src_ptr: u32,
+         src_len: u32,
+         dst_ptr: u32,
+         dst_max_len: u32|
+         -> AnyhowResult<i32> {
+            let source = read_guest(&mut caller, "gzip_compress", src_ptr, src_len)?;
+            let compressed = compression::gzip(&source)
+                .map_err(|e| anyhow!("gzip_compress: compression failed: {}", e))?;
+            write_guest(
+                &mut caller,
+                "gzip_compress",
+                dst_ptr,
+                dst_max_len,
+                &compressed,
+            )
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "gzip_decompress",
+        |mut caller: Caller<'_, WasmCtx>,
+         src_ptr: u32,
+         src_len: u32,
+         dst_ptr: u32,
+         dst_max_len: u32|
+         -> AnyhowResult<i32> {
+            let source = read_guest(&mut caller, "gzip_decompress", src_ptr, src_len)?;
+            match compression::inflate(&source, compression::MAX_GUEST_DECOMPRESSED_SIZE) {
+                Ok(decompressed) => write_guest(
+                    &mut caller,
+                    "gzip_decompress",
+                    dst_ptr,
+                    dst_max_len,
+                    &decompressed,
+                ),
+                Err(InflateError::Corrupt(_)) => Ok(GZIP_ERR_CORRUPT),
+                Err(InflateError::TooLarge) => Ok(GZIP_ERR_TOO_LARGE),
             }
+        },
+    )?;
 
-            // reqwest::blocking::Client::send is a blocking call.
-            // To use it in an async context (like the `fetch` FFI which might be called from an async Wasm module),
-            // it's better to use an async reqwest client and await the send.
-            // However, Wasmtime's func_wrap currently expects a synchronous function.
-            // For now, we'll keep the blocking client, but this is a point of attention if the Wasm host itself is async.
-            // A common pattern is to use `tokio::task::block_in_place` or `spawn_blocking` if this needs to be truly non-blocking
-            // with respect to the host's executor, but that adds complexity.
-            // Given the current structure, the blocking client is consistent with its previous usage in main.rs.
-
-            // We need to handle async reqwest client in a synchronous FFI function
-            // Using tokio::task::block_in_place to execute async code in a blocking context
-
-            // Use tokio's block_in_place to run the async operations in a blocking context
-            let response = match tokio::task::block_in_place(move || {
-                tokio::runtime::Handle::current().block_on(async { request_builder.send().await })
-            }) {
-                Ok(response) => response,
-                Err(e) => {
-                    let error_response = WasmFetchResponse {
-                        status: 0, // 0 indicates network error or failed request
-                        headers: HashMap::new(),
-                        body: String::new(),
-                        error: Some(WasmFetchError {
-                            code: "FETCH_FAILED".to_string(),
-                            message: format!("HTTP request execution failed: {}", e),
-                        }),
-                    };
-                    
-                    let error_json = serde_json::to_vec(&error_response)
-                        .map_err(|e| anyhow!("fetch: failed to serialize error response to JSON: {}", e))?;
-                        
-                    if error_json.len() > resp_buf_max_len as usize {
-                        return Ok(-(error_json.len() as i32));
-                    }
-                    
-                    let memory_data_mut = memory.data_mut(&mut caller);
-                    let response_target_slice = memory_data_mut
-                        .get_mut(resp_buf_ptr as usize..(resp_buf_ptr as usize + error_json.len()))
-                        .ok_or_else(|| {
-                            anyhow!("fetch: response buffer pointer/length out of bounds for writing error")
-                        })?;
-                    
-                    response_target_slice.copy_from_slice(&error_json);
-                    return Ok(error_json.len() as i32);
-                }
+    // Register fetch function for HTTP requests; the guest is suspended, holding
+    // no thread, until the response has been read
+    linker.func_wrap_async(
+        "env",
+        "fetch",
+        |caller: Caller<'_, WasmCtx>,
+         (options_ptr, options_len, resp_ptr, resp_max_len): (u32, u32, u32, u32)| {
+            Box::new(fetch(
+                caller,
+                options_ptr,
+                options_len,
+                resp_ptr,
+                resp_max_len,
+            ))
+        },
+    )?;
+
+    // Register the two-phase fetch functions, which keep the response until the guest has read it
+    linker.func_wrap_async(
+        "env",
+        "fetch_begin",
+        |caller: Caller<'_, WasmCtx>, (options_ptr, options_len): (u32, u32)| {
+            Box::new(fetch_begin(caller, options_ptr, options_len))
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "fetch_len",
+        |caller: Caller<'_, WasmCtx>, handle: i32| -> i32 {
+            caller
+                .data()
+                .fetch_handles
+                .response_len(handle)
+                .map_or(FETCH_ERR_INVALID_HANDLE, |len| len as i32)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "fetch_read",
+        |mut caller: Caller<'_, WasmCtx>,
+         handle: i32,
+         dst_ptr: u32,
+         dst_max_len: u32|
+         -> AnyhowResult<i32> {
+            let Some(piece) = caller
+                .data_mut()
+                .fetch_handles
+                .read(handle, dst_max_len as usize)
+            else {
+                return Ok(FETCH_ERR_INVALID_HANDLE);
             };
+            write_guest(&mut caller, "fetch_read", dst_ptr, dst_max_len, &piece)
+        },
+    )?;
 
-            let status_code = response.status().as_u16();
-            let mut response_headers_map = HashMap::new();
-            for (name, value) in response.headers().iter() {
-                response_headers_map
-                    .insert(name.to_string(), value.to_str().unwrap_or("").to_string());
+    linker.func_wrap(
+        "env",
+        "fetch_close",
+        |mut caller: Caller<'_, WasmCtx>, handle: i32| -> i32 {
+            if caller.data_mut().fetch_handles.close(handle) {
+                0
+            } else {
+                FETCH_ERR_INVALID_HANDLE
             }
+        },
+    )?;
+
+    // Register sleep_ms, which suspends the guest like fetch does
+    linker.func_wrap_async(
+        "env",
+        "sleep_ms",
+        |caller: Caller<'_, WasmCtx>, (ms,): (u32,)| Box::new(sleep_ms(caller, ms)),
+    )?;
 
-            // Get response body text using block_in_place again for the async text() method
-            let response_body_text = tokio::task::block_in_place(move || {
-                tokio::runtime::Handle::current().block_on(async { response.text().await })
-            })
-            .map_err(|e| anyhow!("fetch: failed to read response body as text: {}", e))?;
-
-            let wasm_response = WasmFetchResponse {
-                status: status_code,
-                headers: response_headers_map,
-                body: response_body_text,
-                error: None,
+    // Register persistent storage functions, backed by the module's namespace
+    linker.func_wrap(
+        "env",
+        "pkv_get",
+        |mut caller: Caller<'_, WasmCtx>,
+         key_ptr: u32,
+         key_len: u32,
+         out_ptr: u32,
+         out_max_len: u32|
+         -> AnyhowResult<i32> {
+            let Some(namespace) = caller.data().storage.clone() else {
+                return Ok(PKV_ERR_DENIED);
             };
+            let key = read_guest(&mut caller, "pkv_get", key_ptr, key_len)?;
+            match namespace.get(&key) {
+                Ok(Some(value)) => {
+                    write_guest(&mut caller, "pkv_get", out_ptr, out_max_len, &value)
+                }
+                Ok(None) => Ok(PKV_ERR_NOT_FOUND),
+                Err(e) => storage_status("pkv_get", e),
+            }
+        },
+    )?;
 
-            let response_json_bytes = serde_json::to_vec(&wasm_response)
-                .map_err(|e| anyhow!("fetch: failed to serialize response to JSON: {}", e))?;
+    linker.func_wrap(
+        "env",
+        "pkv_set",
+        |mut caller: Caller<'_, WasmCtx>,
+         key_ptr: u32,
+         key_len: u32,
+         value_ptr: u32,
+         value_len: u32|
+         -> AnyhowResult<i32> {
+            let Some(namespace) = caller.data().storage.clone() else {
+                return Ok(PKV_ERR_DENIED);
+            };
+            let key = read_guest(&mut caller, "pkv_set", key_ptr, key_len)?;
+            let value = read_guest(&mut caller, "pkv_set", value_ptr, value_len)?;
+            match namespace.set(&key, &value) {
+                Ok(()) => Ok(0),
+                Err(e) => storage_status("pkv_set", e),
+            }
+        },
+    )?;
 
-            if response_json_bytes.len() > resp_buf_max_len as usize {
-                // Return negative length if buffer is too small
-                return Ok(-(response_json_bytes.len() as i32));
+    linker.func_wrap(
+        "env",
+        "pkv_delete",
+        |mut caller: Caller<'_, WasmCtx>, key_ptr: u32, key_len: u32| -> AnyhowResult<i32> {
+            let Some(namespace) = caller.data().storage.clone() else {
+                return Ok(PKV_ERR_DENIED);
+            };
+            let key = read_guest(&mut caller, "pkv_delete", key_ptr, key_len)?;
+            match namespace.delete(&key) {
+                Ok(removed) => Ok(i32::from(removed)),
+                Err(e) => storage_status("pkv_delete", e),
+            }
+        },
+    )?;
+
+    // Register key-value functions, backed by the execution's namespace
+    linker.func_wrap(
+        "env",
+        "kv_get",
+        |mut caller: Caller<'_, WasmCtx>,
+         key_ptr: u32,
+         key_len: u32,
+         val_buf: u32,
+         val_max: u32|
+         -> AnyhowResult<i32> {
+            let namespace = caller.data().kv.clone();
+            let key = read_guest(&mut caller, "kv_get", key_ptr, key_len)?;
+            match namespace.get(&key) {
+                Ok(Some(value)) => write_guest(&mut caller, "kv_get", val_buf, val_max, &value),
+                Ok(None) => Ok(KV_ERR_NOT_FOUND),
+                Err(e) => Ok(kv_status(e)),
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "kv_set",
+        |mut caller: Caller<'_, WasmCtx>,
+         key_ptr: u32,
+         key_len: u32,
+         val_ptr: u32,
+         val_len: u32|
+         -> AnyhowResult<i32> {
+            let (key, value) = ((key_ptr, key_len), (val_ptr, val_len));
+            kv_set(&mut caller, "kv_set", key, value, None)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "kv_set_ttl",
+        |mut caller: Caller<'_, WasmCtx>,
+         key_ptr: u32,
+         key_len: u32,
+         val_ptr: u32,
+         val_len: u32,
+         ttl_ms: u32|
+         -> AnyhowResult<i32> {
+            // A zero time to live keeps the entry like `kv_set` does
+            let ttl = (ttl_ms > 0).then(|| Duration::from_millis(u64::from(ttl_ms)));
+            let (key, value) = ((key_ptr, key_len), (val_ptr, val_len));
+            kv_set(&mut caller, "kv_set_ttl", key, value, ttl)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "kv_delete",
+        |mut caller: Caller<'_, WasmCtx>, key_ptr: u32, key_len: u32| -> AnyhowResult<i32> {
+            let namespace = caller.data().kv.clone();
+            let key = read_guest(&mut caller, "kv_delete", key_ptr, key_len)?;
+            match namespace.delete(&key) {
+                Ok(removed) => Ok(i32::from(removed)),
+                Err(e) => Ok(kv_status(e)),
             }
+        },
+    )?;
+
+    // Register environment functions, backed by the request's `env`
+    linker.func_wrap(
+        "env",
+        "env_get",
+        |mut caller: Caller<'_, WasmCtx>,
+         key_ptr: u32,
+         key_len: u32,
+         val_buf_ptr: u32,
+         val_buf_max: u32|
+         -> AnyhowResult<i32> {
+            let key = read_guest(&mut caller, "env_get", key_ptr, key_len)?;
+            let env = caller.data().env.clone();
+            let Some(value) = std::str::from_utf8(&key).ok().and_then(|key| env.get(key)) else {
+                return Ok(ENV_ERR_NOT_FOUND);
+            };
+            write_guest(
+                &mut caller,
+                "env_get",
+                val_buf_ptr,
+                val_buf_max,
+                value.as_bytes(),
+            )
+        },
+    )?;
+
+    linker.func_wrap("env", "env_count", |caller: Caller<'_, WasmCtx>| -> i32 {
+        caller.data().env.len() as i32
+    })?;
+
+    linker.func_wrap(
+        "env",
+        "env_key_at",
+        |mut caller: Caller<'_, WasmCtx>,
+         index: u32,
+         buf_ptr: u32,
+         buf_max: u32|
+         -> AnyhowResult<i32> {
+            let env = caller.data().env.clone();
+            let Some(name) = env.name_at(index as usize) else {
+                return Ok(ENV_ERR_NOT_FOUND);
+            };
+            write_guest(&mut caller, "env_key_at", buf_ptr, buf_max, name.as_bytes())
+        },
+    )?;
+
+    // Register input functions, handing the module the request's `input` as JSON
+    linker.func_wrap("env", "input_len", |caller: Caller<'_, WasmCtx>| -> u32 {
+        caller
+            .data()
+            .input
+            .as_ref()
+            .map_or(0, |input| input.len() as u32)
+    })?;
+
+    linker.func_wrap(
+        "env",
+        "input_read",
+        |mut caller: Caller<'_, WasmCtx>, dst_ptr: u32, dst_max: u32| -> AnyhowResult<i32> {
+            let input = caller.data().input.clone().unwrap_or_default();
+            write_guest(&mut caller, "input_read", dst_ptr, dst_max, &input)
+        },
+    )?;
 
-            let memory_data_mut = memory.data_mut(&mut caller);
-            let response_target_slice = memory_data_mut
-                .get_mut(resp_buf_ptr as usize..(resp_buf_ptr as usize + response_json_bytes.len()))
-                .ok_or_else(|| {
-                    anyhow!("fetch: response buffer pointer/length out of bounds for writing")
-                })?;
+    // Register random_bytes, drawing from the host or the seeded stream in deterministic mode
+    linker.func_wrap(
+        "env",
+        "random_bytes",
+        |mut caller: Caller<'_, WasmCtx>, dst_ptr: u32, len: u32| -> AnyhowResult<i32> {
+            // One byte past the limit is enough for `fill` to refuse an oversized request
+            let mut bytes = vec![0u8; (len as usize).min(MAX_RANDOM_BYTES_PER_CALL + 1)];
+            match caller.data_mut().random.fill(&mut bytes) {
+                Ok(()) => write_guest(&mut caller, "random_bytes", dst_ptr, len, &bytes),
+                Err(RandomError::TooLarge) => Ok(RANDOM_ERR_TOO_LARGE),
+                Err(RandomError::BudgetExhausted) => Ok(RANDOM_ERR_BUDGET),
+                Err(e) => Err(anyhow!("random_bytes: {}", e)),
+            }
+        },
+    )?;
 
-            response_target_slice.copy_from_slice(&response_json_bytes);
-            Ok(response_json_bytes.len() as i32)
+    // Register abort, called by AssemblyScript modules on failed assertions and errors
+    linker.func_wrap(
+        "env",
+        "abort",
+        |mut caller: Caller<'_, WasmCtx>,
+         msg_ptr: u32,
+         file_ptr: u32,
+         line: u32,
+         column: u32|
+         -> AnyhowResult<()> {
+            // Strings that cannot be read leave their fields unset; the abort still ends the run
+            let (message, file) = match exported_memory(&mut caller, "abort") {
+                Ok(memory) => {
+                    let data = memory.data(&caller);
+                    (
+                        read_assemblyscript_string(data, msg_ptr),
+                        read_assemblyscript_string(data, file_ptr),
+                    )
+                }
+                Err(_) => (None, None),
+            };
+            Err(GuestAbort {
+                message,
+                file,
+                line,
+                column,
+            }
+            .into())
         },
     )?;
 
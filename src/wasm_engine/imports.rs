@@ -0,0 +1,202 @@
+//! Checking a module's imports against the host before it is linked.
+//!
+//! wasmtime's `instantiate_pre` stops at the first import the linker does
+//! not define, so a module missing several would take one request per fix.
+//! Modules are checked first instead: every unsatisfied import is reported
+//! in one `MISSING_IMPORTS` error. With `stub_missing_imports`, missing
+//! functions are defined as placeholders instead, which let the module run
+//! until it calls one.
+
+use serde::Serialize;
+use std::fmt;
+use wasmtime::{ExternType, Linker, Module};
+
+use super::emscripten::{Compat, EMSCRIPTEN_SHIMS};
+use super::ffis::HOST_FUNCTIONS;
+use super::WasmCtx;
+
+/// An import the linker does not define
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MissingImport {
+    /// Module name of the import, e.g. `env`
+    pub module: String,
+    /// Field name of the import
+    pub name: String,
+    /// What is imported: a function's signature, e.g. `func(i32, i32) -> i32`,
+    /// or `memory`, `table`, `global` or `tag`
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+impl fmt::Display for MissingImport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.module, self.name)
+    }
+}
+
+/// A module imports what the linker does not define, every such import listed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingImports(pub Vec<MissingImport>);
+
+impl fmt::Display for MissingImports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self.0.iter().map(|import| import.to_string()).collect();
+        write!(f, "unknown imports: {}", names.join(", "))
+    }
+}
+
+impl std::error::Error for MissingImports {}
+
+/// A guest called a placeholder defined by `stub_missing_imports`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StubbedImportCalled {
+    /// Module name of the import
+    pub module: String,
+    /// Field name of the import
+    pub name: String,
+}
+
+impl fmt::Display for StubbedImportCalled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "called {}::{}, which the host does not provide",
+            self.module, self.name
+        )
+    }
+}
+
+impl std::error::Error for StubbedImportCalled {}
+
+/// Imports of `module` that `linker` does not define
+///
+/// A function import is defined when defining it again in a copy of the
+/// linker fails as a duplicate, whatever the signature; a defined function
+/// of the wrong type is left to `instantiate_pre` to report. The linkers
+/// define functions only, so imported memories, tables, globals and tags
+/// are always missing.
+///
+/// # Arguments
+///
+/// * `linker` - The linker the module is about to be linked against
+/// * `module` - The compiled module
+///
+/// # Returns
+///
+/// * `Vec<MissingImport>` - The missing imports in the module's order, each listed once
+pub fn missing_imports(linker: &Linker<WasmCtx>, module: &Module) -> Vec<MissingImport> {
+    let mut probe = linker.clone();
+    probe.allow_shadowing(false);
+    let mut missing: Vec<MissingImport> = Vec::new();
+    for import in module.imports() {
+        let (defined, ty) = match import.ty() {
+            ExternType::Func(ty) => {
+                let signature = describe_func(&ty);
+                let defined = probe
+                    .func_new(import.module(), import.name(), ty, |_, _, _| Ok(()))
+                    .is_err();
+                (defined, signature)
+            }
+            other => (false, describe_extern(&other).to_string()),
+        };
+        let listed = missing
+            .iter()
+            .any(|other| other.module == import.module() && other.name == import.name());
+        if !defined && !listed {
+            missing.push(MissingImport {
+                module: import.module().to_string(),
+                name: import.name().to_string(),
+                ty,
+            });
+        }
+    }
+    missing
+}
+
+/// Define every missing function import of `module` as a placeholder that
+/// fails the execution with `StubbedImportCalled` when called
+///
+/// # Arguments
+///
+/// * `linker` - A copy of the linker the module is linked against
+/// * `module` - The compiled module
+///
+/// # Returns
+///
+/// * `Vec<MissingImport>` - The missing imports that could not be stubbed:
+///   memories, tables, globals and tags
+pub fn stub_missing_functions(linker: &mut Linker<WasmCtx>, module: &Module) -> Vec<MissingImport> {
+    let mut unstubbed = Vec::new();
+    for missing in missing_imports(linker, module) {
+        let ty = module
+            .imports()
+            .find(|import| import.module() == missing.module && import.name() == missing.name)
+            .and_then(|import| import.ty().func().cloned());
+        let Some(ty) = ty else {
+            unstubbed.push(missing);
+            continue;
+        };
+        let called = StubbedImportCalled {
+            module: missing.module.clone(),
+            name: missing.name.clone(),
+        };
+        let defined = linker.func_new(&missing.module, &missing.name, ty, move |_, _, _| {
+            Err(called.clone().into())
+        });
+        if defined.is_err() {
+            unstubbed.push(missing);
+        }
+    }
+    unstubbed
+}
+
+/// The `env` functions a module linked for `compat` can import, as `env::name`
+///
+/// WASI preview1 is defined next to them and not listed.
+///
+/// ```
+/// use hoya::wasm_engine::{host_functions, Compat};
+///
+/// assert!(host_functions(None).contains(&"env::app_log".to_string()));
+/// let emscripten = host_functions(Some(Compat::Emscripten));
+/// assert!(emscripten.contains(&"env::__assert_fail".to_string()));
+/// assert_eq!(emscripten.iter().filter(|name| *name == "env::abort").count(), 1);
+/// ```
+pub fn host_functions(compat: Option<Compat>) -> Vec<String> {
+    let shims = match compat {
+        Some(Compat::Emscripten) => EMSCRIPTEN_SHIMS,
+        None => &[],
+    };
+    let mut names: Vec<String> = Vec::new();
+    for name in HOST_FUNCTIONS.iter().chain(shims) {
+        let name = format!("env::{}", name);
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Kind of an import other than a function
+pub(super) fn describe_extern(ty: &ExternType) -> &'static str {
+    if ty.memory().is_some() {
+        "memory"
+    } else if ty.table().is_some() {
+        "table"
+    } else if ty.global().is_some() {
+        "global"
+    } else {
+        "tag"
+    }
+}
+
+/// Signature of a function type, e.g. `func(i32, i32) -> i32`
+pub(super) fn describe_func(ty: &wasmtime::FuncType) -> String {
+    let params: Vec<String> = ty.params().map(|param| param.to_string()).collect();
+    let results: Vec<String> = ty.results().map(|result| result.to_string()).collect();
+    match results.len() {
+        0 => format!("func({})", params.join(", ")),
+        1 => format!("func({}) -> {}", params.join(", "), results[0]),
+        _ => format!("func({}) -> ({})", params.join(", "), results.join(", ")),
+    }
+}
@@ -0,0 +1,221 @@
+//! Memory and table limits of WebAssembly stores.
+//!
+//! Every store gets a limiter that lets memories grow up to the execution's
+//! `memory_bytes` and caps the number of tables and instances. A module
+//! growing a memory past the limit, or declaring a larger initial memory,
+//! traps with `MemoryLimitExceeded` instead of seeing `memory.grow` return
+//! -1, so the execution fails with `MEMORY_LIMIT_EXCEEDED` rather than
+//! running on without the memory it asked for. The limiter also records the
+//! largest size any memory reached, reported as `metadata.peak_memory_pages`.
+//!
+//! Host functions read and write the memory the module exports. Toolchains
+//! do not all name it `memory`, and with multi-memory a module may export
+//! several, so the memory is chosen by type from the export section: the
+//! only memory export, else the one named `memory`, else memory 0 if it is
+//! exported. A module exporting none of these fails with `INVALID_MODULE`,
+//! listing the memory exports it has.
+
+use crate::manifest::{read_leb128, sections};
+use std::fmt;
+use wasmtime::{ResourceLimiter, StoreLimits, StoreLimitsBuilder};
+
+/// Size of a WebAssembly memory page in bytes
+const WASM_PAGE_BYTES: usize = 65536;
+
+/// Id of the export section
+const EXPORT_SECTION: u8 = 7;
+
+/// Kind byte of memory exports
+const MEMORY_EXPORT: u8 = 2;
+
+/// Name host functions expect the memory under, preferred among several
+pub const CANONICAL_MEMORY_EXPORT: &str = "memory";
+
+/// The memories a module exports and the one host functions use
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryExports {
+    /// Names of the memory exports, in export order
+    pub names: Vec<String>,
+    /// Name of the export host functions read and write, if any is usable
+    pub selected: Option<String>,
+}
+
+impl MemoryExports {
+    /// Find the memory exports of a module and choose the one host functions use
+    ///
+    /// ```
+    /// use hoya::wasm_engine::MemoryExports;
+    ///
+    /// let module = hoya::wat::assemble(br#"(module (memory (export "mem") 1))"#).unwrap();
+    /// assert_eq!(MemoryExports::of(&module).selected.as_deref(), Some("mem"));
+    ///
+    /// let module = hoya::wat::assemble(br#"(module
+    ///   (memory (export "scratch") 1)
+    ///   (memory (export "memory") 1))"#).unwrap();
+    /// assert_eq!(MemoryExports::of(&module).selected.as_deref(), Some("memory"));
+    ///
+    /// let module = hoya::wat::assemble(br#"(module
+    ///   (memory (export "heap") 1)
+    ///   (memory (export "scratch") 1))"#).unwrap();
+    /// assert_eq!(MemoryExports::of(&module).selected.as_deref(), Some("heap"));
+    ///
+    /// let module = hoya::wat::assemble(br#"(module
+    ///   (memory 1)
+    ///   (memory (export "a") 1)
+    ///   (memory (export "b") 1))"#).unwrap();
+    /// let exports = MemoryExports::of(&module);
+    /// assert_eq!(exports.names, ["a", "b"]);
+    /// assert_eq!(exports.selected, None);
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `wasm` - The module binary, already validated by compiling it
+    pub fn of(wasm: &[u8]) -> Self {
+        let exports = memory_exports(wasm);
+        let selected = match exports.as_slice() {
+            [(name, _)] => Some(name.clone()),
+            _ => exports
+                .iter()
+                .find(|(name, _)| name == CANONICAL_MEMORY_EXPORT)
+                .or_else(|| exports.iter().find(|(_, index)| *index == 0))
+                .map(|(name, _)| name.clone()),
+        };
+        MemoryExports {
+            names: exports.into_iter().map(|(name, _)| name).collect(),
+            selected,
+        }
+    }
+}
+
+/// Name and memory index of each memory export, in export order
+fn memory_exports(wasm: &[u8]) -> Vec<(String, usize)> {
+    let mut exports = Vec::new();
+    let Some((_, section)) = sections(wasm)
+        .into_iter()
+        .find(|(id, _)| *id == EXPORT_SECTION)
+    else {
+        return exports;
+    };
+    let Some((count, mut rest)) = read_leb128(section) else {
+        return exports;
+    };
+    for _ in 0..count {
+        let Some((name_len, after_len)) = read_leb128(rest) else {
+            break;
+        };
+        let Some(name) = after_len.get(..name_len) else {
+            break;
+        };
+        let Some((&kind, after_kind)) = after_len[name_len..].split_first() else {
+            break;
+        };
+        let Some((index, after_index)) = read_leb128(after_kind) else {
+            break;
+        };
+        if kind == MEMORY_EXPORT {
+            exports.push((String::from_utf8_lossy(name).into_owned(), index));
+        }
+        rest = after_index;
+    }
+    exports
+}
+
+/// A memory was about to grow past the execution's limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryLimitExceeded {
+    /// The limit, in bytes
+    pub limit_bytes: usize,
+    /// The size the memory would have grown to, in bytes
+    pub requested_bytes: usize,
+}
+
+impl fmt::Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "memory growth to {} bytes exceeds the limit of {} bytes",
+            self.requested_bytes, self.limit_bytes
+        )
+    }
+}
+
+impl std::error::Error for MemoryLimitExceeded {}
+
+/// Resource limiter of one store
+pub struct MemoryLimiter {
+    /// Table and instance limits
+    limits: StoreLimits,
+    /// Largest size each memory may grow to, in bytes
+    memory_bytes: usize,
+    /// Largest size any memory reached, in bytes
+    peak_bytes: usize,
+}
+
+impl MemoryLimiter {
+    /// Create the limiter of one store
+    ///
+    /// # Arguments
+    ///
+    /// * `memory_bytes` - Largest size each memory may grow to
+    /// * `max_tables` - Tables the module may have
+    /// * `max_instances` - Module instances the store may hold
+    pub fn new(memory_bytes: u64, max_tables: usize, max_instances: usize) -> Self {
+        MemoryLimiter {
+            limits: StoreLimitsBuilder::new()
+                .tables(max_tables)
+                .instances(max_instances)
+                .build(),
+            memory_bytes: usize::try_from(memory_bytes).unwrap_or(usize::MAX),
+            peak_bytes: 0,
+        }
+    }
+
+    /// Largest size any memory reached, in 64 KiB pages
+    pub fn peak_pages(&self) -> u64 {
+        self.peak_bytes.div_ceil(WASM_PAGE_BYTES) as u64
+    }
+}
+
+impl ResourceLimiter for MemoryLimiter {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        if desired > self.memory_bytes {
+            return Err(MemoryLimitExceeded {
+                limit_bytes: self.memory_bytes,
+                requested_bytes: desired,
+            }
+            .into());
+        }
+        let allowed = self.limits.memory_growing(current, desired, maximum)?;
+        if allowed {
+            self.peak_bytes = self.peak_bytes.max(desired);
+        }
+        Ok(allowed)
+    }
+
+    fn table_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        self.limits.table_growing(current, desired, maximum)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
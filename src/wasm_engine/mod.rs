@@ -1,151 +1,587 @@
+mod abort;
+mod cache;
+mod disk_cache;
+mod emscripten;
+mod entry;
+mod epoch;
+mod fetch_handles;
 mod ffis;
+mod imports;
+mod memory;
+mod result;
+mod trap;
+mod wasi;
 
-use crate::error::{AppError, ExecuteResponse, ExecutionMetadata};
-use crate::wasm_engine::ffis as wasm_ffis; // Adjusted import path
-use axum::Json;
+pub use abort::{read_assemblyscript_string, GuestAbort, MAX_ABORT_STRING_BYTES};
+pub use cache::{CacheStats, CacheStatus};
+pub use disk_cache::DiskCache;
+pub use emscripten::{read_c_string, Compat};
+pub use epoch::{SharedEngine, MAX_EPOCH_TICK_MS, MIN_EPOCH_TICK_MS};
+pub use ffis::{register_linker_functions, HOST_FUNCTIONS};
+pub use imports::{host_functions, MissingImport, MissingImports, StubbedImportCalled};
+pub use result::OutputEncoding;
+pub use trap::{TrapFrame, TrapKind};
+
+use crate::clock::GuestClock;
+use crate::error::{
+    AppError, EnginePhases, ExecutionArtifacts, ExecutionMetadata, ExecutionResult,
+};
+use crate::executor::ExecOptions;
+use crate::guest_env::GuestEnv;
+use crate::kv::KvNamespace;
+use crate::limits::Limits;
+use crate::net::FetchContext;
+use crate::output::{self, LogEntry, OutputBudget};
+use crate::random::GuestRandom;
+use crate::storage::Namespace;
+use chrono_tz::Tz;
+use entry::Entrypoints;
+use epoch::Interrupt;
+use fetch_handles::FetchHandles;
+pub use memory::MemoryExports;
+use memory::{MemoryLimitExceeded, MemoryLimiter};
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
-use wasmtime::{Engine, Linker, Memory, Module, Store};
+use std::time::Instant;
+use tracing::Instrument;
+use wasi::WasiOutput;
+use wasmtime::{Store, Trap};
+use wasmtime_wasi::p1::WasiP1Ctx;
 
 /// Context for Wasm store to hold shared resources like the HTTP client
 ///
 /// This struct provides access to shared resources for WebAssembly modules.
-/// It includes a reqwest HTTP client and optional memory reference.
+/// It includes the fetch state (HTTP client, cookie jar). Host functions find the
+/// module's memory through its `memory` export on every call, so nothing in the
+/// context depends on the instance it ends up holding.
 pub struct WasmCtx {
-    /// HTTP client for making network requests
-    pub reqwest_client: reqwest::Client,
-    /// Optional reference to the WebAssembly module's memory
-    pub memory: Option<Memory>,
+    /// HTTP client and per-execution cookie jar for guest fetch calls, shared
+    /// with the fetch runtime while a call is in flight
+    pub fetch: Arc<FetchContext>,
+    /// Responses of `fetch_begin` the guest has not closed yet
+    pub fetch_handles: FetchHandles,
     /// Captured stdout content
     pub stdout: Arc<Mutex<String>>,
     /// Captured stderr content
     pub stderr: Arc<Mutex<String>>,
+    /// `app_log` calls admitted to the output budget, in order
+    pub logs: Mutex<Vec<LogEntry>>,
+    /// Pointer and length of the result set with `set_output`, read once `_start` returns
+    pub result: Option<(u32, u32)>,
+    /// Point in time after which the execution is interrupted
+    pub deadline: Instant,
+    /// Byte budget shared by all captured output
+    pub output_budget: Arc<OutputBudget>,
+    /// Hex SHA-256 digest of the executing module, returned by `get_code_hash`
+    pub code_sha256: String,
+    /// Zone of `get_localtime` and `get_utc_offset_seconds`
+    pub timezone: Tz,
+    /// Growth limits of the module's memories and tables, with the peak memory size
+    pub limiter: MemoryLimiter,
+    /// The module's memory exports and the one host functions read and write
+    pub memory: MemoryExports,
+    /// The module's storage namespace, when the execution was granted `storage`
+    pub storage: Option<Namespace>,
+    /// Namespace of the `kv_*` imports
+    pub kv: KvNamespace,
+    /// Variables returned by `env_get`, `env_count` and `env_key_at`
+    pub env: Arc<GuestEnv>,
+    /// The request's `input` as JSON, returned by `input_len` and `input_read`
+    pub input: Option<bytes::Bytes>,
+    /// Source of `random_bytes`, seeded in deterministic mode
+    pub random: GuestRandom,
+    /// Clocks of `get_unixtime`, `get_unixtime_ms` and `monotonic_now_ns`,
+    /// linearized in deterministic mode
+    pub clock: GuestClock,
+    /// WASI context, used by modules importing `wasi_snapshot_preview1`
+    pub wasi: WasiP1Ctx,
+    /// Whether `app_log` replaces invalid UTF-8 rather than failing the execution
+    pub lossy_app_log: bool,
+}
+
+impl WasmCtx {
+    /// Snapshot the `app_log` entries recorded so far
+    fn logs(&self) -> Vec<LogEntry> {
+        output::lock(&self.logs).clone()
+    }
+}
+
+/// Convert a wasmtime error into an `AppError`, recognizing host interruptions,
+/// fuel exhaustion, denied memory growth, WASI exits and guest traps
+///
+/// The module's WASI output is moved into the capture buffers first, so the
+/// output attached to the error by `failed` includes it.
+///
+/// # Arguments
+///
+/// * `error` - The error instantiation or `_start` failed with
+/// * `ctx` - State of the store, holding the output captured so far
+/// * `wasi_output` - Pipes holding the module's WASI output
+/// * `limits` - Limits of the execution
+fn map_wasm_error(
+    error: anyhow::Error,
+    ctx: &WasmCtx,
+    wasi_output: &WasiOutput,
+    limits: &Limits,
+) -> AppError {
+    let captured = wasi_output.capture(ctx);
+    if error.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+        return AppError::FuelExhausted {
+            max_fuel: limits.max_fuel,
+        };
+    }
+    if captured.is_err() || wasi_output.overflowed() {
+        return AppError::OutputBudgetExceeded {
+            limit_bytes: ctx.output_budget.limit(),
+        };
+    }
+    if let Some(exit_code) = wasi::exit_code(&error) {
+        return AppError::WasiExit { exit_code };
+    }
+    if let Some(abort) = error.downcast_ref::<GuestAbort>() {
+        return AppError::GuestAbort(abort.clone());
+    }
+    if let Some(called) = error.downcast_ref::<StubbedImportCalled>() {
+        return AppError::StubbedImportCalled(called.clone());
+    }
+    if let Some(exceeded) = error.downcast_ref::<MemoryLimitExceeded>() {
+        return AppError::MemoryLimitExceeded {
+            limit_bytes: exceeded.limit_bytes as u64,
+            requested_bytes: Some(exceeded.requested_bytes as u64),
+        };
+    }
+    match error.downcast_ref::<Interrupt>() {
+        Some(Interrupt::Timeout) => AppError::Timeout {
+            timeout_ms: limits.timeout.as_millis() as u64,
+        },
+        Some(Interrupt::OutputBudgetExceeded) => AppError::OutputBudgetExceeded {
+            limit_bytes: ctx.output_budget.limit(),
+        },
+        Some(Interrupt::Cancelled) => AppError::Cancelled,
+        None => match error.downcast_ref::<Trap>() {
+            Some(trap) => AppError::WasmTrap {
+                kind: TrapKind::of(*trap),
+                trap: trap.to_string(),
+                backtrace: trap::backtrace(&error),
+            },
+            None => AppError::Wasmtime(error),
+        },
+    }
+}
+
+/// Map an error compiling or linking a module to the AppError reported
+///
+/// # Arguments
+///
+/// * `error` - The error from `SharedEngine::instance_pre`
+/// * `compat` - Toolchain whose imports were shimmed, which the host then also provides
+///
+/// # Returns
+///
+/// * `AppError` - `MissingImports` naming every import the host lacks, or `InvalidModule`
+pub fn compile_error(error: anyhow::Error, compat: Option<Compat>) -> AppError {
+    match error.downcast::<MissingImports>() {
+        Ok(MissingImports(imports)) => AppError::MissingImports {
+            imports,
+            provided: host_functions(compat),
+        },
+        Err(error) => AppError::InvalidModule(format!("{:#}", error)),
+    }
+}
+
+/// Fuel the store has consumed out of the execution's budget
+fn fuel_consumed(store: &Store<WasmCtx>, limits: &Limits) -> u64 {
+    limits.max_fuel - store.get_fuel().unwrap_or(limits.max_fuel)
 }
 
 /// Execute WebAssembly code and return the execution result
 ///
+/// The module runs as part of the returned future: it is compiled on the
+/// blocking pool when it is not cached, and suspended, holding no thread,
+/// while it waits on a `fetch`, `fetch_begin` or `sleep_ms` call. Those calls
+/// and their timers run on the fetch runtime of `fetch_ctx`, and between
+/// epoch ticks the module yields to whatever polls it, so the future can be
+/// awaited on any Tokio runtime, a current-thread one included, or driven by
+/// another executor; without a Tokio runtime the module compiles on the
+/// polling thread.
+///
 /// # Arguments
 ///
-/// * `wasm_code` - WebAssembly code to execute as a byte array
+/// * `shared_engine` - Engine shared by all executions, with its epoch ticker
+/// * `downloaded_code` - The module's binary
+/// * `opts` - Options of the execution:
+///   - `sha256` is returned by `get_code_hash`
+///   - the cancel token of `fetch_ctx` interrupts the module at the next epoch tick
+///   - the timeout and fuel of `limits` cover instantiation and `_start`, and the
+///     timeout is enforced with the shared engine's epoch tick granularity
+///   - `timezone` is the zone of `get_localtime` and `get_utc_offset_seconds`
+///   - `storage` backs the `pkv_*` imports; without it they return `PKV_ERR_DENIED`
+///   - `kv` backs the `kv_*` imports
+///   - `env` backs the `env_*` imports, `input` the `input_*` imports
 ///
 /// # Returns
 ///
-/// * `Result<Json<ExecuteResponse>, AppError>` - Execution result or error
-pub fn execute_wasm(downloaded_code: bytes::Bytes) -> Result<Json<ExecuteResponse>, AppError> {
-    println!(
-        "Code type: WebAssembly, size: {} bytes",
-        downloaded_code.len()
-    );
+/// * `Result<ExecutionResult, AppError>` - Execution result or error
+///
+/// # Examples
+///
+/// A module fetching a closed port, run inside a current-thread runtime:
+///
+/// ```
+/// use hoya::config::Config;
+/// use hoya::executor::ExecOptions;
+/// use hoya::fetch_runtime::FetchRuntime;
+/// use hoya::kv::KvStore;
+/// use hoya::limits::Limits;
+/// use hoya::metrics::Metrics;
+/// use hoya::net::FetchContext;
+/// use hoya::outbound::OutboundPolicy;
+/// use hoya::wasm_engine::{execute_wasm, SharedEngine};
+/// use std::sync::Arc;
+///
+/// // Logs the response document of its fetch call
+/// let module = hoya::wat::assemble(br#"(module
+///   (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+///   (import "env" "fetch" (func $fetch (param i32 i32 i32 i32) (result i32)))
+///   (memory (export "memory") 1)
+///   (data (i32.const 0) "INFO")
+///   (data (i32.const 16) "{\"url\":\"http://127.0.0.1:9/\",\"method\":\"GET\",\"headers\":{}}")
+///   (func (export "_start")
+///     (call $app_log (i32.const 0) (i32.const 4) (i32.const 256)
+///       (call $fetch (i32.const 16) (i32.const 57) (i32.const 256) (i32.const 1024)))))"#)
+/// .unwrap();
+///
+/// let config = Config::default();
+/// let limits = Limits::defaults(&config);
+/// let engine = Arc::new(SharedEngine::new(&config.wasm).unwrap());
+/// let runtime = tokio::runtime::Builder::new_current_thread()
+///     .enable_all()
+///     .build()
+///     .unwrap();
+/// let result = runtime.block_on(async {
+///     let options = ExecOptions {
+///         sha256: String::new(),
+///         url: None,
+///         fetch_ctx: FetchContext::new(
+///             reqwest::Client::new(),
+///             Arc::new(FetchRuntime::start().unwrap()),
+///             Arc::new(OutboundPolicy::unrestricted()),
+///             false,
+///             Arc::new(Metrics::new(&[]).unwrap()),
+///         ),
+///         output_budget: Arc::new(limits.output_budget(false, false)),
+///         deterministic: None,
+///         timezone: chrono_tz::Tz::UTC,
+///         limits,
+///         storage: None,
+///         kv: KvStore::new(&config.kv).isolated(),
+///         env: Arc::default(),
+///         input: None,
+///         compat: None,
+///         stub_missing_imports: false,
+///         lossy_app_log: false,
+///     };
+///     execute_wasm(&engine, module.into(), &options).await
+/// });
+/// assert!(result.unwrap().stdout.contains(r#""code":"FETCH_FAILED""#));
+/// ```
+///
+/// The same module driven from plain synchronous code, without any Tokio
+/// runtime, by an executor parking the thread until the future is woken:
+///
+/// ```
+/// use hoya::config::Config;
+/// use hoya::executor::ExecOptions;
+/// use hoya::fetch_runtime::FetchRuntime;
+/// use hoya::kv::KvStore;
+/// use hoya::limits::Limits;
+/// use hoya::metrics::Metrics;
+/// use hoya::net::FetchContext;
+/// use hoya::outbound::OutboundPolicy;
+/// use hoya::wasm_engine::{execute_wasm, SharedEngine};
+/// use std::future::Future;
+/// use std::sync::Arc;
+/// use std::task::{Context, Poll, Wake, Waker};
+/// use std::thread::Thread;
+///
+/// struct Unpark(Thread);
+///
+/// impl Wake for Unpark {
+///     fn wake(self: Arc<Self>) {
+///         self.0.unpark();
+///     }
+/// }
+///
+/// fn block_on<F: Future>(future: F) -> F::Output {
+///     let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+///     let mut context = Context::from_waker(&waker);
+///     let mut future = std::pin::pin!(future);
+///     loop {
+///         match future.as_mut().poll(&mut context) {
+///             Poll::Ready(output) => return output,
+///             Poll::Pending => std::thread::park(),
+///         }
+///     }
+/// }
+///
+/// let module = hoya::wat::assemble(br#"(module
+///   (import "env" "app_log" (func $app_log (param i32 i32 i32 i32)))
+///   (import "env" "fetch" (func $fetch (param i32 i32 i32 i32) (result i32)))
+///   (memory (export "memory") 1)
+///   (data (i32.const 0) "INFO")
+///   (data (i32.const 16) "{\"url\":\"http://127.0.0.1:9/\",\"method\":\"GET\",\"headers\":{}}")
+///   (func (export "_start")
+///     (call $app_log (i32.const 0) (i32.const 4) (i32.const 256)
+///       (call $fetch (i32.const 16) (i32.const 57) (i32.const 256) (i32.const 1024)))))"#)
+/// .unwrap();
+///
+/// let config = Config::default();
+/// let limits = Limits::defaults(&config);
+/// let engine = Arc::new(SharedEngine::new(&config.wasm).unwrap());
+/// let options = ExecOptions {
+///     sha256: String::new(),
+///     url: None,
+///     fetch_ctx: FetchContext::new(
+///         reqwest::Client::new(),
+///         Arc::new(FetchRuntime::start().unwrap()),
+///         Arc::new(OutboundPolicy::unrestricted()),
+///         false,
+///         Arc::new(Metrics::new(&[]).unwrap()),
+///     ),
+///     output_budget: Arc::new(limits.output_budget(false, false)),
+///     deterministic: None,
+///     timezone: chrono_tz::Tz::UTC,
+///     limits,
+///     storage: None,
+///     kv: KvStore::new(&config.kv).isolated(),
+///     env: Arc::default(),
+///     input: None,
+///     compat: None,
+///     stub_missing_imports: false,
+///     lossy_app_log: false,
+/// };
+/// assert!(tokio::runtime::Handle::try_current().is_err());
+/// let result = block_on(execute_wasm(&engine, module.into(), &options));
+/// assert!(result.unwrap().stdout.contains(r#""code":"FETCH_FAILED""#));
+/// ```
+pub async fn execute_wasm(
+    shared_engine: &Arc<SharedEngine>,
+    downloaded_code: bytes::Bytes,
+    opts: &ExecOptions,
+) -> Result<ExecutionResult, AppError> {
+    let code_sha256 = opts.sha256.as_str();
+    let limits = opts.limits;
+    tracing::info!(size_bytes = downloaded_code.len(), "Running WebAssembly");
 
-    let start_time = std::time::Instant::now();
+    let start_time = Instant::now();
     let resource_size = downloaded_code.len();
+    let timeout = limits.timeout;
 
-    let engine = Engine::default();
+    // Errors carry what the module printed before them, and the execution's metadata
+    let failed = |error: AppError,
+                  store: &Store<WasmCtx>,
+                  module_cache: Option<CacheStatus>,
+                  phases: EnginePhases| {
+        let mut metadata = ExecutionMetadata::new(
+            "webassembly",
+            resource_size,
+            start_time.elapsed().as_millis() as u64,
+        );
+        metadata.set_phases(phases);
+        metadata.module_cache = module_cache;
+        metadata.fuel_consumed = Some(fuel_consumed(store, &limits));
+        metadata.peak_memory_pages = Some(store.data().limiter.peak_pages());
+        let mut partial =
+            ExecutionArtifacts::capture(&store.data().stdout, &store.data().stderr, metadata);
+        partial.logs = Some(store.data().logs());
+        AppError::FailedExecution {
+            error: Box::new(error),
+            partial,
+        }
+    };
+
+    let metrics = opts.fetch_ctx.metrics.clone();
+    let engine = &shared_engine.engine;
+    let (wasi_ctx, wasi_output) = wasi::context(limits.max_output_bytes);
     let wasm_shared_data = WasmCtx {
-        reqwest_client: reqwest::Client::new(),
-        memory: None,
+        fetch: Arc::new(opts.fetch_ctx.clone()),
+        fetch_handles: FetchHandles::default(),
         stdout: Arc::new(Mutex::new(String::new())),
         stderr: Arc::new(Mutex::new(String::new())),
+        logs: Mutex::new(Vec::new()),
+        result: None,
+        deadline: start_time + timeout,
+        output_budget: opts.output_budget.clone(),
+        code_sha256: code_sha256.to_string(),
+        timezone: opts.timezone,
+        limiter: MemoryLimiter::new(
+            limits.memory_bytes,
+            shared_engine.max_tables,
+            shared_engine.max_instances,
+        ),
+        memory: MemoryExports::default(),
+        storage: opts.storage.clone(),
+        kv: opts.kv.clone(),
+        env: opts.env.clone(),
+        input: opts.input.clone(),
+        random: GuestRandom::new(opts.deterministic),
+        clock: GuestClock::new(opts.deterministic.is_some()),
+        wasi: wasi_ctx,
+        lossy_app_log: opts.lossy_app_log,
     };
-    let mut store = Store::new(&engine, wasm_shared_data);
-    let mut linker = Linker::new(&engine);
+    let mut store = Store::new(engine, wasm_shared_data);
+    store.limiter(|ctx| &mut ctx.limiter);
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(epoch::on_epoch_deadline);
+    store.set_fuel(limits.max_fuel).map_err(|e| {
+        let error = AppError::Internal(format!("Failed to set fuel: {}", e));
+        failed(error, &store, None, EnginePhases::default())
+    })?;
 
-    // Call the function from wasm_ffis to register linker functions
-    wasm_ffis::register_linker_functions(&mut linker)
-        .map_err(|e| AppError::Internal(format!("Failed to register linker functions: {}", e)))?;
+    let compile_span = tracing::info_span!("compile", module_cache = tracing::field::Empty);
+    let compile_started = Instant::now();
+    // Compiling is CPU-bound, so it runs off the runtime's workers, or on the
+    // calling thread when no Tokio runtime drives the execution. Imports that
+    // do not link fail here, before any store is instantiated.
+    let compiled = {
+        let shared_engine = shared_engine.clone();
+        let code_sha256 = code_sha256.to_string();
+        let code = downloaded_code.clone();
+        let span = compile_span.clone();
+        let compat = opts.compat;
+        let stub_missing_imports = opts.stub_missing_imports;
+        let compile = move || {
+            span.in_scope(|| {
+                shared_engine.instance_pre(&code_sha256, &code, compat, stub_missing_imports)
+            })
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle.spawn_blocking(compile).await,
+            Err(_) => Ok(compile()),
+        }
+    };
+    let compile_time = compile_started.elapsed();
+    let mut phases = EnginePhases {
+        compile_ms: Some(compile_time.as_millis() as u64),
+        ..EnginePhases::default()
+    };
+    let (instance_pre, module_cache) = match compiled {
+        Ok(Ok(compiled)) => compiled,
+        Ok(Err(e)) => {
+            let error = compile_error(e, opts.compat);
+            return Err(failed(error, &store, None, phases));
+        }
+        Err(e) => {
+            let error = AppError::Internal(format!("Compilation did not finish: {}", e));
+            return Err(failed(error, &store, None, phases));
+        }
+    };
+    compile_span.record("module_cache", tracing::field::debug(&module_cache));
+    // Host functions called during instantiation already need the memory's name
+    store.data_mut().memory = MemoryExports::of(&downloaded_code);
+    metrics.observe_wasm_phase("compile", compile_time);
 
-    let module = Module::from_binary(&engine, &downloaded_code)?;
+    let instantiate_started = Instant::now();
+    let instantiated = instance_pre
+        .instantiate_async(&mut store)
+        .instrument(tracing::info_span!("instantiate"))
+        .await;
+    let instantiate_time = instantiate_started.elapsed();
+    metrics.observe_wasm_phase("instantiate", instantiate_time);
+    phases.instantiate_ms = Some(instantiate_time.as_millis() as u64);
+    let instance = match instantiated {
+        Ok(instance) => instance,
+        Err(e) => {
+            let error = match map_wasm_error(e, store.data(), &wasi_output, &limits) {
+                // Anything but a trap or an interruption means the module could not be set up
+                AppError::Wasmtime(e) => AppError::InvalidModule(format!("{:#}", e)),
+                other => other,
+            };
+            return Err(failed(error, &store, Some(module_cache), phases));
+        }
+    };
 
-    let instance = linker.instantiate(&mut store, &module)?;
+    let selected = store.data().memory.selected.clone();
+    let Some(memory) = selected.and_then(|name| instance.get_memory(&mut store, &name)) else {
+        let error = AppError::NoUsableMemory {
+            memory_exports: store.data().memory.names.clone(),
+        };
+        return Err(failed(error, &store, Some(module_cache), phases));
+    };
 
-    if let Some(wasmtime::Extern::Memory(mem)) = instance.get_export(&mut store, "memory") {
-        store.data_mut().memory = Some(mem);
-    } else {
-        return Err(AppError::Internal(
-            "WASM module does not export 'memory'".to_string(),
-        ));
-    }
+    // Metadata is built once the module is done, so every field reflects the whole run
+    let finished = |store: &Store<WasmCtx>, phases: EnginePhases| {
+        let mut metadata = ExecutionMetadata::new(
+            "webassembly",
+            resource_size,
+            start_time.elapsed().as_millis() as u64,
+        );
+        metadata.set_phases(phases);
+        metadata.module_cache = Some(module_cache);
+        metadata.fuel_consumed = Some(fuel_consumed(store, &limits));
+        metadata.peak_memory_pages = Some(store.data().limiter.peak_pages());
+        metadata
+    };
 
-    // Calculate execution time before function call
-    let instantiation_time = start_time.elapsed().as_millis() as u64;
-
-    // Generate ISO timestamp
-    let now = SystemTime::now();
-    let timestamp = match now.duration_since(UNIX_EPOCH) {
-        Ok(duration) => {
-            let datetime = chrono::DateTime::<chrono::Utc>::from_timestamp(
-                duration.as_secs() as i64,
-                duration.subsec_nanos(),
-            )
-            .unwrap_or_else(|| chrono::Utc::now());
-            datetime.to_rfc3339()
+    let entrypoints = match Entrypoints::of(&instance, &mut store) {
+        Ok(entrypoints) => entrypoints,
+        Err(error) => return Err(failed(error, &store, Some(module_cache), phases)),
+    };
+    let output = if entrypoints.initialize.is_none() && entrypoints.entry.is_none() {
+        "WASM module instantiated (no _start called or found)".to_string()
+    } else {
+        // Guest fetch calls made by the entrypoints are traced under the run span
+        let run_span = tracing::info_span!("run");
+        // No fetch call is in flight before they run, so the context is not copied
+        Arc::make_mut(&mut store.data_mut().fetch).span = run_span.clone();
+        let run_started = Instant::now();
+        let ran = entrypoints.run(&mut store).instrument(run_span).await;
+        phases.run_ms = Some(run_started.elapsed().as_millis() as u64);
+        let exit_code = match ran {
+            Ok(exit_code) => exit_code,
+            // proc_exit(0) ends a WASI program successfully
+            Err(e) if wasi::exit_code(&e) == Some(0) => None,
+            Err(e) => {
+                let error = map_wasm_error(e, store.data(), &wasi_output, &limits);
+                return Err(failed(error, &store, Some(module_cache), phases));
+            }
+        };
+        match (&entrypoints.entry, exit_code) {
+            (Some(entry), Some(exit_code)) => format!(
+                "WASM module executed ({}) with exit code {}",
+                entry.name(),
+                exit_code
+            ),
+            (Some(entry), None) => format!("WASM module executed ({})", entry.name()),
+            (None, _) => {
+                "WASM module initialized (_initialize called, no entrypoint found)".to_string()
+            }
         }
-        Err(_) => chrono::Utc::now().to_rfc3339(),
     };
+    if wasi_output.capture(store.data()).is_err() {
+        let error = AppError::OutputBudgetExceeded {
+            limit_bytes: store.data().output_budget.limit(),
+        };
+        return Err(failed(error, &store, Some(module_cache), phases));
+    }
 
-    let metadata = ExecutionMetadata {
-        execution_time: instantiation_time,
-        code_type: "webassembly".to_string(),
-        timestamp,
-        resource_size,
+    // A result set with set_output replaces the default output
+    let (output, output_encoding) = match result::read(&store, memory) {
+        Some((result, encoding)) => (result, Some(encoding)),
+        None => (output, None),
     };
+    let mut metadata = finished(&store, phases);
+    metadata.output_encoding = output_encoding;
 
-    if let Ok(start_func) = instance.get_typed_func::<(), ()>(&mut store, "_start") {
-        start_func
-            .call(&mut store, ())
-            .map_err(AppError::Wasmtime)?;
+    // Get the captured stdout and stderr
+    let stdout = output::lock(&store.data().stdout).clone();
+    let stderr = output::lock(&store.data().stderr).clone();
 
-        // Update execution time including _start function
-        let total_execution_time = start_time.elapsed().as_millis() as u64;
-        let updated_metadata = ExecutionMetadata {
-            execution_time: total_execution_time,
-            ..metadata
-        };
-
-        // Get the captured stdout and stderr
-        let stdout = store
-            .data()
-            .stdout
-            .lock()
-            .map(|s| s.clone())
-            .unwrap_or_default();
-        let stderr = store
-            .data()
-            .stderr
-            .lock()
-            .map(|s| s.clone())
-            .unwrap_or_default();
-
-        Ok(Json(ExecuteResponse {
-            status: "success".to_string(),
-            output: Some("WASM module executed (_start)".to_string()),
-            stdout: Some(stdout),
-            stderr: Some(stderr),
-            error: None,
-            metadata: updated_metadata,
-        }))
-    } else {
-        // Get the captured stdout and stderr
-        let stdout = store
-            .data()
-            .stdout
-            .lock()
-            .map(|s| s.clone())
-            .unwrap_or_default();
-        let stderr = store
-            .data()
-            .stderr
-            .lock()
-            .map(|s| s.clone())
-            .unwrap_or_default();
-
-        Ok(Json(ExecuteResponse {
-            status: "success".to_string(),
-            output: Some("WASM module instantiated (no _start called or found)".to_string()),
-            stdout: Some(stdout),
-            stderr: Some(stderr),
-            error: None,
-            metadata,
-        }))
-    }
+    Ok(ExecutionResult {
+        output,
+        output_json: None,
+        stdout,
+        stderr,
+        logs: store.data().logs(),
+        metadata,
+    })
 }
@@ -0,0 +1,50 @@
+//! Results handed back by WebAssembly modules.
+//!
+//! A module returns a result, rather than printing it, by calling the
+//! `env.set_output(ptr, len)` import with the location of the bytes in its
+//! memory. Only the location is recorded; the bytes are read once `_start`
+//! returns, so the module must keep them alive and unchanged until then. A
+//! later call replaces the location of an earlier one. Each call charges its
+//! `len` bytes to stdout's share of the output budget; a result the budget
+//! cannot take is dropped, or fails a strict execution. The result becomes
+//! the response's `output`: as is when it is valid UTF-8, base64-encoded
+//! otherwise, with `metadata.output_encoding` telling which.
+
+use serde::Serialize;
+use wasmtime::{Memory, Store};
+
+use super::WasmCtx;
+use crate::base64;
+
+/// How a module's result is written into the response's `output`
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputEncoding {
+    /// The result is valid UTF-8 and returned as is
+    Utf8,
+    /// The result is binary and returned base64-encoded
+    Base64,
+}
+
+/// Read the result the module set with `set_output`, if it set one
+///
+/// # Arguments
+///
+/// * `store` - The store the module ran in
+/// * `memory` - The memory the module exports, which `set_output` pointed into
+///
+/// # Returns
+///
+/// * `Option<(String, OutputEncoding)>` - The result as text, and how it was encoded
+pub fn read(store: &Store<WasmCtx>, memory: Memory) -> Option<(String, OutputEncoding)> {
+    let (ptr, len) = store.data().result?;
+    // set_output checked the bounds, and memories never shrink
+    let bytes = memory
+        .data(store)
+        .get(ptr as usize..ptr as usize + len as usize)?
+        .to_vec();
+    Some(match String::from_utf8(bytes) {
+        Ok(text) => (text, OutputEncoding::Utf8),
+        Err(e) => (base64::encode(e.as_bytes()), OutputEncoding::Base64),
+    })
+}
@@ -0,0 +1,163 @@
+//! Traps of WebAssembly guests.
+//!
+//! A trap ends the execution with an error code naming its kind, such as
+//! `WASM_TRAP_UNREACHABLE` or `WASM_TRAP_INTEGER_DIVIDE_BY_ZERO`, and the
+//! guest's stack at the time, one `TrapFrame` per function, innermost first.
+//! Frames name their function when the module has a name section, and give
+//! the source location when it was built with DWARF debug info.
+
+use serde::Serialize;
+use wasmtime::{Trap, WasmBacktrace};
+
+/// Kind of a trap, as far as the error code tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapKind {
+    /// An `unreachable` instruction was executed
+    Unreachable,
+    /// A load or store went past the end of a memory
+    MemoryOutOfBounds,
+    /// An atomic access was not aligned
+    HeapMisaligned,
+    /// A table access went past the end of a table
+    TableOutOfBounds,
+    /// An indirect call went through a null table entry
+    IndirectCallToNull,
+    /// An indirect call's signature did not match the callee's
+    BadSignature,
+    /// An integer arithmetic operation overflowed
+    IntegerOverflow,
+    /// An integer was divided by zero
+    IntegerDivideByZero,
+    /// A float that does not fit the integer type was converted to it
+    BadConversionToInteger,
+    /// The guest's call stack grew past its limit
+    StackOverflow,
+    /// A null reference was dereferenced
+    NullReference,
+    /// An array access went past the end of a GC array
+    ArrayOutOfBounds,
+    /// A GC allocation was too large
+    AllocationTooLarge,
+    /// A reference cast failed
+    CastFailure,
+    /// Any other trap
+    Other,
+}
+
+impl TrapKind {
+    /// The kind of `trap`
+    pub fn of(trap: Trap) -> Self {
+        match trap {
+            Trap::UnreachableCodeReached => TrapKind::Unreachable,
+            Trap::MemoryOutOfBounds => TrapKind::MemoryOutOfBounds,
+            Trap::HeapMisaligned => TrapKind::HeapMisaligned,
+            Trap::TableOutOfBounds => TrapKind::TableOutOfBounds,
+            Trap::IndirectCallToNull => TrapKind::IndirectCallToNull,
+            Trap::BadSignature => TrapKind::BadSignature,
+            Trap::IntegerOverflow => TrapKind::IntegerOverflow,
+            Trap::IntegerDivisionByZero => TrapKind::IntegerDivideByZero,
+            Trap::BadConversionToInteger => TrapKind::BadConversionToInteger,
+            Trap::StackOverflow => TrapKind::StackOverflow,
+            Trap::NullReference => TrapKind::NullReference,
+            Trap::ArrayOutOfBounds => TrapKind::ArrayOutOfBounds,
+            Trap::AllocationTooLarge => TrapKind::AllocationTooLarge,
+            Trap::CastFailure => TrapKind::CastFailure,
+            _ => TrapKind::Other,
+        }
+    }
+
+    /// Error code of executions ending with a trap of this kind
+    pub fn code(self) -> &'static str {
+        match self {
+            TrapKind::Unreachable => "WASM_TRAP_UNREACHABLE",
+            TrapKind::MemoryOutOfBounds => "WASM_TRAP_MEMORY_OUT_OF_BOUNDS",
+            TrapKind::HeapMisaligned => "WASM_TRAP_HEAP_MISALIGNED",
+            TrapKind::TableOutOfBounds => "WASM_TRAP_TABLE_OUT_OF_BOUNDS",
+            TrapKind::IndirectCallToNull => "WASM_TRAP_INDIRECT_CALL_TO_NULL",
+            TrapKind::BadSignature => "WASM_TRAP_BAD_SIGNATURE",
+            TrapKind::IntegerOverflow => "WASM_TRAP_INTEGER_OVERFLOW",
+            TrapKind::IntegerDivideByZero => "WASM_TRAP_INTEGER_DIVIDE_BY_ZERO",
+            TrapKind::BadConversionToInteger => "WASM_TRAP_BAD_CONVERSION_TO_INTEGER",
+            TrapKind::StackOverflow => "WASM_TRAP_STACK_OVERFLOW",
+            TrapKind::NullReference => "WASM_TRAP_NULL_REFERENCE",
+            TrapKind::ArrayOutOfBounds => "WASM_TRAP_ARRAY_OUT_OF_BOUNDS",
+            TrapKind::AllocationTooLarge => "WASM_TRAP_ALLOCATION_TOO_LARGE",
+            TrapKind::CastFailure => "WASM_TRAP_CAST_FAILURE",
+            TrapKind::Other => "WASM_TRAP",
+        }
+    }
+
+    /// Name of the kind in `details.trap.kind`: the code without its
+    /// `WASM_TRAP_` prefix, in lowercase, or "other"
+    ///
+    /// ```
+    /// use hoya::wasm_engine::TrapKind;
+    ///
+    /// assert_eq!(TrapKind::of(wasmtime::Trap::UnreachableCodeReached).name(), "unreachable");
+    /// assert_eq!(TrapKind::IntegerDivideByZero.code(), "WASM_TRAP_INTEGER_DIVIDE_BY_ZERO");
+    /// ```
+    pub fn name(self) -> String {
+        match self.code().strip_prefix("WASM_TRAP_") {
+            Some(kind) => kind.to_ascii_lowercase(),
+            None => "other".to_string(),
+        }
+    }
+}
+
+/// A function on the guest's stack when it trapped
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TrapFrame {
+    /// Index of the function in the module's function index space
+    pub func_index: u32,
+    /// Name of the function, from the module's name section
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub func_name: Option<String>,
+    /// Offset of the executing instruction in the module's binary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module_offset: Option<usize>,
+    /// Offset of the executing instruction from the start of its function's body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub func_offset: Option<usize>,
+    /// Source file of the instruction, from the module's debug info
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// One-based source line of the instruction, from the module's debug info
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+    /// One-based source column of the instruction, from the module's debug info
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<u32>,
+}
+
+/// Frames of the guest's stack a trap was raised with, innermost first
+///
+/// # Arguments
+///
+/// * `error` - The error the guest call failed with
+///
+/// # Returns
+///
+/// * `Vec<TrapFrame>` - The frames; empty when the error carries no backtrace
+pub fn backtrace(error: &anyhow::Error) -> Vec<TrapFrame> {
+    let Some(backtrace) = error.downcast_ref::<WasmBacktrace>() else {
+        return Vec::new();
+    };
+    backtrace
+        .frames()
+        .iter()
+        .map(|frame| {
+            // Inlined functions give several symbols; the innermost comes first
+            let symbol = frame.symbols().first();
+            TrapFrame {
+                func_index: frame.func_index(),
+                func_name: frame.func_name().map(str::to_string),
+                module_offset: frame.module_offset(),
+                func_offset: frame.func_offset(),
+                file: symbol.and_then(|s| s.file()).map(str::to_string),
+                line: symbol.and_then(|s| s.line()),
+                column: symbol.and_then(|s| s.column()),
+            }
+        })
+        .collect()
+}
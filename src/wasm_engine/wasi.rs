@@ -0,0 +1,87 @@
+//! WASI preview1 support for modules built for `wasm32-wasip1`.
+//!
+//! The shared linker defines the WASI functions next to the `env` imports,
+//! so modules importing `wasi_snapshot_preview1` link against them. Their
+//! context has no preopened directories, no environment variables and no
+//! arguments; stdout and stderr go to memory pipes that are moved into the
+//! execution's capture buffers, line by line and through the output budget,
+//! once the module has run. Lines written through WASI therefore follow the
+//! lines captured with `capture_stdout` and `capture_stderr`.
+
+use anyhow::Result as AnyhowResult;
+use wasmtime_wasi::p1::WasiP1Ctx;
+use wasmtime_wasi::p2::pipe::MemoryOutputPipe;
+use wasmtime_wasi::{I32Exit, WasiCtxBuilder};
+
+use super::ffis::capture_line;
+use super::WasmCtx;
+use crate::output::{self, Channel};
+
+/// Exit code passed to `proc_exit`, if that is why the module stopped
+pub fn exit_code(error: &anyhow::Error) -> Option<i32> {
+    error.downcast_ref::<I32Exit>().map(|exit| exit.0)
+}
+
+/// Pipes receiving a module's WASI stdout and stderr
+pub struct WasiOutput {
+    /// Bytes written to file descriptor 1
+    stdout: MemoryOutputPipe,
+    /// Bytes written to file descriptor 2
+    stderr: MemoryOutputPipe,
+    /// Size of each pipe; writes beyond it trap
+    capacity: usize,
+}
+
+/// Create the WASI context of one execution
+///
+/// # Arguments
+///
+/// * `capacity` - Size of the stdout and stderr pipes, the execution's output budget in bytes
+///
+/// # Returns
+///
+/// * `(WasiP1Ctx, WasiOutput)` - The context and the pipes its output goes to
+pub fn context(capacity: usize) -> (WasiP1Ctx, WasiOutput) {
+    let output = WasiOutput {
+        stdout: MemoryOutputPipe::new(capacity),
+        stderr: MemoryOutputPipe::new(capacity),
+        capacity,
+    };
+    let ctx = WasiCtxBuilder::new()
+        .stdout(output.stdout.clone())
+        .stderr(output.stderr.clone())
+        .build_p1();
+    (ctx, output)
+}
+
+impl WasiOutput {
+    /// Whether the module wrote more than a whole output budget to either pipe
+    pub fn overflowed(&self) -> bool {
+        self.stdout.contents().len() >= self.capacity
+            || self.stderr.contents().len() >= self.capacity
+    }
+
+    /// Move what the module wrote into the capture buffers
+    ///
+    /// Each line is admitted by the output budget like a `capture_stdout` or
+    /// `capture_stderr` call; a last line without a newline counts as a line.
+    ///
+    /// # Returns
+    ///
+    /// * `AnyhowResult<()>` - An `Interrupt::OutputBudgetExceeded` error once a strict budget is exhausted
+    pub fn capture(&self, ctx: &WasmCtx) -> AnyhowResult<()> {
+        for (pipe, channel, buffer) in [
+            (&self.stdout, Channel::Stdout, &ctx.stdout),
+            (&self.stderr, Channel::Stderr, &ctx.stderr),
+        ] {
+            let contents = pipe.contents();
+            let text = String::from_utf8_lossy(&contents);
+            for line in text.lines() {
+                if capture_line(ctx, channel, buffer, line)? && ctx.output_budget.echo_to_host() {
+                    output::echo_line("webassembly", channel, line);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! WebAssembly text format.
+//!
+//! Modules written in the text format (`.wat`) are assembled into the binary
+//! format right after detection, so the manifest reader, the module cache and
+//! the engine only ever see binaries. The metadata of such executions still
+//! says `webassembly`, with `source_format: "wat"` to tell them apart.
+//!
+//! Assembly uses `wast`, the parser behind the `wat` crate, directly: its
+//! errors carry the span they occurred at, which is reported as a line and
+//! column instead of a rendered snippet.
+
+use crate::error::AppError;
+use serde::Serialize;
+
+/// Format a WebAssembly module was written in, when it was not the binary format
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceFormat {
+    /// The WebAssembly text format
+    Wat,
+}
+
+/// Assemble a text format module into a WebAssembly binary
+///
+/// # Arguments
+///
+/// * `source` - The downloaded module, decompressed
+///
+/// # Returns
+///
+/// * `Result<bytes::Bytes, AppError>` - The binary module, or `WatSyntaxError`
+///   with the line and column the text stops making sense at
+pub fn assemble(source: &[u8]) -> Result<bytes::Bytes, AppError> {
+    let text = std::str::from_utf8(source).map_err(|e| {
+        let (line, column) = line_column(source, e.valid_up_to());
+        AppError::WatSyntaxError {
+            message: "the module is not valid UTF-8".to_string(),
+            line,
+            column,
+        }
+    })?;
+    let syntax_error = |error: wast::Error| {
+        let (line, column) = line_column(text.as_bytes(), error.span().offset());
+        AppError::WatSyntaxError {
+            message: error.message(),
+            line,
+            column,
+        }
+    };
+    let buffer = wast::parser::ParseBuffer::new(text).map_err(syntax_error)?;
+    let mut module = wast::parser::parse::<wast::Wat>(&buffer).map_err(syntax_error)?;
+    module
+        .encode()
+        .map(bytes::Bytes::from)
+        .map_err(syntax_error)
+}
+
+/// Whether a payload reads as a text format module: `(module` after any
+/// whitespace and `;;` comment lines
+pub fn looks_like_wat(code: &[u8]) -> bool {
+    let Ok(text) = std::str::from_utf8(code) else {
+        return false;
+    };
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with(";;"))
+        .is_some_and(|line| line.starts_with("(module"))
+}
+
+/// One-based line and column of a byte offset, counting columns in characters
+fn line_column(text: &[u8], offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+    let column = String::from_utf8_lossy(&before[line_start..])
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
@@ -0,0 +1,444 @@
+//! # Response wire formats
+//!
+//! Executions produce an `ExecuteResponse`, which is what the audit log,
+//! history and reporters see. Only when it is sent to the caller is it
+//! converted to the response version the caller asked for, with the
+//! `X-Hoya-Response-Version` header or the `response_version` request field
+//! (the field wins when both are set):
+//!
+//! - Version 1 (the default) is `ExecuteResponse` itself, with snake_case
+//!   field names.
+//! - Version 2 uses camelCase field names throughout, nests the timing fields
+//!   in `metadata.timings` and moves `stdout`/`stderr`/`logs` into `outputs`.
+//!
+//! Version 2 is built from parallel structs below rather than by renaming
+//! fields on the fly, so neither shape can change without touching its own
+//! definition.
+
+use crate::download::DownloadReport;
+use crate::download_cache::DownloadCacheStatus;
+use crate::error::{AppError, ErrorInfo, ExecuteResponse, ExecutionMetadata};
+use crate::executor::EffectiveOptions;
+use crate::manifest::{AppliedManifest, Permission};
+use crate::net::{HostNetworkStats, NetworkStats};
+use crate::output::{DroppedOutput, LogEntry, LogLevel, LogSource, OutputUsage};
+use crate::wasm_engine::{CacheStatus, OutputEncoding};
+use crate::wat::SourceFormat;
+use axum::http::HeaderValue;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Request header selecting the response version
+pub const RESPONSE_VERSION_HEADER: &str = "x-hoya-response-version";
+
+/// Shape of the execute response body
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseVersion {
+    /// snake_case fields, as before versioning existed
+    #[default]
+    V1,
+    /// camelCase fields with nested `timings` and `outputs`
+    V2,
+}
+
+impl ResponseVersion {
+    /// Resolve the version asked for by a request
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - Value of the `X-Hoya-Response-Version` header, if sent
+    /// * `field` - The request's `response_version` field, if set
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ResponseVersion, AppError>` - The version (1 when neither is
+    ///   set), or `UnsupportedResponseVersion`
+    pub fn resolve(
+        header: Option<&HeaderValue>,
+        field: Option<u32>,
+    ) -> Result<ResponseVersion, AppError> {
+        let requested = match (field, header) {
+            (Some(version), _) => version.to_string(),
+            (None, Some(value)) => value.to_str().unwrap_or_default().trim().to_string(),
+            (None, None) => return Ok(ResponseVersion::V1),
+        };
+        match requested.as_str() {
+            "1" => Ok(ResponseVersion::V1),
+            "2" => Ok(ResponseVersion::V2),
+            _ => Err(AppError::UnsupportedResponseVersion(requested)),
+        }
+    }
+
+    /// Convert a response to this version's wire shape
+    pub fn wire(self, response: ExecuteResponse) -> WireResponse {
+        match self {
+            ResponseVersion::V1 => WireResponse::V1(response),
+            ResponseVersion::V2 => WireResponse::V2(response.into()),
+        }
+    }
+}
+
+/// An execute response body in the version the caller asked for
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum WireResponse {
+    /// Version 1
+    V1(ExecuteResponse),
+    /// Version 2
+    V2(ExecuteResponseV2),
+}
+
+/// Version 2 of the execute response
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecuteResponseV2 {
+    /// Status of execution ("success" or "error")
+    pub status: String,
+    /// Output from code execution (if successful)
+    pub output: Option<String>,
+    /// The JavaScript result as JSON (if successful and not `undefined`)
+    pub output_json: Option<serde_json::Value>,
+    /// Captured output streams
+    pub outputs: OutputsV2,
+    /// Error information (if execution failed)
+    pub error: Option<ErrorInfo>,
+    /// Metadata about the execution
+    pub metadata: ExecutionMetadataV2,
+}
+
+/// Captured output streams of a version 2 response
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputsV2 {
+    /// Standard output content captured during execution
+    pub stdout: Option<String>,
+    /// Standard error content captured during execution
+    pub stderr: Option<String>,
+    /// Console calls and `app_log` messages with their severity, in order
+    pub logs: Option<Vec<LogEntryV2>>,
+    /// Whether stdout lost output to its cap or the shared output budget
+    pub stdout_truncated: bool,
+    /// Whether stderr lost output to its cap or the shared output budget
+    pub stderr_truncated: bool,
+    /// Whether `app_log` messages were dropped by their cap or the shared output budget
+    pub logs_truncated: bool,
+}
+
+/// A captured console call or `app_log` message in a version 2 response
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntryV2 {
+    /// Severity of the call
+    pub level: LogLevel,
+    /// What made the call
+    pub source: LogSource,
+    /// The formatted console message, or the `app_log` message without its prefix
+    pub message: String,
+    /// Unix time of the call in milliseconds
+    pub timestamp_ms: u64,
+}
+
+impl From<LogEntry> for LogEntryV2 {
+    fn from(entry: LogEntry) -> Self {
+        LogEntryV2 {
+            level: entry.level,
+            source: entry.source,
+            message: entry.message,
+            timestamp_ms: entry.timestamp_ms,
+        }
+    }
+}
+
+/// Version 2 of the execution metadata
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionMetadataV2 {
+    /// Type of code executed ("javascript" or "webassembly")
+    pub code_type: String,
+    /// Format the WebAssembly module was written in, when it was not the binary format
+    pub source_format: Option<SourceFormat>,
+    /// When and for how long the code ran
+    pub timings: TimingsV2,
+    /// Size of the executed code in bytes
+    pub resource_size: usize,
+    /// Size of the downloaded artifact in bytes, if it was compressed
+    pub compressed_size: Option<usize>,
+    /// Attempts made to download the code, including resumed ones
+    pub download: Option<DownloadReport>,
+    /// Whether the code came from the download cache
+    pub download_cache: Option<DownloadCacheStatus>,
+    /// Hex SHA-256 digest of the executed code (after decompression)
+    pub code_sha256: Option<String>,
+    /// Identifier of the execution, used to look it up in the history
+    pub execution_id: Option<String>,
+    /// Name of the API key the request was made with, never the key itself
+    pub api_key: Option<String>,
+    /// Name of the registered module that ran, for `module` requests
+    pub registered_module: Option<String>,
+    /// Hex id of the execution's trace, when trace export is enabled
+    pub trace_id: Option<String>,
+    /// Whether the compiled WebAssembly module came from the module cache
+    pub module_cache: Option<CacheStatus>,
+    /// Fuel the WebAssembly module consumed
+    pub fuel_consumed: Option<u64>,
+    /// Largest size the WebAssembly module's memory reached, in 64 KiB pages
+    pub peak_memory_pages: Option<u64>,
+    /// Heap the script's runtime held when it finished, in bytes
+    pub memory_used_bytes: Option<u64>,
+    /// How the result a WebAssembly module set with `set_output` is encoded in `output`
+    pub output_encoding: Option<OutputEncoding>,
+    /// JavaScript console/capture calls dropped after `js.max_console_calls` was reached
+    pub console_calls_dropped: Option<u64>,
+    /// Summary of guest fetch calls made during execution
+    pub network: Option<NetworkStatsV2>,
+    /// Output budget usage, including output dropped once it was exhausted
+    pub output: Option<OutputUsageV2>,
+    /// Limits applied from the WebAssembly module's manifest, if it has one
+    pub manifest: Option<AppliedManifestV2>,
+    /// Seed of the guest's random stream, in deterministic mode
+    pub seed: Option<u64>,
+    /// Limits and options the execution ran with, after the server's caps and any manifest
+    pub effective_options: Option<EffectiveOptionsV2>,
+}
+
+/// Timing fields of the version 2 metadata
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TimingsV2 {
+    /// Execution time in milliseconds, from the start of the download to the end of the run
+    pub execution_time: u64,
+    /// Time spent downloading the code, in milliseconds
+    pub download_ms: Option<u64>,
+    /// Time spent compiling the WebAssembly module or script, in milliseconds
+    pub compile_ms: Option<u64>,
+    /// Time spent instantiating the module or setting up the script's context, in milliseconds
+    pub instantiate_ms: Option<u64>,
+    /// Time the guest code ran, in milliseconds
+    pub run_ms: Option<u64>,
+    /// ISO timestamp of execution
+    pub timestamp: String,
+}
+
+/// Version 2 of the guest fetch summary
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkStatsV2 {
+    /// Total number of fetch calls
+    pub fetch_calls: u64,
+    /// Response body bytes received from all hosts
+    pub bytes: u64,
+    /// Breakdown by destination host
+    pub hosts: HashMap<String, HostNetworkStatsV2>,
+}
+
+/// Version 2 of the fetch summary for one destination host
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HostNetworkStatsV2 {
+    /// Number of fetch calls
+    pub calls: u64,
+    /// Calls that received no HTTP response
+    pub errors: u64,
+    /// Summed duration of all calls in milliseconds
+    pub duration_ms: u64,
+    /// Response body bytes received
+    pub bytes: u64,
+    /// Number of calls per response status class
+    pub status_classes: HashMap<String, u64>,
+}
+
+/// Version 2 of the output budget usage
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputUsageV2 {
+    /// The budget in bytes
+    pub limit_bytes: usize,
+    /// Bytes the guest wrote by channel, captured or dropped
+    pub produced_bytes: BTreeMap<&'static str, u64>,
+    /// Bytes captured across all channels
+    pub used_bytes: usize,
+    /// Messages captured before further messages are dropped
+    pub limit_entries: u64,
+    /// Messages captured across all channels
+    pub used_entries: u64,
+    /// Dropped output by channel (only channels that dropped something)
+    pub dropped: BTreeMap<&'static str, DroppedOutput>,
+}
+
+/// Version 2 of the options an execution ran with
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectiveOptionsV2 {
+    /// Execution timeout in milliseconds
+    pub timeout_ms: u64,
+    /// Largest size WebAssembly memories may grow to, or heap the script may allocate, in bytes
+    pub memory_bytes: u64,
+    /// Bytes of output captured across all channels
+    pub max_output_bytes: usize,
+    /// Bytes of stdout captured
+    pub max_stdout_bytes: usize,
+    /// Bytes of stderr captured
+    pub max_stderr_bytes: usize,
+    /// Bytes of `app_log` messages captured
+    pub max_log_bytes: usize,
+    /// Guest fetch calls sent
+    pub max_fetch_calls: u64,
+    /// Response body bytes received by guest fetch calls
+    pub max_fetch_bytes: u64,
+    /// Messages captured across stdout, stderr and `app_log`
+    pub max_log_entries: u64,
+    /// Fuel the WebAssembly module was given; null for scripts
+    pub max_fuel: Option<u64>,
+    /// Whether exhausting the output budget terminates the execution
+    pub strict_output: bool,
+    /// Zone of the guest's local-time functions
+    pub timezone: String,
+    /// Whether clocks and random values were deterministic
+    pub deterministic: bool,
+    /// Number of variables in the guest's `env`
+    pub env_vars: usize,
+}
+
+/// Version 2 of the limits applied from a module's manifest
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedManifestV2 {
+    /// Execution timeout in milliseconds
+    pub timeout_ms: u64,
+    /// Largest size the module's memories may grow to, in bytes
+    pub max_memory_bytes: u64,
+    /// Capabilities granted to the module
+    pub permissions: Vec<Permission>,
+    /// Hosts fetch calls may go to; unset allows any host
+    pub fetch_allowlist: Option<Vec<String>>,
+}
+
+impl From<ExecuteResponse> for ExecuteResponseV2 {
+    fn from(response: ExecuteResponse) -> Self {
+        ExecuteResponseV2 {
+            status: response.status,
+            output: response.output,
+            output_json: response.output_json,
+            outputs: OutputsV2 {
+                stdout: response.stdout,
+                stderr: response.stderr,
+                logs: response
+                    .logs
+                    .map(|logs| logs.into_iter().map(LogEntryV2::from).collect()),
+                stdout_truncated: response.stdout_truncated,
+                stderr_truncated: response.stderr_truncated,
+                logs_truncated: response.logs_truncated,
+            },
+            error: response.error,
+            metadata: response.metadata.into(),
+        }
+    }
+}
+
+impl From<ExecutionMetadata> for ExecutionMetadataV2 {
+    fn from(metadata: ExecutionMetadata) -> Self {
+        ExecutionMetadataV2 {
+            code_type: metadata.code_type,
+            source_format: metadata.source_format,
+            timings: TimingsV2 {
+                execution_time: metadata.execution_time,
+                download_ms: metadata.download_ms,
+                compile_ms: metadata.compile_ms,
+                instantiate_ms: metadata.instantiate_ms,
+                run_ms: metadata.run_ms,
+                timestamp: metadata.timestamp,
+            },
+            resource_size: metadata.resource_size,
+            compressed_size: metadata.compressed_size,
+            download: metadata.download,
+            download_cache: metadata.download_cache,
+            code_sha256: metadata.code_sha256,
+            execution_id: metadata.execution_id,
+            api_key: metadata.api_key,
+            registered_module: metadata.registered_module,
+            trace_id: metadata.trace_id,
+            module_cache: metadata.module_cache,
+            fuel_consumed: metadata.fuel_consumed,
+            peak_memory_pages: metadata.peak_memory_pages,
+            memory_used_bytes: metadata.memory_used_bytes,
+            output_encoding: metadata.output_encoding,
+            console_calls_dropped: metadata.console_calls_dropped,
+            network: metadata.network.map(Into::into),
+            output: metadata.output.map(Into::into),
+            manifest: metadata.manifest.map(Into::into),
+            seed: metadata.seed,
+            effective_options: metadata.effective_options.map(Into::into),
+        }
+    }
+}
+
+impl From<NetworkStats> for NetworkStatsV2 {
+    fn from(stats: NetworkStats) -> Self {
+        NetworkStatsV2 {
+            fetch_calls: stats.fetch_calls,
+            bytes: stats.bytes,
+            hosts: stats
+                .hosts
+                .into_iter()
+                .map(|(host, stats)| (host, stats.into()))
+                .collect(),
+        }
+    }
+}
+
+impl From<HostNetworkStats> for HostNetworkStatsV2 {
+    fn from(stats: HostNetworkStats) -> Self {
+        HostNetworkStatsV2 {
+            calls: stats.calls,
+            errors: stats.errors,
+            duration_ms: stats.duration_ms,
+            bytes: stats.bytes,
+            status_classes: stats.status_classes,
+        }
+    }
+}
+
+impl From<EffectiveOptions> for EffectiveOptionsV2 {
+    fn from(options: EffectiveOptions) -> Self {
+        EffectiveOptionsV2 {
+            timeout_ms: options.timeout_ms,
+            memory_bytes: options.memory_bytes,
+            max_output_bytes: options.max_output_bytes,
+            max_stdout_bytes: options.max_stdout_bytes,
+            max_stderr_bytes: options.max_stderr_bytes,
+            max_log_bytes: options.max_log_bytes,
+            max_fetch_calls: options.max_fetch_calls,
+            max_fetch_bytes: options.max_fetch_bytes,
+            max_log_entries: options.max_log_entries,
+            max_fuel: options.max_fuel,
+            strict_output: options.strict_output,
+            timezone: options.timezone,
+            deterministic: options.deterministic,
+            env_vars: options.env_vars,
+        }
+    }
+}
+
+impl From<OutputUsage> for OutputUsageV2 {
+    fn from(usage: OutputUsage) -> Self {
+        OutputUsageV2 {
+            limit_bytes: usage.limit_bytes,
+            produced_bytes: usage.produced_bytes,
+            used_bytes: usage.used_bytes,
+            limit_entries: usage.limit_entries,
+            used_entries: usage.used_entries,
+            dropped: usage.dropped,
+        }
+    }
+}
+
+impl From<AppliedManifest> for AppliedManifestV2 {
+    fn from(applied: AppliedManifest) -> Self {
+        AppliedManifestV2 {
+            timeout_ms: applied.timeout_ms,
+            max_memory_bytes: applied.max_memory_bytes,
+            permissions: applied.permissions,
+            fetch_allowlist: applied.fetch_allowlist,
+        }
+    }
+}
@@ -30,3 +30,33 @@ curl -X POST http://127.0.0.1:3000/execute \
 # Clean up
 echo "\nStopping Hoya server..."
 kill $SERVER_PID
+
+# Quiet mode: captured output reaches the response but not the server's own stdout/stderr
+echo "\nTesting quiet mode (capture.echo_to_host = false)..."
+QUIET_DIR=$(mktemp -d)
+printf '[capture]\necho_to_host = false\n' > "$QUIET_DIR/hoya.toml"
+(cd examples && python3 -m http.server 8007 > /dev/null 2>&1) &
+QUIET_HTTP_PID=$!
+HOYA_CONFIG="$QUIET_DIR/hoya.toml" cargo run > "$QUIET_DIR/server.log" 2>&1 &
+QUIET_SERVER_PID=$!
+sleep 2
+
+curl -s -X POST http://127.0.0.1:3000/execute \
+  -H "Content-Type: application/json" \
+  -d '{"url": "http://localhost:8007/stdout_stderr_test.js"}' > "$QUIET_DIR/response.json"
+sleep 1
+
+python3 - "$QUIET_DIR" <<'PYEOF'
+import json, sys
+quiet_dir = sys.argv[1]
+body = json.load(open(f"{quiet_dir}/response.json"))
+server_log = open(f"{quiet_dir}/server.log").read()
+markers = ["This is a standard output message", "This is an error message",
+           "This is a log message via app_log"]
+captured = all(m in body["stdout"] + body["stderr"] for m in markers)
+echoed = [m for m in markers if m in server_log]
+print("quiet mode:", "ok" if captured and not echoed else f"FAILED (captured={captured}, echoed={echoed})")
+PYEOF
+
+kill $QUIET_SERVER_PID $QUIET_HTTP_PID
+rm -rf "$QUIET_DIR"